@@ -289,6 +289,11 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::ImportKey => handle_import_key(&self.garage, req).await,
 			Endpoint::UpdateKey { id } => handle_update_key(&self.garage, id, req).await,
 			Endpoint::DeleteKey { id } => handle_delete_key(&self.garage, id).await,
+			Endpoint::CreateSessionToken { id } => {
+				handle_create_session_token(&self.garage, id, req).await
+			}
+			Endpoint::RotateKeySecret { id } => handle_rotate_key_secret(&self.garage, id).await,
+			Endpoint::RetireKeySecret { id } => handle_retire_key_secret(&self.garage, id).await,
 			// Buckets
 			Endpoint::ListBuckets => handle_list_buckets(&self.garage).await,
 			Endpoint::GetBucketInfo { id, global_alias } => {
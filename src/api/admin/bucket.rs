@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
@@ -193,6 +193,13 @@ async fn bucket_info_results(
 					error_document: wsc.error_document,
 				}
 			}),
+			public_read: *state.public_read.get(),
+			public_access_block: state
+				.public_access_block
+				.get()
+				.map(ApiPublicAccessBlock::from)
+				.unwrap_or_default(),
+			tags: state.tags.get().clone().unwrap_or_default(),
 			keys: relevant_keys
 				.into_values()
 				.map(|key| {
@@ -242,6 +249,9 @@ struct GetBucketInfoResult {
 	website_access: bool,
 	#[serde(default)]
 	website_config: Option<GetBucketInfoWebsiteResult>,
+	public_read: bool,
+	public_access_block: ApiPublicAccessBlock,
+	tags: BTreeMap<String, String>,
 	keys: Vec<GetBucketInfoKey>,
 	objects: i64,
 	bytes: i64,
@@ -424,6 +434,8 @@ pub async fn handle_update_bucket(
 					"Please specify indexDocument when enabling website access.",
 				)?,
 				error_document: wa.error_document,
+				redirect_all: None,
+				routing_rules: vec![],
 			}));
 		} else {
 			if wa.index_document.is_some() || wa.error_document.is_some() {
@@ -435,6 +447,32 @@ pub async fn handle_update_bucket(
 		}
 	}
 
+	if let Some(pab) = req.public_access_block {
+		let block = PublicAccessBlock::from(pab);
+		state
+			.public_access_block
+			.update(Some(block).filter(|b| *b != PublicAccessBlock::default()));
+	}
+
+	if let Some(pr) = req.public_read {
+		if pr
+			&& state
+				.effective_public_access_block(&garage.config.public_access_block)
+				.block_public_acls
+		{
+			return Err(Error::bad_request(
+				"Public read access is blocked by the public access block settings of the bucket",
+			));
+		}
+		state.public_read.update(pr);
+	}
+
+	if let Some(tags) = req.tags {
+		state
+			.tags
+			.update(Some(tags).filter(|tags| !tags.is_empty()));
+	}
+
 	if let Some(q) = req.quotas {
 		state.quotas.update(BucketQuotas {
 			max_size: q.max_size,
@@ -451,9 +489,47 @@ pub async fn handle_update_bucket(
 #[serde(rename_all = "camelCase")]
 struct UpdateBucketRequest {
 	website_access: Option<UpdateBucketWebsiteAccess>,
+	public_read: Option<bool>,
+	public_access_block: Option<ApiPublicAccessBlock>,
+	tags: Option<BTreeMap<String, String>>,
 	quotas: Option<ApiBucketQuotas>,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ApiPublicAccessBlock {
+	#[serde(default)]
+	block_public_acls: bool,
+	#[serde(default)]
+	ignore_public_acls: bool,
+	#[serde(default)]
+	block_public_policy: bool,
+	#[serde(default)]
+	restrict_public_buckets: bool,
+}
+
+impl From<PublicAccessBlock> for ApiPublicAccessBlock {
+	fn from(b: PublicAccessBlock) -> Self {
+		Self {
+			block_public_acls: b.block_public_acls,
+			ignore_public_acls: b.ignore_public_acls,
+			block_public_policy: b.block_public_policy,
+			restrict_public_buckets: b.restrict_public_buckets,
+		}
+	}
+}
+
+impl From<ApiPublicAccessBlock> for PublicAccessBlock {
+	fn from(b: ApiPublicAccessBlock) -> Self {
+		Self {
+			block_public_acls: b.block_public_acls,
+			ignore_public_acls: b.ignore_public_acls,
+			block_public_policy: b.block_public_policy,
+			restrict_public_buckets: b.restrict_public_buckets,
+		}
+	}
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct UpdateBucketWebsiteAccess {
@@ -577,7 +653,7 @@ pub async fn handle_local_unalias_bucket(
 
 // ---- HELPER ----
 
-fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
+pub(crate) fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
 	let id_hex = hex::decode(id).ok_or_bad_request("Invalid bucket id")?;
 	Ok(Uuid::try_from(&id_hex).ok_or_bad_request("Invalid bucket id")?)
 }
@@ -4,14 +4,19 @@ use std::sync::Arc;
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
+use garage_util::time::{msec_to_rfc3339, rfc3339_to_msec};
+
 use garage_table::*;
 
 use garage_model::garage::Garage;
 use garage_model::key_table::*;
+use garage_model::permission::BucketKeyPerm;
+use garage_model::session::{SessionClaims, MAX_SESSION_DURATION};
 
 use garage_api_common::helpers::*;
 
 use crate::api_server::ResBody;
+use crate::bucket::parse_bucket_id;
 use crate::error::*;
 
 pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<ResBody>, Error> {
@@ -29,6 +34,8 @@ pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<ResBody>,
 		.map(|k| ListKeyResultItem {
 			id: k.key_id.to_string(),
 			name: k.params().unwrap().name.get().clone(),
+			active: k.is_active(),
+			expired: k.is_expired(),
 		})
 		.collect::<Vec<_>>();
 
@@ -40,6 +47,8 @@ pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<ResBody>,
 struct ListKeyResultItem {
 	id: String,
 	name: String,
+	active: bool,
+	expired: bool,
 }
 
 pub async fn handle_get_key_info(
@@ -134,6 +143,16 @@ pub async fn handle_update_key(
 			key_state.allow_create_bucket.update(false);
 		}
 	}
+	if let Some(active) = req.active {
+		key_state.active.update(active);
+	}
+	if req.never_expires {
+		key_state.expiration.update(None);
+	} else if let Some(expiration) = req.expiration {
+		let expiration = rfc3339_to_msec(&expiration)
+			.ok_or_bad_request("Invalid expiration date, expected a RFC3339 timestamp")?;
+		key_state.expiration.update(Some(expiration));
+	}
 
 	garage.key_table.insert(&key).await?;
 
@@ -146,6 +165,47 @@ struct UpdateKeyRequest {
 	name: Option<String>,
 	allow: Option<KeyPerm>,
 	deny: Option<KeyPerm>,
+	active: Option<bool>,
+	expiration: Option<String>,
+	#[serde(default)]
+	never_expires: bool,
+}
+
+pub async fn handle_rotate_key_secret(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let mut key = garage.key_helper().get_existing_key(&id).await?;
+
+	key.params_mut()
+		.unwrap()
+		.rotate_secret_key()
+		.ok_or_bad_request(format!(
+			"Key {} already has {} secret keys, retire the old ones first",
+			id,
+			KeyParams::MAX_SECRET_KEYS
+		))?;
+	garage.key_table.insert(&key).await?;
+
+	key_info_results(garage, key, true).await
+}
+
+pub async fn handle_retire_key_secret(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let mut key = garage.key_helper().get_existing_key(&id).await?;
+
+	let retired = key.params_mut().unwrap().retire_old_secret_keys();
+	if retired.is_empty() {
+		return Err(Error::bad_request(format!(
+			"Key {} has no old secret key to retire",
+			id
+		)));
+	}
+	garage.key_table.insert(&key).await?;
+
+	key_info_results(garage, key, false).await
 }
 
 pub async fn handle_delete_key(
@@ -163,6 +223,126 @@ pub async fn handle_delete_key(
 		.body(empty_body())?)
 }
 
+pub async fn handle_create_session_token(
+	garage: &Arc<Garage>,
+	id: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<CreateSessionTokenRequest, _, Error>(req).await?;
+
+	let key = garage.key_helper().get_existing_key(&id).await?;
+	if !key.is_usable() {
+		return Err(Error::bad_request(format!(
+			"Key {} is inactive or has expired",
+			key.key_id
+		)));
+	}
+
+	let duration = req.duration.unwrap_or(3600);
+	if !(60..=MAX_SESSION_DURATION).contains(&duration) {
+		return Err(Error::bad_request(format!(
+			"The duration must be between 60 and {} seconds",
+			MAX_SESSION_DURATION
+		)));
+	}
+
+	let key_state = key.state.as_option().unwrap();
+	let buckets = match req.buckets {
+		None => key_state
+			.authorized_buckets
+			.items()
+			.iter()
+			.filter(|(_, perm)| perm.is_any())
+			.map(|(bucket_id, perm)| (*bucket_id, *perm))
+			.collect::<Vec<_>>(),
+		Some(buckets) => {
+			let mut res = Vec::with_capacity(buckets.len());
+			for b in buckets {
+				let bucket_id = parse_bucket_id(&b.bucket_id)?;
+				let parent_perm = key.bucket_permissions(&bucket_id);
+				if (b.permissions.read && !parent_perm.allow_read)
+					|| (b.permissions.write && !parent_perm.allow_write)
+					|| (b.permissions.owner && !parent_perm.allow_owner)
+				{
+					return Err(Error::bad_request(format!(
+						"Key {} does not have the requested permissions on bucket {}",
+						key.key_id, b.bucket_id
+					)));
+				}
+				res.push((
+					bucket_id,
+					BucketKeyPerm {
+						timestamp: parent_perm.timestamp,
+						allow_read: b.permissions.read,
+						allow_write: b.permissions.write,
+						allow_owner: b.permissions.owner,
+					},
+				));
+			}
+			res
+		}
+	};
+	if !buckets.iter().any(|(_, perm)| perm.is_any()) {
+		return Err(Error::bad_request(
+			"The temporary credentials would not give access to any bucket",
+		));
+	}
+
+	let claims = SessionClaims::new(&key.key_id, duration, buckets, req.prefix);
+	let creds = claims.into_credentials(&garage.session_token_key);
+
+	let res = CreateSessionTokenResult {
+		access_key_id: creds.access_key_id,
+		secret_access_key: creds.secret_access_key,
+		session_token: creds.session_token,
+		parent_key_id: creds.claims.parent_key_id,
+		expiration: msec_to_rfc3339(creds.claims.expiration),
+		buckets: creds
+			.claims
+			.buckets
+			.iter()
+			.map(|(bucket_id, perm)| SessionTokenBucket {
+				bucket_id: hex::encode(bucket_id),
+				permissions: ApiBucketKeyPerm {
+					read: perm.allow_read,
+					write: perm.allow_write,
+					owner: perm.allow_owner,
+				},
+			})
+			.collect::<Vec<_>>(),
+		prefix: creds.claims.prefix,
+	};
+
+	json_ok_response(&res)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionTokenRequest {
+	duration: Option<u64>,
+	buckets: Option<Vec<SessionTokenBucket>>,
+	prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionTokenBucket {
+	bucket_id: String,
+	permissions: ApiBucketKeyPerm,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionTokenResult {
+	access_key_id: String,
+	secret_access_key: String,
+	session_token: String,
+	parent_key_id: String,
+	expiration: String,
+	buckets: Vec<SessionTokenBucket>,
+	prefix: Option<String>,
+}
+
 async fn key_info_results(
 	garage: &Arc<Garage>,
 	key: Key,
@@ -197,13 +377,26 @@ async fn key_info_results(
 		name: key_state.name.get().clone(),
 		access_key_id: key.key_id.clone(),
 		secret_access_key: if show_secret {
-			Some(key_state.secret_key.clone())
+			Some(key_state.current_secret_key().to_string())
 		} else {
 			None
 		},
+		previous_secret_access_keys: if show_secret {
+			key_state
+				.old_secret_keys()
+				.into_iter()
+				.map(String::from)
+				.collect()
+		} else {
+			vec![]
+		},
+		pending_secret_retirement: !key_state.old_secret_keys().is_empty(),
 		permissions: KeyPerm {
 			create_bucket: *key_state.allow_create_bucket.get(),
 		},
+		active: *key_state.active.get(),
+		expiration: key_state.expiration.get().map(msec_to_rfc3339),
+		expired: key.is_expired(),
 		buckets: relevant_buckets
 			.into_values()
 			.map(|bucket| {
@@ -248,7 +441,13 @@ struct GetKeyInfoResult {
 	access_key_id: String,
 	#[serde(skip_serializing_if = "is_default")]
 	secret_access_key: Option<String>,
+	#[serde(skip_serializing_if = "is_default")]
+	previous_secret_access_keys: Vec<String>,
+	pending_secret_retirement: bool,
 	permissions: KeyPerm,
+	active: bool,
+	expiration: Option<String>,
+	expired: bool,
 	buckets: Vec<KeyInfoBucketResult>,
 }
 
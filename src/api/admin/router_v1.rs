@@ -45,6 +45,15 @@ pub enum Endpoint {
 	UpdateKey {
 		id: String,
 	},
+	CreateSessionToken {
+		id: String,
+	},
+	RotateKeySecret {
+		id: String,
+	},
+	RetireKeySecret {
+		id: String,
+	},
 	// Buckets
 	ListBuckets,
 	CreateBucket,
@@ -112,6 +121,9 @@ impl Endpoint {
 			POST "/v1/key" if id => UpdateKey (query::id),
 			POST "/v1/key" => CreateKey,
 			POST "/v1/key/import" => ImportKey,
+			POST "/v1/key/session" => CreateSessionToken (query::id),
+			POST "/v1/key/rotate" => RotateKeySecret (query::id),
+			POST "/v1/key/retire-secret" => RetireKeySecret (query::id),
 			DELETE "/v1/key" if id => DeleteKey (query::id),
 			GET "/v1/key" => ListKeys,
 			// Bucket endpoints
@@ -0,0 +1,173 @@
+//! Recording of requests in the access logs of buckets
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::{header, Request, Response};
+
+use garage_model::bucket_table::{BucketLoggingConfig, BucketParams};
+use garage_model::garage::Garage;
+use garage_model::s3::access_log::AccessLogRecord;
+use garage_util::data::gen_uuid;
+use garage_util::forwarded_headers;
+use garage_util::time::now_msec;
+
+use crate::helpers::BoxBody;
+
+/// Access log record of a request being processed, shared between the HTTP
+/// server and the API handler through the extensions of the request.
+///
+/// The API handler gives the bucket targeted by the request using `set_bucket`.
+/// If access logging is enabled on that bucket, the request is recorded
+/// once its response has been sent.
+#[derive(Clone)]
+pub struct AccessLog(Arc<Mutex<AccessLogState>>);
+
+struct AccessLogState {
+	start: Instant,
+	record: AccessLogRecord,
+	target: Option<(Arc<Garage>, BucketLoggingConfig)>,
+}
+
+impl AccessLog {
+	/// Start recording request `req`, received from `addr`
+	pub fn new<B>(req: &Request<B>, addr: &str) -> Self {
+		let header_str = |name: header::HeaderName| {
+			req.headers()
+				.get(name)
+				.and_then(|v| v.to_str().ok())
+				.map(str::to_string)
+		};
+		let remote_ip = forwarded_headers::handle_forwarded_for_headers(req.headers())
+			.ok()
+			.or_else(|| {
+				addr.parse::<std::net::SocketAddr>()
+					.ok()
+					.map(|a| a.ip().to_string())
+			});
+		let version_id = req.uri().query().and_then(|q| {
+			url::form_urlencoded::parse(q.as_bytes())
+				.find(|(k, _)| k == "versionId")
+				.map(|(_, v)| v.into_owned())
+		});
+		let record = AccessLogRecord {
+			time: now_msec(),
+			remote_ip,
+			request_id: hex::encode_upper(&gen_uuid().as_slice()[..8]),
+			request_uri: format!("{} {} {:?}", req.method(), req.uri(), req.version()),
+			referer: header_str(header::REFERER),
+			user_agent: header_str(header::USER_AGENT),
+			host: header_str(header::HOST),
+			version_id,
+			..Default::default()
+		};
+		Self(Arc::new(Mutex::new(AccessLogState {
+			start: Instant::now(),
+			record,
+			target: None,
+		})))
+	}
+
+	pub fn set_operation(&self, operation: &str) {
+		self.0.lock().unwrap().record.operation = operation.to_string();
+	}
+
+	/// Set the bucket targeted by the request, the access key ID of the
+	/// requester and the key of the object targeted by the request, if any
+	pub fn set_bucket(
+		&self,
+		garage: &Arc<Garage>,
+		bucket_name: &str,
+		bucket_params: &BucketParams,
+		requester: Option<&str>,
+		key: Option<&str>,
+	) {
+		let mut state = self.0.lock().unwrap();
+		state.target = bucket_params
+			.logging_config
+			.get()
+			.clone()
+			.map(|config| (garage.clone(), config));
+		state.record.bucket = bucket_name.to_string();
+		state.record.requester = requester.map(str::to_string);
+		state.record.key = key.map(str::to_string);
+	}
+
+	pub fn set_error_code(&self, code: &str) {
+		self.0.lock().unwrap().record.error_code = Some(code.to_string());
+	}
+
+	/// Record the status of response `resp`. The request is written to the
+	/// access log of its bucket once the body of the response has been sent
+	/// (or the client has gone away).
+	pub fn finish<E: 'static>(self, resp: Response<BoxBody<E>>) -> Response<BoxBody<E>> {
+		{
+			let mut state = self.0.lock().unwrap();
+			if state.target.is_none() {
+				return resp;
+			}
+			state.record.http_status = resp.status().as_u16();
+			state.record.turnaround_time = state.start.elapsed().as_millis() as u64;
+		}
+		resp.map(|body| {
+			BoxBody::new(LoggedBody {
+				inner: body,
+				log: self,
+				bytes_sent: 0,
+			})
+		})
+	}
+
+	fn write(&self, bytes_sent: u64) {
+		let mut state = self.0.lock().unwrap();
+		if let Some((garage, config)) = state.target.take() {
+			state.record.bytes_sent = bytes_sent;
+			state.record.total_time = state.start.elapsed().as_millis() as u64;
+			garage.access_log.push(&config, &state.record);
+		}
+	}
+}
+
+/// Response body that counts the bytes that are sent, and writes the
+/// access log record when it is dropped
+struct LoggedBody<B> {
+	inner: B,
+	log: AccessLog,
+	bytes_sent: u64,
+}
+
+impl<B: Body<Data = Bytes> + Unpin> Body for LoggedBody<B> {
+	type Data = Bytes;
+	type Error = B::Error;
+
+	fn poll_frame(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+		let res = Pin::new(&mut self.inner).poll_frame(cx);
+		if let Poll::Ready(Some(Ok(frame))) = &res {
+			if let Some(data) = frame.data_ref() {
+				self.bytes_sent += data.len() as u64;
+			}
+		}
+		res
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.inner.is_end_stream()
+	}
+
+	fn size_hint(&self) -> SizeHint {
+		self.inner.size_hint()
+	}
+}
+
+impl<B> Drop for LoggedBody<B> {
+	fn drop(&mut self) {
+		self.log.write(self.bytes_sent);
+	}
+}
@@ -1,5 +1,6 @@
 use std::convert::Infallible;
 use std::fs::{self, Permissions};
+use std::net::{IpAddr, SocketAddr};
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
 use std::time::Duration;
@@ -32,6 +33,7 @@ use garage_util::forwarded_headers;
 use garage_util::metrics::{gen_trace_id, RecordDuration};
 use garage_util::socket_address::UnixOrTCPSocketAddress;
 
+use crate::access_log::AccessLog;
 use crate::helpers::{BoxBody, ErrorBody};
 
 pub trait ApiEndpoint: Send + Sync + 'static {
@@ -43,6 +45,11 @@ pub trait ApiError: std::error::Error + Send + Sync + 'static {
 	fn http_status_code(&self) -> StatusCode;
 	fn add_http_headers(&self, header_map: &mut HeaderMap<HeaderValue>);
 	fn http_body(&self, garage_region: &str, path: &str) -> ErrorBody;
+
+	/// Error code written in access logs
+	fn access_log_code(&self) -> Option<&'static str> {
+		None
+	}
 }
 
 pub trait ApiHandler: Send + Sync + 'static {
@@ -60,6 +67,12 @@ pub trait ApiHandler: Send + Sync + 'static {
 	) -> impl Future<Output = Result<Response<BoxBody<Self::Error>>, Self::Error>> + Send;
 }
 
+/// IP address of the client that sent a request, as seen on the connection
+/// to the API server (i.e. the address of the reverse proxy, if there is one).
+/// It is stored in the extensions of requests received over TCP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
 pub struct ApiServer<A: ApiHandler> {
 	region: String,
 	api_handler: A,
@@ -137,11 +150,18 @@ impl<A: ApiHandler> ApiServer<A> {
 
 	async fn handler(
 		self: Arc<Self>,
-		req: Request<IncomingBody>,
+		mut req: Request<IncomingBody>,
 		addr: String,
 	) -> Result<Response<BoxBody<A::Error>>, http::Error> {
 		let uri = req.uri().clone();
 
+		if let Ok(socket_addr) = addr.parse::<SocketAddr>() {
+			req.extensions_mut().insert(ClientIp(socket_addr.ip()));
+		}
+
+		let access_log = AccessLog::new(&req, &addr);
+		req.extensions_mut().insert(access_log.clone());
+
 		if let Ok(forwarded_for_ip_addr) =
 			forwarded_headers::handle_forwarded_for_headers(req.headers())
 		{
@@ -168,14 +188,14 @@ impl<A: ApiHandler> ApiServer<A> {
 			.start(&tracer);
 
 		let res = self
-			.handler_stage2(req)
+			.handler_stage2(req, &access_log)
 			.with_context(Context::current_with_span(span))
 			.await;
 
 		match res {
 			Ok(x) => {
 				debug!("{} {:?}", x.status(), x.headers());
-				Ok(x)
+				Ok(access_log.finish(x))
 			}
 			Err(e) => {
 				let body = e.http_body(&self.region, uri.path());
@@ -192,8 +212,13 @@ impl<A: ApiHandler> ApiServer<A> {
 				} else {
 					info!("Response: error {}, {}", e.http_status_code(), e);
 				}
-				Ok(http_error
-					.map(|body| BoxBody::new(body.map_err(|_: Infallible| unreachable!()))))
+				if let Some(code) = e.access_log_code() {
+					access_log.set_error_code(code);
+				}
+				Ok(access_log.finish(
+					http_error
+						.map(|body| BoxBody::new(body.map_err(|_: Infallible| unreachable!()))),
+				))
 			}
 		}
 	}
@@ -201,9 +226,11 @@ impl<A: ApiHandler> ApiServer<A> {
 	async fn handler_stage2(
 		&self,
 		req: Request<IncomingBody>,
+		access_log: &AccessLog,
 	) -> Result<Response<BoxBody<A::Error>>, A::Error> {
 		let endpoint = self.api_handler.parse_endpoint(&req)?;
 		debug!("Endpoint: {}", endpoint.name());
+		access_log.set_operation(endpoint.name());
 
 		let current_context = Context::current();
 		let current_span = current_context.span();
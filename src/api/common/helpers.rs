@@ -14,6 +14,7 @@ use serde::{Deserialize, Serialize};
 use garage_model::bucket_table::BucketParams;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
+use garage_model::session::SessionClaims;
 use garage_util::data::Uuid;
 use garage_util::error::Error as GarageError;
 
@@ -38,7 +39,10 @@ pub struct ReqCtx {
 	pub bucket_id: Uuid,
 	pub bucket_name: String,
 	pub bucket_params: BucketParams,
-	pub api_key: Key,
+	/// Access key used to sign the request, None for anonymous requests
+	pub api_key: Option<Key>,
+	/// Claims of the temporary credentials used to sign the request, if any
+	pub session: Option<SessionClaims>,
 }
 
 /// Host to bucket
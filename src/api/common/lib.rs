@@ -4,6 +4,7 @@ extern crate tracing;
 
 pub mod common_error;
 
+pub mod access_log;
 pub mod cors;
 pub mod encoding;
 pub mod generic_server;
@@ -63,6 +63,30 @@ impl ReqBody {
 		Ok((bytes, checksums))
 	}
 
+	/// Collect the body, failing as soon as it gets larger than `max_size` bytes
+	pub async fn collect_limited(mut self, max_size: usize) -> Result<Bytes, Error> {
+		let mut stream: BoxStream<_> = self.stream.into_inner().unwrap();
+		let mut buf = Vec::new();
+		while let Some(frame) = stream.next().await {
+			if let Ok(data) = frame?.into_data() {
+				if buf.len() + data.len() > max_size {
+					return Err(Error::bad_request(format!(
+						"Request body is larger than {} bytes",
+						max_size
+					)));
+				}
+				buf.extend_from_slice(&data);
+			}
+		}
+		let bytes = Bytes::from(buf);
+
+		self.checksummer.update(&bytes);
+		let checksums = self.checksummer.finalize();
+		checksums.verify(&self.expected_checksums)?;
+
+		Ok(bytes)
+	}
+
 	// ============ streaming =============
 
 	pub fn streaming_with_checksums(
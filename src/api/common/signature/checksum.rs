@@ -4,6 +4,7 @@ use std::hash::Hasher;
 use base64::prelude::*;
 use crc32c::Crc32cHasher as Crc32c;
 use crc32fast::Hasher as Crc32;
+use crc64fast_nvme::Digest as Crc64Nvme;
 use md5::{Digest, Md5};
 use sha1::Sha1;
 use sha2::Sha256;
@@ -14,23 +15,27 @@ use garage_util::data::*;
 
 use super::*;
 
-pub use garage_model::s3::object_table::{ChecksumAlgorithm, ChecksumValue};
+pub use garage_model::s3::object_table::{ChecksumAlgorithm, ChecksumType, ChecksumValue};
 
 pub const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
 
 pub const X_AMZ_CHECKSUM_ALGORITHM: HeaderName =
 	HeaderName::from_static("x-amz-checksum-algorithm");
 pub const X_AMZ_CHECKSUM_MODE: HeaderName = HeaderName::from_static("x-amz-checksum-mode");
+pub const X_AMZ_CHECKSUM_TYPE: HeaderName = HeaderName::from_static("x-amz-checksum-type");
 pub const X_AMZ_CHECKSUM_CRC32: HeaderName = HeaderName::from_static("x-amz-checksum-crc32");
 pub const X_AMZ_CHECKSUM_CRC32C: HeaderName = HeaderName::from_static("x-amz-checksum-crc32c");
 pub const X_AMZ_CHECKSUM_SHA1: HeaderName = HeaderName::from_static("x-amz-checksum-sha1");
 pub const X_AMZ_CHECKSUM_SHA256: HeaderName = HeaderName::from_static("x-amz-checksum-sha256");
+pub const X_AMZ_CHECKSUM_CRC64NVME: HeaderName =
+	HeaderName::from_static("x-amz-checksum-crc64nvme");
 
 pub type Crc32Checksum = [u8; 4];
 pub type Crc32cChecksum = [u8; 4];
 pub type Md5Checksum = [u8; 16];
 pub type Sha1Checksum = [u8; 20];
 pub type Sha256Checksum = [u8; 32];
+pub type Crc64NvmeChecksum = [u8; 8];
 
 #[derive(Debug, Default, Clone)]
 pub struct ExpectedChecksums {
@@ -48,6 +53,7 @@ pub struct Checksummer {
 	pub md5: Option<Md5>,
 	pub sha1: Option<Sha1>,
 	pub sha256: Option<Sha256>,
+	pub crc64nvme: Option<Crc64Nvme>,
 }
 
 #[derive(Default)]
@@ -57,6 +63,7 @@ pub struct Checksums {
 	pub md5: Option<Md5Checksum>,
 	pub sha1: Option<Sha1Checksum>,
 	pub sha256: Option<Sha256Checksum>,
+	pub crc64nvme: Option<Crc64NvmeChecksum>,
 }
 
 impl Checksummer {
@@ -67,6 +74,7 @@ impl Checksummer {
 			md5: None,
 			sha1: None,
 			sha256: None,
+			crc64nvme: None,
 		}
 	}
 
@@ -99,6 +107,9 @@ impl Checksummer {
 		if matches!(&expected.extra, Some(ChecksumValue::Sha1(_))) {
 			self.sha1 = Some(Sha1::new());
 		}
+		if matches!(&expected.extra, Some(ChecksumValue::Crc64Nvme(_))) {
+			self.crc64nvme = Some(Crc64Nvme::new());
+		}
 	}
 
 	pub fn add(mut self, algo: Option<ChecksumAlgorithm>) -> Self {
@@ -115,6 +126,9 @@ impl Checksummer {
 			Some(ChecksumAlgorithm::Sha256) => {
 				self.sha256 = Some(Sha256::new());
 			}
+			Some(ChecksumAlgorithm::Crc64Nvme) => {
+				self.crc64nvme = Some(Crc64Nvme::new());
+			}
 			None => (),
 		}
 		self
@@ -136,6 +150,9 @@ impl Checksummer {
 		if let Some(sha256) = &mut self.sha256 {
 			sha256.update(bytes);
 		}
+		if let Some(crc64nvme) = &mut self.crc64nvme {
+			crc64nvme.write(bytes);
+		}
 	}
 
 	pub fn finalize(self) -> Checksums {
@@ -147,6 +164,7 @@ impl Checksummer {
 			md5: self.md5.map(|x| x.finalize()[..].try_into().unwrap()),
 			sha1: self.sha1.map(|x| x.finalize()[..].try_into().unwrap()),
 			sha256: self.sha256.map(|x| x.finalize()[..].try_into().unwrap()),
+			crc64nvme: self.crc64nvme.map(|x| u64::to_be_bytes(x.sum64())),
 		}
 	}
 }
@@ -192,6 +210,9 @@ impl Checksums {
 			Some(ChecksumAlgorithm::Crc32c) => Some(ChecksumValue::Crc32c(self.crc32c.unwrap())),
 			Some(ChecksumAlgorithm::Sha1) => Some(ChecksumValue::Sha1(self.sha1.unwrap())),
 			Some(ChecksumAlgorithm::Sha256) => Some(ChecksumValue::Sha256(self.sha256.unwrap())),
+			Some(ChecksumAlgorithm::Crc64Nvme) => {
+				Some(ChecksumValue::Crc64Nvme(self.crc64nvme.unwrap()))
+			}
 		}
 	}
 }
@@ -204,10 +225,75 @@ pub fn parse_checksum_algorithm(algo: &str) -> Result<ChecksumAlgorithm, Error>
 		"CRC32C" => Ok(ChecksumAlgorithm::Crc32c),
 		"SHA1" => Ok(ChecksumAlgorithm::Sha1),
 		"SHA256" => Ok(ChecksumAlgorithm::Sha256),
+		"CRC64NVME" => Ok(ChecksumAlgorithm::Crc64Nvme),
 		_ => Err(Error::bad_request("invalid checksum algorithm")),
 	}
 }
 
+pub fn checksum_algorithm_name(algo: ChecksumAlgorithm) -> &'static str {
+	match algo {
+		ChecksumAlgorithm::Crc32 => "CRC32",
+		ChecksumAlgorithm::Crc32c => "CRC32C",
+		ChecksumAlgorithm::Sha1 => "SHA1",
+		ChecksumAlgorithm::Sha256 => "SHA256",
+		ChecksumAlgorithm::Crc64Nvme => "CRC64NVME",
+	}
+}
+
+pub fn checksum_type_name(checksum_type: ChecksumType) -> &'static str {
+	match checksum_type {
+		ChecksumType::Composite => "COMPOSITE",
+		ChecksumType::FullObject => "FULL_OBJECT",
+	}
+}
+
+/// Extract the value of the x-amz-checksum-type header
+pub fn request_checksum_type(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ChecksumType>, Error> {
+	match headers
+		.get(X_AMZ_CHECKSUM_TYPE)
+		.map(|x| x.to_str())
+		.transpose()?
+	{
+		None => Ok(None),
+		Some("COMPOSITE") => Ok(Some(ChecksumType::Composite)),
+		Some("FULL_OBJECT") => Ok(Some(ChecksumType::FullObject)),
+		Some(_) => Err(Error::bad_request("invalid checksum type")),
+	}
+}
+
+/// Determine the type of checksum of a multipart upload, from the requested
+/// algorithm and type. CRC64NVME only supports full object checksums, SHA1
+/// and SHA256 only support composite checksums, CRC32 and CRC32C support both
+/// and default to composite checksums.
+pub fn multipart_checksum_type(
+	algo: Option<ChecksumAlgorithm>,
+	checksum_type: Option<ChecksumType>,
+) -> Result<Option<ChecksumType>, Error> {
+	match (algo, checksum_type) {
+		(None, None) => Ok(None),
+		(None, Some(_)) => Err(Error::bad_request(
+			"x-amz-checksum-type requires x-amz-checksum-algorithm",
+		)),
+		(Some(ChecksumAlgorithm::Crc64Nvme), None | Some(ChecksumType::FullObject)) => {
+			Ok(Some(ChecksumType::FullObject))
+		}
+		(Some(ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c), t) => {
+			Ok(Some(t.unwrap_or(ChecksumType::Composite)))
+		}
+		(
+			Some(ChecksumAlgorithm::Sha1 | ChecksumAlgorithm::Sha256),
+			None | Some(ChecksumType::Composite),
+		) => Ok(Some(ChecksumType::Composite)),
+		(Some(algo), Some(t)) => Err(Error::bad_request(format!(
+			"checksum type {} is not supported for algorithm {}",
+			checksum_type_name(t),
+			checksum_algorithm_name(algo)
+		))),
+	}
+}
+
 /// Extract the value of the x-amz-checksum-algorithm header
 pub fn request_checksum_algorithm(
 	headers: &HeaderMap<HeaderValue>,
@@ -227,6 +313,7 @@ pub fn request_trailer_checksum_algorithm(
 		Some(x) if x == X_AMZ_CHECKSUM_CRC32C => Ok(Some(ChecksumAlgorithm::Crc32c)),
 		Some(x) if x == X_AMZ_CHECKSUM_SHA1 => Ok(Some(ChecksumAlgorithm::Sha1)),
 		Some(x) if x == X_AMZ_CHECKSUM_SHA256 => Ok(Some(ChecksumAlgorithm::Sha256)),
+		Some(x) if x == X_AMZ_CHECKSUM_CRC64NVME => Ok(Some(ChecksumAlgorithm::Crc64Nvme)),
 		_ => Err(Error::bad_request("invalid checksum algorithm")),
 	}
 }
@@ -249,6 +336,12 @@ pub fn request_checksum_value(
 	if headers.contains_key(X_AMZ_CHECKSUM_SHA256) {
 		ret.push(extract_checksum_value(headers, ChecksumAlgorithm::Sha256)?);
 	}
+	if headers.contains_key(X_AMZ_CHECKSUM_CRC64NVME) {
+		ret.push(extract_checksum_value(
+			headers,
+			ChecksumAlgorithm::Crc64Nvme,
+		)?);
+	}
 
 	if ret.len() > 1 {
 		return Err(Error::bad_request(
@@ -297,6 +390,14 @@ pub fn extract_checksum_value(
 				.ok_or_bad_request("invalid x-amz-checksum-sha256 header")?;
 			Ok(ChecksumValue::Sha256(sha256))
 		}
+		ChecksumAlgorithm::Crc64Nvme => {
+			let crc64nvme = headers
+				.get(X_AMZ_CHECKSUM_CRC64NVME)
+				.and_then(|x| BASE64_STANDARD.decode(x).ok())
+				.and_then(|x| x.try_into().ok())
+				.ok_or_bad_request("invalid x-amz-checksum-crc64nvme header")?;
+			Ok(ChecksumValue::Crc64Nvme(crc64nvme))
+		}
 	}
 }
 
@@ -317,7 +418,133 @@ pub fn add_checksum_response_headers(
 		Some(ChecksumValue::Sha256(sha256)) => {
 			resp = resp.header(X_AMZ_CHECKSUM_SHA256, BASE64_STANDARD.encode(&sha256));
 		}
+		Some(ChecksumValue::Crc64Nvme(crc64nvme)) => {
+			resp = resp.header(X_AMZ_CHECKSUM_CRC64NVME, BASE64_STANDARD.encode(crc64nvme));
+		}
 		None => (),
 	}
 	resp
 }
+
+// ---- combination of CRCs ----
+
+// Bit-reflected generator polynomials of the CRC algorithms
+const CRC32_POLY: u64 = 0xedb88320;
+const CRC32C_POLY: u64 = 0x82f63b78;
+const CRC64NVME_POLY: u64 = 0x9a6c9329ac4bc9b5;
+
+/// Compute the CRC of the concatenation of two pieces of data from the CRCs
+/// of both pieces, `len_b` being the length of the second piece.
+/// Returns None if the checksums are not CRCs of the same algorithm.
+pub fn crc_combine(a: ChecksumValue, b: ChecksumValue, len_b: u64) -> Option<ChecksumValue> {
+	let combine32 = |a: [u8; 4], b: [u8; 4], poly: u64| {
+		let crc = gf2_crc_combine(
+			u32::from_be_bytes(a).into(),
+			u32::from_be_bytes(b).into(),
+			len_b,
+			poly,
+			32,
+		);
+		(crc as u32).to_be_bytes()
+	};
+	match (a, b) {
+		(ChecksumValue::Crc32(a), ChecksumValue::Crc32(b)) => {
+			Some(ChecksumValue::Crc32(combine32(a, b, CRC32_POLY)))
+		}
+		(ChecksumValue::Crc32c(a), ChecksumValue::Crc32c(b)) => {
+			Some(ChecksumValue::Crc32c(combine32(a, b, CRC32C_POLY)))
+		}
+		(ChecksumValue::Crc64Nvme(a), ChecksumValue::Crc64Nvme(b)) => {
+			let crc = gf2_crc_combine(
+				u64::from_be_bytes(a),
+				u64::from_be_bytes(b),
+				len_b,
+				CRC64NVME_POLY,
+				64,
+			);
+			Some(ChecksumValue::Crc64Nvme(crc.to_be_bytes()))
+		}
+		_ => None,
+	}
+}
+
+// Polynomials over GF(2) are represented bit-reflected, as in the CRC
+// computation: x^0 is the most significant bit of a `width`-bit value.
+// See crc32_combine in zlib for the reasoning behind this.
+
+/// crc(A || B) = crc(A) * x^(8 * len(B)) + crc(B) mod poly
+fn gf2_crc_combine(a: u64, b: u64, len_b: u64, poly: u64, width: u32) -> u64 {
+	gf2_multmodp(gf2_x8nmodp(len_b, poly, width), a, poly, width) ^ b
+}
+
+/// Compute a * b mod poly
+fn gf2_multmodp(a: u64, mut b: u64, poly: u64, width: u32) -> u64 {
+	let mut m = 1u64 << (width - 1);
+	let mut p = 0;
+	while m != 0 {
+		if a & m != 0 {
+			p ^= b;
+		}
+		m >>= 1;
+		b = if b & 1 != 0 { (b >> 1) ^ poly } else { b >> 1 };
+	}
+	p
+}
+
+/// Compute x^(8 * n) mod poly
+fn gf2_x8nmodp(mut n: u64, poly: u64, width: u32) -> u64 {
+	let mut ret = 1u64 << (width - 1);
+	let mut sq = ret >> 8;
+	while n != 0 {
+		if n & 1 != 0 {
+			ret = gf2_multmodp(ret, sq, poly, width);
+		}
+		sq = gf2_multmodp(sq, sq, poly, width);
+		n >>= 1;
+	}
+	ret
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_crc_combine() {
+		let data = b"The quick brown fox jumps over the lazy dog";
+		for algo in [
+			ChecksumAlgorithm::Crc32,
+			ChecksumAlgorithm::Crc32c,
+			ChecksumAlgorithm::Crc64Nvme,
+		] {
+			let checksum = |bytes: &[u8]| {
+				let mut checksummer = Checksummer::new().add(Some(algo));
+				checksummer.update(bytes);
+				checksummer.finalize().extract(Some(algo)).unwrap()
+			};
+			for split in [0, 1, 10, data.len()] {
+				let (a, b) = data.split_at(split);
+				assert_eq!(
+					crc_combine(checksum(a), checksum(b), b.len() as u64),
+					Some(checksum(data)),
+					"{:?} split at {}",
+					algo,
+					split
+				);
+			}
+		}
+
+		let sha1 = ChecksumValue::Sha1([0u8; 20]);
+		assert_eq!(crc_combine(sha1, sha1, 0), None);
+	}
+
+	#[test]
+	fn test_crc64nvme() {
+		let mut checksummer = Checksummer::new().add(Some(ChecksumAlgorithm::Crc64Nvme));
+		checksummer.update(b"hello world!");
+		assert_eq!(
+			checksummer.finalize().crc64nvme,
+			Some(0xd9160d1fa8e418e3u64.to_be_bytes())
+		);
+	}
+}
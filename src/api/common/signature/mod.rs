@@ -7,6 +7,7 @@ use hyper::{body::Incoming as IncomingBody, Request};
 
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
+use garage_model::session::SessionClaims;
 use garage_util::data::{sha256sum, Hash};
 
 use error::*;
@@ -30,6 +31,7 @@ pub const X_AMZ_SIGNEDHEADERS: HeaderName = HeaderName::from_static("x-amz-signe
 pub const X_AMZ_SIGNATURE: HeaderName = HeaderName::from_static("x-amz-signature");
 pub const X_AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
 pub const X_AMZ_TRAILER: HeaderName = HeaderName::from_static("x-amz-trailer");
+pub const X_AMZ_SECURITY_TOKEN: HeaderName = HeaderName::from_static("x-amz-security-token");
 
 /// Result of `sha256("")`
 pub(crate) const EMPTY_STRING_HEX_DIGEST: &str =
@@ -60,7 +62,10 @@ pub enum ContentSha256Header {
 
 pub struct VerifiedRequest {
 	pub request: Request<streaming::ReqBody>,
-	pub access_key: Key,
+	/// Access key used to sign the request, None for anonymous requests
+	pub access_key: Option<Key>,
+	/// Claims of the temporary credentials used to sign the request, if any
+	pub session: Option<SessionClaims>,
 	pub content_sha256_header: ContentSha256Header,
 }
 
@@ -78,13 +83,10 @@ pub async fn verify_request(
 		service,
 	)?;
 
-	let access_key = checked_signature
-		.key
-		.ok_or_else(|| Error::forbidden("Garage does not support anonymous access yet"))?;
-
 	Ok(VerifiedRequest {
 		request,
-		access_key,
+		access_key: checked_signature.key,
+		session: checked_signature.session,
 		content_sha256_header: checked_signature.content_sha256_header,
 	})
 }
@@ -12,6 +12,7 @@ use garage_util::data::Hash;
 
 use garage_model::garage::Garage;
 use garage_model::key_table::*;
+use garage_model::session::SessionClaims;
 
 use super::*;
 
@@ -28,6 +29,9 @@ pub struct QueryValue {
 #[derive(Debug)]
 pub struct CheckedSignature {
 	pub key: Option<Key>,
+	/// Secret key that was used to sign the request
+	pub secret_key: Option<String>,
+	pub session: Option<SessionClaims>,
 	pub content_sha256_header: ContentSha256Header,
 	pub signature_header: Option<String>,
 }
@@ -57,6 +61,8 @@ pub async fn check_payload_signature(
 			.transpose()?;
 		Ok(CheckedSignature {
 			key: None,
+			secret_key: None,
+			session: None,
 			content_sha256_header: parse_x_amz_content_sha256(content_sha256)?,
 			signature_header: None,
 		})
@@ -98,7 +104,7 @@ fn parse_x_amz_content_sha256(header: Option<&str>) -> Result<ContentSha256Heade
 async fn check_garage_authorization(
 	garage: &Garage,
 	request: &Request<IncomingBody>,
-) -> Result<(Option<Key>, Option<Hash>), Error> {
+) -> Result<CheckedSignature, Error> {
 	let headers = request.headers();
 
 	let secret_key = headers
@@ -111,20 +117,27 @@ async fn check_garage_authorization(
 		.ok_or_bad_request("Missing X-GARAGE-ACCESS-KEY-ID header")?
 		.to_str()?;
 
-	let key = garage
-		.key_table
-		.get(&EmptyKey, &key_id.to_string())
-		.await?
-		.filter(|k| !k.state.is_deleted())
-		.ok_or_else(|| Error::forbidden(format!("No such key: {}", &key_id.to_string())))?;
+	let key = get_existing_key(garage, key_id).await?;
 
 	let key_p = key.params().unwrap();
 
-	if key_p.secret_key != secret_key {
-		return Ok((None, None));
+	if !key_p.valid_secret_keys().contains(&secret_key) {
+		return Err(Error::forbidden("Invalid secret key"));
 	}
+	let secret_key = secret_key.to_string();
+
+	let content_sha256 = headers
+		.get(X_AMZ_CONTENT_SHA256)
+		.map(|x| x.to_str())
+		.transpose()?;
 
-	Ok((Some(key), None))
+	Ok(CheckedSignature {
+		key: Some(key),
+		secret_key: Some(secret_key),
+		session: None,
+		content_sha256_header: parse_x_amz_content_sha256(content_sha256)?,
+		signature_header: None,
+	})
 }
 
 async fn check_standard_signature(
@@ -162,12 +175,15 @@ async fn check_standard_signature(
 	trace!("canonical request:\n{}", canonical_request);
 	trace!("string to sign:\n{}", string_to_sign);
 
-	let key = verify_v4(garage, service, &authorization, string_to_sign.as_bytes()).await?;
+	let (key, secret_key, session) =
+		verify_v4(garage, service, &authorization, string_to_sign.as_bytes()).await?;
 
 	let content_sha256_header = parse_x_amz_content_sha256(Some(&authorization.content_sha256))?;
 
 	Ok(CheckedSignature {
 		key: Some(key),
+		secret_key: Some(secret_key),
+		session,
 		content_sha256_header,
 		signature_header: Some(authorization.signature),
 	})
@@ -212,7 +228,8 @@ async fn check_presigned_signature(
 	trace!("canonical request (presigned url):\n{}", canonical_request);
 	trace!("string to sign (presigned url):\n{}", string_to_sign);
 
-	let key = verify_v4(garage, service, &authorization, string_to_sign.as_bytes()).await?;
+	let (key, secret_key, session) =
+		verify_v4(garage, service, &authorization, string_to_sign.as_bytes()).await?;
 
 	// In the page on presigned URLs, AWS specifies that if a signed query
 	// parameter and a signed header of the same name have different values,
@@ -246,6 +263,8 @@ async fn check_presigned_signature(
 	// so there is no sha256 hash to return.
 	Ok(CheckedSignature {
 		key: Some(key),
+		secret_key: Some(secret_key),
+		session,
 		content_sha256_header: ContentSha256Header::UnsignedPayload,
 		signature_header: Some(authorization.signature),
 	})
@@ -412,37 +431,90 @@ pub fn parse_date(date: &str) -> Result<DateTime<Utc>, Error> {
 	Ok(Utc.from_utc_datetime(&date))
 }
 
+/// Check the signature of a request, returning the key that signed it
+/// and the secret key that was used, as a key can have several valid secret keys.
+/// For temporary credentials, the session claims are returned as well,
+/// and the key is the parent key restricted to the permissions of the session.
 pub async fn verify_v4(
 	garage: &Garage,
 	service: &str,
 	auth: &Authorization,
 	payload: &[u8],
-) -> Result<Key, Error> {
+) -> Result<(Key, String, Option<SessionClaims>), Error> {
 	let scope_expected = compute_scope(&auth.date, &garage.config.s3_api.s3_region, service);
 	if auth.scope != scope_expected {
 		return Err(Error::AuthorizationHeaderMalformed(auth.scope.to_string()));
 	}
 
+	let (key, secret_keys, session) = match &auth.security_token {
+		None => {
+			let key = get_existing_key(garage, &auth.key_id).await?;
+			let secret_keys = key
+				.params()
+				.unwrap()
+				.valid_secret_keys()
+				.into_iter()
+				.map(String::from)
+				.collect::<Vec<_>>();
+			(key, secret_keys, None)
+		}
+		Some(token) => {
+			let (session, secret_key) = SessionClaims::decode(token, &garage.session_token_key)
+				.ok_or_else(|| Error::forbidden("Invalid session token"))?;
+			if session.access_key_id != auth.key_id {
+				return Err(Error::forbidden(
+					"The session token does not match the access key ID",
+				));
+			}
+			if session.is_expired() {
+				return Err(Error::forbidden("The session token has expired"));
+			}
+			let parent = get_existing_key(garage, &session.parent_key_id).await?;
+			(
+				session.restrict_key(&parent),
+				vec![secret_key],
+				Some(session),
+			)
+		}
+	};
+
+	let signature =
+		hex::decode(&auth.signature).map_err(|_| Error::forbidden("Invalid signature"))?;
+
+	// During a rotation, a key has several valid secret keys
+	// and the request can be signed with any of them
+	for secret_key in secret_keys {
+		let mut hmac = signing_hmac(
+			&auth.date,
+			&secret_key,
+			&garage.config.s3_api.s3_region,
+			service,
+		)
+		.ok_or_internal_error("Unable to build signing HMAC")?;
+		hmac.update(payload);
+		if hmac.verify_slice(&signature).is_ok() {
+			return Ok((key, secret_key, session));
+		}
+	}
+
+	Err(Error::forbidden("Invalid signature"))
+}
+
+/// Get a key that can be used to sign requests: it must exist
+/// and must be neither inactive nor expired
+async fn get_existing_key(garage: &Garage, key_id: &str) -> Result<Key, Error> {
 	let key = garage
 		.key_table
-		.get(&EmptyKey, &auth.key_id)
+		.get(&EmptyKey, &key_id.to_string())
 		.await?
 		.filter(|k| !k.state.is_deleted())
-		.ok_or_else(|| Error::forbidden(format!("No such key: {}", &auth.key_id)))?;
-	let key_p = key.params().unwrap();
+		.ok_or_else(|| Error::forbidden(format!("No such key: {}", key_id)))?;
 
-	let mut hmac = signing_hmac(
-		&auth.date,
-		&key_p.secret_key,
-		&garage.config.s3_api.s3_region,
-		service,
-	)
-	.ok_or_internal_error("Unable to build signing HMAC")?;
-	hmac.update(payload);
-	let signature =
-		hex::decode(&auth.signature).map_err(|_| Error::forbidden("Invalid signature"))?;
-	if hmac.verify_slice(&signature).is_err() {
-		return Err(Error::forbidden("Invalid signature"));
+	if !key.is_active() {
+		return Err(Error::forbidden(format!("Key {} is inactive", key_id)));
+	}
+	if key.is_expired() {
+		return Err(Error::forbidden(format!("Key {} has expired", key_id)));
 	}
 
 	Ok(key)
@@ -457,6 +529,7 @@ pub struct Authorization {
 	signature: String,
 	content_sha256: String,
 	date: DateTime<Utc>,
+	security_token: Option<String>,
 }
 
 impl Authorization {
@@ -511,6 +584,11 @@ impl Authorization {
 			return Err(Error::bad_request("Date is too old".to_string()));
 		}
 
+		let security_token = headers
+			.get(X_AMZ_SECURITY_TOKEN)
+			.map(|x| x.to_str().map(String::from))
+			.transpose()?;
+
 		let (key_id, scope) = parse_credential(cred)?;
 		let auth = Authorization {
 			key_id,
@@ -519,6 +597,7 @@ impl Authorization {
 			signature,
 			content_sha256: content_sha256.to_str()?.to_string(),
 			date,
+			security_token,
 		};
 		Ok(auth)
 	}
@@ -570,6 +649,7 @@ impl Authorization {
 			signature: signature.value.clone(),
 			content_sha256: UNSIGNED_PAYLOAD.to_string(),
 			date,
+			security_token: query.get(&X_AMZ_SECURITY_TOKEN).map(|x| x.value.clone()),
 		})
 	}
 
@@ -603,6 +683,11 @@ impl Authorization {
 			return Err(Error::bad_request("Date is too old".to_string()));
 		}
 
+		let security_token = params
+			.get(X_AMZ_SECURITY_TOKEN)
+			.map(|x| x.to_str().map(String::from))
+			.transpose()?;
+
 		let (key_id, scope) = parse_credential(credential)?;
 		let auth = Authorization {
 			key_id,
@@ -611,6 +696,7 @@ impl Authorization {
 			signature,
 			content_sha256: UNSIGNED_PAYLOAD.to_string(),
 			date,
+			security_token,
 		};
 		Ok(auth)
 	}
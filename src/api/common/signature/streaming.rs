@@ -78,14 +78,9 @@ pub fn parse_streaming_body(
 					.ok_or_bad_request("Invalid signature")?;
 
 				let secret_key = checked_signature
-					.key
-					.as_ref()
-					.ok_or_bad_request("Cannot sign streaming payload without signing key")?
-					.state
-					.as_option()
-					.ok_or_internal_error("Deleted key state")?
 					.secret_key
-					.to_string();
+					.clone()
+					.ok_or_bad_request("Cannot sign streaming payload without signing key")?;
 
 				let date = req
 					.headers()
@@ -83,7 +83,15 @@ impl ApiHandler for K2VApiServer {
 
 		let verified_request = verify_request(&garage, req, "k2v").await?;
 		let req = verified_request.request;
-		let api_key = verified_request.access_key;
+		let api_key = verified_request
+			.access_key
+			.ok_or_else(|| Error::forbidden("Garage does not support anonymous access yet"))?;
+		let session = verified_request.session;
+		if session.as_ref().is_some_and(|s| s.prefix.is_some()) {
+			return Err(Error::forbidden(
+				"Temporary credentials restricted to a prefix cannot be used with the K2V API",
+			));
+		}
 
 		let bucket_id = garage
 			.bucket_helper()
@@ -126,7 +134,8 @@ impl ApiHandler for K2VApiServer {
 			bucket_id,
 			bucket_name,
 			bucket_params,
-			api_key,
+			api_key: Some(api_key),
+			session,
 		};
 
 		let resp = match endpoint {
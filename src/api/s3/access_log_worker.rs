@@ -0,0 +1,192 @@
+//! Worker that writes the access log records buffered by this node
+//! as objects in the target buckets
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use hyper::HeaderMap;
+use tokio::select;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+use garage_util::time::now_msec;
+
+use garage_model::garage::Garage;
+use garage_model::s3::access_log::AccessLogBatch;
+use garage_model::s3::object_table::ObjectVersionMetaInner;
+
+use garage_api_common::helpers::ReqCtx;
+
+use crate::encryption::EncryptionParams;
+use crate::error::Error;
+use crate::object_lock::parse_object_lock_headers;
+use crate::put::{save_stream, ChecksumMode, WritePreconditions};
+
+/// Interval at which buffered records are written to the target buckets
+const FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+
+pub struct AccessLogWorker {
+	garage: Arc<Garage>,
+	next_flush: Instant,
+	objects_written: usize,
+	records_dropped: usize,
+}
+
+impl AccessLogWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			next_flush: Instant::now() + FLUSH_INTERVAL,
+			objects_written: 0,
+			records_dropped: 0,
+		}
+	}
+
+	/// Write a batch of records as a new object in the target bucket.
+	/// Returns Ok(false) if the target bucket no longer exists.
+	async fn write_batch(&self, batch: &AccessLogBatch) -> Result<bool, Error> {
+		let garage = &self.garage;
+		let bucket_params = match garage
+			.bucket_helper()
+			.get_existing_bucket(batch.target_bucket)
+			.await
+		{
+			Ok(bucket) => bucket.state.into_option().unwrap(),
+			Err(_) => return Ok(false),
+		};
+
+		// Log objects are named as in the simple prefix format of AWS:
+		// [prefix][YYYY]-[MM]-[DD]-[hh]-[mm]-[ss]-[unique string]
+		let key = format!(
+			"{}{}-{}",
+			batch.target_prefix,
+			Utc::now().format("%Y-%m-%d-%H-%M-%S"),
+			hex::encode_upper(&gen_uuid().as_slice()[..8])
+		);
+		let mut content = batch.lines.join("\n");
+		content.push('\n');
+
+		let no_headers = HeaderMap::new();
+		let encryption = EncryptionParams::new_from_headers(
+			garage,
+			&no_headers,
+			bucket_params.default_encryption.get().as_ref(),
+		)
+		.await?;
+		let lock = parse_object_lock_headers(&no_headers, &bucket_params, now_msec())?;
+		let meta = ObjectVersionMetaInner {
+			headers: vec![(
+				hyper::header::CONTENT_TYPE.to_string(),
+				"text/plain".to_string(),
+			)],
+			checksum: None,
+		};
+
+		let ctx = ReqCtx {
+			garage: garage.clone(),
+			bucket_id: batch.target_bucket,
+			bucket_name: hex::encode(batch.target_bucket),
+			bucket_params,
+			api_key: None,
+			session: None,
+		};
+		save_stream(
+			&ctx,
+			meta,
+			encryption,
+			futures::stream::iter([Ok(Bytes::from(content))]),
+			&key,
+			ChecksumMode::Calculate(None),
+			Default::default(),
+			lock,
+			None,
+			false,
+			None,
+			&WritePreconditions::default(),
+		)
+		.await?;
+		Ok(true)
+	}
+}
+
+#[async_trait]
+impl Worker for AccessLogWorker {
+	fn name(&self) -> String {
+		"Bucket access log writer".to_string()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: Some(self.garage.access_log.buffered_len() as u64),
+			freeform: vec![
+				format!("Log objects written: {}", self.objects_written),
+				format!("Records dropped: {}", self.records_dropped),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(
+		&mut self,
+		must_exit: &mut watch::Receiver<bool>,
+	) -> Result<WorkerState, GarageError> {
+		self.next_flush = Instant::now() + FLUSH_INTERVAL;
+
+		let mut error = None;
+		let batches = self.garage.access_log.take();
+		let dropped = self.garage.access_log.take_dropped();
+		if dropped > 0 {
+			warn!("Access log buffer is full, dropped {} records", dropped);
+			self.records_dropped += dropped;
+		}
+		for batch in batches {
+			if error.is_some() {
+				self.garage.access_log.put_back(batch);
+				continue;
+			}
+			match self.write_batch(&batch).await {
+				Ok(true) => self.objects_written += 1,
+				Ok(false) => {
+					warn!(
+						"Target bucket {:?} of access logs no longer exists, dropping {} records",
+						batch.target_bucket,
+						batch.lines.len()
+					);
+					self.records_dropped += batch.lines.len();
+				}
+				Err(e) => {
+					// Records that could not be written are put back in the
+					// buffer, they will be written with the next flush
+					self.garage.access_log.put_back(batch);
+					error = Some(e);
+				}
+			}
+		}
+		if let Some(e) = error {
+			return Err(GarageError::Message(format!(
+				"Could not write access logs: {}",
+				e
+			)));
+		}
+
+		if *must_exit.borrow() {
+			Ok(WorkerState::Done)
+		} else {
+			Ok(WorkerState::Idle)
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		select! {
+			_ = tokio::time::sleep_until(self.next_flush) => (),
+			_ = self.garage.access_log.wait() => (),
+		}
+		WorkerState::Busy
+	}
+}
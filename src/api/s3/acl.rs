@@ -0,0 +1,997 @@
+//! Compatibility layer for S3 access control lists.
+//!
+//! Garage has no ACLs of its own: access to a bucket is given to access keys
+//! by their bucket permissions, and anonymous users can be allowed to read a
+//! bucket with its public-read flag or individual objects with their own
+//! public-read flag. ACLs given in requests are mapped onto these, and the
+//! ACLs returned by GetBucketAcl and GetObjectAcl are built from them.
+
+use std::collections::BTreeMap;
+
+use quick_xml::de::from_reader;
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use garage_util::config::PublicAccessBlockConfig;
+
+use garage_table::util::*;
+
+use garage_model::bucket_table::{Bucket, BucketParams, ObjectOwnership};
+use garage_model::permission::BucketKeyPerm;
+use garage_model::s3::object_table::*;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::bucket_policy::ignore_public_acls;
+use crate::error::*;
+use crate::router::Endpoint;
+use crate::versioning::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+pub const X_AMZ_ACL: HeaderName = HeaderName::from_static("x-amz-acl");
+pub const X_AMZ_OBJECT_OWNERSHIP: HeaderName = HeaderName::from_static("x-amz-object-ownership");
+
+const GRANT_HEADERS: &[(&str, AclPermission)] = &[
+	("x-amz-grant-read", AclPermission::Read),
+	("x-amz-grant-write", AclPermission::Write),
+	("x-amz-grant-read-acp", AclPermission::ReadAcp),
+	("x-amz-grant-write-acp", AclPermission::WriteAcp),
+	("x-amz-grant-full-control", AclPermission::FullControl),
+];
+
+const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+pub async fn handle_get_bucket_acl(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let public_read = *ctx.bucket_params.public_read.get();
+	let acl = synthesize_acl(&ctx, public_read).await?;
+	let xml = to_xml_with_header(&acl)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_acl(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let acl = match parse_acl_headers(req.headers())? {
+		Some(acl) => {
+			ensure_empty_body(req).await?;
+			acl
+		}
+		None => {
+			let body = req.into_body().collect().await?;
+			let policy: AccessControlPolicy = from_reader(&body as &[u8])?;
+			RequestAcl::Grants(policy.into_grants()?)
+		}
+	};
+
+	if ctx.bucket_params.acls_disabled() {
+		// The only ACL accepted on such buckets is the one that gives
+		// full control to the bucket owner, which is a no-op in Garage
+		return match acl {
+			RequestAcl::Canned(CannedAcl::Private | CannedAcl::BucketOwnerFullControl) => {
+				Ok(Response::builder()
+					.status(StatusCode::OK)
+					.body(empty_body())?)
+			}
+			_ => Err(Error::AccessControlListNotSupported),
+		};
+	}
+
+	let public_read = bucket_acl_public_read(acl, &bucket_key_permissions(&ctx.bucket_params))?;
+	ensure_public_acl_allowed(
+		ctx.bucket_params
+			.effective_public_access_block(&ctx.garage.config.public_access_block)
+			.block_public_acls,
+		public_read,
+	)?;
+
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+	bucket_params.public_read.update(public_read);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+pub async fn handle_get_object_acl(
+	ctx: ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let object = ctx
+		.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = find_data_version(&object, version_id)?;
+
+	let public_read = *ctx.bucket_params.public_read.get()
+		|| (*version.public_read.get() && !ctx.bucket_params.acls_disabled());
+	let acl = synthesize_acl(&ctx, public_read).await?;
+	let xml = to_xml_with_header(&acl)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.header(X_AMZ_VERSION_ID, self::version_id(version))
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_acl(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let acl = match parse_acl_headers(req.headers())? {
+		Some(acl) => {
+			ensure_empty_body(req).await?;
+			acl
+		}
+		None => {
+			let body = req.into_body().collect().await?;
+			let policy: AccessControlPolicy = from_reader(&body as &[u8])?;
+			RequestAcl::Grants(policy.into_grants()?)
+		}
+	};
+	let public_read = object_acl_public_read(acl, &ctx.bucket_params)?;
+	ensure_public_acl_allowed(
+		ctx.bucket_params
+			.effective_public_access_block(&ctx.garage.config.public_access_block)
+			.block_public_acls,
+		public_read,
+	)?;
+
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = &ctx;
+	let object = garage
+		.object_table
+		.get(bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = find_data_version(&object, version_id)?;
+
+	let mut new_version = version.clone();
+	new_version.public_read.update(public_read);
+	let object = Object::new(*bucket_id, key.to_string(), vec![new_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(X_AMZ_VERSION_ID, self::version_id(version))
+		.body(empty_body())?)
+}
+
+pub async fn handle_get_bucket_ownership_controls(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ownership = ctx
+		.bucket_params
+		.object_ownership
+		.get()
+		.ok_or(Error::OwnershipControlsNotFound)?;
+
+	let controls = OwnershipControls {
+		xmlns: (),
+		rule: OwnershipControlsRule {
+			object_ownership: Value(ownership_name(ownership).to_string()),
+		},
+	};
+	let xml = to_xml_with_header(&controls)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_ownership_controls(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let body = req.into_body().collect().await?;
+	let controls: OwnershipControls = from_reader(&body as &[u8])?;
+	let ownership = parse_ownership(&controls.rule.object_ownership.0)?;
+
+	update_object_ownership(ctx, Some(ownership)).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_bucket_ownership_controls(
+	ctx: ReqCtx,
+) -> Result<Response<ResBody>, Error> {
+	update_object_ownership(ctx, None).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+async fn update_object_ownership(
+	ctx: ReqCtx,
+	ownership: Option<ObjectOwnership>,
+) -> Result<(), Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	bucket_params.object_ownership.update(ownership);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+	Ok(())
+}
+
+// ---- access control ----
+
+/// Returns true if the request is a GetObject or HeadObject request for an
+/// object version that was made public with its ACL, and the requester is not
+/// already allowed to read the bucket
+pub(crate) async fn is_public_object(ctx: &ReqCtx, endpoint: &Endpoint) -> Result<bool, Error> {
+	let (key, version_id) = match endpoint {
+		Endpoint::GetObject {
+			key, version_id, ..
+		}
+		| Endpoint::HeadObject {
+			key, version_id, ..
+		} => (key, version_id),
+		_ => return Ok(false),
+	};
+	if ctx.bucket_params.acls_disabled()
+		|| *ctx.bucket_params.public_read.get()
+		|| ignore_public_acls(ctx)
+		|| ctx
+			.api_key
+			.as_ref()
+			.is_some_and(|k| k.allow_read(&ctx.bucket_id))
+	{
+		return Ok(false);
+	}
+
+	let object = match ctx.garage.object_table.get(&ctx.bucket_id, key).await? {
+		Some(object) => object,
+		None => return Ok(false),
+	};
+	// If the version cannot be found, the request is processed as for
+	// any other object, and fails with an access denied error
+	Ok(find_data_version(&object, version_id.as_deref())
+		.map(|v| *v.public_read.get())
+		.unwrap_or(false))
+}
+
+// ---- ACLs in requests ----
+
+/// Returns whether a new object must be public, according to the ACL
+/// given in the headers of the request that creates it
+pub(crate) fn request_object_public_read(
+	ctx: &ReqCtx,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<bool, Error> {
+	let public_read = match parse_acl_headers(headers)? {
+		None => false,
+		Some(acl) => object_acl_public_read(acl, &ctx.bucket_params)?,
+	};
+	ensure_public_acl_allowed(
+		ctx.bucket_params
+			.effective_public_access_block(&ctx.garage.config.public_access_block)
+			.block_public_acls,
+		public_read,
+	)?;
+	Ok(public_read)
+}
+
+/// Reject ACLs that make a bucket or an object public when this is blocked
+/// by the public access block settings
+fn ensure_public_acl_allowed(block_public_acls: bool, public_read: bool) -> Result<(), Error> {
+	if public_read && block_public_acls {
+		return Err(Error::forbidden(
+			"Public ACLs are blocked by the public access block settings of the bucket",
+		));
+	}
+	Ok(())
+}
+
+/// ACL and object ownership given in a CreateBucket request
+pub(crate) struct CreateBucketAcl {
+	ownership: Option<ObjectOwnership>,
+	public_read: Option<bool>,
+}
+
+impl CreateBucketAcl {
+	/// Parse the ACL headers of a CreateBucket request, `creator` being
+	/// the key that creates the bucket and gets all permissions on it
+	pub(crate) fn parse(
+		headers: &HeaderMap<HeaderValue>,
+		creator: &str,
+		cluster_block: &PublicAccessBlockConfig,
+	) -> Result<Self, Error> {
+		let ownership = match headers.get(X_AMZ_OBJECT_OWNERSHIP) {
+			Some(v) => Some(parse_ownership(v.to_str()?)?),
+			None => None,
+		};
+		let creator_keys = BTreeMap::from([(creator.to_string(), BucketKeyPerm::ALL_PERMISSIONS)]);
+		let public_read =
+			match parse_acl_headers(headers)? {
+				None
+				| Some(RequestAcl::Canned(
+					CannedAcl::Private | CannedAcl::BucketOwnerFullControl,
+				)) if ownership == Some(ObjectOwnership::BucketOwnerEnforced) => None,
+				Some(_) if ownership == Some(ObjectOwnership::BucketOwnerEnforced) => {
+					return Err(Error::AccessControlListNotSupported)
+				}
+				acl => acl
+					.map(|acl| bucket_acl_public_read(acl, &creator_keys))
+					.transpose()?,
+			};
+		// A new bucket has no public access block settings of its own,
+		// only those of the cluster apply
+		ensure_public_acl_allowed(cluster_block.block_public_acls, public_read == Some(true))?;
+		Ok(Self {
+			ownership,
+			public_read,
+		})
+	}
+
+	/// Set the object ownership and the public-read flag of the new bucket
+	pub(crate) fn apply_to_params(&self, params: &mut BucketParams) {
+		params.object_ownership.update(self.ownership);
+		if let Some(public_read) = self.public_read {
+			params.public_read.update(public_read);
+		}
+	}
+}
+
+/// ACL given in a request, either as a canned ACL or as a list of grants
+#[derive(Debug, PartialEq, Eq)]
+enum RequestAcl {
+	Canned(CannedAcl),
+	Grants(Vec<AclGrant>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CannedAcl {
+	Private,
+	PublicRead,
+	BucketOwnerRead,
+	BucketOwnerFullControl,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AclGrant {
+	grantee: AclGrantee,
+	permission: AclPermission,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AclGrantee {
+	/// An access key, designated by its ID
+	Key(String),
+	/// Everyone, including anonymous users
+	AllUsers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AclPermission {
+	Read,
+	Write,
+	ReadAcp,
+	WriteAcp,
+	FullControl,
+}
+
+/// Parse the canned ACL of the x-amz-acl header or the grants of the
+/// x-amz-grant-* headers, None if the request has none of these headers
+fn parse_acl_headers(headers: &HeaderMap<HeaderValue>) -> Result<Option<RequestAcl>, Error> {
+	let mut grants = vec![];
+	for (header, permission) in GRANT_HEADERS.iter() {
+		if let Some(v) = headers.get(*header) {
+			for grantee in v.to_str()?.split(',') {
+				grants.push(AclGrant {
+					grantee: parse_grantee_header(grantee)?,
+					permission: *permission,
+				});
+			}
+		}
+	}
+
+	match headers.get(X_AMZ_ACL) {
+		Some(_) if !grants.is_empty() => Err(Error::bad_request(
+			"Specifying both Canned ACLs and Header Grants is not allowed",
+		)),
+		Some(v) => Ok(Some(RequestAcl::Canned(parse_canned_acl(v.to_str()?)?))),
+		None if grants.is_empty() => Ok(None),
+		None => Ok(Some(RequestAcl::Grants(grants))),
+	}
+}
+
+fn parse_canned_acl(acl: &str) -> Result<CannedAcl, Error> {
+	match acl {
+		"private" => Ok(CannedAcl::Private),
+		"public-read" => Ok(CannedAcl::PublicRead),
+		"bucket-owner-read" => Ok(CannedAcl::BucketOwnerRead),
+		"bucket-owner-full-control" => Ok(CannedAcl::BucketOwnerFullControl),
+		"public-read-write" | "authenticated-read" | "aws-exec-read" | "log-delivery-write" => Err(
+			Error::NotImplemented(format!("Garage does not support the {} canned ACL", acl)),
+		),
+		_ => Err(Error::bad_request(format!("Invalid canned ACL: {}", acl))),
+	}
+}
+
+/// Parse a grantee of a x-amz-grant-* header, e.g. `id="GK31c2f218a2e44f485b94239e"`
+fn parse_grantee_header(grantee: &str) -> Result<AclGrantee, Error> {
+	let (kind, value) = grantee
+		.trim()
+		.split_once('=')
+		.ok_or_bad_request("Invalid grantee in ACL header")?;
+	let value = value.trim().trim_matches('"');
+	match kind.trim() {
+		"id" => Ok(AclGrantee::Key(value.to_string())),
+		"uri" => parse_group_uri(value),
+		_ => Err(Error::NotImplemented(format!(
+			"Garage does not support ACL grantees of type {}",
+			kind.trim()
+		))),
+	}
+}
+
+fn parse_group_uri(uri: &str) -> Result<AclGrantee, Error> {
+	if uri == ALL_USERS_URI {
+		Ok(AclGrantee::AllUsers)
+	} else {
+		Err(Error::NotImplemented(format!(
+			"Garage does not support ACL grants to group {}",
+			uri
+		)))
+	}
+}
+
+fn parse_ownership(ownership: &str) -> Result<ObjectOwnership, Error> {
+	match ownership {
+		"BucketOwnerEnforced" => Ok(ObjectOwnership::BucketOwnerEnforced),
+		"BucketOwnerPreferred" => Ok(ObjectOwnership::BucketOwnerPreferred),
+		"ObjectWriter" => Ok(ObjectOwnership::ObjectWriter),
+		_ => Err(Error::bad_request(format!(
+			"Invalid object ownership: {}",
+			ownership
+		))),
+	}
+}
+
+fn ownership_name(ownership: ObjectOwnership) -> &'static str {
+	match ownership {
+		ObjectOwnership::BucketOwnerEnforced => "BucketOwnerEnforced",
+		ObjectOwnership::BucketOwnerPreferred => "BucketOwnerPreferred",
+		ObjectOwnership::ObjectWriter => "ObjectWriter",
+	}
+}
+
+/// Returns whether an object must be public according to its ACL.
+/// Access of individual keys to objects cannot be set in Garage, so grants
+/// to keys are accepted only if they already have access to the bucket:
+/// this allows the ACL returned by GetObjectAcl to be given back as is.
+fn object_acl_public_read(acl: RequestAcl, bucket_params: &BucketParams) -> Result<bool, Error> {
+	if bucket_params.acls_disabled() {
+		return match acl {
+			RequestAcl::Canned(CannedAcl::BucketOwnerFullControl) => Ok(false),
+			_ => Err(Error::AccessControlListNotSupported),
+		};
+	}
+
+	match acl {
+		RequestAcl::Canned(CannedAcl::PublicRead) => Ok(true),
+		RequestAcl::Canned(_) => Ok(false),
+		RequestAcl::Grants(grants) => {
+			let mut public_read = false;
+			for grant in grants {
+				match (grant.grantee, grant.permission) {
+					(AclGrantee::AllUsers, AclPermission::Read) => public_read = true,
+					(AclGrantee::AllUsers, _) => {
+						return Err(Error::NotImplemented(
+							"Garage only supports READ grants to all users on objects".into(),
+						))
+					}
+					(AclGrantee::Key(key_id), _) => {
+						if !bucket_params
+							.authorized_keys
+							.get(&key_id)
+							.is_some_and(|p| p.is_any())
+						{
+							return Err(Error::NotImplemented(format!(
+								"Key {} has no access to the bucket: Garage cannot give access to individual objects",
+								key_id
+							)));
+						}
+					}
+				}
+			}
+			Ok(public_read)
+		}
+	}
+}
+
+/// Returns whether a bucket must be public according to its ACL.
+/// Permissions of access keys on buckets are managed by the administrator
+/// and cannot be changed with ACLs, so grants to keys are accepted only if
+/// they match exactly the current permissions `current_keys` of the keys on
+/// the bucket: this allows the ACL returned by GetBucketAcl to be given back
+/// as is.
+fn bucket_acl_public_read(
+	acl: RequestAcl,
+	current_keys: &BTreeMap<String, BucketKeyPerm>,
+) -> Result<bool, Error> {
+	let grants = match acl {
+		RequestAcl::Canned(canned) => return Ok(canned == CannedAcl::PublicRead),
+		RequestAcl::Grants(grants) => grants,
+	};
+
+	let mut public_read = false;
+	let mut keys = BTreeMap::<String, BucketKeyPerm>::new();
+	for grant in grants {
+		match grant.grantee {
+			AclGrantee::AllUsers => match grant.permission {
+				AclPermission::Read => public_read = true,
+				_ => {
+					return Err(Error::NotImplemented(
+						"Garage only supports READ grants to all users on buckets".into(),
+					))
+				}
+			},
+			AclGrantee::Key(key_id) => {
+				let perm = keys.entry(key_id).or_insert(BucketKeyPerm::NO_PERMISSIONS);
+				match grant.permission {
+					AclPermission::Read => perm.allow_read = true,
+					AclPermission::Write => perm.allow_write = true,
+					AclPermission::WriteAcp => perm.allow_owner = true,
+					AclPermission::FullControl => {
+						perm.allow_read = true;
+						perm.allow_write = true;
+						perm.allow_owner = true;
+					}
+					// Reading the ACL requires owner permission
+					AclPermission::ReadAcp => (),
+				}
+			}
+		}
+	}
+
+	let flags = |keys: &BTreeMap<String, BucketKeyPerm>| {
+		keys.iter()
+			.filter(|(_, p)| p.is_any())
+			.map(|(k, p)| (k.clone(), (p.allow_read, p.allow_write, p.allow_owner)))
+			.collect::<BTreeMap<_, _>>()
+	};
+	if flags(&keys) != flags(current_keys) {
+		return Err(Error::NotImplemented(
+			"Garage cannot change the permissions of access keys on a bucket with an ACL".into(),
+		));
+	}
+
+	Ok(public_read)
+}
+
+/// Permissions of the access keys that have access to a bucket
+fn bucket_key_permissions(bucket_params: &BucketParams) -> BTreeMap<String, BucketKeyPerm> {
+	bucket_params
+		.authorized_keys
+		.items()
+		.iter()
+		.filter(|(_, perm)| perm.is_any())
+		.map(|(key_id, perm)| (key_id.clone(), *perm))
+		.collect()
+}
+
+// ---- ACLs in responses ----
+
+/// Build the ACL of a bucket, or of one of its objects, from the permissions
+/// of the access keys on the bucket. The owner of the ACL is the first key
+/// with owner permission on the bucket.
+async fn synthesize_acl(ctx: &ReqCtx, public_read: bool) -> Result<AccessControlPolicy, Error> {
+	let mut grants = vec![];
+	let mut owner = None;
+	for (key_id, perm) in ctx.bucket_params.authorized_keys.items().iter() {
+		if !perm.is_any() {
+			continue;
+		}
+		let display_name = ctx
+			.garage
+			.key_table
+			.get(&EmptyKey, key_id)
+			.await?
+			.and_then(|k| k.params().map(|p| p.name.get().clone()));
+		let permissions = if perm.allow_read && perm.allow_write && perm.allow_owner {
+			vec!["FULL_CONTROL"]
+		} else {
+			[
+				(perm.allow_read, "READ"),
+				(perm.allow_write, "WRITE"),
+				(perm.allow_owner, "WRITE_ACP"),
+			]
+			.iter()
+			.filter(|(allowed, _)| *allowed)
+			.map(|(_, name)| *name)
+			.collect()
+		};
+		if perm.allow_owner && owner.is_none() {
+			owner = Some(AclOwner {
+				id: Value(key_id.clone()),
+				display_name: display_name.clone().map(Value),
+			});
+		}
+		for permission in permissions {
+			grants.push(Grant {
+				grantee: Grantee::key(key_id, display_name.clone()),
+				permission: Value(permission.to_string()),
+			});
+		}
+	}
+	if public_read {
+		grants.push(Grant {
+			grantee: Grantee::all_users(),
+			permission: Value("READ".to_string()),
+		});
+	}
+
+	Ok(AccessControlPolicy {
+		xmlns: (),
+		owner,
+		access_control_list: AccessControlList { grants },
+	})
+}
+
+/// Ensure that the body of an ACL request is empty when the ACL is given in headers
+async fn ensure_empty_body(req: Request<ReqBody>) -> Result<(), Error> {
+	let body = req.into_body().collect().await?;
+	if body.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::bad_request(
+			"ACLs cannot be given both in headers and in the request body",
+		))
+	}
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccessControlPolicy {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Owner")]
+	pub owner: Option<AclOwner>,
+	#[serde(rename = "AccessControlList")]
+	pub access_control_list: AccessControlList,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AclOwner {
+	#[serde(rename = "ID")]
+	pub id: Value,
+	#[serde(rename = "DisplayName")]
+	pub display_name: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccessControlList {
+	#[serde(rename = "Grant", default)]
+	pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Grant {
+	#[serde(rename = "Grantee")]
+	pub grantee: Grantee,
+	#[serde(rename = "Permission")]
+	pub permission: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Grantee {
+	#[serde(
+		rename = "xmlns:xsi",
+		serialize_with = "xmlns_xsi_tag",
+		skip_deserializing
+	)]
+	pub xmlns_xsi: (),
+	#[serde(rename = "xsi:type")]
+	pub grantee_type: String,
+	#[serde(rename = "ID")]
+	pub id: Option<Value>,
+	#[serde(rename = "DisplayName")]
+	pub display_name: Option<Value>,
+	#[serde(rename = "URI")]
+	pub uri: Option<Value>,
+	#[serde(rename = "EmailAddress")]
+	pub email_address: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OwnershipControls {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub rule: OwnershipControlsRule,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OwnershipControlsRule {
+	#[serde(rename = "ObjectOwnership")]
+	pub object_ownership: Value,
+}
+
+fn xmlns_xsi_tag<S: Serializer>(_v: &(), s: S) -> Result<S::Ok, S::Error> {
+	s.serialize_str(XSI_NAMESPACE)
+}
+
+impl AccessControlPolicy {
+	fn into_grants(self) -> Result<Vec<AclGrant>, Error> {
+		self.access_control_list
+			.grants
+			.into_iter()
+			.map(|grant| {
+				let permission = match grant.permission.0.as_str() {
+					"READ" => AclPermission::Read,
+					"WRITE" => AclPermission::Write,
+					"READ_ACP" => AclPermission::ReadAcp,
+					"WRITE_ACP" => AclPermission::WriteAcp,
+					"FULL_CONTROL" => AclPermission::FullControl,
+					p => return Err(Error::bad_request(format!("Invalid ACL permission: {}", p))),
+				};
+				let Grantee {
+					grantee_type,
+					id,
+					uri,
+					..
+				} = grant.grantee;
+				let grantee = match (grantee_type.as_str(), id, uri) {
+					("CanonicalUser", Some(id), _) => AclGrantee::Key(id.0),
+					("Group", _, Some(uri)) => parse_group_uri(&uri.0)?,
+					(t, _, _) => {
+						return Err(Error::NotImplemented(format!(
+							"Garage does not support ACL grantees of type {}",
+							t
+						)))
+					}
+				};
+				Ok(AclGrant {
+					grantee,
+					permission,
+				})
+			})
+			.collect()
+	}
+}
+
+impl Grantee {
+	fn key(key_id: &str, display_name: Option<String>) -> Self {
+		Self {
+			xmlns_xsi: (),
+			grantee_type: "CanonicalUser".to_string(),
+			id: Some(Value(key_id.to_string())),
+			display_name: display_name.map(Value),
+			uri: None,
+			email_address: None,
+		}
+	}
+
+	fn all_users() -> Self {
+		Self {
+			xmlns_xsi: (),
+			grantee_type: "Group".to_string(),
+			id: None,
+			display_name: None,
+			uri: Some(Value(ALL_USERS_URI.to_string())),
+			email_address: None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_access_control_policy() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<AccessControlPolicy xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Owner>
+    <ID>GK31c2f218a2e44f485b94239e</ID>
+    <DisplayName>admin</DisplayName>
+  </Owner>
+  <AccessControlList>
+    <Grant>
+      <Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="CanonicalUser">
+        <ID>GK31c2f218a2e44f485b94239e</ID>
+        <DisplayName>admin</DisplayName>
+      </Grantee>
+      <Permission>FULL_CONTROL</Permission>
+    </Grant>
+    <Grant>
+      <Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="Group">
+        <URI>http://acs.amazonaws.com/groups/global/AllUsers</URI>
+      </Grantee>
+      <Permission>READ</Permission>
+    </Grant>
+  </AccessControlList>
+</AccessControlPolicy>"#;
+		let policy: AccessControlPolicy = from_str(message)?;
+		let ref_value = AccessControlPolicy {
+			xmlns: (),
+			owner: Some(AclOwner {
+				id: Value("GK31c2f218a2e44f485b94239e".to_string()),
+				display_name: Some(Value("admin".to_string())),
+			}),
+			access_control_list: AccessControlList {
+				grants: vec![
+					Grant {
+						grantee: Grantee::key("GK31c2f218a2e44f485b94239e", Some("admin".into())),
+						permission: Value("FULL_CONTROL".to_string()),
+					},
+					Grant {
+						grantee: Grantee::all_users(),
+						permission: Value("READ".to_string()),
+					},
+				],
+			},
+		};
+		assert_eq!(policy, ref_value);
+
+		let message2 = to_xml_with_header(&ref_value)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		let keys = BTreeMap::from([(
+			"GK31c2f218a2e44f485b94239e".to_string(),
+			BucketKeyPerm::ALL_PERMISSIONS,
+		)]);
+		let grants = RequestAcl::Grants(policy.into_grants()?);
+		assert!(bucket_acl_public_read(grants, &keys)?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_parse_acl_headers() -> Result<(), Error> {
+		let mut headers = HeaderMap::new();
+		assert_eq!(parse_acl_headers(&headers)?, None);
+
+		headers.insert(X_AMZ_ACL, HeaderValue::from_static("public-read"));
+		assert_eq!(
+			parse_acl_headers(&headers)?,
+			Some(RequestAcl::Canned(CannedAcl::PublicRead))
+		);
+
+		headers.insert(X_AMZ_ACL, HeaderValue::from_static("public-read-write"));
+		assert!(parse_acl_headers(&headers).is_err());
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			"x-amz-grant-read",
+			HeaderValue::from_static(
+				r#"id="GK31c2f218a2e44f485b94239e", uri="http://acs.amazonaws.com/groups/global/AllUsers""#,
+			),
+		);
+		headers.insert(
+			"x-amz-grant-write",
+			HeaderValue::from_static(r#"id="GK31c2f218a2e44f485b94239e""#),
+		);
+		let acl = parse_acl_headers(&headers)?.unwrap();
+		assert_eq!(
+			acl,
+			RequestAcl::Grants(vec![
+				AclGrant {
+					grantee: AclGrantee::Key("GK31c2f218a2e44f485b94239e".into()),
+					permission: AclPermission::Read,
+				},
+				AclGrant {
+					grantee: AclGrantee::AllUsers,
+					permission: AclPermission::Read,
+				},
+				AclGrant {
+					grantee: AclGrantee::Key("GK31c2f218a2e44f485b94239e".into()),
+					permission: AclPermission::Write,
+				},
+			])
+		);
+
+		headers.insert(X_AMZ_ACL, HeaderValue::from_static("private"));
+		assert!(parse_acl_headers(&headers).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_bucket_acl_key_grants() -> Result<(), Error> {
+		let grant = |key_id: &str, permission| AclGrant {
+			grantee: AclGrantee::Key(key_id.into()),
+			permission,
+		};
+		let read_write = BucketKeyPerm {
+			allow_owner: false,
+			..BucketKeyPerm::ALL_PERMISSIONS
+		};
+		let keys = BTreeMap::from([
+			("GKowner".to_string(), BucketKeyPerm::ALL_PERMISSIONS),
+			("GKwriter".to_string(), read_write),
+		]);
+
+		// Canned ACLs leave the permissions of keys unchanged
+		assert!(bucket_acl_public_read(
+			RequestAcl::Canned(CannedAcl::PublicRead),
+			&keys
+		)?);
+		assert!(!bucket_acl_public_read(
+			RequestAcl::Canned(CannedAcl::Private),
+			&keys
+		)?);
+
+		// Grants that match the current permissions are accepted
+		let current = vec![
+			grant("GKowner", AclPermission::FullControl),
+			grant("GKwriter", AclPermission::Read),
+			grant("GKwriter", AclPermission::Write),
+			grant("GKwriter", AclPermission::ReadAcp),
+		];
+		assert!(!bucket_acl_public_read(
+			RequestAcl::Grants(current.clone()),
+			&keys
+		)?);
+
+		// Grants that would change them are not
+		let mut escalate = current.clone();
+		escalate.push(grant("GKwriter", AclPermission::WriteAcp));
+		assert!(bucket_acl_public_read(RequestAcl::Grants(escalate), &keys).is_err());
+
+		let lock_out = current[1..].to_vec();
+		assert!(bucket_acl_public_read(RequestAcl::Grants(lock_out), &keys).is_err());
+
+		let mut new_key = current;
+		new_key.push(grant("GKother", AclPermission::Read));
+		assert!(bucket_acl_public_read(RequestAcl::Grants(new_key), &keys).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_ownership_controls() -> Result<(), Error> {
+		let message = r#"<OwnershipControls xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ObjectOwnership>BucketOwnerEnforced</ObjectOwnership>
+  </Rule>
+</OwnershipControls>"#;
+		let controls: OwnershipControls = from_str(message)?;
+		assert_eq!(
+			parse_ownership(&controls.rule.object_ownership.0)?,
+			ObjectOwnership::BucketOwnerEnforced
+		);
+		assert!(parse_ownership("Everyone").is_err());
+
+		Ok(())
+	}
+}
@@ -12,12 +12,17 @@ use garage_util::socket_address::UnixOrTCPSocketAddress;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 
+use garage_api_common::access_log::AccessLog;
 use garage_api_common::cors::*;
 use garage_api_common::generic_server::*;
 use garage_api_common::helpers::*;
 use garage_api_common::signature::verify_request;
 
+use crate::acl::*;
 use crate::bucket::*;
+use crate::bucket_encryption::*;
+use crate::bucket_logging::*;
+use crate::bucket_policy::*;
 use crate::copy::*;
 use crate::cors::*;
 use crate::delete::*;
@@ -26,9 +31,17 @@ use crate::get::*;
 use crate::lifecycle::*;
 use crate::list::*;
 use crate::multipart::*;
+use crate::notification::*;
+use crate::object_attributes::*;
+use crate::object_lock::*;
 use crate::post_object::handle_post_object;
+use crate::public_access_block::*;
 use crate::put::*;
+use crate::replication::*;
 use crate::router::Endpoint;
+use crate::select::handle_select_object_content;
+use crate::tagging::*;
+use crate::versioning::*;
 use crate::website::*;
 
 pub use garage_api_common::signature::streaming::ReqBody;
@@ -124,67 +137,91 @@ impl ApiHandler for S3ApiServer {
 		let verified_request = verify_request(&garage, req, "s3").await?;
 		let req = verified_request.request;
 		let api_key = verified_request.access_key;
+		let session = verified_request.session;
 
 		let bucket_name = match bucket_name {
 			None => {
+				let api_key = api_key.ok_or_else(|| {
+					Error::forbidden("Garage does not support anonymous access yet")
+				})?;
 				return self
 					.handle_request_without_bucket(req, api_key, endpoint)
-					.await
+					.await;
 			}
 			Some(bucket) => bucket.to_string(),
 		};
 
 		// Special code path for CreateBucket API endpoint
 		if let Endpoint::CreateBucket {} = endpoint {
+			let api_key =
+				api_key.ok_or_else(|| Error::forbidden("Anonymous users cannot create buckets"))?;
+			if session.is_some() {
+				return Err(Error::forbidden(
+					"Temporary credentials cannot be used to create buckets",
+				));
+			}
 			return handle_create_bucket(&garage, req, &api_key.key_id, bucket_name).await;
 		}
 
-		let bucket_id = garage
-			.bucket_helper()
-			.resolve_bucket(&bucket_name, &api_key)
-			.await
-			.map_err(pass_helper_error)?;
+		let bucket_id = resolve_bucket(&garage, &bucket_name, api_key.as_ref()).await?;
 		let bucket = garage
 			.bucket_helper()
 			.get_existing_bucket(bucket_id)
 			.await?;
 		let bucket_params = bucket.state.into_option().unwrap();
 
-		let allowed = match endpoint.authorization_type() {
-			Authorization::Read => api_key.allow_read(&bucket_id),
-			Authorization::Write => api_key.allow_write(&bucket_id),
-			Authorization::Owner => api_key.allow_owner(&bucket_id),
-			_ => unreachable!(),
-		};
-
-		if !allowed {
-			return Err(Error::forbidden("Operation is not allowed for this key."));
+		if let Some(access_log) = req.extensions().get::<AccessLog>() {
+			access_log.set_bucket(
+				&garage,
+				&bucket_name,
+				&bucket_params,
+				api_key.as_ref().map(|k| k.key_id.as_str()),
+				endpoint.get_key(),
+			);
 		}
 
-		let matching_cors_rule = find_matching_cors_rule(&bucket_params, &req)?.cloned();
-
 		let ctx = ReqCtx {
 			garage,
 			bucket_id,
 			bucket_name,
 			bucket_params,
 			api_key,
+			session,
 		};
 
+		// For DeleteObjects, access is checked for each object to be deleted
+		if !matches!(endpoint, Endpoint::DeleteObjects {}) {
+			let public_object = is_public_object(&ctx, &endpoint).await?;
+			authorize_endpoint(&ctx, &req, &endpoint, public_object)?;
+		}
+
+		let matching_cors_rule = find_matching_cors_rule(&ctx.bucket_params, &req)?.cloned();
+
 		let resp = match endpoint {
 			Endpoint::HeadObject {
-				key, part_number, ..
-			} => handle_head(ctx, &req.map(|_| ()), &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_head(
+					ctx,
+					&req.map(|_| ()),
+					&key,
+					version_id.as_deref(),
+					part_number,
+				)
+				.await
+			}
 			Endpoint::GetObject {
 				key,
 				part_number,
+				version_id,
 				response_cache_control,
 				response_content_disposition,
 				response_content_encoding,
 				response_content_language,
 				response_content_type,
 				response_expires,
-				..
 			} => {
 				let overrides = GetObjectOverrides {
 					response_cache_control,
@@ -194,7 +231,15 @@ impl ApiHandler for S3ApiServer {
 					response_content_type,
 					response_expires,
 				};
-				handle_get(ctx, &req.map(|_| ()), &key, part_number, overrides).await
+				handle_get(
+					ctx,
+					&req.map(|_| ()),
+					&key,
+					version_id.as_deref(),
+					part_number,
+					overrides,
+				)
+				.await
 			}
 			Endpoint::UploadPart {
 				key,
@@ -208,10 +253,15 @@ impl ApiHandler for S3ApiServer {
 				upload_id,
 			} => handle_upload_part_copy(ctx, &req, &key, part_number, &upload_id).await,
 			Endpoint::PutObject { key } => handle_put(ctx, req, &key).await,
+			Endpoint::SelectObjectContent { key, .. } => {
+				handle_select_object_content(ctx, req, &key).await
+			}
 			Endpoint::AbortMultipartUpload { key, upload_id } => {
 				handle_abort_multipart_upload(ctx, &key, &upload_id).await
 			}
-			Endpoint::DeleteObject { key, .. } => handle_delete(ctx, &key).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				handle_delete(ctx, &req, &key, version_id.as_deref()).await
+			}
 			Endpoint::CreateMultipartUpload { key } => {
 				handle_create_multipart_upload(ctx, &req, &key).await
 			}
@@ -225,7 +275,8 @@ impl ApiHandler for S3ApiServer {
 			}
 			Endpoint::DeleteBucket {} => handle_delete_bucket(ctx).await,
 			Endpoint::GetBucketLocation {} => handle_get_bucket_location(ctx),
-			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(),
+			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(ctx).await,
+			Endpoint::PutBucketVersioning {} => handle_put_bucket_versioning(ctx, req).await,
 			Endpoint::ListObjects {
 				delimiter,
 				encoding_type,
@@ -304,6 +355,28 @@ impl ApiHandler for S3ApiServer {
 				};
 				handle_list_multipart_upload(ctx, &query).await
 			}
+			Endpoint::ListObjectVersions {
+				delimiter,
+				encoding_type,
+				key_marker,
+				max_keys,
+				prefix,
+				version_id_marker,
+			} => {
+				let query = ListObjectVersionsQuery {
+					common: ListQueryCommon {
+						bucket_name: ctx.bucket_name.clone(),
+						bucket_id,
+						delimiter,
+						page_size: max_keys.unwrap_or(1000).clamp(1, 1000) as usize,
+						prefix: prefix.unwrap_or_default(),
+						urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+					},
+					key_marker,
+					version_id_marker,
+				};
+				handle_list_object_versions(ctx, &query).await
+			}
 			Endpoint::ListParts {
 				key,
 				max_parts,
@@ -320,6 +393,45 @@ impl ApiHandler for S3ApiServer {
 				handle_list_parts(ctx, req, &query).await
 			}
 			Endpoint::DeleteObjects {} => handle_delete_objects(ctx, req).await,
+			Endpoint::GetObjectTagging { key, version_id } => {
+				handle_get_object_tagging(ctx, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectTagging { key, version_id } => {
+				handle_put_object_tagging(ctx, req, &key, version_id.as_deref()).await
+			}
+			Endpoint::DeleteObjectTagging { key, version_id } => {
+				handle_delete_object_tagging(ctx, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetObjectLockConfiguration {} => {
+				handle_get_object_lock_configuration(ctx).await
+			}
+			Endpoint::PutObjectLockConfiguration {} => {
+				handle_put_object_lock_configuration(ctx, req).await
+			}
+			Endpoint::GetObjectAttributes { key, version_id } => {
+				handle_get_object_attributes(ctx, req, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetObjectRetention { key, version_id } => {
+				handle_get_object_retention(ctx, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectRetention { key, version_id } => {
+				handle_put_object_retention(ctx, req, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetObjectLegalHold { key, version_id } => {
+				handle_get_object_legal_hold(ctx, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectLegalHold { key, version_id } => {
+				handle_put_object_legal_hold(ctx, req, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetBucketEncryption {} => handle_get_bucket_encryption(ctx).await,
+			Endpoint::PutBucketEncryption {} => handle_put_bucket_encryption(ctx, req).await,
+			Endpoint::DeleteBucketEncryption {} => handle_delete_bucket_encryption(ctx).await,
+			Endpoint::GetBucketTagging {} => handle_get_bucket_tagging(ctx).await,
+			Endpoint::PutBucketTagging {} => handle_put_bucket_tagging(ctx, req).await,
+			Endpoint::DeleteBucketTagging {} => handle_delete_bucket_tagging(ctx).await,
+			Endpoint::GetBucketPolicy {} => handle_get_bucket_policy(ctx).await,
+			Endpoint::PutBucketPolicy {} => handle_put_bucket_policy(ctx, req).await,
+			Endpoint::DeleteBucketPolicy {} => handle_delete_bucket_policy(ctx).await,
 			Endpoint::GetBucketWebsite {} => handle_get_website(ctx).await,
 			Endpoint::PutBucketWebsite {} => handle_put_website(ctx, req).await,
 			Endpoint::DeleteBucketWebsite {} => handle_delete_website(ctx).await,
@@ -329,6 +441,37 @@ impl ApiHandler for S3ApiServer {
 			Endpoint::GetBucketLifecycleConfiguration {} => handle_get_lifecycle(ctx).await,
 			Endpoint::PutBucketLifecycleConfiguration {} => handle_put_lifecycle(ctx, req).await,
 			Endpoint::DeleteBucketLifecycle {} => handle_delete_lifecycle(ctx).await,
+			Endpoint::GetBucketNotificationConfiguration {} => {
+				handle_get_bucket_notification(ctx).await
+			}
+			Endpoint::PutBucketNotificationConfiguration {} => {
+				handle_put_bucket_notification(ctx, req).await
+			}
+			Endpoint::GetBucketLogging {} => handle_get_bucket_logging(ctx).await,
+			Endpoint::PutBucketLogging {} => handle_put_bucket_logging(ctx, req).await,
+			Endpoint::GetBucketAcl {} => handle_get_bucket_acl(ctx).await,
+			Endpoint::PutBucketAcl {} => handle_put_bucket_acl(ctx, req).await,
+			Endpoint::GetObjectAcl { key, version_id } => {
+				handle_get_object_acl(ctx, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectAcl { key, version_id } => {
+				handle_put_object_acl(ctx, req, &key, version_id.as_deref()).await
+			}
+			Endpoint::GetBucketOwnershipControls {} => {
+				handle_get_bucket_ownership_controls(ctx).await
+			}
+			Endpoint::PutBucketOwnershipControls {} => {
+				handle_put_bucket_ownership_controls(ctx, req).await
+			}
+			Endpoint::DeleteBucketOwnershipControls {} => {
+				handle_delete_bucket_ownership_controls(ctx).await
+			}
+			Endpoint::GetPublicAccessBlock {} => handle_get_public_access_block(ctx).await,
+			Endpoint::PutPublicAccessBlock {} => handle_put_public_access_block(ctx, req).await,
+			Endpoint::DeletePublicAccessBlock {} => handle_delete_public_access_block(ctx).await,
+			Endpoint::GetBucketReplication {} => handle_get_bucket_replication(ctx).await,
+			Endpoint::PutBucketReplication {} => handle_put_bucket_replication(ctx, req).await,
+			Endpoint::DeleteBucketReplication {} => handle_delete_bucket_replication(ctx).await,
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		};
 
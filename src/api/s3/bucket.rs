@@ -3,19 +3,22 @@ use std::collections::HashMap;
 use hyper::{Request, Response, StatusCode};
 
 use garage_model::bucket_alias_table::*;
-use garage_model::bucket_table::Bucket;
+use garage_model::bucket_table::{Bucket, BucketVersioning, ObjectLockConfig};
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::permission::BucketKeyPerm;
 use garage_table::util::*;
 use garage_util::crdt::*;
+use garage_util::data::*;
 use garage_util::time::*;
 
 use garage_api_common::common_error::CommonError;
 use garage_api_common::helpers::*;
 
+use crate::acl::CreateBucketAcl;
 use crate::api_server::{ReqBody, ResBody};
 use crate::error::*;
+use crate::object_lock::parse_bucket_object_lock_header;
 use crate::xml as s3_xml;
 
 pub fn handle_get_bucket_location(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
@@ -31,19 +34,6 @@ pub fn handle_get_bucket_location(ctx: ReqCtx) -> Result<Response<ResBody>, Erro
 		.body(string_body(xml))?)
 }
 
-pub fn handle_get_bucket_versioning() -> Result<Response<ResBody>, Error> {
-	let versioning = s3_xml::VersioningConfiguration {
-		xmlns: (),
-		status: None,
-	};
-
-	let xml = s3_xml::to_xml_with_header(&versioning)?;
-
-	Ok(Response::builder()
-		.header("Content-Type", "application/xml")
-		.body(string_body(xml))?)
-}
-
 pub async fn handle_list_buckets(
 	garage: &Garage,
 	api_key: &Key,
@@ -122,6 +112,13 @@ pub async fn handle_create_bucket(
 	api_key_id: &String,
 	bucket_name: String,
 ) -> Result<Response<ResBody>, Error> {
+	let object_lock_enabled = parse_bucket_object_lock_header(req.headers())?;
+	let acl = CreateBucketAcl::parse(
+		req.headers(),
+		api_key_id,
+		&garage.config.public_access_block,
+	)?;
+
 	let body = req.into_body().collect().await?;
 
 	let cmd =
@@ -179,7 +176,14 @@ pub async fn handle_create_bucket(
 			)));
 		}
 
-		let bucket = Bucket::new();
+		let mut bucket = Bucket::new();
+		let params = bucket.params_mut().unwrap();
+		if object_lock_enabled {
+			// Object lock requires versioning to be enabled on the bucket
+			params.versioning.update(Some(BucketVersioning::Enabled));
+			params.object_lock.update(Some(ObjectLockConfig::default()));
+		}
+		acl.apply_to_params(params);
 		garage.bucket_table.insert(&bucket).await?;
 
 		helper
@@ -197,6 +201,27 @@ pub async fn handle_create_bucket(
 		.unwrap())
 }
 
+/// Resolve the bucket name given in a request into a bucket ID. Local aliases of
+/// the access key are considered, or only global aliases for anonymous requests.
+pub(crate) async fn resolve_bucket(
+	garage: &Garage,
+	bucket_name: &String,
+	api_key: Option<&Key>,
+) -> Result<Uuid, Error> {
+	match api_key {
+		Some(api_key) => garage
+			.bucket_helper()
+			.resolve_bucket(bucket_name, api_key)
+			.await
+			.map_err(|e| pass_helper_error(e).into()),
+		None => garage
+			.bucket_helper()
+			.resolve_global_bucket_name(bucket_name)
+			.await?
+			.ok_or_else(|| CommonError::NoSuchBucket(bucket_name.to_string()).into()),
+	}
+}
+
 pub async fn handle_delete_bucket(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
 	let ReqCtx {
 		garage,
@@ -206,6 +231,9 @@ pub async fn handle_delete_bucket(ctx: ReqCtx) -> Result<Response<ResBody>, Erro
 		api_key,
 		..
 	} = &ctx;
+	let api_key = api_key
+		.as_ref()
+		.ok_or_else(|| Error::forbidden("Anonymous users cannot delete buckets"))?;
 	let helper = garage.locked_helper().await;
 
 	let key_params = api_key.params().unwrap();
@@ -0,0 +1,232 @@
+use quick_xml::de::from_reader;
+
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{Bucket, BucketDefaultEncryption};
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+const SSE_ALGORITHM_AES256: &str = "AES256";
+const SSE_ALGORITHM_AWS_KMS: &str = "aws:kms";
+
+pub async fn handle_get_bucket_encryption(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+	let default_encryption = bucket_params
+		.default_encryption
+		.get()
+		.as_ref()
+		.ok_or(Error::ServerSideEncryptionConfigurationNotFound)?;
+
+	let conf =
+		ServerSideEncryptionConfiguration::from_garage_default_encryption(default_encryption);
+	let xml = to_xml_with_header(&conf)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_encryption(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+
+	let conf: ServerSideEncryptionConfiguration = from_reader(&body as &[u8])?;
+	let default_encryption = conf.validate_into_garage_default_encryption()?;
+
+	match &default_encryption {
+		BucketDefaultEncryption::SseS3 if garage.sse_master_key.is_none() => {
+			return Err(Error::NotImplemented(
+				"Server-side encryption with Garage-managed keys requires sse_master_key to be configured".into(),
+			));
+		}
+		BucketDefaultEncryption::SseKms { key_id } => match &garage.key_provider {
+			None => {
+				return Err(Error::NotImplemented(
+					"Server-side encryption with KMS keys requires a key provider to be configured"
+						.into(),
+				));
+			}
+			Some(key_provider) if key_id.is_none() && key_provider.default_key_id().is_none() => {
+				return Err(Error::bad_request(
+					"KMSMasterKeyID must be specified as no default KMS key is configured",
+				));
+			}
+			_ => (),
+		},
+		_ => (),
+	}
+
+	bucket_params
+		.default_encryption
+		.update(Some(default_encryption));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_bucket_encryption(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	bucket_params.default_encryption.update(None);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "ServerSideEncryptionConfiguration")]
+pub struct ServerSideEncryptionConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub rules: Vec<ServerSideEncryptionRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerSideEncryptionRule {
+	#[serde(rename = "ApplyServerSideEncryptionByDefault")]
+	pub apply_by_default: Option<ApplyServerSideEncryptionByDefault>,
+	#[serde(rename = "BucketKeyEnabled")]
+	pub bucket_key_enabled: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApplyServerSideEncryptionByDefault {
+	#[serde(rename = "SSEAlgorithm")]
+	pub sse_algorithm: Value,
+	#[serde(rename = "KMSMasterKeyID")]
+	pub kms_master_key_id: Option<Value>,
+}
+
+impl ServerSideEncryptionConfiguration {
+	pub fn validate_into_garage_default_encryption(self) -> Result<BucketDefaultEncryption, Error> {
+		let mut rules = self.rules.into_iter();
+		let rule = match (rules.next(), rules.next()) {
+			(Some(rule), None) => rule,
+			_ => {
+				return Err(Error::bad_request(
+					"Exactly one server-side encryption rule must be specified",
+				))
+			}
+		};
+		let by_default = rule
+			.apply_by_default
+			.ok_or_bad_request("Missing ApplyServerSideEncryptionByDefault")?;
+
+		match by_default.sse_algorithm.0.as_str() {
+			SSE_ALGORITHM_AES256 => {
+				if by_default.kms_master_key_id.is_some() {
+					return Err(Error::bad_request(
+						"KMSMasterKeyID can only be specified with the aws:kms algorithm",
+					));
+				}
+				Ok(BucketDefaultEncryption::SseS3)
+			}
+			SSE_ALGORITHM_AWS_KMS => Ok(BucketDefaultEncryption::SseKms {
+				key_id: by_default.kms_master_key_id.map(|v| v.0),
+			}),
+			alg => Err(Error::InvalidEncryptionAlgorithm(alg.to_string())),
+		}
+	}
+
+	pub fn from_garage_default_encryption(conf: &BucketDefaultEncryption) -> Self {
+		let apply_by_default = match conf {
+			BucketDefaultEncryption::SseS3 => ApplyServerSideEncryptionByDefault {
+				sse_algorithm: Value(SSE_ALGORITHM_AES256.into()),
+				kms_master_key_id: None,
+			},
+			BucketDefaultEncryption::SseKms { key_id } => ApplyServerSideEncryptionByDefault {
+				sse_algorithm: Value(SSE_ALGORITHM_AWS_KMS.into()),
+				kms_master_key_id: key_id.clone().map(Value),
+			},
+		};
+		Self {
+			xmlns: (),
+			rules: vec![ServerSideEncryptionRule {
+				apply_by_default: Some(apply_by_default),
+				bucket_key_enabled: None,
+			}],
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_encryption_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ServerSideEncryptionConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ApplyServerSideEncryptionByDefault>
+      <SSEAlgorithm>AES256</SSEAlgorithm>
+    </ApplyServerSideEncryptionByDefault>
+  </Rule>
+</ServerSideEncryptionConfiguration>"#;
+		let conf: ServerSideEncryptionConfiguration = from_str(message).unwrap();
+		let garage_conf = conf.validate_into_garage_default_encryption()?;
+		assert_eq!(garage_conf, BucketDefaultEncryption::SseS3);
+
+		let back = ServerSideEncryptionConfiguration::from_garage_default_encryption(&garage_conf);
+		let xml = to_xml_with_header(&back)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&xml));
+
+		let bad_alg = message.replace("AES256", "DES");
+		let conf: ServerSideEncryptionConfiguration = from_str(&bad_alg).unwrap();
+		assert!(conf.validate_into_garage_default_encryption().is_err());
+
+		let kms = message.replace(
+			"<SSEAlgorithm>AES256</SSEAlgorithm>",
+			"<SSEAlgorithm>aws:kms</SSEAlgorithm><KMSMasterKeyID>archive</KMSMasterKeyID>",
+		);
+		let conf: ServerSideEncryptionConfiguration = from_str(&kms).unwrap();
+		let garage_conf = conf.validate_into_garage_default_encryption()?;
+		assert_eq!(
+			garage_conf,
+			BucketDefaultEncryption::SseKms {
+				key_id: Some("archive".into())
+			}
+		);
+		let back = ServerSideEncryptionConfiguration::from_garage_default_encryption(&garage_conf);
+		assert_eq!(cleanup(&kms), cleanup(&to_xml_with_header(&back)?));
+
+		Ok(())
+	}
+}
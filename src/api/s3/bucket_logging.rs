@@ -0,0 +1,146 @@
+use quick_xml::de::from_reader;
+
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{Bucket, BucketLoggingConfig};
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::bucket::resolve_bucket;
+use crate::error::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+pub async fn handle_get_bucket_logging(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let status =
+		BucketLoggingStatus {
+			xmlns: (),
+			logging_enabled: bucket_params.logging_config.get().as_ref().map(|config| {
+				LoggingEnabled {
+					target_bucket: Value(config.target_bucket_name.clone()),
+					target_prefix: Some(Value(config.target_prefix.clone())),
+				}
+			}),
+		};
+	let xml = to_xml_with_header(&status)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_logging(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		api_key,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+	let status: BucketLoggingStatus = from_reader(&body as &[u8])?;
+
+	let config = match status.logging_enabled {
+		None => None,
+		Some(logging_enabled) => {
+			let target_bucket_name = logging_enabled.target_bucket.0;
+			let target_bucket =
+				resolve_bucket(&garage, &target_bucket_name, api_key.as_ref()).await?;
+
+			// Log objects are written on behalf of the user that enabled logging,
+			// who must therefore be allowed to write to the target bucket
+			if !api_key
+				.as_ref()
+				.is_some_and(|k| k.allow_write(&target_bucket))
+			{
+				return Err(Error::bad_request(format!(
+					"Access logs cannot be written to bucket {}: write access is required",
+					target_bucket_name
+				)));
+			}
+
+			Some(BucketLoggingConfig {
+				target_bucket,
+				target_bucket_name,
+				target_prefix: logging_enabled
+					.target_prefix
+					.map(|x| x.0)
+					.unwrap_or_default(),
+			})
+		}
+	};
+
+	bucket_params.logging_config.update(config);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BucketLoggingStatus {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "LoggingEnabled", skip_serializing_if = "Option::is_none")]
+	pub logging_enabled: Option<LoggingEnabled>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LoggingEnabled {
+	#[serde(rename = "TargetBucket")]
+	pub target_bucket: Value,
+	#[serde(rename = "TargetPrefix")]
+	pub target_prefix: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_bucket_logging_status() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<BucketLoggingStatus xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <LoggingEnabled>
+    <TargetBucket>logs</TargetBucket>
+    <TargetPrefix>website/</TargetPrefix>
+  </LoggingEnabled>
+</BucketLoggingStatus>"#;
+		let status: BucketLoggingStatus = from_str(message)?;
+		let ref_value = BucketLoggingStatus {
+			xmlns: (),
+			logging_enabled: Some(LoggingEnabled {
+				target_bucket: Value("logs".to_string()),
+				target_prefix: Some(Value("website/".to_string())),
+			}),
+		};
+		assert_eq!(status, ref_value);
+
+		let message2 = to_xml_with_header(&ref_value)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		let disabled: BucketLoggingStatus =
+			from_str(r#"<BucketLoggingStatus xmlns="http://s3.amazonaws.com/doc/2006-03-01/" />"#)?;
+		assert_eq!(disabled.logging_enabled, None);
+
+		Ok(())
+	}
+}
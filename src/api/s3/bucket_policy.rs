@@ -0,0 +1,1020 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use hyper::{Request, Response, StatusCode};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use garage_util::data::Uuid;
+
+use garage_model::bucket_table::{Bucket, BucketParams};
+
+use garage_api_common::generic_server::ClientIp;
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::router::Endpoint;
+
+/// Maximum size of a bucket policy document, as in AWS S3
+const MAX_POLICY_SIZE: usize = 20 * 1024;
+
+const ARN_PREFIX: &str = "arn:aws:s3:::";
+
+/// Actions that keys with owner permission on the bucket can always do,
+/// so that a bucket policy cannot lock them out of the bucket
+const POLICY_MANAGEMENT_ACTIONS: &[&str] = &[
+	"s3:GetBucketPolicy",
+	"s3:PutBucketPolicy",
+	"s3:DeleteBucketPolicy",
+];
+
+pub async fn handle_get_bucket_policy(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let policy = bucket_params
+		.policy
+		.get()
+		.clone()
+		.ok_or(Error::NoSuchBucketPolicy)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/json")
+		.body(string_body(policy))?)
+}
+
+pub async fn handle_put_bucket_policy(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+	if body.len() > MAX_POLICY_SIZE {
+		return Err(Error::MalformedPolicy(format!(
+			"Policies must be no more than {} bytes",
+			MAX_POLICY_SIZE
+		)));
+	}
+	let policy = std::str::from_utf8(&body)?;
+	let parsed_policy = BucketPolicy::parse(policy)?;
+	parsed_policy.check_resources(&bucket_arn_names(&bucket_id, &bucket_params))?;
+
+	if parsed_policy.is_public()
+		&& bucket_params
+			.effective_public_access_block(&garage.config.public_access_block)
+			.block_public_policy
+	{
+		return Err(Error::forbidden(
+			"Public bucket policies are blocked by the public access block settings of the bucket",
+		));
+	}
+
+	bucket_params.policy.update(Some(policy.to_string()));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_bucket_policy(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	bucket_params.policy.update(None);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+// ---- access control ----
+
+/// Check that the request for an endpoint is allowed, either by the bucket
+/// permissions of the access key or by the bucket policy. On buckets flagged
+/// for public read, GetObject, HeadObject and ListObjectsV2 need no key, and
+/// neither do GetObject and HeadObject if `public_object` is set.
+pub(crate) fn authorize_endpoint<B>(
+	ctx: &ReqCtx,
+	req: &Request<B>,
+	endpoint: &Endpoint,
+	public_object: bool,
+) -> Result<(), Error> {
+	let prefix = match endpoint {
+		Endpoint::ListObjects { prefix, .. }
+		| Endpoint::ListObjectsV2 { prefix, .. }
+		| Endpoint::ListObjectVersions { prefix, .. }
+		| Endpoint::ListMultipartUploads { prefix, .. } => Some(prefix.as_deref().unwrap_or("")),
+		_ => None,
+	};
+	let authorization = match endpoint {
+		Endpoint::GetObject { .. }
+		| Endpoint::HeadObject { .. }
+		| Endpoint::ListObjectsV2 { .. }
+			if *ctx.bucket_params.public_read.get() && !ignore_public_acls(ctx) =>
+		{
+			Authorization::None
+		}
+		Endpoint::GetObject { .. } | Endpoint::HeadObject { .. } if public_object => {
+			Authorization::None
+		}
+		_ => endpoint.authorization_type(),
+	};
+	authorize_request(
+		ctx,
+		req,
+		authorization,
+		&policy_action(endpoint),
+		endpoint.get_key(),
+		prefix,
+	)
+}
+
+/// Check that an action on the bucket, or on an object of the bucket if `key` is set,
+/// is allowed either by the bucket permissions of the access key or by the bucket policy.
+/// An explicit deny in the bucket policy always takes precedence.
+pub(crate) fn authorize_request<B>(
+	ctx: &ReqCtx,
+	req: &Request<B>,
+	authorization: Authorization,
+	action: &str,
+	key: Option<&str>,
+	prefix: Option<&str>,
+) -> Result<(), Error> {
+	if ctx.api_key.is_none()
+		&& ctx
+			.bucket_params
+			.effective_public_access_block(&ctx.garage.config.public_access_block)
+			.restrict_public_buckets
+	{
+		return Err(Error::forbidden(
+			"Anonymous access is blocked by the public access block settings of the bucket",
+		));
+	}
+
+	// Temporary credentials restricted to a prefix can only access objects
+	// under that prefix, list them, and make read-only requests on the bucket
+	if let Some(session) = &ctx.session {
+		let allowed = match (key, prefix) {
+			(Some(key), _) => session.allow_object_key(key),
+			(None, Some(prefix)) => session.allow_object_key(prefix),
+			(None, None) => {
+				session.prefix.is_none()
+					|| matches!(authorization, Authorization::None | Authorization::Read)
+			}
+		};
+		if !allowed {
+			return Err(Error::forbidden(
+				"Operation is not allowed by the prefix of the temporary credentials",
+			));
+		}
+	}
+
+	let key_allowed = match (authorization, &ctx.api_key) {
+		(Authorization::None, _) => true,
+		(_, None) => false,
+		(Authorization::Read, Some(k)) => k.allow_read(&ctx.bucket_id),
+		(Authorization::Write, Some(k)) => k.allow_write(&ctx.bucket_id),
+		(Authorization::Owner, Some(k)) => k.allow_owner(&ctx.bucket_id),
+	};
+
+	if key_allowed && POLICY_MANAGEMENT_ACTIONS.contains(&action) {
+		return Ok(());
+	}
+
+	let decision = match ctx.bucket_params.policy.get() {
+		None => PolicyDecision::NoMatch,
+		Some(policy) => {
+			let policy = BucketPolicy::parse(policy)
+				.ok_or_internal_error("Invalid bucket policy stored for bucket")?;
+			policy.evaluate(&PolicyRequest {
+				principal: ctx.api_key.as_ref().map(|k| k.key_id.as_str()),
+				action,
+				resources: resource_arns(
+					&bucket_arn_names(&ctx.bucket_id, &ctx.bucket_params),
+					key,
+				),
+				source_ip: source_ip(req, &ctx.garage.config.s3_api.trusted_proxies),
+				secure_transport: is_secure_transport(
+					req,
+					&ctx.garage.config.s3_api.trusted_proxies,
+				),
+				prefix,
+			})
+		}
+	};
+
+	// Temporary credentials cannot be given more permissions than those
+	// they were created with by a policy that allows their parent key
+	let decision = match decision {
+		PolicyDecision::Allow if ctx.session.is_some() => PolicyDecision::NoMatch,
+		decision => decision,
+	};
+
+	match decision {
+		PolicyDecision::Deny => Err(Error::forbidden("Access denied by bucket policy")),
+		PolicyDecision::Allow => Ok(()),
+		PolicyDecision::NoMatch if key_allowed => Ok(()),
+		PolicyDecision::NoMatch if ctx.api_key.is_none() => {
+			Err(Error::forbidden("Anonymous access is not allowed"))
+		}
+		PolicyDecision::NoMatch => Err(Error::forbidden("Operation is not allowed for this key.")),
+	}
+}
+
+/// Returns true if the public-read flags of the bucket and of its objects
+/// are ignored because of the public access block settings of the bucket
+pub(crate) fn ignore_public_acls(ctx: &ReqCtx) -> bool {
+	ctx.bucket_params
+		.effective_public_access_block(&ctx.garage.config.public_access_block)
+		.ignore_public_acls
+}
+
+/// Name of the action checked in bucket policies for an endpoint
+fn policy_action(endpoint: &Endpoint) -> String {
+	let action = match endpoint.name() {
+		"HeadObject" | "SelectObjectContent" => "GetObject",
+		"HeadBucket" | "ListObjects" | "ListObjectsV2" => "ListBucket",
+		"ListObjectVersions" => "ListBucketVersions",
+		"ListMultipartUploads" => "ListBucketMultipartUploads",
+		"ListParts" => "ListMultipartUploadParts",
+		"DeleteObjects" => "DeleteObject",
+		"CopyObject"
+		| "CreateMultipartUpload"
+		| "UploadPart"
+		| "UploadPartCopy"
+		| "CompleteMultipartUpload" => "PutObject",
+		"GetBucketCors" => "GetBucketCORS",
+		"PutBucketCors" | "DeleteBucketCors" => "PutBucketCORS",
+		"GetBucketLifecycleConfiguration" => "GetLifecycleConfiguration",
+		"PutBucketLifecycleConfiguration" | "DeleteBucketLifecycle" => "PutLifecycleConfiguration",
+		"GetBucketEncryption" => "GetEncryptionConfiguration",
+		"PutBucketEncryption" | "DeleteBucketEncryption" => "PutEncryptionConfiguration",
+		"GetBucketReplication" => "GetReplicationConfiguration",
+		"PutBucketReplication" | "DeleteBucketReplication" => "PutReplicationConfiguration",
+		"GetBucketNotificationConfiguration" => "GetBucketNotification",
+		"PutBucketNotificationConfiguration" => "PutBucketNotification",
+		"DeleteBucketTagging" => "PutBucketTagging",
+		"DeleteBucketOwnershipControls" => "PutBucketOwnershipControls",
+		"GetPublicAccessBlock" => "GetBucketPublicAccessBlock",
+		"PutPublicAccessBlock" | "DeletePublicAccessBlock" => "PutBucketPublicAccessBlock",
+		"GetObjectLockConfiguration" => "GetBucketObjectLockConfiguration",
+		"PutObjectLockConfiguration" => "PutBucketObjectLockConfiguration",
+		name => name,
+	};
+	format!("s3:{}", action)
+}
+
+/// Names that designate the bucket in the resources of its policy: its global
+/// aliases and its hex-encoded ID. The alias used in the request is not used
+/// directly, so that a policy applies whatever the alias the bucket is accessed by.
+fn bucket_arn_names(bucket_id: &Uuid, bucket_params: &BucketParams) -> Vec<String> {
+	bucket_params
+		.aliases
+		.items()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.clone())
+		.chain(std::iter::once(hex::encode(bucket_id)))
+		.collect()
+}
+
+/// ARNs of the bucket, or of an object in it if `key` is set, under each of its names
+fn resource_arns(bucket_names: &[String], key: Option<&str>) -> Vec<String> {
+	bucket_names
+		.iter()
+		.map(|name| match key {
+			Some(key) => format!("{}{}/{}", ARN_PREFIX, name, key),
+			None => format!("{}{}", ARN_PREFIX, name),
+		})
+		.collect()
+}
+
+fn is_trusted_proxy(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+	trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+/// IP address of the client that made the request. When the request comes
+/// from a trusted proxy, this is the right-most address of the
+/// X-Forwarded-For header that is not itself a trusted proxy: addresses
+/// further left were given by the client or by untrusted proxies, and
+/// could have been set to anything.
+fn source_ip<B>(req: &Request<B>, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+	let mut ip = req.extensions().get::<ClientIp>()?.0;
+
+	let forwarded = req
+		.headers()
+		.get_all("x-forwarded-for")
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(','))
+		.collect::<Vec<_>>();
+	for hop in forwarded.iter().rev() {
+		if !is_trusted_proxy(&ip, trusted_proxies) {
+			break;
+		}
+		match hop.trim().parse::<IpAddr>() {
+			Ok(hop) => ip = hop,
+			Err(_) => break,
+		}
+	}
+	Some(ip)
+}
+
+/// Requests are considered to be made over TLS if the reverse proxy
+/// in front of Garage says so, as Garage does not terminate TLS itself.
+/// The X-Forwarded-Proto header is only trusted if the request comes
+/// from one of the configured trusted proxies, as clients could set it.
+fn is_secure_transport<B>(req: &Request<B>, trusted_proxies: &[IpNet]) -> bool {
+	let from_trusted_proxy = req
+		.extensions()
+		.get::<ClientIp>()
+		.is_some_and(|ip| is_trusted_proxy(&ip.0, trusted_proxies));
+
+	req.uri().scheme() == Some(&http::uri::Scheme::HTTPS)
+		|| (from_trusted_proxy
+			&& req
+				.headers()
+				.get("x-forwarded-proto")
+				.is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"https")))
+}
+
+// ---- policy evaluation ----
+
+/// Properties of a request that bucket policy statements are matched against
+pub(crate) struct PolicyRequest<'a> {
+	/// Access key ID of the request, None for anonymous requests
+	pub principal: Option<&'a str>,
+	pub action: &'a str,
+	/// ARNs of the bucket or object, one for each name of the bucket
+	pub resources: Vec<String>,
+	pub source_ip: Option<IpAddr>,
+	pub secure_transport: bool,
+	/// Prefix of listing requests
+	pub prefix: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PolicyDecision {
+	/// A statement explicitly allows the request
+	Allow,
+	/// A statement explicitly denies the request
+	Deny,
+	/// No statement applies to the request
+	NoMatch,
+}
+
+/// Validated bucket policy
+#[derive(Debug)]
+pub(crate) struct BucketPolicy {
+	statements: Vec<PolicyStatement>,
+}
+
+#[derive(Debug)]
+struct PolicyStatement {
+	effect: Effect,
+	/// Access key IDs, or "*" for everyone including anonymous users
+	principals: Vec<String>,
+	actions: Vec<String>,
+	resources: Vec<String>,
+	conditions: Vec<PolicyCondition>,
+}
+
+#[derive(Debug)]
+enum PolicyCondition {
+	String {
+		key: StringConditionKey,
+		like: bool,
+		negate: bool,
+		values: Vec<String>,
+	},
+	SourceIp {
+		negate: bool,
+		values: Vec<IpNet>,
+	},
+	SecureTransport(Vec<bool>),
+}
+
+#[derive(Debug)]
+enum StringConditionKey {
+	Prefix,
+}
+
+impl BucketPolicy {
+	pub(crate) fn parse(policy: &str) -> Result<Self, Error> {
+		let doc: PolicyDocument =
+			serde_json::from_str(policy).map_err(|e| Error::MalformedPolicy(e.to_string()))?;
+		doc.validate_into_bucket_policy()
+	}
+
+	/// Check that all resources of the policy designate the bucket or objects in it,
+	/// by one of the names of the bucket
+	fn check_resources(&self, bucket_names: &[String]) -> Result<(), Error> {
+		for resource in self.statements.iter().flat_map(|s| s.resources.iter()) {
+			let ok = resource_arns(bucket_names, None).iter().any(|bucket_arn| {
+				match resource.strip_prefix(bucket_arn.as_str()) {
+					Some(rest) => rest.is_empty() || rest.starts_with('/'),
+					None => false,
+				}
+			});
+			if !ok {
+				return Err(Error::MalformedPolicy(format!(
+					"Policy has invalid resource: {}",
+					resource
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns true if the policy allows anonymous access to the bucket,
+	/// i.e. if it has a statement that allows any principal without
+	/// restricting the IP addresses of the requests
+	pub(crate) fn is_public(&self) -> bool {
+		self.statements.iter().any(|s| {
+			matches!(s.effect, Effect::Allow)
+				&& s.principals.iter().any(|p| p == "*")
+				&& !s
+					.conditions
+					.iter()
+					.any(|c| matches!(c, PolicyCondition::SourceIp { negate: false, .. }))
+		})
+	}
+
+	pub(crate) fn evaluate(&self, req: &PolicyRequest<'_>) -> PolicyDecision {
+		let mut decision = PolicyDecision::NoMatch;
+		for statement in self.statements.iter().filter(|s| s.matches(req)) {
+			match statement.effect {
+				Effect::Deny => return PolicyDecision::Deny,
+				Effect::Allow => decision = PolicyDecision::Allow,
+			}
+		}
+		decision
+	}
+}
+
+impl PolicyStatement {
+	fn matches(&self, req: &PolicyRequest<'_>) -> bool {
+		self.principals
+			.iter()
+			.any(|p| p == "*" || Some(p.as_str()) == req.principal)
+			&& self
+				.actions
+				.iter()
+				.any(|a| glob_match(&a.to_lowercase(), &req.action.to_lowercase()))
+			&& self
+				.resources
+				.iter()
+				.any(|r| req.resources.iter().any(|res| glob_match(r, res)))
+			&& self.conditions.iter().all(|c| c.matches(req))
+	}
+}
+
+impl PolicyCondition {
+	fn matches(&self, req: &PolicyRequest<'_>) -> bool {
+		// When the condition key is not present in the request, positive
+		// conditions do not match whereas negated conditions do
+		match self {
+			Self::String {
+				key: StringConditionKey::Prefix,
+				like,
+				negate,
+				values,
+			} => match req.prefix {
+				None => *negate,
+				Some(prefix) => {
+					let found = values.iter().any(|v| match like {
+						true => glob_match(v, prefix),
+						false => v == prefix,
+					});
+					found != *negate
+				}
+			},
+			Self::SourceIp { negate, values } => match req.source_ip {
+				None => *negate,
+				Some(ip) => values.iter().any(|net| net.contains(&ip)) != *negate,
+			},
+			Self::SecureTransport(values) => values.contains(&req.secure_transport),
+		}
+	}
+}
+
+/// Match a string against a pattern where `*` matches any sequence of characters
+/// and `?` matches any single character
+fn glob_match(pattern: &str, s: &str) -> bool {
+	let pattern = pattern.chars().collect::<Vec<_>>();
+	let s = s.chars().collect::<Vec<_>>();
+
+	let (mut pi, mut si) = (0, 0);
+	// Position of the last `*` in the pattern, and of the character of `s`
+	// it was matched up to, for backtracking
+	let mut star = None;
+	while si < s.len() {
+		if pi < pattern.len() && (pattern[pi] == '?' || pattern[pi] == s[si]) {
+			pi += 1;
+			si += 1;
+		} else if pi < pattern.len() && pattern[pi] == '*' {
+			star = Some((pi, si));
+			pi += 1;
+		} else if let Some((star_pi, star_si)) = star {
+			pi = star_pi + 1;
+			si = star_si + 1;
+			star = Some((star_pi, star_si + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[pi..].iter().all(|c| *c == '*')
+}
+
+// ---- DESERIALIZATION FROM JSON POLICY DOCUMENT ----
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct PolicyDocument {
+	version: Option<String>,
+	#[allow(dead_code)]
+	id: Option<String>,
+	statement: OneOrMany<Statement>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct Statement {
+	#[allow(dead_code)]
+	sid: Option<String>,
+	effect: Effect,
+	principal: Principal,
+	action: OneOrMany<String>,
+	resource: OneOrMany<String>,
+	#[serde(default)]
+	condition: BTreeMap<String, BTreeMap<String, OneOrMany<ConditionValue>>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+enum Effect {
+	Allow,
+	Deny,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Principal {
+	Wildcard(String),
+	Aws {
+		#[serde(rename = "AWS")]
+		aws: OneOrMany<String>,
+	},
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ConditionValue {
+	String(String),
+	Bool(bool),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+	One(T),
+	Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+	fn into_vec(self) -> Vec<T> {
+		match self {
+			Self::One(x) => vec![x],
+			Self::Many(v) => v,
+		}
+	}
+}
+
+impl ConditionValue {
+	fn into_string(self) -> String {
+		match self {
+			Self::String(s) => s,
+			Self::Bool(b) => b.to_string(),
+		}
+	}
+}
+
+impl PolicyDocument {
+	fn validate_into_bucket_policy(self) -> Result<BucketPolicy, Error> {
+		if let Some(v) = &self.version {
+			if v != "2012-10-17" && v != "2008-10-17" {
+				return Err(Error::MalformedPolicy(format!(
+					"Invalid policy version: {}",
+					v
+				)));
+			}
+		}
+
+		let statements = self
+			.statement
+			.into_vec()
+			.into_iter()
+			.map(Statement::validate_into_policy_statement)
+			.collect::<Result<Vec<_>, _>>()?;
+		if statements.is_empty() {
+			return Err(Error::MalformedPolicy(
+				"Policy must contain at least one statement".into(),
+			));
+		}
+
+		Ok(BucketPolicy { statements })
+	}
+}
+
+impl Statement {
+	fn validate_into_policy_statement(self) -> Result<PolicyStatement, Error> {
+		let principals = match self.principal {
+			Principal::Wildcard(p) if p == "*" => vec![p],
+			Principal::Wildcard(p) => {
+				return Err(Error::MalformedPolicy(format!("Invalid principal: {}", p)))
+			}
+			Principal::Aws { aws } => aws.into_vec(),
+		};
+
+		let actions = self.action.into_vec();
+		if let Some(a) = actions
+			.iter()
+			.find(|a| *a != "*" && !a.to_lowercase().starts_with("s3:"))
+		{
+			return Err(Error::MalformedPolicy(format!("Invalid action: {}", a)));
+		}
+
+		let resources = self.resource.into_vec();
+		if let Some(r) = resources.iter().find(|r| !r.starts_with(ARN_PREFIX)) {
+			return Err(Error::MalformedPolicy(format!("Invalid resource: {}", r)));
+		}
+
+		if principals.is_empty() || actions.is_empty() || resources.is_empty() {
+			return Err(Error::MalformedPolicy(
+				"Statements must have at least one principal, action and resource".into(),
+			));
+		}
+
+		let mut conditions = vec![];
+		for (operator, entries) in self.condition {
+			for (key, values) in entries {
+				let values = values
+					.into_vec()
+					.into_iter()
+					.map(ConditionValue::into_string)
+					.collect::<Vec<_>>();
+				conditions.push(parse_condition(&operator, &key, values)?);
+			}
+		}
+
+		Ok(PolicyStatement {
+			effect: self.effect,
+			principals,
+			actions,
+			resources,
+			conditions,
+		})
+	}
+}
+
+fn parse_condition(
+	operator: &str,
+	key: &str,
+	values: Vec<String>,
+) -> Result<PolicyCondition, Error> {
+	let invalid = || {
+		Error::MalformedPolicy(format!(
+			"Unsupported condition: {} on key {}",
+			operator, key
+		))
+	};
+
+	match key.to_lowercase().as_str() {
+		"s3:prefix" => {
+			let (like, negate) = match operator {
+				"StringEquals" => (false, false),
+				"StringNotEquals" => (false, true),
+				"StringLike" => (true, false),
+				"StringNotLike" => (true, true),
+				_ => return Err(invalid()),
+			};
+			Ok(PolicyCondition::String {
+				key: StringConditionKey::Prefix,
+				like,
+				negate,
+				values,
+			})
+		}
+		"aws:sourceip" => {
+			let negate = match operator {
+				"IpAddress" => false,
+				"NotIpAddress" => true,
+				_ => return Err(invalid()),
+			};
+			let values = values
+				.iter()
+				.map(|v| {
+					v.parse::<IpNet>()
+						.or_else(|_| v.parse::<IpAddr>().map(IpNet::from))
+						.map_err(|_| {
+							Error::MalformedPolicy(format!("Invalid IP address or range: {}", v))
+						})
+				})
+				.collect::<Result<Vec<_>, _>>()?;
+			Ok(PolicyCondition::SourceIp { negate, values })
+		}
+		"aws:securetransport" => {
+			if operator != "Bool" {
+				return Err(invalid());
+			}
+			let values = values
+				.iter()
+				.map(|v| match v.to_lowercase().as_str() {
+					"true" => Ok(true),
+					"false" => Ok(false),
+					_ => Err(Error::MalformedPolicy(format!(
+						"Invalid boolean value: {}",
+						v
+					))),
+				})
+				.collect::<Result<Vec<_>, _>>()?;
+			Ok(PolicyCondition::SecureTransport(values))
+		}
+		_ => Err(invalid()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn request<'a>(
+		principal: Option<&'a str>,
+		action: &'a str,
+		resource: &str,
+	) -> PolicyRequest<'a> {
+		PolicyRequest {
+			principal,
+			action,
+			resources: vec![resource.to_string()],
+			source_ip: Some("192.0.2.10".parse().unwrap()),
+			secure_transport: true,
+			prefix: None,
+		}
+	}
+
+	#[test]
+	fn test_glob_match() {
+		assert!(glob_match("*", ""));
+		assert!(glob_match("*", "abc"));
+		assert!(glob_match("a*c", "abbbc"));
+		assert!(glob_match("a?c", "abc"));
+		assert!(!glob_match("a?c", "abbc"));
+		assert!(glob_match("tenant-a/*", "tenant-a/x/y"));
+		assert!(!glob_match("tenant-a/*", "tenant-b/x"));
+		assert!(glob_match("*a*b", "xaxxab"));
+		assert!(!glob_match("abc", "abcd"));
+	}
+
+	#[test]
+	fn test_evaluate_policy() -> Result<(), Error> {
+		let policy = BucketPolicy::parse(
+			r#"{
+  "Version": "2012-10-17",
+  "Statement": [
+    {
+      "Sid": "TenantA",
+      "Effect": "Allow",
+      "Principal": {"AWS": ["GK31c2f218a2e44f485b94239e"]},
+      "Action": ["s3:GetObject", "s3:Put*"],
+      "Resource": "arn:aws:s3:::shared/tenant-a/*"
+    },
+    {
+      "Effect": "Allow",
+      "Principal": {"AWS": "GK31c2f218a2e44f485b94239e"},
+      "Action": "s3:ListBucket",
+      "Resource": "arn:aws:s3:::shared",
+      "Condition": {"StringLike": {"s3:prefix": ["tenant-a/*", "tenant-a/"]}}
+    },
+    {
+      "Effect": "Allow",
+      "Principal": "*",
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::shared/public/*",
+      "Condition": {"IpAddress": {"aws:SourceIp": "192.0.2.0/24"}}
+    },
+    {
+      "Effect": "Deny",
+      "Principal": "*",
+      "Action": "s3:*",
+      "Resource": ["arn:aws:s3:::shared", "arn:aws:s3:::shared/*"],
+      "Condition": {"Bool": {"aws:SecureTransport": false}}
+    }
+  ]
+}"#,
+		)?;
+		policy.check_resources(&["shared".to_string()])?;
+		assert!(policy.check_resources(&["other".to_string()]).is_err());
+
+		let tenant = Some("GK31c2f218a2e44f485b94239e");
+		let other = Some("GK000000000000000000000000");
+
+		let req = request(tenant, "s3:GetObject", "arn:aws:s3:::shared/tenant-a/file");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Allow);
+		let req = request(tenant, "s3:PutObject", "arn:aws:s3:::shared/tenant-a/file");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Allow);
+		let req = request(
+			tenant,
+			"s3:DeleteObject",
+			"arn:aws:s3:::shared/tenant-a/file",
+		);
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+		let req = request(tenant, "s3:GetObject", "arn:aws:s3:::shared/tenant-b/file");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+		let req = request(other, "s3:GetObject", "arn:aws:s3:::shared/tenant-a/file");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+
+		// listing is restricted to the tenant's prefix
+		let mut req = request(tenant, "s3:ListBucket", "arn:aws:s3:::shared");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+		req.prefix = Some("tenant-a/");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Allow);
+		req.prefix = Some("tenant-b/");
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+
+		// anonymous access, restricted by source IP
+		let mut req = request(
+			None,
+			"s3:GetObject",
+			"arn:aws:s3:::shared/public/index.html",
+		);
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Allow);
+		req.source_ip = Some("198.51.100.1".parse().unwrap());
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+		req.source_ip = None;
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+
+		// explicit deny takes precedence
+		let mut req = request(tenant, "s3:GetObject", "arn:aws:s3:::shared/tenant-a/file");
+		req.secure_transport = false;
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Deny);
+
+		// anonymous access is only given to some IP addresses
+		assert!(!policy.is_public());
+		let public = BucketPolicy::parse(
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::shared/*"}}"#,
+		)?;
+		assert!(public.is_public());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_policy_applies_to_all_aliases() -> Result<(), Error> {
+		let mut bucket = Bucket::new();
+		let params = bucket.params_mut().unwrap();
+		params.aliases.update_in_place("shared".to_string(), true);
+		params.aliases.update_in_place("second".to_string(), true);
+		params.aliases.update_in_place("removed".to_string(), false);
+		let names = bucket_arn_names(&bucket.id, bucket.params().unwrap());
+		assert_eq!(
+			names,
+			vec![
+				"second".to_string(),
+				"shared".to_string(),
+				hex::encode(bucket.id)
+			]
+		);
+
+		let policy = BucketPolicy::parse(
+			r#"{"Statement": {"Effect": "Deny", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::shared/private/*"}}"#,
+		)?;
+		policy.check_resources(&names)?;
+		assert!(policy.check_resources(&["second".to_string()]).is_err());
+
+		// the deny applies when the bucket is accessed through its second alias
+		let req = PolicyRequest {
+			resources: resource_arns(&names, Some("private/file")),
+			..request(None, "s3:GetObject", "")
+		};
+		assert_eq!(policy.evaluate(&req), PolicyDecision::Deny);
+		let req = PolicyRequest {
+			resources: resource_arns(&names, Some("public/file")),
+			..request(None, "s3:GetObject", "")
+		};
+		assert_eq!(policy.evaluate(&req), PolicyDecision::NoMatch);
+
+		// policies can also designate the bucket by its ID
+		let by_id = BucketPolicy::parse(&format!(
+			r#"{{"Statement": {{"Effect": "Deny", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::{}/*"}}}}"#,
+			hex::encode(bucket.id)
+		))?;
+		by_id.check_resources(&names)?;
+		let req = PolicyRequest {
+			resources: resource_arns(&names, Some("public/file")),
+			..request(None, "s3:GetObject", "")
+		};
+		assert_eq!(by_id.evaluate(&req), PolicyDecision::Deny);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_secure_transport() {
+		let trusted_proxies = vec!["10.0.0.0/8".parse::<IpNet>().unwrap()];
+		let request_from = |ip: &str| {
+			let mut req = Request::builder()
+				.header("x-forwarded-proto", "https")
+				.body(())
+				.unwrap();
+			req.extensions_mut().insert(ClientIp(ip.parse().unwrap()));
+			req
+		};
+
+		assert!(is_secure_transport(
+			&request_from("10.1.2.3"),
+			&trusted_proxies
+		));
+		// the header is ignored when it is not set by a trusted proxy
+		assert!(!is_secure_transport(
+			&request_from("192.0.2.10"),
+			&trusted_proxies
+		));
+		assert!(!is_secure_transport(&request_from("10.1.2.3"), &[]));
+	}
+
+	#[test]
+	fn test_source_ip() {
+		let trusted_proxies = vec!["10.0.0.0/8".parse::<IpNet>().unwrap()];
+		let request_from = |ip: &str, forwarded_for: Option<&str>| {
+			let mut req = Request::builder();
+			if let Some(f) = forwarded_for {
+				req = req.header("x-forwarded-for", f);
+			}
+			let mut req = req.body(()).unwrap();
+			req.extensions_mut().insert(ClientIp(ip.parse().unwrap()));
+			req
+		};
+		let source_ip = |req: &Request<()>, trusted_proxies: &[IpNet]| {
+			source_ip(req, trusted_proxies).map(|ip| ip.to_string())
+		};
+
+		// direct requests
+		let req = request_from("192.0.2.10", None);
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "192.0.2.10");
+		let req = request_from("192.0.2.10", Some("198.51.100.1"));
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "192.0.2.10");
+
+		// requests through trusted proxies
+		let req = request_from("10.0.0.1", Some("198.51.100.1"));
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "198.51.100.1");
+		assert_eq!(source_ip(&req, &[]).unwrap(), "10.0.0.1");
+		let req = request_from("10.0.0.1", Some("203.0.113.5, 198.51.100.1, 10.0.0.2"));
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "198.51.100.1");
+		let req = request_from("10.0.0.1", Some("10.0.0.3, 10.0.0.2"));
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "10.0.0.3");
+		let req = request_from("10.0.0.1", Some("198.51.100.1, garbage"));
+		assert_eq!(source_ip(&req, &trusted_proxies).unwrap(), "10.0.0.1");
+	}
+
+	#[test]
+	fn test_invalid_policies() {
+		let invalid = [
+			r#"{"Statement": []}"#,
+			r#"{"Version": "2000-01-01", "Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}}"#,
+			r#"{"Statement": {"Effect": "Maybe", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "me", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "ec2:RunInstances", "Resource": "arn:aws:s3:::b/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "b/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "NotPrincipal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*", "Condition": {"DateGreaterThan": {"aws:CurrentTime": "2020-01-01T00:00:00Z"}}}}"#,
+			r#"{"Statement": {"Effect": "Allow", "Principal": "*", "Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*", "Condition": {"IpAddress": {"aws:SourceIp": "not-an-ip"}}}}"#,
+		];
+		for policy in invalid {
+			assert!(
+				matches!(BucketPolicy::parse(policy), Err(Error::MalformedPolicy(_))),
+				"{}",
+				policy
+			);
+		}
+	}
+}
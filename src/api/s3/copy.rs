@@ -17,18 +17,32 @@ use garage_util::time::*;
 
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::mpu_table::*;
+use garage_model::s3::notification::ObjectEventName;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use garage_api_common::helpers::*;
 use garage_api_common::signature::checksum::*;
 
+use crate::acl::request_object_public_read;
 use crate::api_server::{ReqBody, ResBody};
+use crate::bucket::resolve_bucket;
+use crate::bucket_policy::authorize_request;
 use crate::encryption::EncryptionParams;
 use crate::error::*;
 use crate::get::{full_object_byte_stream, PreconditionHeaders};
 use crate::multipart;
-use crate::put::{extract_metadata_headers, save_stream, ChecksumMode, SaveStreamResult};
+use crate::notification::notify_object_event;
+use crate::object_lock::parse_object_lock_headers;
+use crate::put::{
+	extract_metadata_headers, extract_website_redirect_location, save_stream, ChecksumMode,
+	SaveStreamResult, WritePreconditions,
+};
+use crate::replication::{queue_replication, request_replication_status};
+use crate::storage_class::request_storage_class;
+use crate::tagging::{parse_tagging_header, X_AMZ_TAGGING_DIRECTIVE};
+use crate::versioning::*;
+use crate::website::X_AMZ_WEBSITE_REDIRECT_LOCATION;
 use crate::xml::{self as s3_xml, xmlns_tag};
 
 pub const X_AMZ_COPY_SOURCE_IF_MATCH: HeaderName =
@@ -51,10 +65,10 @@ pub async fn handle_copy(
 
 	let checksum_algorithm = request_checksum_algorithm(req.headers())?;
 
-	let source_object = get_copy_source(&ctx, req).await?;
+	let (source_object, source_version_id) = get_copy_source(&ctx, req).await?;
 
 	let (source_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		extract_source_info(&source_object, source_version_id.as_deref())?;
 
 	// Check precondition, e.g. x-amz-copy-source-if-match
 	copy_precondition.check_copy_source(source_version, &source_version_meta.etag)?;
@@ -65,8 +79,16 @@ pub async fn handle_copy(
 			&ctx.garage,
 			req.headers(),
 			&source_version_meta.encryption,
-		)?;
-	let dest_encryption = EncryptionParams::new_from_headers(&ctx.garage, req.headers())?;
+		)
+		.await?;
+	let dest_encryption = EncryptionParams::new_from_headers(
+		&ctx.garage,
+		req.headers(),
+		ctx.bucket_params.default_encryption.get().as_ref(),
+	)
+	.await?
+	.reuse_source_data_key(&ctx.garage, &source_encryption)
+	.await?;
 
 	// Extract source checksum info before source_object_meta_inner is consumed
 	let source_checksum = source_object_meta_inner.checksum;
@@ -84,11 +106,43 @@ pub async fn handle_copy(
 			Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
 				extract_metadata_headers(req.headers())?
 			}
-			_ => source_object_meta_inner.into_owned().headers,
+			_ => {
+				// The website redirect location is not copied with other metadata,
+				// it can only be given in the copy request
+				let mut headers = source_object_meta_inner.into_owned().headers;
+				headers.retain(|(name, _)| name != X_AMZ_WEBSITE_REDIRECT_LOCATION.as_str());
+				if let Some(value) = extract_website_redirect_location(req.headers())? {
+					headers.push((X_AMZ_WEBSITE_REDIRECT_LOCATION.to_string(), value));
+				}
+				headers
+			}
 		},
 		checksum: source_checksum,
 	};
 
+	// Determine tags of destination object
+	let dest_tags = match req.headers().get(X_AMZ_TAGGING_DIRECTIVE) {
+		Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => {
+			parse_tagging_header(req.headers())?.unwrap_or_default()
+		}
+		_ => source_version.tags.get().clone(),
+	};
+
+	// Object lock of the destination object is never copied from the source object
+	let dest_lock = parse_object_lock_headers(req.headers(), &ctx.bucket_params, now_msec())?;
+
+	// Replication status is never copied from the source object either
+	let dest_replication =
+		request_replication_status(&ctx, req.headers(), dest_key, &dest_tags, false)?;
+
+	// Neither is the ACL, the destination object is private unless
+	// another ACL is given in the copy request
+	let dest_public_read = request_object_public_read(&ctx, req.headers())?;
+
+	// The storage class is not copied either, the destination object is
+	// in the default storage class unless another one is given
+	let dest_storage_class = request_storage_class(&ctx.garage, req.headers())?;
+
 	// Do actual object copying
 	//
 	// In any of the following scenarios, we need to read the whole object
@@ -113,10 +167,15 @@ pub async fn handle_copy(
 		// In most cases, we can just copy the metadata and link blocks of the
 		// old object from the new object.
 		handle_copy_metaonly(
-			ctx,
+			&ctx,
 			dest_key,
 			dest_object_meta,
-			dest_encryption,
+			dest_tags,
+			dest_lock,
+			dest_replication,
+			dest_public_read,
+			dest_storage_class.clone(),
+			dest_encryption.clone(),
 			source_version,
 			source_version_data,
 			source_version_meta,
@@ -136,10 +195,15 @@ pub async fn handle_copy(
 		// If source and dest encryption use different keys,
 		// we must decrypt content and re-encrypt, so rewrite all data blocks.
 		handle_copy_reencrypt(
-			ctx,
+			&ctx,
 			dest_key,
 			dest_object_meta,
-			dest_encryption,
+			dest_tags,
+			dest_lock,
+			dest_replication,
+			dest_public_read,
+			dest_storage_class.clone(),
+			dest_encryption.clone(),
 			source_version,
 			source_version_data,
 			source_encryption,
@@ -148,6 +212,16 @@ pub async fn handle_copy(
 		.await?
 	};
 
+	notify_object_event(
+		&ctx,
+		ObjectEventName::Copy,
+		dest_key,
+		encode_version_id(res.version_uuid, res.versioned),
+		res.version_timestamp,
+		Some((res.size, &res.etag)),
+	);
+	queue_replication(&ctx, dest_key, res.version_uuid, dest_replication);
+
 	let last_modified = msec_to_rfc3339(res.version_timestamp);
 	let result = CopyObjectResult {
 		last_modified: s3_xml::Value(last_modified),
@@ -157,19 +231,25 @@ pub async fn handle_copy(
 
 	let mut resp = Response::builder()
 		.header("Content-Type", "application/xml")
-		.header("x-amz-version-id", hex::encode(res.version_uuid))
 		.header(
-			"x-amz-copy-source-version-id",
-			hex::encode(source_version.uuid),
-		);
+			X_AMZ_VERSION_ID,
+			encode_version_id(res.version_uuid, res.versioned),
+		)
+		.header("x-amz-copy-source-version-id", version_id(source_version));
 	dest_encryption.add_response_headers(&mut resp);
 	Ok(resp.body(string_body(xml))?)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_copy_metaonly(
-	ctx: ReqCtx,
+	ctx: &ReqCtx,
 	dest_key: &str,
 	dest_object_meta: ObjectVersionMetaInner,
+	dest_tags: ObjectTagSet,
+	dest_lock: ObjectVersionLock,
+	dest_replication: Option<ReplicationStatus>,
+	dest_public_read: bool,
+	dest_storage_class: Option<String>,
 	dest_encryption: EncryptionParams,
 	source_version: &ObjectVersion,
 	source_version_data: &ObjectVersionData,
@@ -178,12 +258,19 @@ async fn handle_copy_metaonly(
 	let ReqCtx {
 		garage,
 		bucket_id: dest_bucket_id,
+		bucket_params: dest_bucket_params,
 		..
 	} = ctx;
+	let dest_bucket_id = *dest_bucket_id;
 
 	// Generate parameters for copied object
 	let new_uuid = gen_uuid();
 	let new_timestamp = now_msec();
+	let versioned = dest_bucket_params.versioning_enabled();
+	let tags = crdt::Lww::new(dest_tags);
+	let replication = crdt::Lww::new(dest_replication);
+	let public_read = crdt::Lww::new(dest_public_read);
+	let storage_class = crdt::Lww::new(dest_storage_class);
 
 	let new_meta = ObjectVersionMeta {
 		encryption: dest_encryption.encrypt_meta(dest_object_meta)?,
@@ -194,7 +281,9 @@ async fn handle_copy_metaonly(
 	let res = SaveStreamResult {
 		version_uuid: new_uuid,
 		version_timestamp: new_timestamp,
+		versioned,
 		etag: new_meta.etag.clone(),
+		size: new_meta.size,
 	};
 
 	// Save object copy
@@ -210,6 +299,12 @@ async fn handle_copy_metaonly(
 					new_meta,
 					bytes.clone(),
 				)),
+				versioned,
+				tags: tags.clone(),
+				lock: dest_lock.clone(),
+				replication: replication.clone(),
+				public_read: public_read.clone(),
+				storage_class: storage_class.clone(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -235,8 +330,16 @@ async fn handle_copy_metaonly(
 				state: ObjectVersionState::Uploading {
 					encryption: new_meta.encryption.clone(),
 					checksum_algorithm: None,
+					checksum_type: None,
+					commit_marker: false,
 					multipart: false,
 				},
+				versioned,
+				tags: tags.clone(),
+				lock: dest_lock.clone(),
+				replication: replication.clone(),
+				public_read: public_read.clone(),
+				storage_class: storage_class.clone(),
 			};
 			let tmp_dest_object = Object::new(
 				dest_bucket_id,
@@ -263,6 +366,9 @@ async fn handle_copy_metaonly(
 			for (bk, bv) in source_version.blocks.items().iter() {
 				dest_version.blocks.put(*bk, *bv);
 			}
+			for (pn, checksum) in source_version.parts_checksums.items().iter() {
+				dest_version.parts_checksums.put(*pn, *checksum);
+			}
 			let dest_block_refs = dest_version
 				.blocks
 				.items()
@@ -271,6 +377,7 @@ async fn handle_copy_metaonly(
 					block: b.1.hash,
 					version: new_uuid,
 					deleted: false.into(),
+					storage_class: storage_class.clone(),
 				})
 				.collect::<Vec<_>>();
 			futures::try_join!(
@@ -291,6 +398,12 @@ async fn handle_copy_metaonly(
 					new_meta,
 					*first_block_hash,
 				)),
+				versioned,
+				tags: tags.clone(),
+				lock: dest_lock.clone(),
+				replication: replication.clone(),
+				public_read: public_read.clone(),
+				storage_class: storage_class.clone(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -304,10 +417,16 @@ async fn handle_copy_metaonly(
 	Ok(res)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_copy_reencrypt(
-	ctx: ReqCtx,
+	ctx: &ReqCtx,
 	dest_key: &str,
 	dest_object_meta: ObjectVersionMetaInner,
+	dest_tags: ObjectTagSet,
+	dest_lock: ObjectVersionLock,
+	dest_replication: Option<ReplicationStatus>,
+	dest_public_read: bool,
+	dest_storage_class: Option<String>,
 	dest_encryption: EncryptionParams,
 	source_version: &ObjectVersion,
 	source_version_data: &ObjectVersionData,
@@ -325,12 +444,18 @@ async fn handle_copy_reencrypt(
 	);
 
 	save_stream(
-		&ctx,
+		ctx,
 		dest_object_meta,
 		dest_encryption,
 		source_stream.map_err(|e| Error::from(GarageError::from(e))),
 		&dest_key.to_string(),
 		checksum_mode,
+		dest_tags,
+		dest_lock,
+		dest_replication,
+		dest_public_read,
+		dest_storage_class,
+		&WritePreconditions::default(),
 	)
 	.await
 }
@@ -349,7 +474,7 @@ pub async fn handle_upload_part_copy(
 	let dest_upload_id = multipart::decode_upload_id(upload_id)?;
 
 	let dest_key = dest_key.to_string();
-	let (source_object, (_, dest_version, mut dest_mpu)) = futures::try_join!(
+	let ((source_object, source_version_id), (_, dest_version, mut dest_mpu)) = futures::try_join!(
 		get_copy_source(&ctx, req),
 		multipart::get_upload(&ctx, &dest_key, &dest_upload_id)
 	)?;
@@ -357,7 +482,7 @@ pub async fn handle_upload_part_copy(
 	let ReqCtx { garage, .. } = ctx;
 
 	let (source_object_version, source_version_data, source_version_meta) =
-		extract_source_info(&source_object)?;
+		extract_source_info(&source_object, source_version_id.as_deref())?;
 
 	// Check precondition on source, e.g. x-amz-copy-source-if-match
 	copy_precondition.check_copy_source(source_object_version, &source_version_meta.etag)?;
@@ -367,7 +492,9 @@ pub async fn handle_upload_part_copy(
 		&garage,
 		req.headers(),
 		&source_version_meta.encryption,
-	)?;
+	)
+	.await?;
+	let dest_storage_class = dest_version.storage_class.get().clone();
 	let (dest_object_encryption, dest_object_checksum_algorithm) = match dest_version.state {
 		ObjectVersionState::Uploading {
 			encryption,
@@ -377,7 +504,7 @@ pub async fn handle_upload_part_copy(
 		_ => unreachable!(),
 	};
 	let (dest_encryption, _) =
-		EncryptionParams::check_decrypt(&garage, req.headers(), &dest_object_encryption)?;
+		EncryptionParams::check_decrypt(&garage, req.headers(), &dest_object_encryption).await?;
 	let same_encryption = EncryptionParams::is_same(&source_encryption, &dest_encryption);
 
 	// Check source range is valid
@@ -490,7 +617,7 @@ pub async fn handle_upload_part_copy(
 	garage.version_table.insert(&dest_version).await?;
 
 	// Now, actually copy the blocks
-	let mut checksummer = Checksummer::init(&Default::default(), !dest_encryption.is_encrypted())
+	let mut checksummer = Checksummer::init(&Default::default(), dest_encryption.has_md5_etag())
 		.add(dest_object_checksum_algorithm);
 
 	// First, create a stream that is able to read the source blocks
@@ -506,6 +633,7 @@ pub async fn handle_upload_part_copy(
 		.enumerate()
 		.map(|(i, (block_hash, range_to_copy))| {
 			let garage3 = garage2.clone();
+			let source_encryption = source_encryption.clone();
 			async move {
 				let stream = source_encryption
 					.get_block(&garage3, &block_hash, Some(order_stream.order(i as u64)))
@@ -558,6 +686,7 @@ pub async fn handle_upload_part_copy(
 
 		let data_len = data.len() as u64;
 
+		let block_encryption = dest_encryption.clone();
 		let (checksummer_updated, (data_to_upload, final_hash)) =
 			tokio::task::spawn_blocking(move || {
 				checksummer.update(&data[..]);
@@ -565,7 +694,7 @@ pub async fn handle_upload_part_copy(
 				let tup = match existing_block_hash {
 					Some(hash) if same_encryption => (None, hash),
 					_ => {
-						let data_enc = dest_encryption.encrypt_block(data)?;
+						let data_enc = block_encryption.encrypt_block(data)?;
 						let hash = blake2sum(&data_enc);
 						(Some(data_enc), hash)
 					}
@@ -593,6 +722,7 @@ pub async fn handle_upload_part_copy(
 			block: final_hash,
 			version: dest_version_id,
 			deleted: false.into(),
+			storage_class: crdt::Lww::new(dest_storage_class.clone()),
 		};
 
 		let (_, _, _, next) = futures::try_join!(
@@ -602,7 +732,13 @@ pub async fn handle_upload_part_copy(
 				if let Some(final_data) = data_to_upload {
 					garage
 						.block_manager
-						.rpc_put_block(final_hash, final_data, dest_encryption.is_encrypted(), None)
+						.rpc_put_block(
+							final_hash,
+							final_data,
+							dest_encryption.is_encrypted(),
+							dest_storage_class.clone(),
+							None,
+						)
 						.await
 				} else {
 					Ok(())
@@ -647,54 +783,79 @@ pub async fn handle_upload_part_copy(
 		.header("Content-Type", "application/xml")
 		.header(
 			"x-amz-copy-source-version-id",
-			hex::encode(source_object_version.uuid),
+			version_id(source_object_version),
 		);
 	dest_encryption.add_response_headers(&mut resp);
 	Ok(resp.body(string_body(resp_xml))?)
 }
 
-async fn get_copy_source(ctx: &ReqCtx, req: &Request<ReqBody>) -> Result<Object, Error> {
+/// Get the source object of a copy, and the version ID of the source
+/// version if one was specified in the x-amz-copy-source header
+async fn get_copy_source(
+	ctx: &ReqCtx,
+	req: &Request<ReqBody>,
+) -> Result<(Object, Option<String>), Error> {
 	let ReqCtx {
 		garage, api_key, ..
 	} = ctx;
 
 	let copy_source = req.headers().get("x-amz-copy-source").unwrap().to_str()?;
+	let (copy_source, source_version_id) = match copy_source.rsplit_once("?versionId=") {
+		Some((cs, vid)) => (cs, Some(vid.to_string())),
+		None => (copy_source, None),
+	};
 	let copy_source = percent_encoding::percent_decode_str(copy_source).decode_utf8()?;
 
 	let (source_bucket, source_key) = parse_bucket_key(&copy_source, None)?;
-	let source_bucket_id = garage
-		.bucket_helper()
-		.resolve_bucket(&source_bucket.to_string(), api_key)
-		.await
-		.map_err(pass_helper_error)?;
-
-	if !api_key.allow_read(&source_bucket_id) {
-		return Err(Error::forbidden(format!(
-			"Reading from bucket {} not allowed for this key",
-			source_bucket
-		)));
-	}
-
 	let source_key = source_key.ok_or_bad_request("No source key specified")?;
 
+	let source_bucket_id =
+		resolve_bucket(garage, &source_bucket.to_string(), api_key.as_ref()).await?;
+	let source_bucket_params = garage
+		.bucket_helper()
+		.get_existing_bucket(source_bucket_id)
+		.await?
+		.state
+		.into_option()
+		.unwrap();
+	let source_ctx = ReqCtx {
+		garage: garage.clone(),
+		bucket_id: source_bucket_id,
+		bucket_name: source_bucket.to_string(),
+		bucket_params: source_bucket_params,
+		api_key: api_key.clone(),
+		session: ctx.session.clone(),
+	};
+	authorize_request(
+		&source_ctx,
+		req,
+		Authorization::Read,
+		"s3:GetObject",
+		Some(source_key),
+		None,
+	)?;
+
 	let source_object = garage
 		.object_table
 		.get(&source_bucket_id, &source_key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	Ok(source_object)
+	Ok((source_object, source_version_id))
 }
 
-fn extract_source_info(
-	source_object: &Object,
-) -> Result<(&ObjectVersion, &ObjectVersionData, &ObjectVersionMeta), Error> {
-	let source_version = source_object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+fn extract_source_info<'a>(
+	source_object: &'a Object,
+	source_version_id: Option<&str>,
+) -> Result<
+	(
+		&'a ObjectVersion,
+		&'a ObjectVersionData,
+		&'a ObjectVersionMeta,
+	),
+	Error,
+> {
+	let source_version = find_data_version(source_object, source_version_id)?;
 
 	let source_version_data = match &source_version.state {
 		ObjectVersionState::Complete(x) => x,
@@ -702,9 +863,7 @@ fn extract_source_info(
 	};
 
 	let source_version_meta = match source_version_data {
-		ObjectVersionData::DeleteMarker => {
-			return Err(Error::NoSuchKey);
-		}
+		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(meta, _bytes) => meta,
 		ObjectVersionData::FirstBlock(meta, _fbh) => meta,
 	};
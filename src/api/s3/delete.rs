@@ -1,19 +1,40 @@
-use hyper::{Request, Response, StatusCode};
+use hyper::{HeaderMap, Request, Response, StatusCode};
 
+use garage_util::crdt;
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
+use garage_model::s3::notification::ObjectEventName;
 use garage_model::s3::object_table::*;
 
 use garage_api_common::helpers::*;
 
 use crate::api_server::{ReqBody, ResBody};
+use crate::bucket_policy::authorize_request;
 use crate::error::*;
+use crate::notification::notify_object_event;
+use crate::object_lock::{bypass_governance_retention, check_version_deletable};
 use crate::put::next_timestamp;
+use crate::replication::{queue_replication, request_replication_status};
+use crate::versioning::*;
 use crate::xml as s3_xml;
 
-async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid), Error> {
+/// Delete an object, or a specific version of an object if version_id is set.
+///
+/// Returns the version ID of the deleted version (when a version ID was specified),
+/// and the version ID of the delete marker that was created or deleted, if any.
+async fn handle_delete_internal(
+	ctx: &ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+	bypass_governance: bool,
+	headers: &HeaderMap,
+) -> Result<(Option<String>, Option<String>), Error> {
 	let ReqCtx {
-		garage, bucket_id, ..
+		garage,
+		bucket_id,
+		bucket_params,
+		..
 	} = ctx;
 	let object = garage
 		.object_table
@@ -22,21 +43,59 @@ async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid),
 		.ok_or(Error::NoSuchKey)?; // No need to delete
 
 	let del_timestamp = next_timestamp(Some(&object));
-	let del_uuid = gen_uuid();
 
-	let deleted_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| !matches!(&v.state, ObjectVersionState::Aborted))
-		.or_else(|| object.versions().iter().rev().next());
-	let deleted_version = match deleted_version {
-		Some(dv) => dv.uuid,
-		None => {
-			warn!("Object has no versions: {:?}", object);
-			Uuid::from([0u8; 32])
+	if let Some(version_id) = version_id {
+		// Permanently delete the specified version, by marking it as aborted.
+		let version = find_version(&object, version_id)?;
+		check_version_deletable(version, bypass_governance, now_msec())?;
+		let was_delete_marker = !version.is_data();
+
+		let mut new_versions = vec![ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			..version.clone()
+		}];
+
+		// If this was the last remaining version, write a non-versioned
+		// delete marker so that the object entry can be garbage collected.
+		if !object
+			.versions()
+			.iter()
+			.any(|v| v.uuid != version.uuid && (v.is_complete() || v.is_uploading(None)))
+		{
+			new_versions.push(ObjectVersion {
+				uuid: gen_uuid(),
+				timestamp: del_timestamp,
+				state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+				versioned: false,
+				tags: Default::default(),
+				lock: Default::default(),
+				replication: Default::default(),
+				public_read: Default::default(),
+				storage_class: Default::default(),
+			});
 		}
-	};
+
+		let object = Object::new(*bucket_id, key.into(), new_versions);
+		garage.object_table.insert(&object).await?;
+
+		notify_object_event(
+			ctx,
+			ObjectEventName::Delete,
+			key,
+			version_id.to_string(),
+			del_timestamp,
+			None,
+		);
+
+		let deleted_version_id = version_id.to_string();
+		let delete_marker = was_delete_marker.then(|| deleted_version_id.clone());
+		return Ok((Some(deleted_version_id), delete_marker));
+	}
+
+	let versioned = bucket_params.versioning_enabled();
+	let del_uuid = gen_uuid();
+	let replication =
+		request_replication_status(ctx, headers, key, &ObjectTagSet::default(), true)?;
 
 	let object = Object::new(
 		*bucket_id,
@@ -45,17 +104,61 @@ async fn handle_delete_internal(ctx: &ReqCtx, key: &str) -> Result<(Uuid, Uuid),
 			uuid: del_uuid,
 			timestamp: del_timestamp,
 			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: crdt::Lww::new(replication),
+			public_read: Default::default(),
+			storage_class: Default::default(),
 		}],
 	);
 
 	garage.object_table.insert(&object).await?;
 
-	Ok((deleted_version, del_uuid))
+	// Delete markers are only visible to clients in buckets
+	// where versioning has been enabled at some point
+	let delete_marker = bucket_params
+		.versioning
+		.get()
+		.is_some()
+		.then(|| encode_version_id(del_uuid, versioned));
+
+	let event_name = match delete_marker {
+		Some(_) => ObjectEventName::DeleteMarkerCreated,
+		None => ObjectEventName::Delete,
+	};
+	notify_object_event(
+		ctx,
+		event_name,
+		key,
+		encode_version_id(del_uuid, versioned),
+		del_timestamp,
+		None,
+	);
+	queue_replication(ctx, key, del_uuid, replication);
+	Ok((None, delete_marker))
 }
 
-pub async fn handle_delete(ctx: ReqCtx, key: &str) -> Result<Response<ResBody>, Error> {
-	match handle_delete_internal(&ctx, key).await {
-		Ok(_) | Err(Error::NoSuchKey) => Ok(Response::builder()
+pub async fn handle_delete(
+	ctx: ReqCtx,
+	req: &Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let bypass_governance = bypass_governance_retention(&ctx, req.headers())?;
+
+	match handle_delete_internal(&ctx, key, version_id, bypass_governance, req.headers()).await {
+		Ok((deleted_version_id, delete_marker)) => {
+			let mut resp = Response::builder().status(StatusCode::NO_CONTENT);
+			if let Some(dm) = &delete_marker {
+				resp = resp.header(X_AMZ_DELETE_MARKER, "true");
+				resp = resp.header(X_AMZ_VERSION_ID, dm);
+			} else if let Some(v) = &deleted_version_id {
+				resp = resp.header(X_AMZ_VERSION_ID, v);
+			}
+			Ok(resp.body(empty_body())?)
+		}
+		Err(Error::NoSuchKey) | Err(Error::NoSuchVersion) => Ok(Response::builder()
 			.status(StatusCode::NO_CONTENT)
 			.body(empty_body())
 			.unwrap()),
@@ -67,7 +170,11 @@ pub async fn handle_delete_objects(
 	ctx: ReqCtx,
 	req: Request<ReqBody>,
 ) -> Result<Response<ResBody>, Error> {
-	let body = req.into_body().collect().await?;
+	let bypass_governance = bypass_governance_retention(&ctx, req.headers())?;
+
+	let (head, body) = req.into_parts();
+	let head_req = Request::from_parts(head, ());
+	let body = body.collect().await?;
 
 	let cmd_xml = roxmltree::Document::parse(std::str::from_utf8(&body)?)?;
 	let cmd = parse_delete_objects_xml(&cmd_xml).ok_or_bad_request("Invalid delete XML query")?;
@@ -76,15 +183,36 @@ pub async fn handle_delete_objects(
 	let mut ret_errors = Vec::new();
 
 	for obj in cmd.objects.iter() {
-		match handle_delete_internal(&ctx, &obj.key).await {
-			Ok((deleted_version, delete_marker_version)) => {
+		let res = match authorize_request(
+			&ctx,
+			&head_req,
+			Authorization::Write,
+			"s3:DeleteObject",
+			Some(&obj.key),
+			None,
+		) {
+			Ok(()) => {
+				handle_delete_internal(
+					&ctx,
+					&obj.key,
+					obj.version_id.as_deref(),
+					bypass_governance,
+					head_req.headers(),
+				)
+				.await
+			}
+			Err(e) => Err(e),
+		};
+		match res {
+			Ok((deleted_version_id, delete_marker)) => {
 				if cmd.quiet {
 					continue;
 				}
 				ret_deleted.push(s3_xml::Deleted {
 					key: s3_xml::Value(obj.key.clone()),
-					version_id: s3_xml::Value(hex::encode(deleted_version)),
-					delete_marker_version_id: s3_xml::Value(hex::encode(delete_marker_version)),
+					version_id: deleted_version_id.map(s3_xml::Value),
+					delete_marker: delete_marker.as_ref().map(|_| s3_xml::Value("true".into())),
+					delete_marker_version_id: delete_marker.map(s3_xml::Value),
 				});
 			}
 			Err(e) => {
@@ -92,7 +220,7 @@ pub async fn handle_delete_objects(
 					code: s3_xml::Value(e.aws_code().to_string()),
 					key: Some(s3_xml::Value(obj.key.clone())),
 					message: s3_xml::Value(format!("{}", e)),
-					version_id: None,
+					version_id: obj.version_id.clone().map(s3_xml::Value),
 				});
 			}
 		}
@@ -116,6 +244,7 @@ struct DeleteRequest {
 
 struct DeleteObject {
 	key: String,
+	version_id: Option<String>,
 }
 
 fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest> {
@@ -135,8 +264,13 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 		if item.has_tag_name("Object") {
 			let key = item.children().find(|e| e.has_tag_name("Key"))?;
 			let key_str = key.text()?;
+			let version_id = match item.children().find(|e| e.has_tag_name("VersionId")) {
+				Some(v) => Some(v.text()?.to_string()),
+				None => None,
+			};
 			ret.objects.push(DeleteObject {
 				key: key_str.to_string(),
+				version_id,
 			});
 		} else if item.has_tag_name("Quiet") {
 			if item.text()? == "true" {
@@ -25,7 +25,9 @@ use garage_util::data::Hash;
 use garage_util::error::Error as GarageError;
 use garage_util::migrate::Migrate;
 
+use garage_model::bucket_table::BucketDefaultEncryption;
 use garage_model::garage::Garage;
+use garage_model::kms::{KeyProvider, KmsError};
 use garage_model::s3::object_table::{ObjectVersionEncryption, ObjectVersionMetaInner};
 
 use garage_api_common::common_error::*;
@@ -33,6 +35,11 @@ use garage_api_common::signature::checksum::Md5Checksum;
 
 use crate::error::Error;
 
+const X_AMZ_SERVER_SIDE_ENCRYPTION: HeaderName =
+	HeaderName::from_static("x-amz-server-side-encryption");
+const X_AMZ_SERVER_SIDE_ENCRYPTION_AWS_KMS_KEY_ID: HeaderName =
+	HeaderName::from_static("x-amz-server-side-encryption-aws-kms-key-id");
+
 const X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_ALGORITHM: HeaderName =
 	HeaderName::from_static("x-amz-server-side-encryption-customer-algorithm");
 const X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY: HeaderName =
@@ -48,6 +55,8 @@ const X_AMZ_COPY_SOURCE_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5: HeaderName =
 	HeaderName::from_static("x-amz-copy-source-server-side-encryption-customer-key-md5");
 
 const CUSTOMER_ALGORITHM_AES256: &[u8] = b"AES256";
+const SSE_ALGORITHM_AES256: &[u8] = b"AES256";
+const SSE_ALGORITHM_AWS_KMS: &[u8] = b"aws:kms";
 
 type Md5Output = md5::digest::Output<md5::Md5Core>;
 
@@ -60,7 +69,7 @@ type StreamNonceSize = aes_gcm::aead::stream::NonceSize<Aes256Gcm, StreamLE31<Ae
 const STREAM_ENC_PLAIN_CHUNK_SIZE: usize = 0x1000; // 4096 bytes
 const STREAM_ENC_CYPER_CHUNK_SIZE: usize = STREAM_ENC_PLAIN_CHUNK_SIZE + 16;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum EncryptionParams {
 	Plaintext,
 	SseC {
@@ -68,6 +77,17 @@ pub enum EncryptionParams {
 		client_key_md5: Md5Output,
 		compression_level: Option<i32>,
 	},
+	SseS3 {
+		data_key: Key<Aes256Gcm>,
+		wrapped_key: Vec<u8>,
+		compression_level: Option<i32>,
+	},
+	SseKms {
+		data_key: Key<Aes256Gcm>,
+		key_id: String,
+		wrapped_key: Vec<u8>,
+		compression_level: Option<i32>,
+	},
 }
 
 impl EncryptionParams {
@@ -75,21 +95,40 @@ impl EncryptionParams {
 		!matches!(self, Self::Plaintext)
 	}
 
+	/// Whether the etag of the object is the md5sum of its content,
+	/// which then has to be computed when writing it
+	pub fn has_md5_etag(&self) -> bool {
+		!matches!(self, Self::SseC { .. })
+	}
+
 	pub fn is_same(a: &Self, b: &Self) -> bool {
 		let relevant_info = |x: &Self| match x {
 			Self::Plaintext => None,
 			Self::SseC {
-				client_key,
+				client_key: key,
 				compression_level,
 				..
-			} => Some((*client_key, compression_level.is_some())),
+			}
+			| Self::SseS3 {
+				data_key: key,
+				compression_level,
+				..
+			}
+			| Self::SseKms {
+				data_key: key,
+				compression_level,
+				..
+			} => Some((*key, compression_level.is_some())),
 		};
 		relevant_info(a) == relevant_info(b)
 	}
 
-	pub fn new_from_headers(
+	/// Determine how a new object should be encrypted, from the request headers
+	/// or otherwise from the default encryption of the bucket
+	pub async fn new_from_headers(
 		garage: &Garage,
 		headers: &HeaderMap,
+		default: Option<&BucketDefaultEncryption>,
 	) -> Result<EncryptionParams, Error> {
 		let key = parse_request_headers(
 			headers,
@@ -97,32 +136,155 @@ impl EncryptionParams {
 			&X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY,
 			&X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5,
 		)?;
-		match key {
-			Some((client_key, client_key_md5)) => Ok(EncryptionParams::SseC {
+		let sse = headers
+			.get(X_AMZ_SERVER_SIDE_ENCRYPTION)
+			.map(HeaderValue::as_bytes);
+		let kms_key_id = headers
+			.get(X_AMZ_SERVER_SIDE_ENCRYPTION_AWS_KMS_KEY_ID)
+			.map(|v| v.to_str())
+			.transpose()?;
+		if kms_key_id.is_some() && sse != Some(SSE_ALGORITHM_AWS_KMS) {
+			return Err(Error::bad_request(
+				"x-amz-server-side-encryption-aws-kms-key-id requires aws:kms server-side encryption",
+			));
+		}
+
+		let data_key = Aes256Gcm::generate_key(&mut OsRng);
+		match (key, sse) {
+			(Some(_), Some(_)) => Err(Error::bad_request(
+				"x-amz-server-side-encryption cannot be used together with customer-provided keys",
+			)),
+			(Some((client_key, client_key_md5)), None) => Ok(EncryptionParams::SseC {
 				client_key,
 				client_key_md5,
 				compression_level: garage.config.compression_level,
 			}),
-			None => Ok(EncryptionParams::Plaintext),
+			(None, Some(SSE_ALGORITHM_AES256)) => Self::new_sse_s3(garage, data_key),
+			(None, Some(SSE_ALGORITHM_AWS_KMS)) => {
+				Self::new_sse_kms(garage, kms_key_id, data_key).await
+			}
+			(None, Some(alg)) => Err(Error::InvalidEncryptionAlgorithm(
+				String::from_utf8_lossy(alg).into_owned(),
+			)),
+			(None, None) => match default {
+				Some(BucketDefaultEncryption::SseS3) => Self::new_sse_s3(garage, data_key),
+				Some(BucketDefaultEncryption::SseKms { key_id }) => {
+					Self::new_sse_kms(garage, key_id.as_deref(), data_key).await
+				}
+				None => Ok(EncryptionParams::Plaintext),
+			},
+		}
+	}
+
+	fn new_sse_s3(garage: &Garage, data_key: Key<Aes256Gcm>) -> Result<EncryptionParams, Error> {
+		let wrapped_key = wrap_data_key(&sse_master_key(garage)?, &data_key)?;
+		Ok(EncryptionParams::SseS3 {
+			data_key,
+			wrapped_key,
+			compression_level: garage.config.compression_level,
+		})
+	}
+
+	async fn new_sse_kms(
+		garage: &Garage,
+		key_id: Option<&str>,
+		data_key: Key<Aes256Gcm>,
+	) -> Result<EncryptionParams, Error> {
+		let key_provider = key_provider(garage)?;
+		let key_id = match key_id {
+			Some(key_id) => key_id,
+			None => key_provider
+				.default_key_id()
+				.ok_or(KmsError::NoDefaultKey)?,
+		};
+		let wrapped_key = key_provider.wrap_data_key(key_id, &data_key.into()).await?;
+		Ok(EncryptionParams::SseKms {
+			data_key,
+			key_id: key_id.to_string(),
+			wrapped_key,
+			compression_level: garage.config.compression_level,
+		})
+	}
+
+	/// For a copy, use the data key of the source object for the destination
+	/// object if both are encrypted with server-managed keys, so that data
+	/// blocks can be reused without being decrypted and encrypted again.
+	/// The data key is wrapped again as required by the destination encryption.
+	pub async fn reuse_source_data_key(
+		self,
+		garage: &Garage,
+		source: &Self,
+	) -> Result<EncryptionParams, Error> {
+		let (data_key, compression_level) = match source {
+			Self::SseS3 {
+				data_key,
+				compression_level,
+				..
+			}
+			| Self::SseKms {
+				data_key,
+				compression_level,
+				..
+			} => (*data_key, *compression_level),
+			_ => return Ok(self),
+		};
+		match self {
+			Self::SseS3 { .. } => Ok(Self::SseS3 {
+				data_key,
+				wrapped_key: wrap_data_key(&sse_master_key(garage)?, &data_key)?,
+				compression_level,
+			}),
+			Self::SseKms { key_id, .. } => {
+				let wrapped_key = key_provider(garage)?
+					.wrap_data_key(&key_id, &data_key.into())
+					.await?;
+				Ok(Self::SseKms {
+					data_key,
+					key_id,
+					wrapped_key,
+					compression_level,
+				})
+			}
+			_ => Ok(self),
 		}
 	}
 
 	pub fn add_response_headers(&self, resp: &mut http::response::Builder) {
-		if let Self::SseC { client_key_md5, .. } = self {
-			let md5 = BASE64_STANDARD.encode(&client_key_md5);
-
-			resp.headers_mut().unwrap().insert(
-				X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_ALGORITHM,
-				HeaderValue::from_bytes(CUSTOMER_ALGORITHM_AES256).unwrap(),
-			);
-			resp.headers_mut().unwrap().insert(
-				X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5,
-				HeaderValue::from_bytes(md5.as_bytes()).unwrap(),
-			);
+		match self {
+			Self::SseC { client_key_md5, .. } => {
+				let md5 = BASE64_STANDARD.encode(client_key_md5);
+
+				resp.headers_mut().unwrap().insert(
+					X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_ALGORITHM,
+					HeaderValue::from_bytes(CUSTOMER_ALGORITHM_AES256).unwrap(),
+				);
+				resp.headers_mut().unwrap().insert(
+					X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5,
+					HeaderValue::from_bytes(md5.as_bytes()).unwrap(),
+				);
+			}
+			Self::SseS3 { .. } => {
+				resp.headers_mut().unwrap().insert(
+					X_AMZ_SERVER_SIDE_ENCRYPTION,
+					HeaderValue::from_bytes(SSE_ALGORITHM_AES256).unwrap(),
+				);
+			}
+			Self::SseKms { key_id, .. } => {
+				resp.headers_mut().unwrap().insert(
+					X_AMZ_SERVER_SIDE_ENCRYPTION,
+					HeaderValue::from_bytes(SSE_ALGORITHM_AWS_KMS).unwrap(),
+				);
+				if let Ok(key_id) = HeaderValue::from_str(key_id) {
+					resp.headers_mut()
+						.unwrap()
+						.insert(X_AMZ_SERVER_SIDE_ENCRYPTION_AWS_KMS_KEY_ID, key_id);
+				}
+			}
+			Self::Plaintext => (),
 		}
 	}
 
-	pub fn check_decrypt<'a>(
+	pub async fn check_decrypt<'a>(
 		garage: &Garage,
 		headers: &HeaderMap,
 		obj_enc: &'a ObjectVersionEncryption,
@@ -133,10 +295,10 @@ impl EncryptionParams {
 			&X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY,
 			&X_AMZ_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5,
 		)?;
-		Self::check_decrypt_common(garage, key, obj_enc)
+		Self::check_decrypt_common(garage, key, obj_enc).await
 	}
 
-	pub fn check_decrypt_for_copy_source<'a>(
+	pub async fn check_decrypt_for_copy_source<'a>(
 		garage: &Garage,
 		headers: &HeaderMap,
 		obj_enc: &'a ObjectVersionEncryption,
@@ -147,15 +309,22 @@ impl EncryptionParams {
 			&X_AMZ_COPY_SOURCE_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY,
 			&X_AMZ_COPY_SOURCE_SERVER_SIDE_ENCRYPTION_CUSTOMER_KEY_MD5,
 		)?;
-		Self::check_decrypt_common(garage, key, obj_enc)
+		Self::check_decrypt_common(garage, key, obj_enc).await
 	}
 
-	fn check_decrypt_common<'a>(
+	async fn check_decrypt_common<'a>(
 		garage: &Garage,
 		key: Option<(Key<Aes256Gcm>, Md5Output)>,
 		obj_enc: &'a ObjectVersionEncryption,
 	) -> Result<(Self, Cow<'a, ObjectVersionMetaInner>), Error> {
-		match (key, &obj_enc) {
+		let compression_level = |compressed: bool| {
+			if compressed {
+				Some(garage.config.compression_level.unwrap_or(1))
+			} else {
+				None
+			}
+		};
+		let (enc, inner) = match (key, &obj_enc) {
 			(
 				Some((client_key, client_key_md5)),
 				ObjectVersionEncryption::SseC { inner, compressed },
@@ -163,53 +332,113 @@ impl EncryptionParams {
 				let enc = Self::SseC {
 					client_key,
 					client_key_md5,
-					compression_level: if *compressed {
-						Some(garage.config.compression_level.unwrap_or(1))
-					} else {
-						None
-					},
+					compression_level: compression_level(*compressed),
 				};
-				let plaintext = enc.decrypt_blob(&inner)?;
-				let inner = ObjectVersionMetaInner::decode(&plaintext)
-					.ok_or_internal_error("Could not decode encrypted metadata")?;
-				Ok((enc, Cow::Owned(inner)))
+				(enc, inner)
 			}
 			(None, ObjectVersionEncryption::Plaintext { inner }) => {
-				Ok((Self::Plaintext, Cow::Borrowed(inner)))
+				return Ok((Self::Plaintext, Cow::Borrowed(inner)));
+			}
+			(
+				None,
+				ObjectVersionEncryption::SseS3 {
+					inner,
+					compressed,
+					wrapped_key,
+				},
+			) => {
+				let enc = Self::SseS3 {
+					data_key: unwrap_data_key(&sse_master_key(garage)?, wrapped_key)?,
+					wrapped_key: wrapped_key.clone(),
+					compression_level: compression_level(*compressed),
+				};
+				(enc, inner)
+			}
+			(
+				None,
+				ObjectVersionEncryption::SseKms {
+					inner,
+					compressed,
+					key_id,
+					wrapped_key,
+				},
+			) => {
+				let data_key = key_provider(garage)?
+					.unwrap_data_key(key_id, wrapped_key)
+					.await?;
+				let enc = Self::SseKms {
+					data_key: data_key.into(),
+					key_id: key_id.clone(),
+					wrapped_key: wrapped_key.clone(),
+					compression_level: compression_level(*compressed),
+				};
+				(enc, inner)
+			}
+			(Some(_), ObjectVersionEncryption::SseS3 { .. })
+			| (Some(_), ObjectVersionEncryption::SseKms { .. }) => {
+				return Err(Error::bad_request(
+					"Object is not encrypted with a customer-provided key",
+				));
 			}
 			(_, ObjectVersionEncryption::SseC { .. }) => {
-				Err(Error::bad_request("Object is encrypted"))
+				return Err(Error::bad_request("Object is encrypted"));
 			}
 			(Some(_), _) => {
 				// TODO: should this be an OK scenario?
-				Err(Error::bad_request("Trying to decrypt a plaintext object"))
+				return Err(Error::bad_request("Trying to decrypt a plaintext object"));
 			}
-		}
+		};
+		let plaintext = enc.decrypt_blob(inner)?;
+		let inner = ObjectVersionMetaInner::decode(&plaintext)
+			.ok_or_internal_error("Could not decode encrypted metadata")?;
+		Ok((enc, Cow::Owned(inner)))
 	}
 
 	pub fn encrypt_meta(
 		&self,
 		meta: ObjectVersionMetaInner,
 	) -> Result<ObjectVersionEncryption, Error> {
+		if let Self::Plaintext = self {
+			return Ok(ObjectVersionEncryption::Plaintext { inner: meta });
+		}
+
+		let plaintext = meta.encode().map_err(GarageError::from)?;
+		let inner = self.encrypt_blob(&plaintext)?.into_owned();
 		match self {
 			Self::SseC {
 				compression_level, ..
-			} => {
-				let plaintext = meta.encode().map_err(GarageError::from)?;
-				let ciphertext = self.encrypt_blob(&plaintext)?;
-				Ok(ObjectVersionEncryption::SseC {
-					inner: ciphertext.into_owned(),
-					compressed: compression_level.is_some(),
-				})
-			}
-			Self::Plaintext => Ok(ObjectVersionEncryption::Plaintext { inner: meta }),
+			} => Ok(ObjectVersionEncryption::SseC {
+				inner,
+				compressed: compression_level.is_some(),
+			}),
+			Self::SseS3 {
+				wrapped_key,
+				compression_level,
+				..
+			} => Ok(ObjectVersionEncryption::SseS3 {
+				inner,
+				compressed: compression_level.is_some(),
+				wrapped_key: wrapped_key.clone(),
+			}),
+			Self::SseKms {
+				key_id,
+				wrapped_key,
+				compression_level,
+				..
+			} => Ok(ObjectVersionEncryption::SseKms {
+				inner,
+				compressed: compression_level.is_some(),
+				key_id: key_id.clone(),
+				wrapped_key: wrapped_key.clone(),
+			}),
+			Self::Plaintext => unreachable!(),
 		}
 	}
 
 	// ---- generating object Etag values ----
 	pub fn etag_from_md5(&self, md5sum: &Option<Md5Checksum>) -> String {
 		match self {
-			Self::Plaintext => md5sum
+			Self::Plaintext | Self::SseS3 { .. } | Self::SseKms { .. } => md5sum
 				.map(|x| hex::encode(&x[..]))
 				.expect("md5 digest should have been computed"),
 			Self::SseC { .. } => {
@@ -230,8 +459,12 @@ impl EncryptionParams {
 
 	pub fn encrypt_blob<'a>(&self, blob: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
 		match self {
-			Self::SseC { client_key, .. } => {
-				let cipher = Aes256Gcm::new(&client_key);
+			Self::SseC {
+				client_key: key, ..
+			}
+			| Self::SseS3 { data_key: key, .. }
+			| Self::SseKms { data_key: key, .. } => {
+				let cipher = Aes256Gcm::new(key);
 				let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
 				let ciphertext = cipher
 					.encrypt(&nonce, blob)
@@ -244,8 +477,12 @@ impl EncryptionParams {
 
 	pub fn decrypt_blob<'a>(&self, blob: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
 		match self {
-			Self::SseC { client_key, .. } => {
-				let cipher = Aes256Gcm::new(&client_key);
+			Self::SseC {
+				client_key: key, ..
+			}
+			| Self::SseS3 { data_key: key, .. }
+			| Self::SseKms { data_key: key, .. } => {
+				let cipher = Aes256Gcm::new(key);
 				let nonce_size = <Aes256Gcm as AeadCore>::NonceSize::to_usize();
 				let nonce = Nonce::from_slice(
 					blob.get(..nonce_size)
@@ -283,11 +520,21 @@ impl EncryptionParams {
 		match self {
 			Self::Plaintext => stream,
 			Self::SseC {
-				client_key,
+				client_key: key,
+				compression_level,
+				..
+			}
+			| Self::SseS3 {
+				data_key: key,
+				compression_level,
+				..
+			}
+			| Self::SseKms {
+				data_key: key,
 				compression_level,
 				..
 			} => {
-				let plaintext = DecryptStream::new(stream, *client_key);
+				let plaintext = DecryptStream::new(stream, *key);
 				if compression_level.is_some() {
 					let reader = stream_asyncread(Box::pin(plaintext));
 					let reader = BufReader::new(reader);
@@ -306,7 +553,17 @@ impl EncryptionParams {
 		match self {
 			Self::Plaintext => Ok(block),
 			Self::SseC {
-				client_key,
+				client_key: key,
+				compression_level,
+				..
+			}
+			| Self::SseS3 {
+				data_key: key,
+				compression_level,
+				..
+			}
+			| Self::SseKms {
+				data_key: key,
 				compression_level,
 				..
 			} => {
@@ -325,7 +582,7 @@ impl EncryptionParams {
 				OsRng.fill_bytes(&mut nonce);
 				ret.extend_from_slice(nonce.as_slice());
 
-				let mut cipher = EncryptorLE31::<Aes256Gcm>::new(&client_key, &nonce);
+				let mut cipher = EncryptorLE31::<Aes256Gcm>::new(key, &nonce);
 				let mut iter = block.chunks(STREAM_ENC_PLAIN_CHUNK_SIZE).peekable();
 
 				if iter.peek().is_none() {
@@ -420,6 +677,55 @@ fn parse_request_headers(
 	}
 }
 
+// ---- SSE-S3 data keys ----
+
+fn sse_master_key(garage: &Garage) -> Result<Key<Aes256Gcm>, Error> {
+	let master_key = garage.sse_master_key.ok_or_else(|| {
+		Error::NotImplemented(
+			"Server-side encryption with Garage-managed keys requires sse_master_key to be configured".into(),
+		)
+	})?;
+	Ok(master_key.into())
+}
+
+// Data keys of SSE-S3 objects are stored encrypted with the master key,
+// prefixed by the nonce.
+fn wrap_data_key(master_key: &Key<Aes256Gcm>, data_key: &Key<Aes256Gcm>) -> Result<Vec<u8>, Error> {
+	let cipher = Aes256Gcm::new(master_key);
+	let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+	let ciphertext = cipher
+		.encrypt(&nonce, data_key.as_slice())
+		.ok_or_internal_error("Encryption failed")?;
+	Ok([nonce.to_vec(), ciphertext].concat())
+}
+
+fn unwrap_data_key(master_key: &Key<Aes256Gcm>, wrapped: &[u8]) -> Result<Key<Aes256Gcm>, Error> {
+	let cipher = Aes256Gcm::new(master_key);
+	let nonce_size = <Aes256Gcm as AeadCore>::NonceSize::to_usize();
+	let nonce = Nonce::from_slice(
+		wrapped
+			.get(..nonce_size)
+			.ok_or_internal_error("Invalid wrapped data key")?,
+	);
+	let data_key: [u8; 32] = cipher
+		.decrypt(nonce, &wrapped[nonce_size..])
+		.ok_or_internal_error("Could not unwrap data key, sse_master_key may have changed")?
+		.try_into()
+		.ok()
+		.ok_or_internal_error("Invalid data key length")?;
+	Ok(data_key.into())
+}
+
+// ---- SSE-KMS data keys ----
+
+fn key_provider(garage: &Garage) -> Result<&dyn KeyProvider, Error> {
+	garage.key_provider.as_deref().ok_or_else(|| {
+		Error::NotImplemented(
+			"Server-side encryption with KMS keys requires a key provider to be configured".into(),
+		)
+	})
+}
+
 // ---- encrypt & decrypt streams ----
 
 #[pin_project::pin_project]
@@ -593,4 +899,17 @@ mod tests {
 	async fn test_encrypt_block_compressed() {
 		test_block_enc(Some(1)).await
 	}
+
+	#[test]
+	fn test_wrap_data_key() {
+		let master_key = Aes256Gcm::generate_key(&mut OsRng);
+		let data_key = Aes256Gcm::generate_key(&mut OsRng);
+
+		let wrapped = wrap_data_key(&master_key, &data_key).unwrap();
+		assert_eq!(unwrap_data_key(&master_key, &wrapped).unwrap(), data_key);
+
+		let other_key = Aes256Gcm::generate_key(&mut OsRng);
+		assert!(unwrap_data_key(&other_key, &wrapped).is_err());
+		assert!(unwrap_data_key(&master_key, &wrapped[..20]).is_err());
+	}
 }
@@ -5,6 +5,8 @@ use hyper::header::HeaderValue;
 use hyper::{HeaderMap, StatusCode};
 
 use garage_model::helper::error::Error as HelperError;
+use garage_model::kms::KmsError;
+use garage_util::error::Error as GarageError;
 
 pub(crate) use garage_api_common::common_error::pass_helper_error;
 
@@ -20,6 +22,7 @@ use garage_api_common::generic_server::ApiError;
 use garage_api_common::helpers::*;
 use garage_api_common::signature::error::Error as SignatureError;
 
+use crate::versioning::{X_AMZ_DELETE_MARKER, X_AMZ_VERSION_ID};
 use crate::xml as s3_xml;
 
 /// Errors of this crate
@@ -38,6 +41,18 @@ pub enum Error {
 	#[error(display = "Key not found")]
 	NoSuchKey,
 
+	/// The object version requested don't exists
+	#[error(display = "Version not found")]
+	NoSuchVersion,
+
+	/// The current version of the object requested is a delete marker
+	#[error(display = "Key not found")]
+	CurrentVersionIsDeleteMarker(String),
+
+	/// The object version requested is a delete marker
+	#[error(display = "The specified method is not allowed against a delete marker")]
+	VersionIsDeleteMarker(String),
+
 	/// The multipart upload requested don't exists
 	#[error(display = "Upload not found")]
 	NoSuchUpload,
@@ -46,6 +61,62 @@ pub enum Error {
 	#[error(display = "At least one of the preconditions you specified did not hold")]
 	PreconditionFailed,
 
+	/// A conflicting operation happened while a conditional write was being committed
+	#[error(display = "A conflicting operation occurred, please retry the request")]
+	ConditionalRequestConflict,
+
+	/// The tag set given in the request is invalid
+	#[error(display = "Invalid tag: {}", _0)]
+	InvalidTag(String),
+
+	/// The operation is not valid for the current state of the bucket
+	#[error(display = "Invalid bucket state: {}", _0)]
+	InvalidBucketState(String),
+
+	/// Object lock is not enabled on the bucket
+	#[error(display = "Object Lock configuration does not exist for this bucket")]
+	ObjectLockConfigurationNotFound,
+
+	/// The object version has no retention or legal hold configuration
+	#[error(display = "The specified object does not have a ObjectLock configuration")]
+	NoSuchObjectLockConfiguration,
+
+	/// The bucket has no bucket policy
+	#[error(display = "The bucket policy does not exist")]
+	NoSuchBucketPolicy,
+
+	/// The bucket has no tag set
+	#[error(display = "There is no tag set associated with the bucket")]
+	NoSuchTagSet,
+
+	/// The bucket has no default encryption configuration
+	#[error(display = "The server side encryption configuration was not found")]
+	ServerSideEncryptionConfigurationNotFound,
+
+	/// The bucket has no replication configuration
+	#[error(display = "The replication configuration was not found")]
+	ReplicationConfigurationNotFound,
+
+	/// The bucket has no ownership controls
+	#[error(display = "The bucket ownership controls were not found")]
+	OwnershipControlsNotFound,
+
+	/// The bucket has no public access block configuration
+	#[error(display = "The public access block configuration was not found")]
+	NoSuchPublicAccessBlockConfiguration,
+
+	/// ACLs are disabled on the bucket by its ownership controls
+	#[error(display = "The bucket does not allow ACLs")]
+	AccessControlListNotSupported,
+
+	/// The bucket policy given in the request is invalid
+	#[error(display = "Malformed policy: {}", _0)]
+	MalformedPolicy(String),
+
+	/// The storage class given in the request is not available on this cluster
+	#[error(display = "Invalid storage class: {}", _0)]
+	InvalidStorageClass(String),
+
 	/// Parts specified in CMU request do not match parts actually uploaded
 	#[error(display = "Parts given to CompleteMultipartUpload do not match uploaded parts")]
 	InvalidPart,
@@ -84,6 +155,10 @@ pub enum Error {
 	#[error(display = "Invalid digest: {}", _0)]
 	InvalidDigest(String),
 
+	/// The KMS key given for server-side encryption does not exist
+	#[error(display = "KMS key not found: {}", _0)]
+	KmsKeyNotFound(String),
+
 	/// The client sent a request for an action not supported by garage
 	#[error(display = "Unimplemented action: {}", _0)]
 	NotImplemented(String),
@@ -124,6 +199,18 @@ impl From<SignatureError> for Error {
 	}
 }
 
+impl From<KmsError> for Error {
+	fn from(err: KmsError) -> Self {
+		match err {
+			KmsError::NoSuchKey(key_id) => Self::KmsKeyNotFound(key_id),
+			KmsError::NoDefaultKey => Self::bad_request(err),
+			e => Self::Common(CommonError::InternalError(GarageError::Message(
+				e.to_string(),
+			))),
+		}
+	}
+}
+
 impl From<multer::Error> for Error {
 	fn from(err: multer::Error) -> Self {
 		Self::bad_request(err)
@@ -134,9 +221,27 @@ impl Error {
 	pub fn aws_code(&self) -> &'static str {
 		match self {
 			Error::Common(c) => c.aws_code(),
-			Error::NoSuchKey => "NoSuchKey",
+			Error::NoSuchKey | Error::CurrentVersionIsDeleteMarker(_) => "NoSuchKey",
+			Error::NoSuchVersion => "NoSuchVersion",
+			Error::VersionIsDeleteMarker(_) => "MethodNotAllowed",
 			Error::NoSuchUpload => "NoSuchUpload",
 			Error::PreconditionFailed => "PreconditionFailed",
+			Error::ConditionalRequestConflict => "ConditionalRequestConflict",
+			Error::InvalidTag(_) => "InvalidTag",
+			Error::InvalidBucketState(_) => "InvalidBucketState",
+			Error::ObjectLockConfigurationNotFound => "ObjectLockConfigurationNotFoundError",
+			Error::NoSuchObjectLockConfiguration => "NoSuchObjectLockConfiguration",
+			Error::NoSuchBucketPolicy => "NoSuchBucketPolicy",
+			Error::NoSuchTagSet => "NoSuchTagSet",
+			Error::ReplicationConfigurationNotFound => "ReplicationConfigurationNotFoundError",
+			Error::OwnershipControlsNotFound => "OwnershipControlsNotFoundError",
+			Error::NoSuchPublicAccessBlockConfiguration => "NoSuchPublicAccessBlockConfiguration",
+			Error::AccessControlListNotSupported => "AccessControlListNotSupported",
+			Error::MalformedPolicy(_) => "MalformedPolicy",
+			Error::InvalidStorageClass(_) => "InvalidStorageClass",
+			Error::ServerSideEncryptionConfigurationNotFound => {
+				"ServerSideEncryptionConfigurationNotFoundError"
+			}
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
 			Error::EntityTooSmall => "EntityTooSmall",
@@ -147,6 +252,7 @@ impl Error {
 			Error::InvalidDigest(_) => "InvalidDigest",
 			Error::InvalidUtf8Str(_) | Error::InvalidUtf8String(_) => "InvalidRequest",
 			Error::InvalidEncryptionAlgorithm(_) => "InvalidEncryptionAlgorithmError",
+			Error::KmsKeyNotFound(_) => "KMS.NotFoundException",
 		}
 	}
 }
@@ -156,16 +262,36 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
+			Error::NoSuchKey
+			| Error::NoSuchVersion
+			| Error::CurrentVersionIsDeleteMarker(_)
+			| Error::NoSuchUpload
+			| Error::ObjectLockConfigurationNotFound
+			| Error::NoSuchObjectLockConfiguration
+			| Error::NoSuchBucketPolicy
+			| Error::NoSuchTagSet
+			| Error::ReplicationConfigurationNotFound
+			| Error::OwnershipControlsNotFound
+			| Error::NoSuchPublicAccessBlockConfiguration
+			| Error::ServerSideEncryptionConfigurationNotFound => StatusCode::NOT_FOUND,
+			Error::VersionIsDeleteMarker(_) => StatusCode::METHOD_NOT_ALLOWED,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+			Error::ConditionalRequestConflict | Error::InvalidBucketState(_) => {
+				StatusCode::CONFLICT
+			}
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
 			Error::AuthorizationHeaderMalformed(_)
+			| Error::InvalidTag(_)
+			| Error::MalformedPolicy(_)
+			| Error::InvalidStorageClass(_)
+			| Error::AccessControlListNotSupported
 			| Error::InvalidPart
 			| Error::InvalidPartOrder
 			| Error::EntityTooSmall
 			| Error::InvalidDigest(_)
 			| Error::InvalidEncryptionAlgorithm(_)
+			| Error::KmsKeyNotFound(_)
 			| Error::InvalidXml(_)
 			| Error::InvalidUtf8Str(_)
 			| Error::InvalidUtf8String(_) => StatusCode::BAD_REQUEST,
@@ -177,7 +303,6 @@ impl ApiError for Error {
 
 		header_map.append(header::CONTENT_TYPE, "application/xml".parse().unwrap());
 
-		#[allow(clippy::single_match)]
 		match self {
 			Error::InvalidRange((_, len)) => {
 				header_map.append(
@@ -187,6 +312,13 @@ impl ApiError for Error {
 						.expect("header value only contain ascii"),
 				);
 			}
+			Error::CurrentVersionIsDeleteMarker(version_id)
+			| Error::VersionIsDeleteMarker(version_id) => {
+				header_map.append(X_AMZ_DELETE_MARKER, HeaderValue::from_static("true"));
+				if let Ok(v) = version_id.try_into() {
+					header_map.append(X_AMZ_VERSION_ID, v);
+				}
+			}
 			_ => (),
 		}
 	}
@@ -210,4 +342,8 @@ impl ApiError for Error {
 		});
 		error_body(error_str)
 	}
+
+	fn access_log_code(&self) -> Option<&'static str> {
+		Some(self.aws_code())
+	}
 }
@@ -32,6 +32,11 @@ use crate::api_server::ResBody;
 use crate::copy::*;
 use crate::encryption::EncryptionParams;
 use crate::error::*;
+use crate::object_lock::add_object_lock_headers;
+use crate::replication::add_replication_status_header;
+use crate::storage_class::X_AMZ_STORAGE_CLASS;
+use crate::tagging::{tagging_count, X_AMZ_TAGGING_COUNT};
+use crate::versioning::*;
 
 const X_AMZ_MP_PARTS_COUNT: HeaderName = HeaderName::from_static("x-amz-mp-parts-count");
 
@@ -49,7 +54,7 @@ fn object_headers(
 	version: &ObjectVersion,
 	version_meta: &ObjectVersionMeta,
 	meta_inner: &ObjectVersionMetaInner,
-	encryption: EncryptionParams,
+	encryption: &EncryptionParams,
 	checksum_mode: ChecksumMode,
 ) -> http::response::Builder {
 	debug!("Version meta: {:?}", version_meta);
@@ -65,6 +70,22 @@ fn object_headers(
 		resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
 	}
 
+	if version.versioned {
+		resp = resp.header(X_AMZ_VERSION_ID, version_id(version));
+	}
+
+	if let Some(count) = tagging_count(version) {
+		resp = resp.header(X_AMZ_TAGGING_COUNT, count.to_string());
+	}
+
+	// Like AWS S3, only advertise the storage class if it is not the default one
+	if let Some(class) = version.storage_class.get() {
+		resp = resp.header(X_AMZ_STORAGE_CLASS, class.as_str());
+	}
+
+	resp = add_object_lock_headers(&version.lock, resp);
+	resp = add_replication_status_header(&version.replication, resp);
+
 	// When metadata is retrieved through the REST API, Amazon S3 combines headers that
 	// have the same name (ignoring case) into a comma-delimited list.
 	// See: https://docs.aws.amazon.com/AmazonS3/latest/userguide/UsingMetadata.html
@@ -140,9 +161,10 @@ pub async fn handle_head(
 	ctx: ReqCtx,
 	req: &Request<()>,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<ResBody>, Error> {
-	handle_head_without_ctx(ctx.garage, req, ctx.bucket_id, key, part_number).await
+	handle_head_without_ctx(ctx.garage, req, ctx.bucket_id, key, version_id, part_number).await
 }
 
 /// Handle HEAD request for website
@@ -151,6 +173,7 @@ pub async fn handle_head_without_ctx(
 	req: &Request<()>,
 	bucket_id: Uuid,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 ) -> Result<Response<ResBody>, Error> {
 	let object = garage
@@ -159,12 +182,7 @@ pub async fn handle_head_without_ctx(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let object_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_data())
-		.ok_or(Error::NoSuchKey)?;
+	let object_version = find_data_version(&object, version_id)?;
 
 	let version_data = match &object_version.state {
 		ObjectVersionState::Complete(c) => c,
@@ -182,7 +200,7 @@ pub async fn handle_head_without_ctx(
 	}
 
 	let (encryption, headers) =
-		EncryptionParams::check_decrypt(&garage, req.headers(), &version_meta.encryption)?;
+		EncryptionParams::check_decrypt(&garage, req.headers(), &version_meta.encryption).await?;
 
 	let checksum_mode = checksum_mode(&req);
 
@@ -197,7 +215,7 @@ pub async fn handle_head_without_ctx(
 					object_version,
 					version_meta,
 					&headers,
-					encryption,
+					&encryption,
 					checksum_mode,
 				)
 				.header(CONTENT_LENGTH, format!("{}", bytes_len))
@@ -223,7 +241,7 @@ pub async fn handle_head_without_ctx(
 					object_version,
 					version_meta,
 					&headers,
-					encryption,
+					&encryption,
 					checksum_mode,
 				)
 				.header(CONTENT_LENGTH, format!("{}", part_end - part_offset))
@@ -247,7 +265,7 @@ pub async fn handle_head_without_ctx(
 			object_version,
 			version_meta,
 			&headers,
-			encryption,
+			&encryption,
 			checksum_mode,
 		)
 		.header(CONTENT_LENGTH, format!("{}", version_meta.size))
@@ -261,10 +279,20 @@ pub async fn handle_get(
 	ctx: ReqCtx,
 	req: &Request<()>,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 	overrides: GetObjectOverrides,
 ) -> Result<Response<ResBody>, Error> {
-	handle_get_without_ctx(ctx.garage, req, ctx.bucket_id, key, part_number, overrides).await
+	handle_get_without_ctx(
+		ctx.garage,
+		req,
+		ctx.bucket_id,
+		key,
+		version_id,
+		part_number,
+		overrides,
+	)
+	.await
 }
 
 /// Handle GET request
@@ -273,6 +301,7 @@ pub async fn handle_get_without_ctx(
 	req: &Request<()>,
 	bucket_id: Uuid,
 	key: &str,
+	version_id: Option<&str>,
 	part_number: Option<u64>,
 	overrides: GetObjectOverrides,
 ) -> Result<Response<ResBody>, Error> {
@@ -282,19 +311,14 @@ pub async fn handle_get_without_ctx(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let last_v = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	let last_v = find_data_version(&object, version_id)?;
 
 	let last_v_data = match &last_v.state {
 		ObjectVersionState::Complete(x) => x,
 		_ => unreachable!(),
 	};
 	let last_v_meta = match last_v_data {
-		ObjectVersionData::DeleteMarker => return Err(Error::NoSuchKey),
+		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(meta, _) => meta,
 		ObjectVersionData::FirstBlock(meta, _) => meta,
 	};
@@ -304,7 +328,7 @@ pub async fn handle_get_without_ctx(
 	}
 
 	let (enc, headers) =
-		EncryptionParams::check_decrypt(&garage, req.headers(), &last_v_meta.encryption)?;
+		EncryptionParams::check_decrypt(&garage, req.headers(), &last_v_meta.encryption).await?;
 
 	let checksum_mode = checksum_mode(&req);
 
@@ -379,7 +403,7 @@ async fn handle_get_full(
 		version,
 		version_meta,
 		&meta_inner,
-		encryption,
+		&encryption,
 		checksum_mode,
 	)
 	.header(CONTENT_LENGTH, format!("{}", version_meta.size))
@@ -470,13 +494,19 @@ async fn handle_get_range(
 	// Here we do not use getobject_override_headers because we don't
 	// want to add any overridden headers (those should not be added
 	// when returning PARTIAL_CONTENT)
-	let resp_builder = object_headers(version, version_meta, meta_inner, encryption, checksum_mode)
-		.header(CONTENT_LENGTH, format!("{}", end - begin))
-		.header(
-			CONTENT_RANGE,
-			format!("bytes {}-{}/{}", begin, end - 1, version_meta.size),
-		)
-		.status(StatusCode::PARTIAL_CONTENT);
+	let resp_builder = object_headers(
+		version,
+		version_meta,
+		meta_inner,
+		&encryption,
+		checksum_mode,
+	)
+	.header(CONTENT_LENGTH, format!("{}", end - begin))
+	.header(
+		CONTENT_RANGE,
+		format!("bytes {}-{}/{}", begin, end - 1, version_meta.size),
+	)
+	.status(StatusCode::PARTIAL_CONTENT);
 
 	match &version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
@@ -520,7 +550,7 @@ async fn handle_get_part(
 		object_version,
 		version_meta,
 		meta_inner,
-		encryption,
+		&encryption,
 		checksum_mode,
 	)
 	.status(StatusCode::PARTIAL_CONTENT);
@@ -719,7 +749,7 @@ fn response_body_from_block_stream(rx: mpsc::Receiver<ByteStream>) -> ResBody {
 	response_body_from_stream(body_stream)
 }
 
-fn response_body_from_stream<S>(stream: S) -> ResBody
+pub(crate) fn response_body_from_stream<S>(stream: S) -> ResBody
 where
 	S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static,
 {
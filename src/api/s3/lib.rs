@@ -1,10 +1,15 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod access_log_worker;
 pub mod api_server;
 pub mod error;
 
+mod acl;
 mod bucket;
+mod bucket_encryption;
+mod bucket_logging;
+mod bucket_policy;
 mod copy;
 pub mod cors;
 mod delete;
@@ -12,8 +17,17 @@ pub mod get;
 mod lifecycle;
 mod list;
 mod multipart;
+mod notification;
+mod object_attributes;
+mod object_lock;
 mod post_object;
+mod public_access_block;
 mod put;
+pub mod replication;
+mod select;
+mod storage_class;
+mod tagging;
+mod versioning;
 pub mod website;
 
 mod encryption;
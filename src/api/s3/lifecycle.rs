@@ -8,12 +8,17 @@ use garage_api_common::helpers::*;
 
 use crate::api_server::{ReqBody, ResBody};
 use crate::error::*;
+use crate::storage_class::parse_storage_class;
+use crate::tagging::Tag;
 use crate::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
 
 use garage_model::bucket_table::{
 	parse_lifecycle_date, Bucket, LifecycleExpiration as GarageLifecycleExpiration,
-	LifecycleFilter as GarageLifecycleFilter, LifecycleRule as GarageLifecycleRule,
+	LifecycleFilter as GarageLifecycleFilter,
+	LifecycleNoncurrentExpiration as GarageLifecycleNoncurrentExpiration,
+	LifecycleRule as GarageLifecycleRule, LifecycleTransition as GarageLifecycleTransition,
 };
+use garage_util::config::DEFAULT_STORAGE_CLASS;
 
 pub async fn handle_get_lifecycle(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
 	let ReqCtx { bucket_params, .. } = ctx;
@@ -68,6 +73,11 @@ pub async fn handle_put_lifecycle(
 		.validate_into_garage_lifecycle_config()
 		.ok_or_bad_request("Invalid lifecycle configuration")?;
 
+	// Objects can only be moved to storage classes that exist on the cluster
+	for transition in config.iter().flat_map(|rule| rule.transitions.iter()) {
+		parse_storage_class(&garage, &transition.storage_class)?;
+	}
+
 	bucket_params.lifecycle_config.update(Some(config));
 	garage
 		.bucket_table
@@ -99,6 +109,10 @@ pub struct LifecycleRule {
 	pub filter: Option<Filter>,
 	#[serde(rename = "Expiration", default)]
 	pub expiration: Option<Expiration>,
+	#[serde(rename = "Transition", default)]
+	pub transitions: Vec<Transition>,
+	#[serde(rename = "NoncurrentVersionExpiration", default)]
+	pub noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
 	#[serde(rename = "AbortIncompleteMultipartUpload", default)]
 	pub abort_incomplete_mpu: Option<AbortIncompleteMpu>,
 }
@@ -109,6 +123,8 @@ pub struct Filter {
 	pub and: Option<Box<Filter>>,
 	#[serde(rename = "Prefix")]
 	pub prefix: Option<Value>,
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
 	#[serde(rename = "ObjectSizeGreaterThan")]
 	pub size_gt: Option<IntValue>,
 	#[serde(rename = "ObjectSizeLessThan")]
@@ -121,6 +137,26 @@ pub struct Expiration {
 	pub days: Option<IntValue>,
 	#[serde(rename = "Date")]
 	pub at_date: Option<Value>,
+	#[serde(rename = "ExpiredObjectDeleteMarker")]
+	pub expired_object_delete_marker: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Transition {
+	#[serde(rename = "Days")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Date")]
+	pub at_date: Option<Value>,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NoncurrentVersionExpiration {
+	#[serde(rename = "NoncurrentDays")]
+	pub noncurrent_days: IntValue,
+	#[serde(rename = "NewerNoncurrentVersions")]
+	pub newer_noncurrent_versions: Option<IntValue>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -167,17 +203,50 @@ impl LifecycleRule {
 
 		let abort_incomplete_mpu_days = self.abort_incomplete_mpu.map(|x| x.days.0 as usize);
 
-		let expiration = self
-			.expiration
-			.map(Expiration::validate_into_garage_lifecycle_expiration)
+		let (expiration, expired_object_delete_marker) = match self.expiration {
+			Some(exp) => exp.validate_into_garage_lifecycle_expiration()?,
+			None => (None, false),
+		};
+
+		let transitions = self
+			.transitions
+			.into_iter()
+			.map(Transition::validate_into_garage_lifecycle_transition)
+			.collect::<Result<Vec<_>, _>>()?;
+		for (i, t) in transitions.iter().enumerate() {
+			if transitions[..i]
+				.iter()
+				.any(|t2| t2.storage_class == t.storage_class)
+			{
+				return Err("a rule cannot have several <Transition> to the same storage class");
+			}
+		}
+
+		let noncurrent_version_expiration = self
+			.noncurrent_version_expiration
+			.map(NoncurrentVersionExpiration::validate_into_garage_lifecycle_expiration)
 			.transpose()?;
 
+		if !filter.tags.is_empty() {
+			if abort_incomplete_mpu_days.is_some() {
+				return Err(
+					"<AbortIncompleteMultipartUpload> cannot be specified with tag filters",
+				);
+			}
+			if expired_object_delete_marker {
+				return Err("<ExpiredObjectDeleteMarker> cannot be specified with tag filters");
+			}
+		}
+
 		Ok(GarageLifecycleRule {
 			id: self.id.map(|x| x.0),
 			enabled,
 			filter,
 			abort_incomplete_mpu_days,
 			expiration,
+			transitions,
+			noncurrent_version_expiration,
+			expired_object_delete_marker,
 		})
 	}
 
@@ -195,10 +264,19 @@ impl LifecycleRule {
 				.map(|days| AbortIncompleteMpu {
 					days: IntValue(days as i64),
 				}),
-			expiration: rule
-				.expiration
+			expiration: Expiration::from_garage_lifecycle_expiration(
+				rule.expiration.as_ref(),
+				rule.expired_object_delete_marker,
+			),
+			transitions: rule
+				.transitions
+				.iter()
+				.map(Transition::from_garage_lifecycle_transition)
+				.collect(),
+			noncurrent_version_expiration: rule
+				.noncurrent_version_expiration
 				.as_ref()
-				.map(Expiration::from_garage_lifecycle_expiration),
+				.map(NoncurrentVersionExpiration::from_garage_lifecycle_expiration),
 		}
 	}
 }
@@ -208,7 +286,7 @@ impl Filter {
 		fn count<T>(x: &Option<T>) -> i32 {
 			x.as_ref().map(|_| 1).unwrap_or(0)
 		}
-		count(&self.prefix) + count(&self.size_gt) + count(&self.size_lt)
+		count(&self.prefix) + self.tags.len() as i32 + count(&self.size_gt) + count(&self.size_lt)
 	}
 
 	pub fn validate_into_garage_lifecycle_filter(
@@ -233,6 +311,11 @@ impl Filter {
 			prefix: self.prefix.map(|x| x.0),
 			size_gt: self.size_gt.map(|x| x.0 as u64),
 			size_lt: self.size_lt.map(|x| x.0 as u64),
+			tags: self
+				.tags
+				.into_iter()
+				.map(|t| (t.key.0, t.value.0))
+				.collect(),
 		}
 	}
 
@@ -240,6 +323,14 @@ impl Filter {
 		let filter = Filter {
 			and: None,
 			prefix: rule.prefix.as_deref().map(Value::from),
+			tags: rule
+				.tags
+				.iter()
+				.map(|(k, v)| Tag {
+					key: Value(k.clone()),
+					value: Value(v.clone()),
+				})
+				.collect(),
 			size_gt: rule.size_gt.map(|x| IntValue(x as i64)),
 			size_lt: rule.size_lt.map(|x| IntValue(x as i64)),
 		};
@@ -257,28 +348,121 @@ impl Filter {
 impl Expiration {
 	pub fn validate_into_garage_lifecycle_expiration(
 		self,
-	) -> Result<GarageLifecycleExpiration, &'static str> {
-		match (self.days, self.at_date) {
-			(Some(_), Some(_)) => Err("cannot have both <Days> and <Date> in <Expiration>"),
-			(None, None) => Err("<Expiration> must contain either <Days> or <Date>"),
-			(Some(days), None) => Ok(GarageLifecycleExpiration::AfterDays(days.0 as usize)),
+	) -> Result<(Option<GarageLifecycleExpiration>, bool), &'static str> {
+		let expired_object_delete_marker = match self.expired_object_delete_marker {
+			None => false,
+			Some(v) => match v.0.as_str() {
+				"true" => true,
+				"false" => false,
+				_ => return Err("invalid value for <ExpiredObjectDeleteMarker>"),
+			},
+		};
+		let expiration = match (self.days, self.at_date) {
+			(Some(_), Some(_)) => return Err("cannot have both <Days> and <Date> in <Expiration>"),
+			(None, None) => None,
+			(Some(days), None) => Some(GarageLifecycleExpiration::AfterDays(days.0 as usize)),
 			(None, Some(date)) => {
 				parse_lifecycle_date(&date.0)?;
-				Ok(GarageLifecycleExpiration::AtDate(date.0))
+				Some(GarageLifecycleExpiration::AtDate(date.0))
 			}
+		};
+		match (&expiration, expired_object_delete_marker) {
+			(Some(_), true) => {
+				Err("<ExpiredObjectDeleteMarker> cannot be specified with <Days> or <Date>")
+			}
+			(None, false) => Err(
+				"<Expiration> must contain either <Days>, <Date> or <ExpiredObjectDeleteMarker>",
+			),
+			_ => Ok((expiration, expired_object_delete_marker)),
 		}
 	}
 
-	pub fn from_garage_lifecycle_expiration(exp: &GarageLifecycleExpiration) -> Self {
+	pub fn from_garage_lifecycle_expiration(
+		exp: Option<&GarageLifecycleExpiration>,
+		expired_object_delete_marker: bool,
+	) -> Option<Self> {
 		match exp {
-			GarageLifecycleExpiration::AfterDays(days) => Expiration {
+			Some(GarageLifecycleExpiration::AfterDays(days)) => Some(Expiration {
 				days: Some(IntValue(*days as i64)),
 				at_date: None,
-			},
-			GarageLifecycleExpiration::AtDate(date) => Expiration {
+				expired_object_delete_marker: None,
+			}),
+			Some(GarageLifecycleExpiration::AtDate(date)) => Some(Expiration {
 				days: None,
 				at_date: Some(Value(date.to_string())),
-			},
+				expired_object_delete_marker: None,
+			}),
+			None if expired_object_delete_marker => Some(Expiration {
+				days: None,
+				at_date: None,
+				expired_object_delete_marker: Some(Value::from("true")),
+			}),
+			None => None,
+		}
+	}
+}
+
+impl Transition {
+	pub fn validate_into_garage_lifecycle_transition(
+		self,
+	) -> Result<GarageLifecycleTransition, &'static str> {
+		let when = match (self.days, self.at_date) {
+			(Some(_), Some(_)) => return Err("cannot have both <Days> and <Date> in <Transition>"),
+			(None, None) => return Err("<Transition> must contain either <Days> or <Date>"),
+			(Some(days), None) if days.0 < 0 => {
+				return Err("<Days> in <Transition> must be a non-negative integer")
+			}
+			(Some(days), None) => GarageLifecycleExpiration::AfterDays(days.0 as usize),
+			(None, Some(date)) => {
+				parse_lifecycle_date(&date.0)?;
+				GarageLifecycleExpiration::AtDate(date.0)
+			}
+		};
+		if self.storage_class.0 == DEFAULT_STORAGE_CLASS {
+			return Err("objects cannot be moved to the STANDARD storage class");
+		}
+		Ok(GarageLifecycleTransition {
+			when,
+			storage_class: self.storage_class.0,
+		})
+	}
+
+	pub fn from_garage_lifecycle_transition(transition: &GarageLifecycleTransition) -> Self {
+		let (days, at_date) = match &transition.when {
+			GarageLifecycleExpiration::AfterDays(days) => (Some(IntValue(*days as i64)), None),
+			GarageLifecycleExpiration::AtDate(date) => (None, Some(Value(date.to_string()))),
+		};
+		Self {
+			days,
+			at_date,
+			storage_class: Value(transition.storage_class.clone()),
+		}
+	}
+}
+
+impl NoncurrentVersionExpiration {
+	pub fn validate_into_garage_lifecycle_expiration(
+		self,
+	) -> Result<GarageLifecycleNoncurrentExpiration, &'static str> {
+		if self.noncurrent_days.0 <= 0 {
+			return Err("<NoncurrentDays> must be a positive integer");
+		}
+		let newer_noncurrent_versions = match self.newer_noncurrent_versions {
+			Some(n) if !(1..=100).contains(&n.0) => {
+				return Err("<NewerNoncurrentVersions> must be between 1 and 100")
+			}
+			n => n.map(|n| n.0 as usize),
+		};
+		Ok(GarageLifecycleNoncurrentExpiration {
+			noncurrent_days: self.noncurrent_days.0 as usize,
+			newer_noncurrent_versions,
+		})
+	}
+
+	pub fn from_garage_lifecycle_expiration(exp: &GarageLifecycleNoncurrentExpiration) -> Self {
+		Self {
+			noncurrent_days: IntValue(exp.noncurrent_days as i64),
+			newer_noncurrent_versions: exp.newer_noncurrent_versions.map(|n| IntValue(n as i64)),
 		}
 	}
 }
@@ -315,6 +499,34 @@ mod tests {
     <Expiration>
       <Days>365</Days>
     </Expiration>
+    <Transition>
+      <Days>30</Days>
+      <StorageClass>STANDARD_IA</StorageClass>
+    </Transition>
+  </Rule>
+  <Rule>
+    <ID>id3</ID>
+    <Status>Enabled</Status>
+    <Filter>
+       <And>
+          <Prefix>data/</Prefix>
+          <Tag>
+             <Key>retention</Key>
+             <Value>short</Value>
+          </Tag>
+       </And>
+    </Filter>
+    <NoncurrentVersionExpiration>
+      <NoncurrentDays>30</NoncurrentDays>
+      <NewerNoncurrentVersions>3</NewerNoncurrentVersions>
+    </NoncurrentVersionExpiration>
+  </Rule>
+  <Rule>
+    <ID>id4</ID>
+    <Status>Enabled</Status>
+    <Expiration>
+      <ExpiredObjectDeleteMarker>true</ExpiredObjectDeleteMarker>
+    </Expiration>
   </Rule>
 </LifecycleConfiguration>"#;
 		let conf: LifecycleConfiguration = from_str(message).unwrap();
@@ -329,6 +541,8 @@ mod tests {
 						..Default::default()
 					}),
 					expiration: None,
+					transitions: vec![],
+					noncurrent_version_expiration: None,
 					abort_incomplete_mpu: Some(AbortIncompleteMpu { days: IntValue(7) }),
 				},
 				LifecycleRule {
@@ -345,7 +559,49 @@ mod tests {
 					expiration: Some(Expiration {
 						days: Some(IntValue(365)),
 						at_date: None,
+						expired_object_delete_marker: None,
+					}),
+					transitions: vec![Transition {
+						days: Some(IntValue(30)),
+						at_date: None,
+						storage_class: "STANDARD_IA".into(),
+					}],
+					noncurrent_version_expiration: None,
+					abort_incomplete_mpu: None,
+				},
+				LifecycleRule {
+					id: Some("id3".into()),
+					status: "Enabled".into(),
+					filter: Some(Filter {
+						and: Some(Box::new(Filter {
+							prefix: Some("data/".into()),
+							tags: vec![Tag {
+								key: "retention".into(),
+								value: "short".into(),
+							}],
+							..Default::default()
+						})),
+						..Default::default()
+					}),
+					expiration: None,
+					transitions: vec![],
+					noncurrent_version_expiration: Some(NoncurrentVersionExpiration {
+						noncurrent_days: IntValue(30),
+						newer_noncurrent_versions: Some(IntValue(3)),
+					}),
+					abort_incomplete_mpu: None,
+				},
+				LifecycleRule {
+					id: Some("id4".into()),
+					status: "Enabled".into(),
+					filter: None,
+					expiration: Some(Expiration {
+						days: None,
+						at_date: None,
+						expired_object_delete_marker: Some("true".into()),
 					}),
+					transitions: vec![],
+					noncurrent_version_expiration: None,
 					abort_incomplete_mpu: None,
 				},
 			],
@@ -374,6 +630,9 @@ mod tests {
 					..Default::default()
 				},
 				expiration: None,
+				transitions: vec![],
+				noncurrent_version_expiration: None,
+				expired_object_delete_marker: false,
 				abort_incomplete_mpu_days: Some(7),
 			},
 			GarageLifecycleRule {
@@ -385,6 +644,39 @@ mod tests {
 					..Default::default()
 				},
 				expiration: Some(GarageLifecycleExpiration::AfterDays(365)),
+				transitions: vec![GarageLifecycleTransition {
+					when: GarageLifecycleExpiration::AfterDays(30),
+					storage_class: "STANDARD_IA".into(),
+				}],
+				noncurrent_version_expiration: None,
+				expired_object_delete_marker: false,
+				abort_incomplete_mpu_days: None,
+			},
+			GarageLifecycleRule {
+				id: Some("id3".into()),
+				enabled: true,
+				filter: GarageLifecycleFilter {
+					prefix: Some("data/".into()),
+					tags: vec![("retention".into(), "short".into())],
+					..Default::default()
+				},
+				expiration: None,
+				transitions: vec![],
+				noncurrent_version_expiration: Some(GarageLifecycleNoncurrentExpiration {
+					noncurrent_days: 30,
+					newer_noncurrent_versions: Some(3),
+				}),
+				expired_object_delete_marker: false,
+				abort_incomplete_mpu_days: None,
+			},
+			GarageLifecycleRule {
+				id: Some("id4".into()),
+				enabled: true,
+				filter: GarageLifecycleFilter::default(),
+				expiration: None,
+				transitions: vec![],
+				noncurrent_version_expiration: None,
+				expired_object_delete_marker: true,
 				abort_incomplete_mpu_days: None,
 			},
 		];
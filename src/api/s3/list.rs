@@ -11,7 +11,7 @@ use garage_util::time::*;
 use garage_model::s3::mpu_table::*;
 use garage_model::s3::object_table::*;
 
-use garage_table::EnumerationOrder;
+use garage_table::{Entry, EnumerationOrder};
 
 use garage_api_common::encoding::*;
 use garage_api_common::helpers::*;
@@ -20,6 +20,7 @@ use crate::api_server::{ReqBody, ResBody};
 use crate::encryption::EncryptionParams;
 use crate::error::*;
 use crate::multipart as s3_multipart;
+use crate::versioning::version_id;
 use crate::xml as s3_xml;
 
 const DUMMY_NAME: &str = "Dummy Key";
@@ -51,6 +52,13 @@ pub struct ListMultipartUploadsQuery {
 	pub common: ListQueryCommon,
 }
 
+#[derive(Debug)]
+pub struct ListObjectVersionsQuery {
+	pub key_marker: Option<String>,
+	pub version_id_marker: Option<String>,
+	pub common: ListQueryCommon,
+}
+
 #[derive(Debug)]
 pub struct ListPartsQuery {
 	pub bucket_name: String,
@@ -148,7 +156,7 @@ pub async fn handle_list(
 				last_modified: s3_xml::Value(msec_to_rfc3339(info.last_modified)),
 				size: s3_xml::IntValue(info.size as i64),
 				etag: s3_xml::Value(format!("\"{}\"", info.etag)),
-				storage_class: s3_xml::Value("STANDARD".to_string()),
+				storage_class: s3_xml::Value(info.storage_class.clone()),
 			})
 			.collect(),
 		common_prefixes: acc
@@ -223,6 +231,7 @@ pub async fn handle_list_multipart_upload(
 			None => None,
 			Some(RangeBegin::AfterKey { key })
 			| Some(RangeBegin::AfterUpload { key, .. })
+			| Some(RangeBegin::AfterVersion { key, .. })
 			| Some(RangeBegin::IncludingKey { key, .. }) => {
 				Some(uriencode_maybe(key, query.common.urlencode_resp))
 			}
@@ -243,7 +252,7 @@ pub async fn handle_list_multipart_upload(
 				initiated: s3_xml::Value(msec_to_rfc3339(info.timestamp)),
 				key: uriencode_maybe(&info.key, query.common.urlencode_resp),
 				upload_id: s3_xml::Value(hex::encode(uuid)),
-				storage_class: s3_xml::Value("STANDARD".to_string()),
+				storage_class: s3_xml::Value(info.storage_class.clone()),
 				initiator: s3_xml::Initiator {
 					display_name: s3_xml::Value(DUMMY_NAME.to_string()),
 					id: s3_xml::Value(DUMMY_KEY.to_string()),
@@ -270,6 +279,111 @@ pub async fn handle_list_multipart_upload(
 		.body(string_body(xml))?)
 }
 
+pub async fn handle_list_object_versions(
+	ctx: ReqCtx,
+	query: &ListObjectVersionsQuery,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { garage, .. } = &ctx;
+
+	let io = |bucket, key, count| {
+		let t = &garage.object_table;
+		async move {
+			t.get_range(&bucket, key, None, count, EnumerationOrder::Forward)
+				.await
+		}
+	};
+
+	debug!("ListObjectVersions {:?}", query);
+	let mut acc = query.build_accumulator();
+	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+
+	let mut versions = vec![];
+	let mut delete_markers = vec![];
+	for ((key, _), info) in acc.keys.iter() {
+		let key = uriencode_maybe(key, query.common.urlencode_resp);
+		let version_id = s3_xml::Value(info.version_id.to_string());
+		let is_latest = s3_xml::Value(format!("{}", info.is_latest));
+		let last_modified = s3_xml::Value(msec_to_rfc3339(info.last_modified));
+		match &info.data {
+			Some((size, etag)) => versions.push(s3_xml::ListVersionsItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				etag: s3_xml::Value(format!("\"{}\"", etag)),
+				size: s3_xml::IntValue(*size as i64),
+				storage_class: s3_xml::Value(info.storage_class.clone()),
+			}),
+			None => delete_markers.push(s3_xml::ListDeleteMarkerItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+			}),
+		}
+	}
+
+	let result = s3_xml::ListVersionsResult {
+		xmlns: (),
+
+		// Sending back some information about the request
+		name: s3_xml::Value(query.common.bucket_name.to_string()),
+		prefix: uriencode_maybe(&query.common.prefix, query.common.urlencode_resp),
+		delimiter: query
+			.common
+			.delimiter
+			.as_ref()
+			.map(|d| uriencode_maybe(d, query.common.urlencode_resp)),
+		max_keys: s3_xml::IntValue(query.common.page_size as i64),
+		key_marker: query
+			.key_marker
+			.as_ref()
+			.map(|m| uriencode_maybe(m, query.common.urlencode_resp)),
+		version_id_marker: query
+			.version_id_marker
+			.as_ref()
+			.map(|m| s3_xml::Value(m.to_string())),
+		encoding_type: match query.common.urlencode_resp {
+			true => Some(s3_xml::Value("url".to_string())),
+			false => None,
+		},
+
+		// Handling pagination
+		is_truncated: s3_xml::Value(format!("{}", pagination.is_some())),
+		next_key_marker: match &pagination {
+			None => None,
+			Some(RangeBegin::AfterKey { key })
+			| Some(RangeBegin::AfterUpload { key, .. })
+			| Some(RangeBegin::AfterVersion { key, .. })
+			| Some(RangeBegin::IncludingKey { key, .. }) => {
+				Some(uriencode_maybe(key, query.common.urlencode_resp))
+			}
+		},
+		next_version_id_marker: match pagination {
+			Some(RangeBegin::AfterVersion { version, .. }) => Some(s3_xml::Value(version)),
+			Some(RangeBegin::IncludingKey { .. }) => Some(s3_xml::Value("include".to_string())),
+			_ => None,
+		},
+
+		// Result body
+		versions,
+		delete_markers,
+		common_prefixes: acc
+			.common_prefixes
+			.iter()
+			.map(|c| s3_xml::CommonPrefix {
+				prefix: uriencode_maybe(c, query.common.urlencode_resp),
+			})
+			.collect(),
+	};
+
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(string_body(xml))?)
+}
+
 pub async fn handle_list_parts(
 	ctx: ReqCtx,
 	req: Request<ReqBody>,
@@ -280,13 +394,14 @@ pub async fn handle_list_parts(
 	let upload_id = s3_multipart::decode_upload_id(&query.upload_id)?;
 
 	let (_, object_version, mpu) = s3_multipart::get_upload(&ctx, &query.key, &upload_id).await?;
+	let storage_class = object_version.storage_class_name().to_string();
 
 	let object_encryption = match object_version.state {
 		ObjectVersionState::Uploading { encryption, .. } => encryption,
 		_ => unreachable!(),
 	};
 	let encryption_res =
-		EncryptionParams::check_decrypt(&ctx.garage, req.headers(), &object_encryption);
+		EncryptionParams::check_decrypt(&ctx.garage, req.headers(), &object_encryption).await;
 
 	let (info, next) = fetch_part_info(query, &mpu)?;
 
@@ -338,6 +453,12 @@ pub async fn handle_list_parts(
 						}
 						_ => None,
 					},
+					checksum_crc64nvme: match &checksum {
+						Some(ChecksumValue::Crc64Nvme(x)) => {
+							Some(s3_xml::Value(BASE64_STANDARD.encode(x)))
+						}
+						_ => None,
+					},
 				}
 			})
 			.collect(),
@@ -351,7 +472,7 @@ pub async fn handle_list_parts(
 			display_name: s3_xml::Value(DUMMY_NAME.to_string()),
 			id: s3_xml::Value(DUMMY_KEY.to_string()),
 		},
-		storage_class: s3_xml::Value("STANDARD".to_string()),
+		storage_class: s3_xml::Value(storage_class),
 	};
 
 	let xml = s3_xml::to_xml_with_header(&result)?;
@@ -370,12 +491,24 @@ struct ObjectInfo {
 	last_modified: u64,
 	size: u64,
 	etag: String,
+	storage_class: String,
 }
 
 #[derive(Debug, PartialEq)]
 struct UploadInfo {
 	key: String,
 	timestamp: u64,
+	storage_class: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct VersionInfo {
+	version_id: String,
+	is_latest: bool,
+	last_modified: u64,
+	/// Size and etag of the version, None for delete markers
+	data: Option<(u64, String)>,
+	storage_class: String,
 }
 
 #[derive(Debug, PartialEq)]
@@ -394,6 +527,10 @@ enum ExtractionResult {
 		key: String,
 		upload: Uuid,
 	},
+	FilledAtVersion {
+		key: String,
+		version: String,
+	},
 	Extracted {
 		key: String,
 	},
@@ -420,6 +557,10 @@ enum RangeBegin {
 		key: String,
 		upload: Uuid,
 	},
+	AfterVersion {
+		key: String,
+		version: String,
+	},
 }
 type Pagination = Option<RangeBegin>;
 
@@ -446,6 +587,7 @@ where
 		let start_key = match cursor {
 			RangeBegin::AfterKey { ref key }
 			| RangeBegin::AfterUpload { ref key, .. }
+			| RangeBegin::AfterVersion { ref key, .. }
 			| RangeBegin::IncludingKey { ref key, .. } => Some(key.clone()),
 		};
 
@@ -488,6 +630,9 @@ where
 				ExtractionResult::FilledAtUpload { key, upload } => {
 					return Ok(Some(RangeBegin::AfterUpload { key, upload }));
 				}
+				ExtractionResult::FilledAtVersion { key, version } => {
+					return Ok(Some(RangeBegin::AfterVersion { key, version }));
+				}
 				ExtractionResult::Filled => {
 					return Ok(Some(cursor));
 				}
@@ -667,6 +812,36 @@ impl ListMultipartUploadsQuery {
 	}
 }
 
+impl ListObjectVersionsQuery {
+	fn build_accumulator(&self) -> VersionAccumulator {
+		VersionAccumulator::new(self.common.page_size)
+	}
+
+	fn begin(&self) -> Result<RangeBegin, Error> {
+		match (&self.version_id_marker, &self.key_marker) {
+			// As for ListMultipartUploads, we use the special version id marker "include"
+			// to emulate "IncludingKey" when we paginated on a common prefix.
+			(Some(version_marker), Some(key_marker)) => match &version_marker[..] {
+				"include" => Ok(RangeBegin::IncludingKey {
+					key: key_marker.to_string(),
+					fallback_key: None,
+				}),
+				version => Ok(RangeBegin::AfterVersion {
+					key: key_marker.to_string(),
+					version: version.to_string(),
+				}),
+			},
+			(None, Some(key_marker)) => Ok(RangeBegin::AfterKey {
+				key: key_marker.to_string(),
+			}),
+			_ => Ok(RangeBegin::IncludingKey {
+				key: self.common.prefix.to_string(),
+				fallback_key: None,
+			}),
+		}
+	}
+}
+
 /*
  * Accumulator logic
  */
@@ -688,6 +863,8 @@ struct Accumulator<K, V> {
 
 type ObjectAccumulator = Accumulator<String, ObjectInfo>;
 type UploadAccumulator = Accumulator<Uuid, UploadInfo>;
+// Versions are sorted by key, then from the most recent to the oldest
+type VersionAccumulator = Accumulator<(String, usize), VersionInfo>;
 
 impl<K: std::cmp::Ord, V> Accumulator<K, V> {
 	fn new(page_size: usize) -> Accumulator<K, V> {
@@ -795,7 +972,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
 		assert!(object.key.starts_with(&query.prefix));
 
-		let version = match object.versions().iter().find(|x| x.is_data()) {
+		let version = match object.current_version().filter(|x| x.is_data()) {
 			Some(v) => v,
 			None => unreachable!(
 				"Expect to have objects having data due to earlier filtering. This is a logic bug."
@@ -811,6 +988,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 			last_modified: version.timestamp,
 			size: meta.size,
 			etag: meta.etag.to_string(),
+			storage_class: version.storage_class_name().to_string(),
 		};
 
 		match self.try_insert_entry(object.key.clone(), info) {
@@ -882,6 +1060,7 @@ impl ExtractAccumulator for UploadAccumulator {
 		let first_up_info = UploadInfo {
 			key: object.key.to_string(),
 			timestamp: first_upload.timestamp,
+			storage_class: first_upload.storage_class_name().to_string(),
 		};
 		if !self.try_insert_entry(first_upload.uuid, first_up_info) {
 			return ExtractionResult::Filled;
@@ -893,6 +1072,7 @@ impl ExtractAccumulator for UploadAccumulator {
 			let up_info = UploadInfo {
 				key: object.key.to_string(),
 				timestamp: upload.timestamp,
+				storage_class: upload.storage_class_name().to_string(),
 			};
 
 			// Insert data in our accumulator
@@ -914,6 +1094,88 @@ impl ExtractAccumulator for UploadAccumulator {
 	}
 }
 
+impl ExtractAccumulator for VersionAccumulator {
+	/// Observe the iterator, process a single key, and try to extract one or more versions
+	fn extract<'a>(
+		&mut self,
+		query: &ListQueryCommon,
+		cursor: &RangeBegin,
+		objects: &mut Peekable<impl Iterator<Item = &'a Object>>,
+	) -> ExtractionResult {
+		if let Some(e) = self.extract_common_prefix(objects, query) {
+			return e;
+		}
+
+		// Get the next object from the iterator
+		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
+
+		// Objects whose only version is a non-versioned delete marker
+		// are just waiting to be garbage collected
+		if object.is_tombstone() {
+			return ExtractionResult::Extracted {
+				key: object.key.clone(),
+			};
+		}
+
+		// Versions of the object, from the most recent to the oldest
+		let mut versions = object
+			.versions()
+			.iter()
+			.rev()
+			.filter(|v| v.is_complete())
+			.enumerate()
+			.collect::<Vec<_>>();
+
+		// Skip results if a version marker is provided
+		if let RangeBegin::AfterVersion { key, version } = cursor {
+			if *key == object.key {
+				if let Some(i) = versions
+					.iter()
+					.position(|(_, v)| version_id(v) == *version || hex::encode(v.uuid) == *version)
+				{
+					versions = versions.split_off(i + 1);
+				}
+			}
+		}
+
+		let mut prev_version = None;
+		for (i, v) in versions {
+			let info = VersionInfo {
+				version_id: version_id(v),
+				is_latest: i == 0,
+				last_modified: v.timestamp,
+				data: match &v.state {
+					ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+					| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+						Some((meta.size, meta.etag.to_string()))
+					}
+					_ => None,
+				},
+				storage_class: v.storage_class_name().to_string(),
+			};
+			let version = info.version_id.clone();
+
+			// Insert data in our accumulator
+			// If it is full, return information to paginate.
+			if !self.try_insert_entry((object.key.clone(), i), info) {
+				return match prev_version {
+					None => ExtractionResult::Filled,
+					Some(version) => ExtractionResult::FilledAtVersion {
+						key: object.key.clone(),
+						version,
+					},
+				};
+			}
+			prev_version = Some(version);
+		}
+
+		// We successfully collected all the versions
+		ExtractionResult::Extracted {
+			key: object.key.clone(),
+		}
+	}
+}
+
 /*
  * Utility functions
  */
@@ -991,7 +1253,41 @@ mod tests {
 					},
 				},
 				checksum_algorithm: None,
+				checksum_type: None,
+				commit_marker: false,
 			},
+			versioned: false,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
+		}
+	}
+
+	fn objdata_version(uuid: [u8; 32], timestamp: u64, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: Uuid::from(uuid),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					size: 3,
+					etag: "etag".into(),
+					encryption: ObjectVersionEncryption::Plaintext {
+						inner: ObjectVersionMetaInner {
+							headers: vec![],
+							checksum: None,
+						},
+					},
+				},
+				vec![],
+			)),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
 		}
 	}
 
@@ -1064,14 +1360,16 @@ mod tests {
 			acc.keys.get(&Uuid::from([0x80; 32])).unwrap(),
 			&UploadInfo {
 				timestamp: TS,
-				key: "b".to_string()
+				key: "b".to_string(),
+				storage_class: "STANDARD".to_string(),
 			}
 		);
 		assert_eq!(
 			acc.keys.get(&Uuid::from([0x8f; 32])).unwrap(),
 			&UploadInfo {
 				timestamp: TS,
-				key: "b".to_string()
+				key: "b".to_string(),
+				storage_class: "STANDARD".to_string(),
 			}
 		);
 
@@ -1089,6 +1387,66 @@ mod tests {
 		};
 	}
 
+	#[test]
+	fn test_extract_versions() {
+		let objs = vec![
+			Object::new(
+				bucket(),
+				"b".to_string(),
+				vec![
+					objdata_version([0x01; 32], TS, false),
+					objdata_version([0x02; 32], TS + 1, true),
+					objdata_version([0x03; 32], TS + 2, true),
+				],
+			),
+			Object::new(bucket(), "c".to_string(), vec![]),
+		];
+
+		let mut acc = VersionAccumulator::new(2);
+		let start = RangeBegin::IncludingKey {
+			key: "b".to_string(),
+			fallback_key: None,
+		};
+		let mut iter = objs.iter().peekable();
+
+		// Versions are listed from the most recent to the oldest
+		match acc.extract(&(query().common), &start, &mut iter) {
+			ExtractionResult::FilledAtVersion { key, version } => {
+				assert_eq!(key, "b");
+				assert_eq!(version, hex::encode([0x02; 32]));
+			}
+			_ => panic!("wrong result"),
+		};
+		assert_eq!(acc.keys.len(), 2);
+		let latest = acc.keys.get(&("b".to_string(), 0)).unwrap();
+		assert_eq!(latest.version_id, hex::encode([0x03; 32]));
+		assert!(latest.is_latest);
+		assert!(!acc.keys.get(&("b".to_string(), 1)).unwrap().is_latest);
+
+		// Continue after the version marker
+		let mut acc = VersionAccumulator::new(2);
+		let start = RangeBegin::AfterVersion {
+			key: "b".to_string(),
+			version: hex::encode([0x02; 32]),
+		};
+		let mut iter = objs.iter().peekable();
+		match acc.extract(&(query().common), &start, &mut iter) {
+			ExtractionResult::Extracted { key } if key.as_str() == "b" => (),
+			_ => panic!("wrong result"),
+		};
+		assert_eq!(acc.keys.len(), 1);
+		assert_eq!(
+			acc.keys.get(&("b".to_string(), 2)).unwrap(),
+			&VersionInfo {
+				version_id: "null".to_string(),
+				is_latest: false,
+				last_modified: TS,
+				data: Some((3, "etag".to_string())),
+				storage_class: "STANDARD".to_string(),
+			}
+		);
+	}
+
 	#[tokio::test]
 	async fn test_fetch_uploads_no_result() -> Result<(), Error> {
 		let query = query();
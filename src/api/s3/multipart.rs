@@ -15,20 +15,29 @@ use sha2::Sha256;
 use garage_table::*;
 use garage_util::data::*;
 use garage_util::error::OkOrMessage;
+use garage_util::time::*;
 
 use garage_model::garage::Garage;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::mpu_table::*;
+use garage_model::s3::notification::ObjectEventName;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use garage_api_common::helpers::*;
 use garage_api_common::signature::checksum::*;
 
+use crate::acl::request_object_public_read;
 use crate::api_server::{ReqBody, ResBody};
 use crate::encryption::EncryptionParams;
 use crate::error::*;
+use crate::notification::notify_object_event;
+use crate::object_lock::parse_object_lock_headers;
 use crate::put::*;
+use crate::replication::{queue_replication, request_replication_status};
+use crate::storage_class::*;
+use crate::tagging::parse_tagging_header;
+use crate::versioning::{version_id, X_AMZ_VERSION_ID};
 use crate::xml as s3_xml;
 
 // ----
@@ -42,6 +51,7 @@ pub async fn handle_create_multipart_upload(
 		garage,
 		bucket_id,
 		bucket_name,
+		bucket_params,
 		..
 	} = &ctx;
 	let existing_object = garage.object_table.get(&bucket_id, &key).await?;
@@ -56,10 +66,22 @@ pub async fn handle_create_multipart_upload(
 	};
 
 	// Determine whether object should be encrypted, and if so the key
-	let encryption = EncryptionParams::new_from_headers(&garage, req.headers())?;
+	let encryption = EncryptionParams::new_from_headers(
+		garage,
+		req.headers(),
+		bucket_params.default_encryption.get().as_ref(),
+	)
+	.await?;
 	let object_encryption = encryption.encrypt_meta(meta)?;
 
 	let checksum_algorithm = request_checksum_algorithm(req.headers())?;
+	let checksum_type =
+		multipart_checksum_type(checksum_algorithm, request_checksum_type(req.headers())?)?;
+	let tags = parse_tagging_header(req.headers())?.unwrap_or_default();
+	let lock = parse_object_lock_headers(req.headers(), bucket_params, now_msec())?;
+	let replication = request_replication_status(&ctx, req.headers(), key, &tags, false)?;
+	let public_read = request_object_public_read(&ctx, req.headers())?;
+	let storage_class = request_storage_class(garage, req.headers())?;
 
 	// Create object in object table
 	let object_version = ObjectVersion {
@@ -69,7 +91,15 @@ pub async fn handle_create_multipart_upload(
 			multipart: true,
 			encryption: object_encryption,
 			checksum_algorithm,
+			checksum_type,
+			commit_marker: false,
 		},
+		versioned: bucket_params.versioning_enabled(),
+		tags: crdt::Lww::new(tags),
+		lock,
+		replication: crdt::Lww::new(replication),
+		public_read: crdt::Lww::new(public_read),
+		storage_class: crdt::Lww::new(storage_class.clone()),
 	};
 	let object = Object::new(*bucket_id, key.to_string(), vec![object_version]);
 	garage.object_table.insert(&object).await?;
@@ -91,6 +121,14 @@ pub async fn handle_create_multipart_upload(
 
 	let mut resp = Response::builder();
 	encryption.add_response_headers(&mut resp);
+	if let (Some(algo), Some(checksum_type)) = (checksum_algorithm, checksum_type) {
+		resp = resp
+			.header(X_AMZ_CHECKSUM_ALGORITHM, checksum_algorithm_name(algo))
+			.header(X_AMZ_CHECKSUM_TYPE, checksum_type_name(checksum_type));
+	}
+	if let Some(class) = storage_class {
+		resp = resp.header(X_AMZ_STORAGE_CLASS, class);
+	}
 	Ok(resp.body(string_body(xml))?)
 }
 
@@ -121,8 +159,11 @@ pub async fn handle_put_part(
 	// Before we stream the body, configure the needed checksums.
 	req_body.add_expected_checksums(expected_checksums.clone());
 	// TODO: avoid parsing encryption headers twice...
-	if !EncryptionParams::new_from_headers(&garage, &req_head.headers)?.is_encrypted() {
-		// For non-encrypted objects, we need to compute the md5sum in all cases
+	if EncryptionParams::new_from_headers(garage, &req_head.headers, None)
+		.await?
+		.has_md5_etag()
+	{
+		// For non-encrypted and SSE-S3 objects, we need to compute the md5sum in all cases
 		// (even if content-md5 is not set), because it is used as an etag of the
 		// part, which is in turn used in the etag computation of the whole object
 		req_body.add_md5();
@@ -137,6 +178,8 @@ pub async fn handle_put_part(
 	let ((_, object_version, mut mpu), first_block) =
 		futures::try_join!(get_upload(&ctx, &key, &upload_id), chunker.next(),)?;
 
+	let storage_class = object_version.storage_class.get().clone();
+
 	// Check encryption params
 	let (object_encryption, checksum_algorithm) = match object_version.state {
 		ObjectVersionState::Uploading {
@@ -147,7 +190,7 @@ pub async fn handle_put_part(
 		_ => unreachable!(),
 	};
 	let (encryption, _) =
-		EncryptionParams::check_decrypt(&garage, &req_head.headers, &object_encryption)?;
+		EncryptionParams::check_decrypt(garage, &req_head.headers, &object_encryption).await?;
 
 	// Check object is valid and part can be accepted
 	let first_block = first_block.ok_or_bad_request("Empty body")?;
@@ -193,7 +236,8 @@ pub async fn handle_put_part(
 	let (total_size, _, _) = read_and_put_blocks(
 		&ctx,
 		&version,
-		encryption,
+		encryption.clone(),
+		storage_class,
 		part_number,
 		first_block,
 		chunker,
@@ -276,6 +320,8 @@ pub async fn handle_complete_multipart_upload(
 	let (req_head, req_body) = req.into_parts();
 
 	let expected_checksum = request_checksum_value(&req_head.headers)?;
+	let expected_checksum_type = request_checksum_type(&req_head.headers)?;
+	let preconditions = WritePreconditions::parse(&req_head.headers)?;
 
 	let body = req_body.collect().await?;
 
@@ -297,14 +343,30 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::bad_request("No data was uploaded"));
 	}
 
-	let (object_encryption, checksum_algorithm) = match object_version.state {
+	// Fail early if the preconditions do not hold; they are checked again
+	// when the final version is committed
+	preconditions.check(Some(&object))?;
+
+	let storage_class = object_version.storage_class.clone();
+	let (object_encryption, checksum_algorithm, checksum_type) = match object_version.state {
 		ObjectVersionState::Uploading {
 			encryption,
 			checksum_algorithm,
+			checksum_type,
 			..
-		} => (encryption, checksum_algorithm),
+		} => (encryption, checksum_algorithm, checksum_type),
 		_ => unreachable!(),
 	};
+	// Uploads created before checksum types were introduced
+	// only supported composite checksums
+	let checksum_type =
+		checksum_algorithm.map(|_| checksum_type.unwrap_or(ChecksumType::Composite));
+
+	if expected_checksum_type.is_some() && expected_checksum_type != checksum_type {
+		return Err(Error::bad_request(
+			"x-amz-checksum-type does not match the checksum type of the multipart upload",
+		));
+	}
 
 	// Check that part numbers are an increasing sequence.
 	// (it doesn't need to start at 1 nor to be a continuous sequence,
@@ -374,6 +436,11 @@ pub async fn handle_complete_multipart_upload(
 				*vb,
 			);
 		}
+		if let Some(checksum) = parts[part_number].checksum {
+			final_version
+				.parts_checksums
+				.put((part_number + 1) as u64, checksum);
+		}
 	}
 	garage.version_table.insert(&final_version).await?;
 
@@ -381,6 +448,7 @@ pub async fn handle_complete_multipart_upload(
 		block: b.hash,
 		version: upload_id,
 		deleted: false.into(),
+		storage_class: storage_class.clone(),
 	});
 	garage.block_ref_table.insert_many(block_refs).await?;
 
@@ -388,9 +456,13 @@ pub async fn handle_complete_multipart_upload(
 	// To understand how etags are calculated, read more here:
 	// https://docs.aws.amazon.com/AmazonS3/latest/userguide/checking-object-integrity.html
 	// https://teppen.io/2018/06/23/aws_s3_etags/
-	let mut checksummer = MultipartChecksummer::init(checksum_algorithm);
+	let mut checksummer = MultipartChecksummer::init(checksum_algorithm, checksum_type);
 	for part in parts.iter() {
-		checksummer.update(part.etag.as_ref().unwrap(), part.checksum)?;
+		checksummer.update(
+			part.etag.as_ref().unwrap(),
+			part.checksum,
+			part.size.unwrap(),
+		)?;
 	}
 	let (checksum_md5, checksum_extra) = checksummer.finalize();
 
@@ -418,7 +490,8 @@ pub async fn handle_complete_multipart_upload(
 		None => object_encryption,
 		Some(_) => {
 			let (encryption, meta) =
-				EncryptionParams::check_decrypt(&garage, &req_head.headers, &object_encryption)?;
+				EncryptionParams::check_decrypt(garage, &req_head.headers, &object_encryption)
+					.await?;
 			let new_meta = ObjectVersionMetaInner {
 				headers: meta.into_owned().headers,
 				checksum: checksum_extra,
@@ -437,8 +510,21 @@ pub async fn handle_complete_multipart_upload(
 		final_version.blocks.items()[0].1.hash,
 	));
 
-	let final_object = Object::new(*bucket_id, key.clone(), vec![object_version]);
-	garage.object_table.insert(&final_object).await?;
+	let final_version_id = version_id(&object_version);
+	let final_timestamp = object_version.timestamp;
+	let final_uuid = object_version.uuid;
+	let final_replication = *object_version.replication.get();
+	commit_object_version(&ctx, &key, object_version, &preconditions).await?;
+
+	notify_object_event(
+		&ctx,
+		ObjectEventName::CompleteMultipartUpload,
+		&key,
+		final_version_id.clone(),
+		final_timestamp,
+		Some((total_size, &etag)),
+	);
+	queue_replication(&ctx, &key, final_uuid, final_replication);
 
 	// Send response saying ok we're done
 	let result = s3_xml::CompleteMultipartUploadResult {
@@ -472,11 +558,19 @@ pub async fn handle_complete_multipart_upload(
 			Some(ChecksumValue::Sha256(x)) => Some(s3_xml::Value(BASE64_STANDARD.encode(&x))),
 			_ => None,
 		},
+		checksum_crc64nvme: match &checksum_extra {
+			Some(ChecksumValue::Crc64Nvme(x)) => Some(s3_xml::Value(BASE64_STANDARD.encode(x))),
+			_ => None,
+		},
+		checksum_type: checksum_type.map(|t| s3_xml::Value(checksum_type_name(t).to_string())),
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	let resp = Response::builder();
-	let resp = add_checksum_response_headers(&expected_checksum, resp);
+	let mut resp = Response::builder().header(X_AMZ_VERSION_ID, final_version_id);
+	resp = add_checksum_response_headers(&expected_checksum, resp);
+	if let Some(checksum_type) = checksum_type {
+		resp = resp.header(X_AMZ_CHECKSUM_TYPE, checksum_type_name(checksum_type));
+	}
 	Ok(resp.body(string_body(xml))?)
 }
 
@@ -600,6 +694,15 @@ fn parse_complete_multipart_upload_body(
 						.try_into()
 						.ok()?,
 				))
+			} else if let Some(crc64nvme) = item
+				.children()
+				.find(|e| e.has_tag_name("ChecksumCRC64NVME"))
+			{
+				Some(ChecksumValue::Crc64Nvme(
+					BASE64_STANDARD.decode(crc64nvme.text()?).ok()?[..]
+						.try_into()
+						.ok()?,
+				))
 			} else {
 				None
 			};
@@ -629,14 +732,23 @@ pub(crate) enum MultipartExtraChecksummer {
 	Crc32c(Crc32c),
 	Sha1(Sha1),
 	Sha256(Sha256),
+	/// Full object checksum of the parts seen so far, combined
+	/// from the checksums of the parts (CRC algorithms only)
+	FullObject(ChecksumAlgorithm, Option<ChecksumValue>),
 }
 
 impl MultipartChecksummer {
-	pub(crate) fn init(algo: Option<ChecksumAlgorithm>) -> Self {
+	pub(crate) fn init(
+		algo: Option<ChecksumAlgorithm>,
+		checksum_type: Option<ChecksumType>,
+	) -> Self {
 		Self {
 			md5: Md5::new(),
 			extra: match algo {
 				None => None,
+				Some(algo) if checksum_type == Some(ChecksumType::FullObject) => {
+					Some(MultipartExtraChecksummer::FullObject(algo, None))
+				}
 				Some(ChecksumAlgorithm::Crc32) => {
 					Some(MultipartExtraChecksummer::Crc32(Crc32::new()))
 				}
@@ -647,6 +759,10 @@ impl MultipartChecksummer {
 				Some(ChecksumAlgorithm::Sha256) => {
 					Some(MultipartExtraChecksummer::Sha256(Sha256::new()))
 				}
+				Some(ChecksumAlgorithm::Crc64Nvme) => Some(MultipartExtraChecksummer::FullObject(
+					ChecksumAlgorithm::Crc64Nvme,
+					None,
+				)),
 			},
 		}
 	}
@@ -655,6 +771,7 @@ impl MultipartChecksummer {
 		&mut self,
 		etag: &str,
 		checksum: Option<ChecksumValue>,
+		size: u64,
 	) -> Result<(), Error> {
 		self.md5
 			.update(&hex::decode(&etag).ok_or_message("invalid etag hex")?);
@@ -681,6 +798,17 @@ impl MultipartChecksummer {
 			) => {
 				sha256.update(&x);
 			}
+			(Some(MultipartExtraChecksummer::FullObject(algo, ref mut acc)), Some(x))
+				if x.algorithm() == *algo =>
+			{
+				*acc = match acc {
+					None => Some(x),
+					Some(a) => Some(
+						crc_combine(*a, x, size)
+							.ok_or_internal_error("full object checksum of non-CRC algorithm")?,
+					),
+				};
+			}
 			(Some(_), b) => {
 				return Err(Error::internal_error(format!(
 					"part checksum was not computed correctly, got: {:?}",
@@ -707,6 +835,7 @@ impl MultipartChecksummer {
 			Some(MultipartExtraChecksummer::Sha256(sha256)) => Some(ChecksumValue::Sha256(
 				sha256.finalize()[..].try_into().unwrap(),
 			)),
+			Some(MultipartExtraChecksummer::FullObject(_, acc)) => acc,
 		};
 		(md5, extra)
 	}
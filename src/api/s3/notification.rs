@@ -0,0 +1,436 @@
+use quick_xml::de::from_reader;
+
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{
+	Bucket, NotificationRule, NotificationRuleKind, NotificationTarget,
+};
+use garage_model::s3::notification::{ObjectEvent, ObjectEventName, SUPPORTED_EVENT_NAMES};
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::bucket::resolve_bucket;
+use crate::error::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+const WEBHOOK_ARN_PREFIX: &str = "arn:garage:webhook::";
+const K2V_ARN_PREFIX: &str = "arn:garage:k2v::";
+
+pub async fn handle_get_bucket_notification(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+	let rules: &[NotificationRule] = bucket_params
+		.notification_config
+		.get()
+		.as_deref()
+		.unwrap_or_default();
+
+	let conf = NotificationConfiguration::from_garage_notification_config(rules);
+	let xml = to_xml_with_header(&conf)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_notification(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let body = req.into_body().collect().await?;
+
+	let conf: NotificationConfiguration = from_reader(&body as &[u8])?;
+	let rules = conf.validate_into_garage_notification_config(&ctx).await?;
+
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+	bucket_params
+		.notification_config
+		.update((!rules.is_empty()).then_some(rules));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+/// Queue notifications for an event caused by a request, if the bucket
+/// has matching notification rules. Failing to do so does not fail the request.
+pub(crate) fn notify_object_event(
+	ctx: &ReqCtx,
+	name: ObjectEventName,
+	key: &str,
+	version_id: String,
+	timestamp: u64,
+	size_etag: Option<(u64, &str)>,
+) {
+	let rules = match ctx.bucket_params.notification_config.get() {
+		Some(rules) => rules,
+		None => return,
+	};
+
+	let event = ObjectEvent {
+		name,
+		bucket_name: ctx.bucket_name.clone(),
+		key: key.to_string(),
+		version_id,
+		size: size_etag.map(|(size, _)| size),
+		etag: size_etag.map(|(_, etag)| etag.to_string()),
+		timestamp,
+		principal: ctx.api_key.as_ref().map(|k| k.key_id.clone()),
+	};
+	if let Err(e) = ctx.garage.notifications.emit(rules, &event) {
+		error!("Could not queue event notification: {}", e);
+	}
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "NotificationConfiguration")]
+pub struct NotificationConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "TopicConfiguration", default)]
+	pub topic_configurations: Vec<DestinationConfiguration>,
+	#[serde(rename = "QueueConfiguration", default)]
+	pub queue_configurations: Vec<DestinationConfiguration>,
+	#[serde(
+		rename = "CloudFunctionConfiguration",
+		default,
+		skip_serializing_if = "Vec::is_empty"
+	)]
+	pub cloud_function_configurations: Vec<DestinationConfiguration>,
+	#[serde(
+		rename = "EventBridgeConfiguration",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub event_bridge_configuration: Option<EventBridgeConfiguration>,
+}
+
+/// Rule of a TopicConfiguration or QueueConfiguration element,
+/// the destination ARN being in a Topic or Queue element respectively
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DestinationConfiguration {
+	#[serde(rename = "Id")]
+	pub id: Option<Value>,
+	#[serde(rename = "Topic", skip_serializing_if = "Option::is_none")]
+	pub topic: Option<Value>,
+	#[serde(rename = "Queue", skip_serializing_if = "Option::is_none")]
+	pub queue: Option<Value>,
+	#[serde(rename = "Event", default)]
+	pub events: Vec<Value>,
+	#[serde(rename = "Filter")]
+	pub filter: Option<Filter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EventBridgeConfiguration {}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Filter {
+	#[serde(rename = "S3Key")]
+	pub s3_key: S3KeyFilter,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct S3KeyFilter {
+	#[serde(rename = "FilterRule", default)]
+	pub filter_rules: Vec<FilterRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FilterRule {
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl NotificationConfiguration {
+	async fn validate_into_garage_notification_config(
+		self,
+		ctx: &ReqCtx,
+	) -> Result<Vec<NotificationRule>, Error> {
+		if !self.cloud_function_configurations.is_empty()
+			|| self.event_bridge_configuration.is_some()
+		{
+			return Err(Error::NotImplemented(
+				"Only TopicConfiguration and QueueConfiguration are supported".into(),
+			));
+		}
+
+		let mut rules = vec![];
+		for (kind, conf) in self
+			.topic_configurations
+			.into_iter()
+			.map(|c| (NotificationRuleKind::Topic, c))
+			.chain(
+				self.queue_configurations
+					.into_iter()
+					.map(|c| (NotificationRuleKind::Queue, c)),
+			) {
+			rules.push(
+				conf.validate_into_garage_notification_rule(ctx, kind)
+					.await?,
+			);
+		}
+		Ok(rules)
+	}
+
+	fn from_garage_notification_config(rules: &[NotificationRule]) -> Self {
+		let mut conf = Self {
+			xmlns: (),
+			topic_configurations: vec![],
+			queue_configurations: vec![],
+			cloud_function_configurations: vec![],
+			event_bridge_configuration: None,
+		};
+		for rule in rules.iter() {
+			let arn = Some(Value(rule.destination_arn.clone()));
+			let mut filter_rules = vec![];
+			if let Some(prefix) = &rule.prefix {
+				filter_rules.push(FilterRule {
+					name: Value("prefix".into()),
+					value: Value(prefix.clone()),
+				});
+			}
+			if let Some(suffix) = &rule.suffix {
+				filter_rules.push(FilterRule {
+					name: Value("suffix".into()),
+					value: Value(suffix.clone()),
+				});
+			}
+			let dest = DestinationConfiguration {
+				id: rule.id.clone().map(Value),
+				topic: None,
+				queue: None,
+				events: rule.events.iter().cloned().map(Value).collect(),
+				filter: (!filter_rules.is_empty()).then_some(Filter {
+					s3_key: S3KeyFilter { filter_rules },
+				}),
+			};
+			match rule.kind {
+				NotificationRuleKind::Topic => conf
+					.topic_configurations
+					.push(DestinationConfiguration { topic: arn, ..dest }),
+				NotificationRuleKind::Queue => conf
+					.queue_configurations
+					.push(DestinationConfiguration { queue: arn, ..dest }),
+			}
+		}
+		conf
+	}
+}
+
+impl DestinationConfiguration {
+	async fn validate_into_garage_notification_rule(
+		self,
+		ctx: &ReqCtx,
+		kind: NotificationRuleKind,
+	) -> Result<NotificationRule, Error> {
+		let arn = match kind {
+			NotificationRuleKind::Topic => self.topic,
+			NotificationRuleKind::Queue => self.queue,
+		}
+		.ok_or_bad_request("Missing destination ARN in notification configuration")?
+		.0;
+		let target = parse_destination_arn(ctx, &arn).await?;
+
+		if self.events.is_empty() {
+			return Err(Error::bad_request(
+				"At least one event must be specified in notification configuration",
+			));
+		}
+		for event in self.events.iter() {
+			if !SUPPORTED_EVENT_NAMES.contains(&event.0.as_str()) {
+				return Err(Error::NotImplemented(format!(
+					"Unsupported event type: {}",
+					event.0
+				)));
+			}
+		}
+
+		let mut prefix = None;
+		let mut suffix = None;
+		for rule in self.filter.into_iter().flat_map(|f| f.s3_key.filter_rules) {
+			let slot = match rule.name.0.to_lowercase().as_str() {
+				"prefix" => &mut prefix,
+				"suffix" => &mut suffix,
+				_ => return Err(Error::bad_request("Invalid filter rule name")),
+			};
+			if slot.replace(rule.value.0).is_some() {
+				return Err(Error::bad_request(
+					"Filter rule names must not be repeated in a notification configuration",
+				));
+			}
+		}
+
+		Ok(NotificationRule {
+			id: self.id.map(|x| x.0),
+			kind,
+			destination_arn: arn,
+			target,
+			events: self.events.into_iter().map(|x| x.0).collect(),
+			prefix,
+			suffix,
+		})
+	}
+}
+
+/// Destinations are designated by ARNs of the form `arn:garage:webhook::<name>`
+/// for webhooks declared in the configuration file, and
+/// `arn:garage:k2v::<bucket>/<partition key>` for K2V partitions, where the
+/// requester must be allowed to write to the K2V bucket
+async fn parse_destination_arn(ctx: &ReqCtx, arn: &str) -> Result<NotificationTarget, Error> {
+	if let Some(name) = arn.strip_prefix(WEBHOOK_ARN_PREFIX) {
+		if !ctx.garage.config.notifications.webhooks.contains_key(name) {
+			return Err(Error::bad_request(format!(
+				"Unable to validate destination: webhook {} is not configured",
+				name
+			)));
+		}
+		Ok(NotificationTarget::Webhook {
+			name: name.to_string(),
+		})
+	} else if let Some(dest) = arn.strip_prefix(K2V_ARN_PREFIX) {
+		let (bucket_name, partition_key) = dest
+			.split_once('/')
+			.filter(|(b, p)| !b.is_empty() && !p.is_empty())
+			.ok_or_bad_request("Invalid K2V destination, expected <bucket>/<partition key>")?;
+		let bucket_id =
+			resolve_bucket(&ctx.garage, &bucket_name.to_string(), ctx.api_key.as_ref()).await?;
+		if !ctx
+			.api_key
+			.as_ref()
+			.is_some_and(|k| k.allow_write(&bucket_id))
+		{
+			return Err(Error::forbidden(
+				"Access key is not allowed to write to the destination K2V bucket",
+			));
+		}
+		Ok(NotificationTarget::K2V {
+			bucket_id,
+			partition_key: partition_key.to_string(),
+		})
+	} else {
+		Err(Error::bad_request(format!(
+			"Unable to validate destination: unsupported ARN {}",
+			arn
+		)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_notification_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<NotificationConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <QueueConfiguration>
+    <Id>uploads</Id>
+    <Queue>arn:garage:webhook::pipeline</Queue>
+    <Event>s3:ObjectCreated:*</Event>
+    <Filter>
+      <S3Key>
+        <FilterRule>
+          <Name>prefix</Name>
+          <Value>incoming/</Value>
+        </FilterRule>
+        <FilterRule>
+          <Name>suffix</Name>
+          <Value>.csv</Value>
+        </FilterRule>
+      </S3Key>
+    </Filter>
+  </QueueConfiguration>
+  <TopicConfiguration>
+    <Topic>arn:garage:k2v::events/deletions</Topic>
+    <Event>s3:ObjectRemoved:Delete</Event>
+    <Event>s3:ObjectRemoved:DeleteMarkerCreated</Event>
+  </TopicConfiguration>
+</NotificationConfiguration>"#;
+		let conf: NotificationConfiguration = from_str(message).unwrap();
+		let ref_value = NotificationConfiguration {
+			xmlns: (),
+			topic_configurations: vec![DestinationConfiguration {
+				id: None,
+				topic: Some(Value("arn:garage:k2v::events/deletions".into())),
+				queue: None,
+				events: vec![
+					Value("s3:ObjectRemoved:Delete".into()),
+					Value("s3:ObjectRemoved:DeleteMarkerCreated".into()),
+				],
+				filter: None,
+			}],
+			queue_configurations: vec![DestinationConfiguration {
+				id: Some(Value("uploads".into())),
+				topic: None,
+				queue: Some(Value("arn:garage:webhook::pipeline".into())),
+				events: vec![Value("s3:ObjectCreated:*".into())],
+				filter: Some(Filter {
+					s3_key: S3KeyFilter {
+						filter_rules: vec![
+							FilterRule {
+								name: Value("prefix".into()),
+								value: Value("incoming/".into()),
+							},
+							FilterRule {
+								name: Value("suffix".into()),
+								value: Value(".csv".into()),
+							},
+						],
+					},
+				}),
+			}],
+			cloud_function_configurations: vec![],
+			event_bridge_configuration: None,
+		};
+		assert_eq!(ref_value, conf);
+
+		let garage_rules = vec![NotificationRule {
+			id: Some("uploads".into()),
+			kind: NotificationRuleKind::Queue,
+			destination_arn: "arn:garage:webhook::pipeline".into(),
+			target: NotificationTarget::Webhook {
+				name: "pipeline".into(),
+			},
+			events: vec!["s3:ObjectCreated:*".into()],
+			prefix: Some("incoming/".into()),
+			suffix: Some(".csv".into()),
+		}];
+		let serialized = to_xml_with_header(
+			&NotificationConfiguration::from_garage_notification_config(&garage_rules),
+		)?;
+		let conf2: NotificationConfiguration = from_str(&serialized).unwrap();
+		assert_eq!(conf2.queue_configurations, ref_value.queue_configurations);
+		assert!(conf2.topic_configurations.is_empty());
+
+		// Empty configuration, which disables notifications
+		let empty: NotificationConfiguration =
+			from_str(r#"<NotificationConfiguration></NotificationConfiguration>"#).unwrap();
+		assert!(empty.topic_configurations.is_empty() && empty.queue_configurations.is_empty());
+
+		let event_bridge: NotificationConfiguration = from_str(
+			r#"<NotificationConfiguration><EventBridgeConfiguration/></NotificationConfiguration>"#,
+		)
+		.unwrap();
+		assert!(event_bridge.event_bridge_configuration.is_some());
+
+		Ok(())
+	}
+}
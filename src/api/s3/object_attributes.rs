@@ -0,0 +1,409 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64::prelude::*;
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::{Request, Response, StatusCode};
+
+use serde::Serialize;
+
+use garage_table::EmptyKey;
+
+use garage_model::s3::object_table::*;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::encryption::EncryptionParams;
+use crate::error::*;
+use crate::versioning::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+
+pub const X_AMZ_OBJECT_ATTRIBUTES: HeaderName = HeaderName::from_static("x-amz-object-attributes");
+pub const X_AMZ_MAX_PARTS: HeaderName = HeaderName::from_static("x-amz-max-parts");
+pub const X_AMZ_PART_NUMBER_MARKER: HeaderName =
+	HeaderName::from_static("x-amz-part-number-marker");
+
+/// Attributes of an object that can be selected in a GetObjectAttributes request
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SelectedAttributes {
+	etag: bool,
+	checksum: bool,
+	object_parts: bool,
+	storage_class: bool,
+	object_size: bool,
+}
+
+pub async fn handle_get_object_attributes(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = &ctx;
+
+	let selected = parse_object_attributes(req.headers())?;
+	let max_parts = parse_header_u64(req.headers(), &X_AMZ_MAX_PARTS)?
+		.unwrap_or(1000)
+		.clamp(1, 1000);
+	let part_number_marker =
+		parse_header_u64(req.headers(), &X_AMZ_PART_NUMBER_MARKER)?.map(|p| p.min(10000));
+
+	let object = garage
+		.object_table
+		.get(bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let object_version = find_data_version(&object, version_id)?;
+
+	let (version_meta, first_block) = match &object_version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _)) => (meta, false),
+		ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => (meta, true),
+		_ => unreachable!(),
+	};
+
+	let (_, meta_inner) =
+		EncryptionParams::check_decrypt(garage, req.headers(), &version_meta.encryption).await?;
+
+	let object_parts = match multipart_parts_count(&version_meta.etag) {
+		Some(parts_count) if selected.object_parts && first_block => {
+			let version = garage
+				.version_table
+				.get(&object_version.uuid, &EmptyKey)
+				.await?
+				.ok_or(Error::NoSuchKey)?;
+
+			let mut part_sizes = BTreeMap::new();
+			for (vbk, vb) in version.blocks.items().iter() {
+				*part_sizes.entry(vbk.part_number).or_insert(0u64) += vb.size;
+			}
+			let parts = part_sizes
+				.into_iter()
+				.map(|(part_number, size)| PartAttributes {
+					part_number,
+					size,
+					checksum: version.parts_checksums.get(&part_number).copied(),
+				})
+				.collect::<Vec<_>>();
+
+			Some(ObjectParts::paginate(
+				parts_count,
+				parts,
+				part_number_marker,
+				max_parts,
+			))
+		}
+		_ => None,
+	};
+
+	let result = GetObjectAttributesResponse {
+		xmlns: (),
+		etag: selected
+			.etag
+			.then(|| Value(version_meta.etag.clone()))
+			.filter(|v| !v.0.is_empty()),
+		checksum: match meta_inner.checksum {
+			Some(checksum) if selected.checksum => Some(Checksum::from_checksum_value(checksum)),
+			_ => None,
+		},
+		object_parts,
+		storage_class: selected
+			.storage_class
+			.then(|| Value(object_version.storage_class_name().to_string())),
+		object_size: selected
+			.object_size
+			.then_some(IntValue(version_meta.size as i64)),
+	};
+	let xml = to_xml_with_header(&result)?;
+
+	let date = UNIX_EPOCH + Duration::from_millis(object_version.timestamp);
+	let mut resp = Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.header(http::header::LAST_MODIFIED, httpdate::fmt_http_date(date));
+	if object_version.versioned {
+		resp = resp.header(
+			X_AMZ_VERSION_ID,
+			crate::versioning::version_id(object_version),
+		);
+	}
+	Ok(resp.body(string_body(xml))?)
+}
+
+// ---- helpers ----
+
+fn parse_object_attributes(headers: &HeaderMap) -> Result<SelectedAttributes, Error> {
+	let mut selected = SelectedAttributes::default();
+	for value in headers.get_all(X_AMZ_OBJECT_ATTRIBUTES).iter() {
+		for attr in value.to_str()?.split(',').map(str::trim) {
+			match attr {
+				"ETag" => selected.etag = true,
+				"Checksum" => selected.checksum = true,
+				"ObjectParts" => selected.object_parts = true,
+				"StorageClass" => selected.storage_class = true,
+				"ObjectSize" => selected.object_size = true,
+				"" => (),
+				_ => {
+					return Err(Error::bad_request(format!(
+						"Invalid object attribute: {}",
+						attr
+					)))
+				}
+			}
+		}
+	}
+	if selected == SelectedAttributes::default() {
+		return Err(Error::bad_request(
+			"At least one object attribute must be given in x-amz-object-attributes",
+		));
+	}
+	Ok(selected)
+}
+
+fn parse_header_u64(headers: &HeaderMap, name: &HeaderName) -> Result<Option<u64>, Error> {
+	headers
+		.get(name)
+		.map(|v| {
+			v.to_str()?
+				.parse::<u64>()
+				.map_err(|_| Error::bad_request(format!("Invalid value for {}", name)))
+		})
+		.transpose()
+}
+
+/// Number of parts of an object uploaded with a multipart upload,
+/// as given by the `-N` suffix of its ETag
+fn multipart_parts_count(etag: &str) -> Option<u64> {
+	let (_, count) = etag.rsplit_once('-')?;
+	count.parse().ok()
+}
+
+struct PartAttributes {
+	part_number: u64,
+	size: u64,
+	checksum: Option<ChecksumValue>,
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct GetObjectAttributesResponse {
+	#[serde(serialize_with = "xmlns_tag")]
+	pub xmlns: (),
+	#[serde(rename = "ETag")]
+	pub etag: Option<Value>,
+	#[serde(rename = "Checksum")]
+	pub checksum: Option<Checksum>,
+	#[serde(rename = "ObjectParts")]
+	pub object_parts: Option<ObjectParts>,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Option<Value>,
+	#[serde(rename = "ObjectSize")]
+	pub object_size: Option<IntValue>,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct Checksum {
+	#[serde(rename = "ChecksumCRC32")]
+	pub checksum_crc32: Option<Value>,
+	#[serde(rename = "ChecksumCRC32C")]
+	pub checksum_crc32c: Option<Value>,
+	#[serde(rename = "ChecksumSHA1")]
+	pub checksum_sha1: Option<Value>,
+	#[serde(rename = "ChecksumSHA256")]
+	pub checksum_sha256: Option<Value>,
+	#[serde(rename = "ChecksumCRC64NVME")]
+	pub checksum_crc64nvme: Option<Value>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ObjectParts {
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "MaxParts")]
+	pub max_parts: IntValue,
+	#[serde(rename = "NextPartNumberMarker")]
+	pub next_part_number_marker: Option<IntValue>,
+	#[serde(rename = "PartNumberMarker")]
+	pub part_number_marker: Option<IntValue>,
+	#[serde(rename = "Part")]
+	pub parts: Vec<ObjectPart>,
+	#[serde(rename = "PartsCount")]
+	pub parts_count: IntValue,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ObjectPart {
+	#[serde(rename = "ChecksumCRC32")]
+	pub checksum_crc32: Option<Value>,
+	#[serde(rename = "ChecksumCRC32C")]
+	pub checksum_crc32c: Option<Value>,
+	#[serde(rename = "ChecksumSHA1")]
+	pub checksum_sha1: Option<Value>,
+	#[serde(rename = "ChecksumSHA256")]
+	pub checksum_sha256: Option<Value>,
+	#[serde(rename = "ChecksumCRC64NVME")]
+	pub checksum_crc64nvme: Option<Value>,
+	#[serde(rename = "PartNumber")]
+	pub part_number: IntValue,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+}
+
+impl Checksum {
+	fn from_checksum_value(checksum: ChecksumValue) -> Self {
+		let mut ret = Self::default();
+		match checksum {
+			ChecksumValue::Crc32(x) => {
+				ret.checksum_crc32 = Some(Value(BASE64_STANDARD.encode(x)));
+			}
+			ChecksumValue::Crc32c(x) => {
+				ret.checksum_crc32c = Some(Value(BASE64_STANDARD.encode(x)));
+			}
+			ChecksumValue::Sha1(x) => {
+				ret.checksum_sha1 = Some(Value(BASE64_STANDARD.encode(x)));
+			}
+			ChecksumValue::Sha256(x) => {
+				ret.checksum_sha256 = Some(Value(BASE64_STANDARD.encode(x)));
+			}
+			ChecksumValue::Crc64Nvme(x) => {
+				ret.checksum_crc64nvme = Some(Value(BASE64_STANDARD.encode(x)));
+			}
+		}
+		ret
+	}
+}
+
+impl ObjectParts {
+	/// Build the page of parts that follows `part_number_marker`,
+	/// `parts` being sorted by part number
+	fn paginate(
+		parts_count: u64,
+		parts: Vec<PartAttributes>,
+		part_number_marker: Option<u64>,
+		max_parts: u64,
+	) -> Self {
+		let mut parts = parts
+			.into_iter()
+			.filter(|p| p.part_number > part_number_marker.unwrap_or(0))
+			.collect::<Vec<_>>();
+		let is_truncated = parts.len() > max_parts as usize;
+		parts.truncate(max_parts as usize);
+		let next_part_number_marker = match parts.last() {
+			Some(last) if is_truncated => Some(IntValue(last.part_number as i64)),
+			_ => None,
+		};
+
+		ObjectParts {
+			is_truncated: Value(is_truncated.to_string()),
+			max_parts: IntValue(max_parts as i64),
+			next_part_number_marker,
+			part_number_marker: part_number_marker.map(|m| IntValue(m as i64)),
+			parts: parts
+				.into_iter()
+				.map(|p| {
+					let checksum = p
+						.checksum
+						.map(Checksum::from_checksum_value)
+						.unwrap_or_default();
+					ObjectPart {
+						checksum_crc32: checksum.checksum_crc32,
+						checksum_crc32c: checksum.checksum_crc32c,
+						checksum_sha1: checksum.checksum_sha1,
+						checksum_sha256: checksum.checksum_sha256,
+						checksum_crc64nvme: checksum.checksum_crc64nvme,
+						part_number: IntValue(p.part_number as i64),
+						size: IntValue(p.size as i64),
+					}
+				})
+				.collect(),
+			parts_count: IntValue(parts_count as i64),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_object_attributes() {
+		let mut headers = HeaderMap::new();
+		assert!(parse_object_attributes(&headers).is_err());
+
+		headers.insert(
+			X_AMZ_OBJECT_ATTRIBUTES,
+			"ETag, ObjectParts,ObjectSize".parse().unwrap(),
+		);
+		assert_eq!(
+			parse_object_attributes(&headers).unwrap(),
+			SelectedAttributes {
+				etag: true,
+				object_parts: true,
+				object_size: true,
+				..Default::default()
+			}
+		);
+
+		headers.append(X_AMZ_OBJECT_ATTRIBUTES, "Size".parse().unwrap());
+		assert!(parse_object_attributes(&headers).is_err());
+
+		assert_eq!(
+			multipart_parts_count("b662d79adeb7c8d787ea7eafb9ef6207-3"),
+			Some(3)
+		);
+		assert_eq!(
+			multipart_parts_count("b662d79adeb7c8d787ea7eafb9ef6207"),
+			None
+		);
+	}
+
+	#[test]
+	fn test_serialize_get_object_attributes() -> Result<(), Error> {
+		let parts = (1..=3)
+			.map(|part_number| PartAttributes {
+				part_number,
+				size: 5 << 20,
+				checksum: Some(ChecksumValue::Crc32([0x64, 0x90, 0x27, 0x1f])),
+			})
+			.collect();
+		let result = GetObjectAttributesResponse {
+			xmlns: (),
+			etag: Some(Value("b662d79adeb7c8d787ea7eafb9ef6207-3".to_string())),
+			checksum: Some(Checksum::from_checksum_value(ChecksumValue::Crc32([
+				0x61, 0x0c, 0x2a, 0x4b,
+			]))),
+			object_parts: Some(ObjectParts::paginate(3, parts, Some(1), 1)),
+			storage_class: Some(Value("STANDARD".to_string())),
+			object_size: Some(IntValue(15 << 20)),
+		};
+
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<GetObjectAttributesResponse xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <ETag>b662d79adeb7c8d787ea7eafb9ef6207-3</ETag>
+  <Checksum>
+    <ChecksumCRC32>YQwqSw==</ChecksumCRC32>
+  </Checksum>
+  <ObjectParts>
+    <IsTruncated>true</IsTruncated>
+    <MaxParts>1</MaxParts>
+    <NextPartNumberMarker>2</NextPartNumberMarker>
+    <PartNumberMarker>1</PartNumberMarker>
+    <Part>
+      <ChecksumCRC32>ZJAnHw==</ChecksumCRC32>
+      <PartNumber>2</PartNumber>
+      <Size>5242880</Size>
+    </Part>
+    <PartsCount>3</PartsCount>
+  </ObjectParts>
+  <StorageClass>STANDARD</StorageClass>
+  <ObjectSize>15728640</ObjectSize>
+</GetObjectAttributesResponse>"#;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&to_xml_with_header(&result)?));
+
+		Ok(())
+	}
+}
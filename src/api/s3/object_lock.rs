@@ -0,0 +1,728 @@
+use std::convert::TryFrom;
+
+use quick_xml::de::from_reader;
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_util::crdt;
+use garage_util::time::*;
+
+use garage_model::bucket_table::*;
+use garage_model::s3::object_table::*;
+
+use garage_api_common::common_error::CommonError;
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::versioning::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+
+pub const X_AMZ_BUCKET_OBJECT_LOCK_ENABLED: HeaderName =
+	HeaderName::from_static("x-amz-bucket-object-lock-enabled");
+pub const X_AMZ_OBJECT_LOCK_MODE: HeaderName = HeaderName::from_static("x-amz-object-lock-mode");
+pub const X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE: HeaderName =
+	HeaderName::from_static("x-amz-object-lock-retain-until-date");
+pub const X_AMZ_OBJECT_LOCK_LEGAL_HOLD: HeaderName =
+	HeaderName::from_static("x-amz-object-lock-legal-hold");
+pub const X_AMZ_BYPASS_GOVERNANCE_RETENTION: HeaderName =
+	HeaderName::from_static("x-amz-bypass-governance-retention");
+
+const MODE_GOVERNANCE: &str = "GOVERNANCE";
+const MODE_COMPLIANCE: &str = "COMPLIANCE";
+const LEGAL_HOLD_ON: &str = "ON";
+const LEGAL_HOLD_OFF: &str = "OFF";
+
+// ---- bucket object lock configuration ----
+
+pub async fn handle_get_object_lock_configuration(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let config = bucket_params
+		.object_lock
+		.get()
+		.as_ref()
+		.ok_or(Error::ObjectLockConfigurationNotFound)?;
+	let xml = to_xml_with_header(&ObjectLockConfiguration::from_garage_object_lock_config(
+		config,
+	))?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_lock_configuration(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+
+	let conf: ObjectLockConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_object_lock_config()?;
+
+	if !bucket_params.object_lock_enabled() && !bucket_params.versioning_enabled() {
+		return Err(Error::InvalidBucketState(
+			"Versioning must be 'Enabled' on the bucket to apply a Object Lock configuration"
+				.into(),
+		));
+	}
+
+	bucket_params.object_lock.update(Some(config));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+// ---- object version retention and legal hold ----
+
+pub async fn handle_get_object_retention(
+	ctx: ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_object_lock_enabled(&ctx.bucket_params)?;
+
+	let object = get_object(&ctx, key).await?;
+	let version = find_data_version(&object, version_id)?;
+
+	let retention = version
+		.lock
+		.retention
+		.get()
+		.as_ref()
+		.ok_or(Error::NoSuchObjectLockConfiguration)?;
+	let xml = to_xml_with_header(&Retention::from_garage_retention(retention))?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_retention(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_object_lock_enabled(&ctx.bucket_params)?;
+	let bypass_governance = bypass_governance_retention(&ctx, req.headers())?;
+
+	let body = req.into_body().collect().await?;
+
+	let retention: Retention = from_reader(&body as &[u8])?;
+	let retention = retention.validate_into_garage_retention()?;
+
+	let now = now_msec();
+	if retention.is_some_and(|r| r.retain_until <= now) {
+		return Err(Error::bad_request(
+			"The retain until date must be in the future",
+		));
+	}
+
+	update_object_lock(&ctx, key, version_id, |lock| {
+		check_retention_update(
+			lock.active_retention(now),
+			retention.as_ref(),
+			bypass_governance,
+		)?;
+		lock.retention.update(retention);
+		Ok(())
+	})
+	.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+pub async fn handle_get_object_legal_hold(
+	ctx: ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_object_lock_enabled(&ctx.bucket_params)?;
+
+	let object = get_object(&ctx, key).await?;
+	let version = find_data_version(&object, version_id)?;
+
+	let xml = to_xml_with_header(&LegalHold::from_garage_legal_hold(
+		*version.lock.legal_hold.get(),
+	))?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_legal_hold(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	check_object_lock_enabled(&ctx.bucket_params)?;
+
+	let body = req.into_body().collect().await?;
+
+	let legal_hold: LegalHold = from_reader(&body as &[u8])?;
+	let legal_hold = legal_hold.validate_into_garage_legal_hold()?;
+
+	update_object_lock(&ctx, key, version_id, |lock| {
+		lock.legal_hold.update(legal_hold);
+		Ok(())
+	})
+	.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+async fn get_object(ctx: &ReqCtx, key: &str) -> Result<Object, Error> {
+	ctx.garage
+		.object_table
+		.get(&ctx.bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)
+}
+
+/// Apply a modification to the object lock information of an object version
+async fn update_object_lock<F>(
+	ctx: &ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+	f: F,
+) -> Result<(), Error>
+where
+	F: FnOnce(&mut ObjectVersionLock) -> Result<(), Error>,
+{
+	let object = get_object(ctx, key).await?;
+	let version = find_data_version(&object, version_id)?;
+
+	let mut new_version = version.clone();
+	f(&mut new_version.lock)?;
+
+	let object = Object::new(ctx.bucket_id, key.to_string(), vec![new_version]);
+	ctx.garage.object_table.insert(&object).await?;
+
+	Ok(())
+}
+
+// ---- helpers ----
+
+fn check_object_lock_enabled(bucket_params: &BucketParams) -> Result<(), Error> {
+	if bucket_params.object_lock_enabled() {
+		Ok(())
+	} else {
+		Err(Error::bad_request(
+			"Bucket is missing Object Lock Configuration",
+		))
+	}
+}
+
+/// Whether the create bucket request asks for object lock to be enabled
+pub(crate) fn parse_bucket_object_lock_header(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<bool, Error> {
+	match headers.get(X_AMZ_BUCKET_OBJECT_LOCK_ENABLED) {
+		Some(v) => Ok(v.to_str()?.eq_ignore_ascii_case("true")),
+		None => Ok(false),
+	}
+}
+
+/// Determine the object lock information of a new object version, from the
+/// x-amz-object-lock-* headers, or from the default retention of the bucket
+/// if no retention is specified.
+pub(crate) fn parse_object_lock_headers(
+	headers: &HeaderMap<HeaderValue>,
+	bucket_params: &BucketParams,
+	now: u64,
+) -> Result<ObjectVersionLock, Error> {
+	let mode = headers
+		.get(X_AMZ_OBJECT_LOCK_MODE)
+		.map(|v| v.to_str())
+		.transpose()?;
+	let retain_until = headers
+		.get(X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE)
+		.map(|v| v.to_str())
+		.transpose()?;
+	let legal_hold = headers
+		.get(X_AMZ_OBJECT_LOCK_LEGAL_HOLD)
+		.map(|v| v.to_str())
+		.transpose()?;
+
+	let config = match bucket_params.object_lock.get() {
+		Some(config) => config,
+		None if mode.is_none() && retain_until.is_none() && legal_hold.is_none() => {
+			return Ok(ObjectVersionLock::default());
+		}
+		None => {
+			return Err(Error::bad_request(
+				"Bucket is missing Object Lock Configuration",
+			))
+		}
+	};
+
+	let retention = match (mode, retain_until) {
+		(Some(mode), Some(retain_until)) => {
+			let retain_until = parse_retain_until_date(retain_until)?;
+			if retain_until <= now {
+				return Err(Error::bad_request(
+					"The retain until date must be in the future",
+				));
+			}
+			Some(ObjectRetention {
+				mode: parse_mode(mode)?,
+				retain_until,
+			})
+		}
+		(None, None) => config.default_retention.as_ref().map(|r| ObjectRetention {
+			mode: r.mode,
+			retain_until: r.period.retain_until(now),
+		}),
+		_ => return Err(Error::bad_request(
+			"x-amz-object-lock-retain-until-date and x-amz-object-lock-mode must both be supplied",
+		)),
+	};
+
+	let legal_hold = match legal_hold {
+		None => false,
+		Some(s) => parse_legal_hold_status(s)?,
+	};
+
+	Ok(ObjectVersionLock {
+		retention: crdt::Lww::new(retention),
+		legal_hold: crdt::Lww::new(legal_hold),
+	})
+}
+
+/// Add the x-amz-object-lock-* headers describing the lock of an object version
+pub(crate) fn add_object_lock_headers(
+	lock: &ObjectVersionLock,
+	mut resp: http::response::Builder,
+) -> http::response::Builder {
+	if let Some(retention) = lock.retention.get() {
+		resp = resp
+			.header(X_AMZ_OBJECT_LOCK_MODE, mode_str(retention.mode))
+			.header(
+				X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE,
+				msec_to_rfc3339(retention.retain_until),
+			);
+	}
+	if *lock.legal_hold.get() {
+		resp = resp.header(X_AMZ_OBJECT_LOCK_LEGAL_HOLD, LEGAL_HOLD_ON);
+	}
+	resp
+}
+
+/// Whether the request asks to bypass governance retention. This is only
+/// allowed for keys that have owner permission on the bucket.
+pub(crate) fn bypass_governance_retention(
+	ctx: &ReqCtx,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<bool, Error> {
+	let bypass = match headers.get(X_AMZ_BYPASS_GOVERNANCE_RETENTION) {
+		Some(v) => v.to_str()?.eq_ignore_ascii_case("true"),
+		None => false,
+	};
+	if bypass
+		&& !ctx
+			.api_key
+			.as_ref()
+			.is_some_and(|k| k.allow_owner(&ctx.bucket_id))
+	{
+		return Err(CommonError::Forbidden(
+			"Bypassing governance retention requires owner permission on the bucket".into(),
+		)
+		.into());
+	}
+	Ok(bypass)
+}
+
+/// Check that an object version can be permanently deleted
+pub(crate) fn check_version_deletable(
+	version: &ObjectVersion,
+	bypass_governance: bool,
+	now: u64,
+) -> Result<(), Error> {
+	if version.lock.is_locked(now, bypass_governance) {
+		return Err(CommonError::Forbidden(
+			"Access Denied because object protected by object lock".into(),
+		)
+		.into());
+	}
+	Ok(())
+}
+
+/// Check that the retention of an object version can be changed from `current` to `new`.
+/// Retention can always be extended, but it can be shortened or removed only
+/// in governance mode, when bypassing governance retention.
+fn check_retention_update(
+	current: Option<&ObjectRetention>,
+	new: Option<&ObjectRetention>,
+	bypass_governance: bool,
+) -> Result<(), Error> {
+	let current = match current {
+		None => return Ok(()),
+		Some(c) => c,
+	};
+	let weakened = match new {
+		None => true,
+		Some(n) => {
+			n.retain_until < current.retain_until
+				|| (current.mode == ObjectLockMode::Compliance
+					&& n.mode == ObjectLockMode::Governance)
+		}
+	};
+	if weakened && (current.mode == ObjectLockMode::Compliance || !bypass_governance) {
+		return Err(CommonError::Forbidden(
+			"Access Denied because object protected by object lock".into(),
+		)
+		.into());
+	}
+	Ok(())
+}
+
+fn parse_mode(mode: &str) -> Result<ObjectLockMode, Error> {
+	match mode {
+		MODE_GOVERNANCE => Ok(ObjectLockMode::Governance),
+		MODE_COMPLIANCE => Ok(ObjectLockMode::Compliance),
+		_ => Err(Error::bad_request(format!(
+			"Unknown object lock mode: {}",
+			mode
+		))),
+	}
+}
+
+fn mode_str(mode: ObjectLockMode) -> &'static str {
+	match mode {
+		ObjectLockMode::Governance => MODE_GOVERNANCE,
+		ObjectLockMode::Compliance => MODE_COMPLIANCE,
+	}
+}
+
+fn parse_retain_until_date(date: &str) -> Result<u64, Error> {
+	let date = chrono::DateTime::parse_from_rfc3339(date)
+		.ok_or_bad_request("Invalid retain until date")?;
+	Ok(u64::try_from(date.timestamp_millis()).ok_or_bad_request("Invalid retain until date")?)
+}
+
+fn parse_legal_hold_status(status: &str) -> Result<bool, Error> {
+	match status {
+		LEGAL_HOLD_ON => Ok(true),
+		LEGAL_HOLD_OFF => Ok(false),
+		_ => Err(Error::bad_request(format!(
+			"Invalid legal hold status: {}",
+			status
+		))),
+	}
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "ObjectLockConfiguration")]
+pub struct ObjectLockConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "ObjectLockEnabled")]
+	pub object_lock_enabled: Option<Value>,
+	#[serde(rename = "Rule")]
+	pub rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ObjectLockRule {
+	#[serde(rename = "DefaultRetention")]
+	pub default_retention: DefaultRetention,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DefaultRetention {
+	#[serde(rename = "Mode")]
+	pub mode: Value,
+	#[serde(rename = "Days")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Years")]
+	pub years: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "Retention")]
+pub struct Retention {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Mode")]
+	pub mode: Option<Value>,
+	#[serde(rename = "RetainUntilDate")]
+	pub retain_until_date: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "LegalHold")]
+pub struct LegalHold {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Status")]
+	pub status: Value,
+}
+
+impl ObjectLockConfiguration {
+	pub fn validate_into_garage_object_lock_config(self) -> Result<ObjectLockConfig, Error> {
+		if self.object_lock_enabled.as_ref().map(|v| v.0.as_str()) != Some("Enabled") {
+			return Err(Error::bad_request(
+				"ObjectLockEnabled must be set to 'Enabled'",
+			));
+		}
+
+		let default_retention = match self.rule {
+			None => None,
+			Some(rule) => {
+				let DefaultRetention { mode, days, years } = rule.default_retention;
+				let period = match (days, years) {
+					(Some(days), None) => ObjectLockPeriod::Days(parse_period(days)?),
+					(None, Some(years)) => ObjectLockPeriod::Years(parse_period(years)?),
+					_ => {
+						return Err(Error::bad_request(
+							"Default retention must specify either Days or Years",
+						))
+					}
+				};
+				Some(ObjectLockDefaultRetention {
+					mode: parse_mode(&mode.0)?,
+					period,
+				})
+			}
+		};
+
+		Ok(ObjectLockConfig { default_retention })
+	}
+
+	pub fn from_garage_object_lock_config(config: &ObjectLockConfig) -> Self {
+		Self {
+			xmlns: (),
+			object_lock_enabled: Some(Value("Enabled".into())),
+			rule: config.default_retention.as_ref().map(|r| {
+				let (days, years) = match r.period {
+					ObjectLockPeriod::Days(d) => (Some(IntValue(d as i64)), None),
+					ObjectLockPeriod::Years(y) => (None, Some(IntValue(y as i64))),
+				};
+				ObjectLockRule {
+					default_retention: DefaultRetention {
+						mode: Value(mode_str(r.mode).into()),
+						days,
+						years,
+					},
+				}
+			}),
+		}
+	}
+}
+
+fn parse_period(value: IntValue) -> Result<u32, Error> {
+	match u32::try_from(value.0) {
+		Ok(v) if v > 0 => Ok(v),
+		_ => Err(Error::bad_request(
+			"Default retention period must be a positive integer",
+		)),
+	}
+}
+
+impl Retention {
+	pub fn validate_into_garage_retention(self) -> Result<Option<ObjectRetention>, Error> {
+		match (self.mode, self.retain_until_date) {
+			(Some(mode), Some(date)) => Ok(Some(ObjectRetention {
+				mode: parse_mode(&mode.0)?,
+				retain_until: parse_retain_until_date(&date.0)?,
+			})),
+			(None, None) => Ok(None),
+			_ => Err(Error::bad_request(
+				"Retention must specify both Mode and RetainUntilDate",
+			)),
+		}
+	}
+
+	pub fn from_garage_retention(retention: &ObjectRetention) -> Self {
+		Self {
+			xmlns: (),
+			mode: Some(Value(mode_str(retention.mode).into())),
+			retain_until_date: Some(Value(msec_to_rfc3339(retention.retain_until))),
+		}
+	}
+}
+
+impl LegalHold {
+	pub fn validate_into_garage_legal_hold(self) -> Result<bool, Error> {
+		parse_legal_hold_status(&self.status.0)
+	}
+
+	pub fn from_garage_legal_hold(legal_hold: bool) -> Self {
+		Self {
+			xmlns: (),
+			status: Value(
+				if legal_hold {
+					LEGAL_HOLD_ON
+				} else {
+					LEGAL_HOLD_OFF
+				}
+				.into(),
+			),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_object_lock_configuration() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <ObjectLockEnabled>Enabled</ObjectLockEnabled>
+  <Rule>
+    <DefaultRetention>
+      <Mode>COMPLIANCE</Mode>
+      <Days>30</Days>
+    </DefaultRetention>
+  </Rule>
+</ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(message).unwrap();
+		let config = conf.validate_into_garage_object_lock_config()?;
+		assert_eq!(
+			config,
+			ObjectLockConfig {
+				default_retention: Some(ObjectLockDefaultRetention {
+					mode: ObjectLockMode::Compliance,
+					period: ObjectLockPeriod::Days(30),
+				}),
+			}
+		);
+
+		let message2 = to_xml_with_header(
+			&ObjectLockConfiguration::from_garage_object_lock_config(&config),
+		)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		let both_periods = r#"<ObjectLockConfiguration>
+  <ObjectLockEnabled>Enabled</ObjectLockEnabled>
+  <Rule><DefaultRetention><Mode>GOVERNANCE</Mode><Days>1</Days><Years>1</Years></DefaultRetention></Rule>
+</ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(both_periods).unwrap();
+		assert!(conf.validate_into_garage_object_lock_config().is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_parse_object_lock_headers() -> Result<(), Error> {
+		let now = 1_700_000_000_000;
+		let mut headers = HeaderMap::new();
+
+		let mut bucket_params = BucketParams::default();
+		assert_eq!(
+			parse_object_lock_headers(&headers, &bucket_params, now)?,
+			ObjectVersionLock::default()
+		);
+
+		headers.insert(X_AMZ_OBJECT_LOCK_LEGAL_HOLD, "ON".parse().unwrap());
+		assert!(parse_object_lock_headers(&headers, &bucket_params, now).is_err());
+
+		bucket_params.object_lock.update(Some(ObjectLockConfig {
+			default_retention: Some(ObjectLockDefaultRetention {
+				mode: ObjectLockMode::Governance,
+				period: ObjectLockPeriod::Days(1),
+			}),
+		}));
+		let lock = parse_object_lock_headers(&headers, &bucket_params, now)?;
+		assert!(*lock.legal_hold.get());
+		assert_eq!(
+			*lock.retention.get(),
+			Some(ObjectRetention {
+				mode: ObjectLockMode::Governance,
+				retain_until: now + 24 * 3600 * 1000,
+			})
+		);
+
+		headers.insert(X_AMZ_OBJECT_LOCK_MODE, "COMPLIANCE".parse().unwrap());
+		assert!(parse_object_lock_headers(&headers, &bucket_params, now).is_err());
+
+		headers.insert(
+			X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE,
+			"2030-01-01T00:00:00.000Z".parse().unwrap(),
+		);
+		let lock = parse_object_lock_headers(&headers, &bucket_params, now)?;
+		assert_eq!(
+			*lock.retention.get(),
+			Some(ObjectRetention {
+				mode: ObjectLockMode::Compliance,
+				retain_until: 1_893_456_000_000,
+			})
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_check_retention_update() {
+		let governance = |retain_until| ObjectRetention {
+			mode: ObjectLockMode::Governance,
+			retain_until,
+		};
+		let compliance = |retain_until| ObjectRetention {
+			mode: ObjectLockMode::Compliance,
+			retain_until,
+		};
+
+		assert!(check_retention_update(None, None, false).is_ok());
+		assert!(check_retention_update(None, Some(&compliance(10)), false).is_ok());
+
+		// extending retention is always allowed
+		assert!(
+			check_retention_update(Some(&governance(10)), Some(&governance(20)), false).is_ok()
+		);
+		assert!(
+			check_retention_update(Some(&compliance(10)), Some(&compliance(20)), false).is_ok()
+		);
+		assert!(
+			check_retention_update(Some(&governance(10)), Some(&compliance(10)), false).is_ok()
+		);
+
+		// governance retention can be weakened only when bypassing it
+		assert!(
+			check_retention_update(Some(&governance(10)), Some(&governance(5)), false).is_err()
+		);
+		assert!(check_retention_update(Some(&governance(10)), None, false).is_err());
+		assert!(check_retention_update(Some(&governance(10)), Some(&governance(5)), true).is_ok());
+		assert!(check_retention_update(Some(&governance(10)), None, true).is_ok());
+
+		// compliance retention can never be weakened
+		assert!(check_retention_update(Some(&compliance(10)), Some(&compliance(5)), true).is_err());
+		assert!(
+			check_retention_update(Some(&compliance(10)), Some(&governance(10)), true).is_err()
+		);
+		assert!(check_retention_update(Some(&compliance(10)), None, true).is_err());
+	}
+}
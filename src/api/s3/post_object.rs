@@ -13,18 +13,28 @@ use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use multer::{Constraints, Multipart, SizeLimit};
 use serde::Deserialize;
 
+use garage_util::time::now_msec;
+
 use garage_model::garage::Garage;
+use garage_model::s3::notification::ObjectEventName;
 use garage_model::s3::object_table::*;
 
 use garage_api_common::cors::*;
-use garage_api_common::helpers::*;
+use garage_api_common::helpers::{self, *};
 use garage_api_common::signature::checksum::*;
 use garage_api_common::signature::payload::{verify_v4, Authorization};
 
+use crate::acl::{request_object_public_read, X_AMZ_ACL};
 use crate::api_server::ResBody;
+use crate::bucket_policy::authorize_request;
 use crate::encryption::EncryptionParams;
 use crate::error::*;
-use crate::put::{extract_metadata_headers, save_stream, ChecksumMode};
+use crate::notification::notify_object_event;
+use crate::object_lock::parse_object_lock_headers;
+use crate::put::{extract_metadata_headers, save_stream, ChecksumMode, WritePreconditions};
+use crate::replication::{queue_replication, request_replication_status};
+use crate::storage_class::request_storage_class;
+use crate::versioning::encode_version_id;
 use crate::xml as s3_xml;
 
 pub async fn handle_post_object(
@@ -103,7 +113,7 @@ pub async fn handle_post_object(
 		key.to_owned()
 	};
 
-	let api_key = verify_v4(&garage, "s3", &authorization, policy.as_bytes()).await?;
+	let (api_key, _, session) = verify_v4(&garage, "s3", &authorization, policy.as_bytes()).await?;
 
 	let bucket_id = garage
 		.bucket_helper()
@@ -111,10 +121,6 @@ pub async fn handle_post_object(
 		.await
 		.map_err(pass_helper_error)?;
 
-	if !api_key.allow_write(&bucket_id) {
-		return Err(Error::forbidden("Operation is not allowed for this key."));
-	}
-
 	let bucket = garage
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
@@ -214,10 +220,14 @@ pub async fn handle_post_object(
 		)));
 	}
 
-	// if we ever start supporting ACLs, we likely want to map "acl" to x-amz-acl" somewhere
-	// around here to make sure the rest of the machinery takes our acl into account.
 	let headers = extract_metadata_headers(&params)?;
 
+	// The "acl" field has the same meaning as the x-amz-acl header of PutObject
+	let mut acl_headers = HeaderMap::new();
+	if let Some(acl) = params.get("acl") {
+		acl_headers.insert(X_AMZ_ACL, acl.clone());
+	}
+
 	let checksum_algorithm = request_checksum_algorithm(&params)?;
 	let expected_checksums = ExpectedChecksums {
 		md5: params
@@ -236,7 +246,12 @@ pub async fn handle_post_object(
 		checksum: expected_checksums.extra,
 	};
 
-	let encryption = EncryptionParams::new_from_headers(&garage, &params)?;
+	let encryption = EncryptionParams::new_from_headers(
+		&garage,
+		&params,
+		bucket_params.default_encryption.get().as_ref(),
+	)
+	.await?;
 
 	let stream = file_field.map(|r| r.map_err(Into::into));
 	let ctx = ReqCtx {
@@ -244,19 +259,51 @@ pub async fn handle_post_object(
 		bucket_id,
 		bucket_name,
 		bucket_params,
-		api_key,
+		api_key: Some(api_key),
+		session,
 	};
 
+	authorize_request(
+		&ctx,
+		&Request::from_parts(head.clone(), ()),
+		helpers::Authorization::Write,
+		"s3:PutObject",
+		Some(&key),
+		None,
+	)?;
+
+	let lock = parse_object_lock_headers(&params, &ctx.bucket_params, now_msec())?;
+	let replication =
+		request_replication_status(&ctx, &params, &key, &ObjectTagSet::default(), false)?;
+	let public_read = request_object_public_read(&ctx, &acl_headers)?;
+	let storage_class = request_storage_class(&ctx.garage, &params)?;
+
 	let res = save_stream(
 		&ctx,
 		meta,
-		encryption,
+		encryption.clone(),
 		StreamLimiter::new(stream, conditions.content_length),
 		&key,
 		ChecksumMode::Verify(&expected_checksums),
+		ObjectTagSet::default(),
+		lock,
+		replication,
+		public_read,
+		storage_class,
+		&WritePreconditions::default(),
 	)
 	.await?;
 
+	notify_object_event(
+		&ctx,
+		ObjectEventName::Post,
+		&key,
+		encode_version_id(res.version_uuid, res.versioned),
+		res.version_timestamp,
+		Some((res.size, &res.etag)),
+	);
+	queue_replication(&ctx, &key, res.version_uuid, replication);
+
 	let etag = format!("\"{}\"", res.etag);
 
 	let mut resp = if let Some(mut target) = params
@@ -0,0 +1,179 @@
+use quick_xml::de::from_reader;
+
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{Bucket, PublicAccessBlock};
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+pub async fn handle_get_public_access_block(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+	let block = bucket_params
+		.public_access_block
+		.get()
+		.ok_or(Error::NoSuchPublicAccessBlockConfiguration)?;
+
+	let conf = PublicAccessBlockConfiguration::from_garage_public_access_block(&block);
+	let xml = to_xml_with_header(&conf)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_public_access_block(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+
+	let conf: PublicAccessBlockConfiguration = from_reader(&body as &[u8])?;
+	let block = conf.validate_into_garage_public_access_block()?;
+
+	bucket_params.public_access_block.update(Some(block));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_public_access_block(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	bucket_params.public_access_block.update(None);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "PublicAccessBlockConfiguration")]
+pub struct PublicAccessBlockConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "BlockPublicAcls")]
+	pub block_public_acls: Option<Value>,
+	#[serde(rename = "IgnorePublicAcls")]
+	pub ignore_public_acls: Option<Value>,
+	#[serde(rename = "BlockPublicPolicy")]
+	pub block_public_policy: Option<Value>,
+	#[serde(rename = "RestrictPublicBuckets")]
+	pub restrict_public_buckets: Option<Value>,
+}
+
+impl PublicAccessBlockConfiguration {
+	pub fn validate_into_garage_public_access_block(self) -> Result<PublicAccessBlock, Error> {
+		Ok(PublicAccessBlock {
+			block_public_acls: parse_flag("BlockPublicAcls", self.block_public_acls)?,
+			ignore_public_acls: parse_flag("IgnorePublicAcls", self.ignore_public_acls)?,
+			block_public_policy: parse_flag("BlockPublicPolicy", self.block_public_policy)?,
+			restrict_public_buckets: parse_flag(
+				"RestrictPublicBuckets",
+				self.restrict_public_buckets,
+			)?,
+		})
+	}
+
+	pub fn from_garage_public_access_block(block: &PublicAccessBlock) -> Self {
+		let flag = |v: bool| Some(Value(v.to_string()));
+		Self {
+			xmlns: (),
+			block_public_acls: flag(block.block_public_acls),
+			ignore_public_acls: flag(block.ignore_public_acls),
+			block_public_policy: flag(block.block_public_policy),
+			restrict_public_buckets: flag(block.restrict_public_buckets),
+		}
+	}
+}
+
+fn parse_flag(name: &str, value: Option<Value>) -> Result<bool, Error> {
+	match value.as_ref().map(|v| v.0.trim()) {
+		None => Ok(false),
+		Some("true") => Ok(true),
+		Some("false") => Ok(false),
+		Some(v) => Err(Error::bad_request(format!(
+			"Invalid value for {}: {}",
+			name, v
+		))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_public_access_block() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<PublicAccessBlockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <BlockPublicAcls>true</BlockPublicAcls>
+  <IgnorePublicAcls>false</IgnorePublicAcls>
+  <BlockPublicPolicy>true</BlockPublicPolicy>
+  <RestrictPublicBuckets>false</RestrictPublicBuckets>
+</PublicAccessBlockConfiguration>"#;
+		let conf: PublicAccessBlockConfiguration = from_str(message).unwrap();
+		let block = conf.validate_into_garage_public_access_block()?;
+		assert_eq!(
+			block,
+			PublicAccessBlock {
+				block_public_acls: true,
+				ignore_public_acls: false,
+				block_public_policy: true,
+				restrict_public_buckets: false,
+			}
+		);
+
+		let back = PublicAccessBlockConfiguration::from_garage_public_access_block(&block);
+		let xml = to_xml_with_header(&back)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&xml));
+
+		let partial = r#"<PublicAccessBlockConfiguration>
+  <RestrictPublicBuckets>true</RestrictPublicBuckets>
+</PublicAccessBlockConfiguration>"#;
+		let conf: PublicAccessBlockConfiguration = from_str(partial).unwrap();
+		let block = conf.validate_into_garage_public_access_block()?;
+		assert!(block.restrict_public_buckets);
+		assert!(
+			!block.block_public_acls && !block.ignore_public_acls && !block.block_public_policy
+		);
+
+		let invalid = message.replace("<BlockPublicAcls>true", "<BlockPublicAcls>yes");
+		let conf: PublicAccessBlockConfiguration = from_str(&invalid).unwrap();
+		assert!(conf.validate_into_garage_public_access_block().is_err());
+
+		Ok(())
+	}
+}
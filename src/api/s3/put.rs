@@ -8,7 +8,7 @@ use futures::try_join;
 use tokio::sync::mpsc;
 
 use hyper::body::Bytes;
-use hyper::header::{HeaderMap, HeaderValue};
+use hyper::header::{HeaderMap, HeaderValue, IF_MATCH, IF_NONE_MATCH};
 use hyper::{Request, Response};
 
 use opentelemetry::{
@@ -27,6 +27,7 @@ use garage_block::manager::INLINE_THRESHOLD;
 use garage_model::garage::Garage;
 use garage_model::index_counter::CountedItem;
 use garage_model::s3::block_ref_table::*;
+use garage_model::s3::notification::ObjectEventName;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
@@ -34,18 +35,32 @@ use garage_api_common::helpers::*;
 use garage_api_common::signature::body::StreamingChecksumReceiver;
 use garage_api_common::signature::checksum::*;
 
+use crate::acl::request_object_public_read;
 use crate::api_server::{ReqBody, ResBody};
 use crate::encryption::EncryptionParams;
 use crate::error::*;
+use crate::notification::notify_object_event;
+use crate::object_lock::parse_object_lock_headers;
+use crate::replication::{queue_replication, request_replication_status};
+use crate::storage_class::request_storage_class;
+use crate::tagging::parse_tagging_header;
+use crate::versioning::{encode_version_id, X_AMZ_VERSION_ID};
 use crate::website::X_AMZ_WEBSITE_REDIRECT_LOCATION;
 
 const PUT_BLOCKS_MAX_PARALLEL: usize = 3;
 
+/// Commit markers of conditional writes older than this are ignored,
+/// as they might have been left over by a node that crashed while committing
+const COMMIT_MARKER_TIMEOUT_MSEC: u64 = 30_000;
+
 pub(crate) struct SaveStreamResult {
 	pub(crate) version_uuid: Uuid,
 	pub(crate) version_timestamp: u64,
+	/// Whether the version was written with versioning enabled
+	pub(crate) versioned: bool,
 	/// Etag WITHOUT THE QUOTES (just the hex value)
 	pub(crate) etag: String,
+	pub(crate) size: u64,
 }
 
 pub(crate) enum ChecksumMode<'a> {
@@ -75,6 +90,12 @@ pub async fn handle_put(
 		extra: request_checksum_value(req.headers())?,
 	};
 	let trailer_checksum_algorithm = request_trailer_checksum_algorithm(req.headers())?;
+	let preconditions = WritePreconditions::parse(req.headers())?;
+	let tags = parse_tagging_header(req.headers())?.unwrap_or_default();
+	let lock = parse_object_lock_headers(req.headers(), &ctx.bucket_params, now_msec())?;
+	let replication = request_replication_status(&ctx, req.headers(), key, &tags, false)?;
+	let public_read = request_object_public_read(&ctx, req.headers())?;
+	let storage_class = request_storage_class(&ctx.garage, req.headers())?;
 
 	let meta = ObjectVersionMetaInner {
 		headers,
@@ -82,15 +103,20 @@ pub async fn handle_put(
 	};
 
 	// Determine whether object should be encrypted, and if so the key
-	let encryption = EncryptionParams::new_from_headers(&ctx.garage, req.headers())?;
+	let encryption = EncryptionParams::new_from_headers(
+		&ctx.garage,
+		req.headers(),
+		ctx.bucket_params.default_encryption.get().as_ref(),
+	)
+	.await?;
 
 	// The request body is a special ReqBody object (see garage_api_common::signature::body)
 	// which supports calculating checksums while streaming the data.
 	// Before we start streaming, we configure it to calculate all the checksums we need.
 	let mut req_body = req.into_body();
 	req_body.add_expected_checksums(expected_checksums.clone());
-	if !encryption.is_encrypted() {
-		// For non-encrypted objects, we need to compute the md5sum in all cases
+	if encryption.has_md5_etag() {
+		// For non-encrypted and SSE-S3 objects, we need to compute the md5sum in all cases
 		// (even if content-md5 is not set), because it is used as the object etag
 		req_body.add_md5();
 	}
@@ -101,24 +127,44 @@ pub async fn handle_put(
 	let res = save_stream(
 		&ctx,
 		meta,
-		encryption,
+		encryption.clone(),
 		stream,
 		key,
 		ChecksumMode::VerifyFrom {
 			checksummer,
 			trailer_algo: trailer_checksum_algorithm,
 		},
+		tags,
+		lock,
+		replication,
+		public_read,
+		storage_class,
+		&preconditions,
 	)
 	.await?;
 
+	notify_object_event(
+		&ctx,
+		ObjectEventName::Put,
+		key,
+		encode_version_id(res.version_uuid, res.versioned),
+		res.version_timestamp,
+		Some((res.size, &res.etag)),
+	);
+	queue_replication(&ctx, key, res.version_uuid, replication);
+
 	let mut resp = Response::builder()
-		.header("x-amz-version-id", hex::encode(res.version_uuid))
+		.header(
+			X_AMZ_VERSION_ID,
+			encode_version_id(res.version_uuid, res.versioned),
+		)
 		.header("ETag", format!("\"{}\"", res.etag));
 	encryption.add_response_headers(&mut resp);
 	let resp = add_checksum_response_headers(&expected_checksums.extra, resp);
 	Ok(resp.body(empty_body())?)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	ctx: &ReqCtx,
 	mut meta: ObjectVersionMetaInner,
@@ -126,9 +172,18 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	body: S,
 	key: &String,
 	checksum_mode: ChecksumMode<'_>,
+	tags: ObjectTagSet,
+	lock: ObjectVersionLock,
+	replication: Option<ReplicationStatus>,
+	public_read: bool,
+	storage_class: Option<String>,
+	preconditions: &WritePreconditions,
 ) -> Result<SaveStreamResult, Error> {
 	let ReqCtx {
-		garage, bucket_id, ..
+		garage,
+		bucket_id,
+		bucket_params,
+		..
 	} = ctx;
 
 	let mut chunker = StreamChunker::new(body, garage.config.block_size);
@@ -137,16 +192,25 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		garage.object_table.get(bucket_id, key).map_err(Error::from),
 	)?;
 
+	// Fail early if the preconditions do not hold; they are checked again
+	// when the new version is committed
+	preconditions.check(existing_object.as_ref())?;
+
 	let first_block = first_block_opt.unwrap_or_default();
 
 	// Generate identity of new version
 	let version_uuid = gen_uuid();
 	let version_timestamp = next_timestamp(existing_object.as_ref());
+	let versioned = bucket_params.versioning_enabled();
+	let tags = crdt::Lww::new(tags);
+	let replication = crdt::Lww::new(replication);
+	let public_read = crdt::Lww::new(public_read);
+	let storage_class = crdt::Lww::new(storage_class);
 
 	let mut checksummer = match &checksum_mode {
-		ChecksumMode::Verify(expected) => Checksummer::init(expected, !encryption.is_encrypted()),
+		ChecksumMode::Verify(expected) => Checksummer::init(expected, encryption.has_md5_etag()),
 		ChecksumMode::Calculate(algo) => {
-			Checksummer::init(&Default::default(), !encryption.is_encrypted()).add(*algo)
+			Checksummer::init(&Default::default(), encryption.has_md5_etag()).add(*algo)
 		}
 		ChecksumMode::VerifyFrom { .. } => {
 			// Checksums are calculated by the garage_api_common::signature module
@@ -199,15 +263,21 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 				},
 				inline_data,
 			)),
+			versioned,
+			tags,
+			lock,
+			replication,
+			public_read,
+			storage_class,
 		};
-
-		let object = Object::new(*bucket_id, key.into(), vec![object_version]);
-		garage.object_table.insert(&object).await?;
+		commit_object_version(ctx, key, object_version, preconditions).await?;
 
 		return Ok(SaveStreamResult {
 			version_uuid,
 			version_timestamp,
+			versioned,
 			etag,
+			size,
 		});
 	}
 
@@ -230,8 +300,16 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		state: ObjectVersionState::Uploading {
 			encryption: encryption.encrypt_meta(meta.clone())?,
 			checksum_algorithm: None, // don't care; overwritten later
+			checksum_type: None,
+			commit_marker: false,
 			multipart: false,
 		},
+		versioned,
+		tags,
+		lock,
+		replication,
+		public_read,
+		storage_class,
 	};
 	let object = Object::new(*bucket_id, key.into(), vec![object_version.clone()]);
 	garage.object_table.insert(&object).await?;
@@ -254,7 +332,8 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	let (total_size, mut checksums, first_block_hash) = read_and_put_blocks(
 		ctx,
 		&version,
-		encryption,
+		encryption.clone(),
+		object_version.storage_class.get().clone(),
 		1,
 		first_block,
 		chunker,
@@ -297,8 +376,7 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		},
 		first_block_hash,
 	));
-	let object = Object::new(*bucket_id, key.into(), vec![object_version]);
-	garage.object_table.insert(&object).await?;
+	commit_object_version(ctx, key, object_version, preconditions).await?;
 
 	// We were not interrupted, everything went fine.
 	// We won't have to clean up on drop.
@@ -307,10 +385,178 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	Ok(SaveStreamResult {
 		version_uuid,
 		version_timestamp,
+		versioned,
 		etag,
+		size: total_size,
 	})
 }
 
+/// Preconditions on the current state of an object for a write to be
+/// accepted, as given by the If-Match and If-None-Match headers
+#[derive(Default)]
+pub(crate) struct WritePreconditions {
+	if_match: Option<Vec<String>>,
+	if_none_match: bool,
+}
+
+impl WritePreconditions {
+	pub(crate) fn parse(headers: &HeaderMap<HeaderValue>) -> Result<Self, Error> {
+		let if_match = headers
+			.get(IF_MATCH)
+			.map(|x| x.to_str())
+			.transpose()?
+			.map(|x| {
+				x.split(',')
+					.map(|m| m.trim().trim_matches('"').to_string())
+					.collect::<Vec<_>>()
+			});
+		let if_none_match = match headers.get(IF_NONE_MATCH) {
+			None => false,
+			Some(x) if x.to_str()?.trim() == "*" => true,
+			Some(_) => {
+				return Err(Error::NotImplemented(
+					"If-None-Match is only supported with value *".into(),
+				))
+			}
+		};
+		Ok(Self {
+			if_match,
+			if_none_match,
+		})
+	}
+
+	fn is_empty(&self) -> bool {
+		self.if_match.is_none() && !self.if_none_match
+	}
+
+	/// Check the preconditions against the current version of the object.
+	/// An object whose current version is a delete marker does not exist.
+	pub(crate) fn check(&self, object: Option<&Object>) -> Result<(), Error> {
+		let current_etag = object
+			.and_then(|o| o.current_version())
+			.and_then(|v| match &v.state {
+				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+					Some(meta.etag.as_str())
+				}
+				_ => None,
+			});
+
+		if self.if_none_match && current_etag.is_some() {
+			return Err(Error::PreconditionFailed);
+		}
+		if let Some(im) = &self.if_match {
+			let etag = current_etag.ok_or(Error::NoSuchKey)?;
+			if !im.iter().any(|x| x == etag || x == "*") {
+				return Err(Error::PreconditionFailed);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Write a complete object version in the object table.
+///
+/// If the write is conditional, a commit marker (a short-lived version in
+/// uploading state, flagged with `commit_marker`) is written first, and the
+/// preconditions are checked again against the object as read back from the
+/// table. When two conditional writes are committed concurrently, at least one
+/// of them sees the marker of the other and fails with
+/// ConditionalRequestConflict.
+#[allow(clippy::ptr_arg)]
+pub(crate) async fn commit_object_version(
+	ctx: &ReqCtx,
+	key: &String,
+	object_version: ObjectVersion,
+	preconditions: &WritePreconditions,
+) -> Result<(), Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = ctx;
+
+	if preconditions.is_empty() {
+		let object = Object::new(*bucket_id, key.into(), vec![object_version]);
+		garage.object_table.insert(&object).await?;
+		return Ok(());
+	}
+
+	let mut marker = ObjectVersion {
+		uuid: gen_uuid(),
+		timestamp: std::cmp::max(now_msec(), object_version.timestamp + 1),
+		state: ObjectVersionState::Uploading {
+			encryption: ObjectVersionEncryption::Plaintext {
+				inner: ObjectVersionMetaInner {
+					headers: vec![],
+					checksum: None,
+				},
+			},
+			checksum_algorithm: None,
+			checksum_type: None,
+			commit_marker: true,
+			multipart: false,
+		},
+		versioned: false,
+		tags: Default::default(),
+		lock: Default::default(),
+		replication: Default::default(),
+		public_read: Default::default(),
+		storage_class: Default::default(),
+	};
+	let mut marker_cleanup = InterruptedCleanup(Some(InterruptedCleanupInner {
+		garage: garage.clone(),
+		bucket_id: *bucket_id,
+		key: key.into(),
+		version_uuid: marker.uuid,
+		version_timestamp: marker.timestamp,
+	}));
+	let object = Object::new(*bucket_id, key.into(), vec![marker.clone()]);
+	garage.object_table.insert(&object).await?;
+
+	let existing_object = garage.object_table.get(bucket_id, key).await?;
+	check_commit(
+		existing_object.as_ref(),
+		&object_version,
+		&marker,
+		preconditions,
+		now_msec(),
+	)?;
+
+	// Write the new version and remove the commit marker at once
+	marker.state = ObjectVersionState::Aborted;
+	let object = Object::new(*bucket_id, key.into(), vec![object_version, marker]);
+	garage.object_table.insert(&object).await?;
+	marker_cleanup.cancel();
+
+	Ok(())
+}
+
+fn check_commit(
+	object: Option<&Object>,
+	object_version: &ObjectVersion,
+	marker: &ObjectVersion,
+	preconditions: &WritePreconditions,
+	now: u64,
+) -> Result<(), Error> {
+	preconditions.check(object)?;
+
+	let conflict = object
+		.map(|o| o.versions())
+		.unwrap_or_default()
+		.iter()
+		.filter(|v| v.uuid != object_version.uuid && v.uuid != marker.uuid)
+		.any(|v| {
+			// another version became the current version while we were uploading
+			(v.is_complete() && v.timestamp > object_version.timestamp)
+				// another conditional write is being committed concurrently
+				|| (v.is_commit_marker() && v.timestamp + COMMIT_MARKER_TIMEOUT_MSEC > now)
+		});
+	if conflict {
+		return Err(Error::ConditionalRequestConflict);
+	}
+
+	Ok(())
+}
+
 /// Check that inserting this object with this size doesn't exceed bucket quotas
 pub(crate) async fn check_quotas(
 	ctx: &ReqCtx,
@@ -340,6 +586,12 @@ pub(crate) async fn check_quotas(
 		.unwrap_or_default();
 
 	let (prev_cnt_obj, prev_cnt_size) = match prev_object {
+		// With versioning enabled, previous versions are kept and count
+		// towards the bucket size
+		Some(o) if bucket_params.versioning_enabled() => {
+			let prev_cnt = o.counts().into_iter().collect::<HashMap<_, _>>();
+			(prev_cnt.get(OBJECTS).cloned().unwrap_or_default(), 0)
+		}
 		Some(o) => {
 			let prev_cnt = o.counts().into_iter().collect::<HashMap<_, _>>();
 			(
@@ -375,10 +627,12 @@ pub(crate) async fn check_quotas(
 	Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	ctx: &ReqCtx,
 	version: &Version,
 	encryption: EncryptionParams,
+	storage_class: Option<String>,
 	part_number: u64,
 	first_block: Bytes,
 	mut chunker: StreamChunker<S>,
@@ -443,6 +697,7 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 			match next {
 				Ok(block) => {
 					let unencrypted_len = block.len() as u64;
+					let encryption = encryption.clone();
 					let res = tokio::task::spawn_blocking(move || {
 						let block = encryption.encrypt_block(block)?;
 						let hash = blake2sum(&block);
@@ -520,6 +775,7 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 				block,
 				unencrypted_len,
 				encryption.is_encrypted(),
+				storage_class.clone(),
 				order_stream.order(written_bytes),
 			));
 			written_bytes += unencrypted_len;
@@ -542,6 +798,7 @@ pub(crate) async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> +
 	Ok((total_size, checksums, first_block_hash))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn put_block_and_meta(
 	ctx: &ReqCtx,
 	version: &Version,
@@ -551,6 +808,7 @@ async fn put_block_and_meta(
 	block: Bytes,
 	size: u64,
 	is_encrypted: bool,
+	storage_class: Option<String>,
 	order_tag: OrderTag,
 ) -> Result<(), GarageError> {
 	let ReqCtx { garage, .. } = ctx;
@@ -568,12 +826,17 @@ async fn put_block_and_meta(
 		block: hash,
 		version: version.uuid,
 		deleted: false.into(),
+		storage_class: crdt::Lww::new(storage_class.clone()),
 	};
 
 	futures::try_join!(
-		garage
-			.block_manager
-			.rpc_put_block(hash, block, is_encrypted, Some(order_tag)),
+		garage.block_manager.rpc_put_block(
+			hash,
+			block,
+			is_encrypted,
+			storage_class,
+			Some(order_tag)
+		),
 		garage.version_table.insert(&version),
 		garage.block_ref_table.insert(&block_ref),
 	)?;
@@ -638,6 +901,12 @@ impl Drop for InterruptedCleanup {
 					uuid: info.version_uuid,
 					timestamp: info.version_timestamp,
 					state: ObjectVersionState::Aborted,
+					versioned: false,
+					tags: Default::default(),
+					lock: Default::default(),
+					replication: Default::default(),
+					public_read: Default::default(),
+					storage_class: Default::default(),
 				};
 				let object = Object::new(info.bucket_id, info.key, vec![object_version]);
 				if let Err(e) = info.garage.object_table.insert(&object).await {
@@ -678,23 +947,38 @@ pub(crate) fn extract_metadata_headers(
 				std::str::from_utf8(value.as_bytes())?.to_string(),
 			));
 		}
-		if name == X_AMZ_WEBSITE_REDIRECT_LOCATION {
-			let value = std::str::from_utf8(value.as_bytes())?.to_string();
-			if !(value.starts_with("/")
-				|| value.starts_with("http://")
-				|| value.starts_with("https://"))
-			{
-				return Err(Error::bad_request(format!(
-					"Invalid {X_AMZ_WEBSITE_REDIRECT_LOCATION} header",
-				)));
-			}
-			ret.push((X_AMZ_WEBSITE_REDIRECT_LOCATION.to_string(), value));
-		}
+	}
+
+	// Preserve website redirect location
+	if let Some(value) = extract_website_redirect_location(headers)? {
+		ret.push((X_AMZ_WEBSITE_REDIRECT_LOCATION.to_string(), value));
 	}
 
 	Ok(ret)
 }
 
+/// Maximum length of the x-amz-website-redirect-location header
+const MAX_WEBSITE_REDIRECT_LOCATION_LEN: usize = 2048;
+
+/// Returns the value of the x-amz-website-redirect-location header,
+/// which must be an absolute path or an http(s) URL
+pub(crate) fn extract_website_redirect_location(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<String>, Error> {
+	let value = match headers.get(X_AMZ_WEBSITE_REDIRECT_LOCATION) {
+		Some(v) => std::str::from_utf8(v.as_bytes())?.to_string(),
+		None => return Ok(None),
+	};
+	if !(value.starts_with('/') || value.starts_with("http://") || value.starts_with("https://"))
+		|| value.len() > MAX_WEBSITE_REDIRECT_LOCATION_LEN
+	{
+		return Err(Error::bad_request(format!(
+			"Invalid {X_AMZ_WEBSITE_REDIRECT_LOCATION} header",
+		)));
+	}
+	Ok(Some(value))
+}
+
 pub(crate) fn next_timestamp(existing_object: Option<&Object>) -> u64 {
 	existing_object
 		.as_ref()
@@ -702,3 +986,209 @@ pub(crate) fn next_timestamp(existing_object: Option<&Object>) -> u64 {
 		.map(|t| std::cmp::max(t + 1, now_msec()))
 		.unwrap_or_else(now_msec)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(uuid: u8, timestamp: u64, state: ObjectVersionState) -> ObjectVersion {
+		ObjectVersion {
+			uuid: Uuid::from([uuid; 32]),
+			timestamp,
+			state,
+			versioned: false,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
+		}
+	}
+
+	fn data(etag: &str) -> ObjectVersionState {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(
+			ObjectVersionMeta {
+				size: 0,
+				etag: etag.into(),
+				encryption: ObjectVersionEncryption::Plaintext {
+					inner: ObjectVersionMetaInner {
+						headers: vec![],
+						checksum: None,
+					},
+				},
+			},
+			vec![],
+		))
+	}
+
+	fn uploading(commit_marker: bool) -> ObjectVersionState {
+		ObjectVersionState::Uploading {
+			encryption: ObjectVersionEncryption::Plaintext {
+				inner: ObjectVersionMetaInner {
+					headers: vec![],
+					checksum: None,
+				},
+			},
+			checksum_algorithm: None,
+			checksum_type: None,
+			commit_marker,
+			multipart: false,
+		}
+	}
+
+	fn object(versions: Vec<ObjectVersion>) -> Object {
+		Object::new(Uuid::from([0x42; 32]), "a".into(), versions)
+	}
+
+	fn preconditions(if_match: Option<&str>, if_none_match: bool) -> WritePreconditions {
+		WritePreconditions {
+			if_match: if_match.map(|x| vec![x.to_string()]),
+			if_none_match,
+		}
+	}
+
+	#[test]
+	fn test_write_preconditions() {
+		let obj = object(vec![version(1, 10, data("abc"))]);
+		let deleted = object(vec![
+			version(1, 10, data("abc")),
+			version(
+				2,
+				20,
+				ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			),
+		]);
+
+		let inm = preconditions(None, true);
+		assert!(inm.check(None).is_ok());
+		assert!(inm.check(Some(&deleted)).is_ok());
+		assert!(matches!(
+			inm.check(Some(&obj)),
+			Err(Error::PreconditionFailed)
+		));
+
+		assert!(preconditions(Some("abc"), false).check(Some(&obj)).is_ok());
+		assert!(preconditions(Some("*"), false).check(Some(&obj)).is_ok());
+		assert!(matches!(
+			preconditions(Some("def"), false).check(Some(&obj)),
+			Err(Error::PreconditionFailed)
+		));
+		assert!(matches!(
+			preconditions(Some("abc"), false).check(Some(&deleted)),
+			Err(Error::NoSuchKey)
+		));
+		assert!(matches!(
+			preconditions(Some("abc"), false).check(None),
+			Err(Error::NoSuchKey)
+		));
+	}
+
+	#[test]
+	fn test_check_commit() {
+		let inm = preconditions(None, true);
+		let ours = version(1, 100, data("abc"));
+		let marker = version(2, 1000, uploading(true));
+		// as seen in the object table before the commit
+		let uploading_ours = version(1, 100, uploading(false));
+
+		// no concurrent write
+		let obj = object(vec![uploading_ours.clone(), marker.clone()]);
+		assert!(check_commit(Some(&obj), &ours, &marker, &inm, 1000).is_ok());
+
+		// concurrent write committed first
+		let obj = object(vec![
+			uploading_ours.clone(),
+			version(3, 500, data("def")),
+			marker.clone(),
+		]);
+		assert!(matches!(
+			check_commit(Some(&obj), &ours, &marker, &inm, 1000),
+			Err(Error::PreconditionFailed)
+		));
+
+		// concurrent write is being committed
+		let obj = object(vec![
+			uploading_ours.clone(),
+			marker.clone(),
+			version(3, 1001, uploading(true)),
+		]);
+		assert!(matches!(
+			check_commit(Some(&obj), &ours, &marker, &inm, 1000),
+			Err(Error::ConditionalRequestConflict)
+		));
+
+		// stale commit marker is ignored
+		let obj = object(vec![
+			uploading_ours.clone(),
+			version(3, 200, uploading(true)),
+			marker.clone(),
+		]);
+		assert!(check_commit(
+			Some(&obj),
+			&ours,
+			&marker,
+			&inm,
+			200 + COMMIT_MARKER_TIMEOUT_MSEC
+		)
+		.is_ok());
+
+		// a plain upload still in progress is not a conflict
+		let obj = object(vec![
+			uploading_ours.clone(),
+			marker.clone(),
+			version(3, 1001, uploading(false)),
+		]);
+		assert!(check_commit(Some(&obj), &ours, &marker, &inm, 1000).is_ok());
+
+		// overwrite with if-match after a newer version was written
+		let obj = object(vec![
+			uploading_ours.clone(),
+			version(3, 500, data("def")),
+			marker.clone(),
+		]);
+		assert!(matches!(
+			check_commit(
+				Some(&obj),
+				&ours,
+				&marker,
+				&preconditions(Some("def"), false),
+				1000
+			),
+			Err(Error::ConditionalRequestConflict)
+		));
+	}
+
+	#[test]
+	fn test_website_redirect_location() {
+		let headers = |value: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(
+				X_AMZ_WEBSITE_REDIRECT_LOCATION,
+				HeaderValue::from_str(value).unwrap(),
+			);
+			headers
+		};
+
+		assert_eq!(
+			extract_website_redirect_location(&HeaderMap::new()).unwrap(),
+			None
+		);
+		assert_eq!(
+			extract_website_redirect_location(&headers("/docs/new.html")).unwrap(),
+			Some("/docs/new.html".to_string())
+		);
+		assert_eq!(
+			extract_metadata_headers(&headers("https://garage.tld/")).unwrap(),
+			vec![(
+				X_AMZ_WEBSITE_REDIRECT_LOCATION.to_string(),
+				"https://garage.tld/".to_string()
+			)]
+		);
+		assert!(extract_website_redirect_location(&headers("docs/new.html")).is_err());
+		assert!(extract_website_redirect_location(&headers(&format!(
+			"/{}",
+			"a".repeat(MAX_WEBSITE_REDIRECT_LOCATION_LEN)
+		)))
+		.is_err());
+	}
+}
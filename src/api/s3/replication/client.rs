@@ -0,0 +1,179 @@
+//! Minimal S3 client used to write replicated objects to remote endpoints.
+//! Requests are signed with AWS signature v4, without signing the payload
+//! so that object data can be streamed.
+
+use std::convert::TryFrom;
+
+use bytes::Bytes;
+use chrono::Utc;
+use futures::stream::StreamExt;
+use hmac::Mac;
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, HOST};
+use http::{Method, Request, Uri};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, StreamBody};
+use hyper::body::Frame;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::{connect::HttpConnector, Client as HttpClient};
+use hyper_util::rt::TokioExecutor;
+
+use garage_net::stream::ByteStream;
+use garage_util::config::ReplicationRemoteConfig;
+use garage_util::error::Error as GarageError;
+
+use garage_api_common::encoding::uri_encode;
+use garage_api_common::signature::payload::{canonical_request, string_to_sign, QueryMap};
+use garage_api_common::signature::{
+	compute_scope, signing_hmac, AWS4_HMAC_SHA256, LONG_DATETIME, UNSIGNED_PAYLOAD,
+	X_AMZ_CONTENT_SHA256, X_AMZ_DATE,
+};
+
+type Body = BoxBody<Bytes, std::io::Error>;
+
+/// Maximum length of the error message returned by the remote endpoint
+/// that is included in our error messages
+const MAX_ERROR_BODY_LEN: usize = 1024;
+
+pub(crate) struct RemoteClient {
+	client: HttpClient<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl RemoteClient {
+	pub(crate) fn new() -> Result<Self, GarageError> {
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()?
+			.https_or_http()
+			.enable_http1()
+			.build();
+		let client = HttpClient::builder(TokioExecutor::new()).build(connector);
+		Ok(Self { client })
+	}
+
+	/// Write an object of size `size` in bucket `bucket` of the remote endpoint,
+	/// `headers` being its metadata headers
+	pub(crate) async fn put_object(
+		&self,
+		remote: &ReplicationRemoteConfig,
+		bucket: &str,
+		key: &str,
+		mut headers: HeaderMap,
+		size: u64,
+		data: ByteStream,
+	) -> Result<(), GarageError> {
+		headers.insert(CONTENT_LENGTH, HeaderValue::from(size));
+		let body = BodyExt::boxed(StreamBody::new(data.map(|x| x.map(Frame::data))));
+		self.request(remote, Method::PUT, bucket, key, headers, body)
+			.await
+	}
+
+	/// Delete an object in bucket `bucket` of the remote endpoint
+	pub(crate) async fn delete_object(
+		&self,
+		remote: &ReplicationRemoteConfig,
+		bucket: &str,
+		key: &str,
+		headers: HeaderMap,
+	) -> Result<(), GarageError> {
+		let body = Empty::new().map_err(|never| match never {}).boxed();
+		self.request(remote, Method::DELETE, bucket, key, headers, body)
+			.await
+	}
+
+	async fn request(
+		&self,
+		remote: &ReplicationRemoteConfig,
+		method: Method,
+		bucket: &str,
+		key: &str,
+		mut headers: HeaderMap,
+		body: Body,
+	) -> Result<(), GarageError> {
+		let uri = format!(
+			"{}/{}/{}",
+			remote.endpoint.trim_end_matches('/'),
+			bucket,
+			uri_encode(key, false)
+		);
+		let uri = Uri::try_from(&uri)
+			.map_err(|e| GarageError::Message(format!("invalid remote URI {}: {}", uri, e)))?;
+		let host = uri
+			.authority()
+			.ok_or_else(|| GarageError::Message(format!("remote URI {} has no host", uri)))?
+			.to_string();
+
+		// Sign all headers of the request
+		let now = Utc::now();
+		headers.insert(HOST, header_value(&host)?);
+		headers.insert(
+			X_AMZ_DATE,
+			header_value(&now.format(LONG_DATETIME).to_string())?,
+		);
+		headers.insert(
+			X_AMZ_CONTENT_SHA256,
+			HeaderValue::from_static(UNSIGNED_PAYLOAD),
+		);
+
+		let mut signed_headers = headers.keys().cloned().collect::<Vec<HeaderName>>();
+		signed_headers.sort_by(|h1, h2| h1.as_str().cmp(h2.as_str()));
+
+		let canonical_request = canonical_request(
+			"s3",
+			&method,
+			uri.path(),
+			&QueryMap::with_capacity(0),
+			&headers,
+			&signed_headers,
+			UNSIGNED_PAYLOAD,
+		)
+		.map_err(|e| GarageError::Message(format!("could not sign request: {}", e)))?;
+		let scope = compute_scope(&now, &remote.region, "s3");
+		let mut hmac = signing_hmac(&now, &remote.secret_access_key, &remote.region, "s3")
+			.map_err(|e| GarageError::Message(format!("could not sign request: {}", e)))?;
+		hmac.update(string_to_sign(&now, &scope, &canonical_request).as_bytes());
+		let signature = hex::encode(hmac.finalize().into_bytes());
+
+		let signed_headers = signed_headers
+			.iter()
+			.map(HeaderName::as_str)
+			.collect::<Vec<_>>()
+			.join(";");
+		let authorization = format!(
+			"{} Credential={}/{},SignedHeaders={},Signature={}",
+			AWS4_HMAC_SHA256, remote.access_key_id, scope, signed_headers, signature
+		);
+		headers.insert(AUTHORIZATION, header_value(&authorization)?);
+
+		let mut req = Request::builder().method(method).uri(uri);
+		*req.headers_mut().unwrap() = headers;
+		let req = req
+			.body(body)
+			.map_err(|e| GarageError::Message(format!("invalid request: {}", e)))?;
+
+		let resp = self
+			.client
+			.request(req)
+			.await
+			.map_err(|e| GarageError::Message(format!("request to remote failed: {}", e)))?;
+		if resp.status().is_success() {
+			return Ok(());
+		}
+
+		let status = resp.status();
+		let body = resp
+			.into_body()
+			.collect()
+			.await
+			.map(|b| b.to_bytes())
+			.unwrap_or_default();
+		let body = String::from_utf8_lossy(&body[..std::cmp::min(body.len(), MAX_ERROR_BODY_LEN)]);
+		Err(GarageError::Message(format!(
+			"remote returned status {}: {}",
+			status, body
+		)))
+	}
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, GarageError> {
+	HeaderValue::from_str(value)
+		.map_err(|e| GarageError::Message(format!("invalid header value {}: {}", value, e)))
+}
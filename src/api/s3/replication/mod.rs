@@ -0,0 +1,555 @@
+//! Asynchronous replication of buckets to remote S3 endpoints.
+//!
+//! New object versions that match a replication rule of their bucket are
+//! created with the `PENDING` replication status, and queued on the node
+//! that handled the request. The replication worker then copies them to the
+//! destination bucket on the remote endpoint, and sets their status to
+//! `COMPLETED` (or `FAILED` if all attempts failed). Versions written by the
+//! replication worker of another cluster carry the `REPLICA` status and are
+//! never replicated again, so that two clusters can replicate to each other.
+
+mod client;
+mod worker;
+
+pub use worker::ReplicationWorker;
+
+use std::collections::HashSet;
+
+use quick_xml::de::from_reader;
+
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::{
+	Bucket, ReplicationConfig as GarageReplicationConfig, ReplicationRule as GarageReplicationRule,
+};
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::{ObjectTagSet, ReplicationStatus};
+use garage_model::s3::replication::{new_version_replication_status, ReplicationTask};
+use garage_util::crdt;
+use garage_util::data::*;
+use garage_util::time::now_msec;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::tagging::Tag;
+use crate::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+
+pub const X_AMZ_REPLICATION_STATUS: HeaderName =
+	HeaderName::from_static("x-amz-replication-status");
+
+const S3_ARN_PREFIX: &str = "arn:garage:s3::";
+const MAX_RULES: usize = 1000;
+
+pub async fn handle_get_bucket_replication(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let config = bucket_params
+		.replication_config
+		.get()
+		.as_ref()
+		.ok_or(Error::ReplicationConfigurationNotFound)?;
+
+	let conf = ReplicationConfiguration::from_garage_replication_config(config);
+	let xml = to_xml_with_header(&conf)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_delete_bucket_replication(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+	bucket_params.replication_config.update(None);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+pub async fn handle_put_bucket_replication(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	if !bucket_params.versioning_enabled() {
+		return Err(Error::InvalidBucketState(
+			"Versioning must be 'Enabled' on the bucket to apply a replication configuration"
+				.into(),
+		));
+	}
+
+	let body = req.into_body().collect().await?;
+
+	let conf: ReplicationConfiguration = from_reader(&body as &[u8])?;
+	let config = conf.validate_into_garage_replication_config(&garage)?;
+	let backfill = config
+		.rules
+		.iter()
+		.any(|r| r.enabled && r.existing_object_replication);
+
+	bucket_params.replication_config.update(Some(config));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	if backfill {
+		garage.replication_queue.push(
+			now_msec(),
+			&ReplicationTask::Backfill {
+				bucket_id,
+				start_after: None,
+			},
+		)?;
+	}
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+/// Determine the replication status of a version created by a request:
+/// versions written by the replication worker of another cluster are marked
+/// as replicas, other versions are pending if they match a replication rule
+pub(crate) fn request_replication_status(
+	ctx: &ReqCtx,
+	headers: &HeaderMap,
+	key: &str,
+	tags: &ObjectTagSet,
+	delete_marker: bool,
+) -> Result<Option<ReplicationStatus>, Error> {
+	match headers.get(X_AMZ_REPLICATION_STATUS) {
+		Some(v) if v == "REPLICA" => Ok(Some(ReplicationStatus::Replica)),
+		Some(_) => Err(Error::bad_request(
+			"x-amz-replication-status can only be set to REPLICA",
+		)),
+		None => Ok(new_version_replication_status(
+			&ctx.bucket_params,
+			key,
+			tags,
+			delete_marker,
+		)),
+	}
+}
+
+/// Queue the replication of a version created by a request, if its
+/// replication status is pending. Failing to do so does not fail the request.
+pub(crate) fn queue_replication(
+	ctx: &ReqCtx,
+	key: &str,
+	version_uuid: Uuid,
+	status: Option<ReplicationStatus>,
+) {
+	if status != Some(ReplicationStatus::Pending) {
+		return;
+	}
+	let task = ReplicationTask::Version {
+		bucket_id: ctx.bucket_id,
+		key: key.to_string(),
+		version_uuid,
+		attempts: 0,
+	};
+	if let Err(e) = ctx.garage.replication_queue.push(now_msec(), &task) {
+		error!("Could not queue object replication: {}", e);
+	}
+}
+
+pub(crate) fn add_replication_status_header(
+	replication: &crdt::Lww<Option<ReplicationStatus>>,
+	mut resp: http::response::Builder,
+) -> http::response::Builder {
+	if let Some(status) = replication.get() {
+		let status = match status {
+			ReplicationStatus::Pending => "PENDING",
+			ReplicationStatus::Completed => "COMPLETED",
+			ReplicationStatus::Failed => "FAILED",
+			ReplicationStatus::Replica => "REPLICA",
+		};
+		resp = resp.header(X_AMZ_REPLICATION_STATUS, status);
+	}
+	resp
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "ReplicationConfiguration")]
+pub struct ReplicationConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Role")]
+	pub role: Value,
+	#[serde(rename = "Rule", default)]
+	pub rules: Vec<ReplicationRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReplicationRule {
+	#[serde(rename = "ID")]
+	pub id: Option<Value>,
+	#[serde(rename = "Priority")]
+	pub priority: Option<IntValue>,
+	#[serde(rename = "Status")]
+	pub status: Value,
+	/// Prefix of the first version of the configuration schema,
+	/// which cannot be used together with a Filter
+	#[serde(rename = "Prefix", skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<Value>,
+	#[serde(rename = "Filter")]
+	pub filter: Option<Filter>,
+	#[serde(rename = "DeleteMarkerReplication")]
+	pub delete_marker_replication: Option<StatusElement>,
+	#[serde(rename = "ExistingObjectReplication")]
+	pub existing_object_replication: Option<StatusElement>,
+	#[serde(rename = "Destination")]
+	pub destination: Destination,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Filter {
+	#[serde(rename = "And", skip_serializing_if = "Option::is_none")]
+	pub and: Option<FilterAnd>,
+	#[serde(rename = "Prefix", skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<Value>,
+	#[serde(rename = "Tag", skip_serializing_if = "Option::is_none")]
+	pub tag: Option<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FilterAnd {
+	#[serde(rename = "Prefix", skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<Value>,
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatusElement {
+	#[serde(rename = "Status")]
+	pub status: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Destination {
+	#[serde(rename = "Bucket")]
+	pub bucket: Value,
+}
+
+impl ReplicationConfiguration {
+	fn validate_into_garage_replication_config(
+		self,
+		garage: &Garage,
+	) -> Result<GarageReplicationConfig, Error> {
+		if self.rules.is_empty() {
+			return Err(Error::bad_request(
+				"Replication configuration must contain at least one rule",
+			));
+		}
+		if self.rules.len() > MAX_RULES {
+			return Err(Error::bad_request(format!(
+				"Replication configuration cannot contain more than {} rules",
+				MAX_RULES
+			)));
+		}
+
+		let mut ids = HashSet::new();
+		let mut rules = vec![];
+		for rule in self.rules {
+			let rule = rule.validate_into_garage_replication_rule(garage)?;
+			if let Some(id) = &rule.id {
+				if !ids.insert(id.clone()) {
+					return Err(Error::bad_request(format!("Duplicate rule ID: {}", id)));
+				}
+			}
+			rules.push(rule);
+		}
+
+		Ok(GarageReplicationConfig {
+			role: self.role.0,
+			rules,
+		})
+	}
+
+	fn from_garage_replication_config(config: &GarageReplicationConfig) -> Self {
+		Self {
+			xmlns: (),
+			role: Value(config.role.clone()),
+			rules: config
+				.rules
+				.iter()
+				.map(ReplicationRule::from_garage_replication_rule)
+				.collect(),
+		}
+	}
+}
+
+impl ReplicationRule {
+	fn validate_into_garage_replication_rule(
+		self,
+		garage: &Garage,
+	) -> Result<GarageReplicationRule, Error> {
+		let enabled = parse_status(&self.status, "Status")?;
+
+		let (prefix, tags) = match (self.prefix, self.filter) {
+			(Some(_), Some(_)) => {
+				return Err(Error::bad_request(
+					"Rule cannot contain both <Prefix> and <Filter>",
+				))
+			}
+			(Some(prefix), None) => (Some(prefix.0), vec![]),
+			(None, Some(filter)) => filter.validate()?,
+			(None, None) => (None, vec![]),
+		};
+
+		let delete_marker_replication = self
+			.delete_marker_replication
+			.map(|x| parse_status(&x.status, "DeleteMarkerReplication"))
+			.transpose()?
+			.unwrap_or(false);
+		if delete_marker_replication && !tags.is_empty() {
+			return Err(Error::bad_request(
+				"Delete marker replication is not supported if any tag filter is specified",
+			));
+		}
+
+		let existing_object_replication = self
+			.existing_object_replication
+			.map(|x| parse_status(&x.status, "ExistingObjectReplication"))
+			.transpose()?
+			.unwrap_or(false);
+
+		let destination_arn = self.destination.bucket.0;
+		let (remote, destination_bucket) = destination_arn
+			.strip_prefix(S3_ARN_PREFIX)
+			.and_then(|x| x.split_once(':'))
+			.filter(|(remote, bucket)| !remote.is_empty() && !bucket.is_empty())
+			.ok_or_else(|| {
+				Error::bad_request(format!(
+					"Invalid destination bucket ARN {}, expected {}<remote>:<bucket>",
+					destination_arn, S3_ARN_PREFIX
+				))
+			})?;
+		if !garage
+			.config
+			.bucket_replication
+			.remotes
+			.contains_key(remote)
+		{
+			return Err(Error::bad_request(format!(
+				"Replication remote {} is not configured",
+				remote
+			)));
+		}
+
+		Ok(GarageReplicationRule {
+			id: self.id.map(|x| x.0),
+			priority: self.priority.map(|x| x.0).unwrap_or(0),
+			enabled,
+			prefix,
+			tags,
+			remote: remote.to_string(),
+			destination_bucket: destination_bucket.to_string(),
+			delete_marker_replication,
+			existing_object_replication,
+		})
+	}
+
+	fn from_garage_replication_rule(rule: &GarageReplicationRule) -> Self {
+		Self {
+			id: rule.id.as_deref().map(Value::from),
+			priority: Some(IntValue(rule.priority)),
+			status: status_value(rule.enabled),
+			prefix: None,
+			filter: Some(Filter::from_garage_replication_rule(rule)),
+			delete_marker_replication: Some(StatusElement {
+				status: status_value(rule.delete_marker_replication),
+			}),
+			existing_object_replication: Some(StatusElement {
+				status: status_value(rule.existing_object_replication),
+			}),
+			destination: Destination {
+				bucket: Value(format!(
+					"{}{}:{}",
+					S3_ARN_PREFIX, rule.remote, rule.destination_bucket
+				)),
+			},
+		}
+	}
+}
+
+/// Prefix and tags of a validated filter
+type FilterParams = (Option<String>, Vec<(String, String)>);
+
+impl Filter {
+	fn validate(self) -> Result<FilterParams, Error> {
+		let tag_tuple = |t: Tag| (t.key.0, t.value.0);
+		match self {
+			Filter {
+				and: Some(and),
+				prefix: None,
+				tag: None,
+			} => Ok((
+				and.prefix.map(|x| x.0),
+				and.tags.into_iter().map(tag_tuple).collect(),
+			)),
+			Filter { and: Some(_), .. } => Err(Error::bad_request(
+				"Filter cannot contain both <And> and another condition",
+			)),
+			Filter {
+				prefix: Some(_),
+				tag: Some(_),
+				..
+			} => Err(Error::bad_request(
+				"Multiple Filter conditions must be wrapped in an <And> tag",
+			)),
+			Filter { prefix, tag, .. } => Ok((
+				prefix.map(|x| x.0),
+				tag.into_iter().map(tag_tuple).collect(),
+			)),
+		}
+	}
+
+	fn from_garage_replication_rule(rule: &GarageReplicationRule) -> Self {
+		let prefix = rule.prefix.as_deref().map(Value::from);
+		let mut tags = rule
+			.tags
+			.iter()
+			.map(|(k, v)| Tag {
+				key: Value(k.clone()),
+				value: Value(v.clone()),
+			})
+			.collect::<Vec<_>>();
+		match (prefix.is_some() as usize) + tags.len() {
+			0 | 1 => Filter {
+				and: None,
+				prefix,
+				tag: tags.pop(),
+			},
+			_ => Filter {
+				and: Some(FilterAnd { prefix, tags }),
+				..Default::default()
+			},
+		}
+	}
+}
+
+fn parse_status(status: &Value, element: &str) -> Result<bool, Error> {
+	match status.0.as_str() {
+		"Enabled" => Ok(true),
+		"Disabled" => Ok(false),
+		_ => Err(Error::bad_request(format!(
+			"Invalid value for <Status> in <{}>",
+			element
+		))),
+	}
+}
+
+fn status_value(enabled: bool) -> Value {
+	if enabled {
+		Value::from("Enabled")
+	} else {
+		Value::from("Disabled")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_replication_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ReplicationConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Role>arn:aws:iam::123456789012:role/replication</Role>
+  <Rule>
+    <ID>documents</ID>
+    <Priority>2</Priority>
+    <Status>Enabled</Status>
+    <Filter>
+      <And>
+        <Prefix>documents/</Prefix>
+        <Tag><Key>replicate</Key><Value>yes</Value></Tag>
+      </And>
+    </Filter>
+    <DeleteMarkerReplication><Status>Disabled</Status></DeleteMarkerReplication>
+    <ExistingObjectReplication><Status>Enabled</Status></ExistingObjectReplication>
+    <Destination><Bucket>arn:garage:s3::site2:backup</Bucket></Destination>
+  </Rule>
+  <Rule>
+    <Status>Disabled</Status>
+    <Prefix>logs/</Prefix>
+    <Destination><Bucket>arn:garage:s3::site2:logs</Bucket></Destination>
+  </Rule>
+</ReplicationConfiguration>"#;
+		let conf: ReplicationConfiguration = from_str(message).unwrap();
+		assert_eq!(conf.rules.len(), 2);
+		assert_eq!(conf.rules[0].priority, Some(IntValue(2)));
+		assert_eq!(conf.rules[1].prefix, Some(Value::from("logs/")));
+
+		let rule = GarageReplicationRule {
+			id: Some("documents".into()),
+			priority: 2,
+			enabled: true,
+			prefix: Some("documents/".into()),
+			tags: vec![("replicate".into(), "yes".into())],
+			remote: "site2".into(),
+			destination_bucket: "backup".into(),
+			delete_marker_replication: false,
+			existing_object_replication: true,
+		};
+		assert_eq!(
+			conf.rules[0].filter.as_ref().unwrap(),
+			&Filter::from_garage_replication_rule(&rule)
+		);
+
+		let serialized = to_xml_with_header(
+			&ReplicationConfiguration::from_garage_replication_config(&GarageReplicationConfig {
+				role: conf.role.0.clone(),
+				rules: vec![rule],
+			}),
+		)?;
+		let reparsed: ReplicationConfiguration = from_str(&serialized).unwrap();
+		assert_eq!(reparsed.rules[0].filter, conf.rules[0].filter);
+		assert_eq!(reparsed.rules[0].destination, conf.rules[0].destination);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_filter_validation() {
+		let filter: Filter =
+			from_str("<Filter><Prefix>a/</Prefix><Tag><Key>k</Key><Value>v</Value></Tag></Filter>")
+				.unwrap();
+		assert!(filter.validate().is_err());
+
+		let filter: Filter =
+			from_str("<Filter><Tag><Key>k</Key><Value>v</Value></Tag></Filter>").unwrap();
+		assert_eq!(
+			filter.validate().unwrap(),
+			(None, vec![("k".to_string(), "v".to_string())])
+		);
+	}
+}
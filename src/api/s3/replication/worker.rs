@@ -0,0 +1,447 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::select;
+use tokio::sync::watch;
+
+use garage_table::{EmptyKey, EnumerationOrder};
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+use garage_util::time::*;
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+use garage_model::s3::replication::{QueuedTask, ReplicationTask};
+
+use super::client::RemoteClient;
+use super::X_AMZ_REPLICATION_STATUS;
+use crate::encryption::EncryptionParams;
+use crate::get::full_object_byte_stream;
+use crate::tagging::X_AMZ_TAGGING;
+
+// The delay before a failed replication is retried, multiplied by 2, 4, 8, etc.
+// for every consecutive failure, up to 10 seconds * 2^8 (~43 minutes).
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const RETRY_MAX_BACKOFF_POWER: u32 = 8;
+// Versions that could not be replicated after this number of attempts
+// (about 6 hours) are marked as failed.
+const MAX_ATTEMPTS: u32 = 16;
+
+// Number of objects that are examined in each step of a backfill
+const BACKFILL_BATCH_SIZE: usize = 100;
+
+pub struct ReplicationWorker {
+	garage: Arc<Garage>,
+	client: RemoteClient,
+	replicated: usize,
+	failed: usize,
+	next_attempt: Option<u64>,
+}
+
+impl ReplicationWorker {
+	pub fn new(garage: Arc<Garage>) -> Result<Self, GarageError> {
+		Ok(Self {
+			garage,
+			client: RemoteClient::new()?,
+			replicated: 0,
+			failed: 0,
+			next_attempt: None,
+		})
+	}
+
+	/// Copy a version to the remote endpoint of the replication rule that
+	/// applies to it, and return its new replication status
+	async fn replicate_version(
+		&self,
+		bucket_id: Uuid,
+		key: &str,
+		version_uuid: Uuid,
+	) -> Result<Option<ReplicationStatus>, GarageError> {
+		let bucket = self
+			.garage
+			.bucket_table
+			.get(&EmptyKey, &bucket_id)
+			.await?
+			.filter(|b| !b.is_deleted());
+		let object = self
+			.garage
+			.object_table
+			.get(&bucket_id, &key.to_string())
+			.await?;
+		let (params, version) = match (
+			bucket.as_ref().and_then(|b| b.params()),
+			object
+				.as_ref()
+				.and_then(|o| o.versions().iter().find(|v| v.uuid == version_uuid)),
+		) {
+			(Some(params), Some(version)) => (params, version),
+			// The bucket or the version no longer exist, nothing to do
+			_ => return Ok(None),
+		};
+		let data = match &version.state {
+			ObjectVersionState::Complete(data) => data,
+			_ => return Ok(None),
+		};
+
+		let rule = match params
+			.replication_config
+			.get()
+			.as_ref()
+			.and_then(|c| c.matching_rule(key, &version.tags.get().0))
+		{
+			Some(rule) => rule,
+			None => return Ok(None),
+		};
+		let remote = self
+			.garage
+			.config
+			.bucket_replication
+			.remotes
+			.get(&rule.remote)
+			.ok_or_else(|| {
+				GarageError::Message(format!(
+					"replication remote {} is not configured",
+					rule.remote
+				))
+			})?;
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			X_AMZ_REPLICATION_STATUS,
+			HeaderValue::from_static("REPLICA"),
+		);
+
+		let meta = match data {
+			ObjectVersionData::DeleteMarker if rule.delete_marker_replication => {
+				self.client
+					.delete_object(remote, &rule.destination_bucket, key, headers)
+					.await?;
+				return Ok(Some(ReplicationStatus::Completed));
+			}
+			ObjectVersionData::DeleteMarker => return Ok(None),
+			ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) => meta,
+		};
+
+		if matches!(meta.encryption, ObjectVersionEncryption::SseC { .. }) {
+			warn!(
+				"Object {:?} in bucket {:?} is encrypted with a customer-provided key and cannot be replicated",
+				key, bucket_id
+			);
+			return Ok(Some(ReplicationStatus::Failed));
+		}
+		let (encryption, meta_inner) =
+			EncryptionParams::check_decrypt(&self.garage, &HeaderMap::new(), &meta.encryption)
+				.await
+				.map_err(|e| GarageError::Message(format!("could not decrypt object: {}", e)))?;
+
+		for (name, value) in meta_inner.headers.iter() {
+			let name = HeaderName::from_bytes(name.as_bytes())
+				.map_err(|e| GarageError::Message(format!("invalid header name: {}", e)))?;
+			let value = HeaderValue::from_str(value)
+				.map_err(|e| GarageError::Message(format!("invalid header value: {}", e)))?;
+			headers.append(name, value);
+		}
+		let tags = &version.tags.get().0;
+		if !tags.is_empty() {
+			let tagging = form_urlencoded::Serializer::new(String::new())
+				.extend_pairs(tags.iter())
+				.finish();
+			headers.insert(
+				X_AMZ_TAGGING,
+				HeaderValue::from_str(&tagging)
+					.map_err(|e| GarageError::Message(format!("invalid tags: {}", e)))?,
+			);
+		}
+
+		let stream = full_object_byte_stream(self.garage.clone(), version, data, encryption);
+		self.client
+			.put_object(
+				remote,
+				&rule.destination_bucket,
+				key,
+				headers,
+				meta.size,
+				stream,
+			)
+			.await?;
+		Ok(Some(ReplicationStatus::Completed))
+	}
+
+	/// Mark the versions of a batch of objects that match replication rules
+	/// with existing object replication as pending, and queue their replication.
+	/// Returns the key after which the backfill continues, if there are more objects.
+	async fn backfill(
+		&self,
+		bucket_id: Uuid,
+		start_after: Option<String>,
+	) -> Result<Option<String>, GarageError> {
+		let bucket = self
+			.garage
+			.bucket_table
+			.get(&EmptyKey, &bucket_id)
+			.await?
+			.filter(|b| !b.is_deleted());
+		let config = match bucket
+			.as_ref()
+			.and_then(|b| b.params())
+			.and_then(|p| p.replication_config.get().as_ref())
+		{
+			Some(config) => config,
+			None => return Ok(None),
+		};
+
+		// get_range includes the start key, which has already been examined
+		let objects = self
+			.garage
+			.object_table
+			.get_range(
+				&bucket_id,
+				start_after.clone(),
+				Some(ObjectFilter::HasVersions),
+				BACKFILL_BATCH_SIZE + 1,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		let is_last_batch = objects.len() <= BACKFILL_BATCH_SIZE;
+
+		let mut last_key = None;
+		for object in objects
+			.iter()
+			.filter(|o| Some(&o.key) != start_after.as_ref())
+			.take(BACKFILL_BATCH_SIZE)
+		{
+			let mut pending = vec![];
+			for version in object.versions().iter() {
+				let is_data = matches!(
+					version.state,
+					ObjectVersionState::Complete(ObjectVersionData::Inline(..))
+						| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(..))
+				);
+				let rule = config.matching_rule(&object.key, &version.tags.get().0);
+				if is_data
+					&& version.replication.get().is_none()
+					&& rule.is_some_and(|r| r.existing_object_replication)
+				{
+					let mut version = version.clone();
+					version.replication.update(Some(ReplicationStatus::Pending));
+					pending.push(version);
+				}
+			}
+
+			if !pending.is_empty() {
+				let uuids = pending.iter().map(|v| v.uuid).collect::<Vec<_>>();
+				self.garage
+					.object_table
+					.insert(&Object::new(bucket_id, object.key.clone(), pending))
+					.await?;
+				for version_uuid in uuids {
+					self.garage.replication_queue.push(
+						now_msec(),
+						&ReplicationTask::Version {
+							bucket_id,
+							key: object.key.clone(),
+							version_uuid,
+							attempts: 0,
+						},
+					)?;
+				}
+			}
+			last_key = Some(object.key.clone());
+		}
+
+		if is_last_batch {
+			Ok(None)
+		} else {
+			Ok(last_key)
+		}
+	}
+
+	async fn set_status(
+		&self,
+		bucket_id: Uuid,
+		key: &str,
+		version_uuid: Uuid,
+		status: Option<ReplicationStatus>,
+	) -> Result<(), GarageError> {
+		let object = match self
+			.garage
+			.object_table
+			.get(&bucket_id, &key.to_string())
+			.await?
+		{
+			Some(o) => o,
+			None => return Ok(()),
+		};
+		if let Some(version) = object.versions().iter().find(|v| v.uuid == version_uuid) {
+			if *version.replication.get() != status {
+				let mut version = version.clone();
+				version.replication.update(status);
+				self.garage
+					.object_table
+					.insert(&Object::new(bucket_id, key.to_string(), vec![version]))
+					.await?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for ReplicationWorker {
+	fn name(&self) -> String {
+		"Bucket replication".to_string()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: self
+				.garage
+				.replication_queue
+				.queue_len()
+				.ok()
+				.map(|x| x as u64),
+			freeform: vec![
+				format!("Versions replicated: {}", self.replicated),
+				format!("Versions failed: {}", self.failed),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(
+		&mut self,
+		_must_exit: &mut watch::Receiver<bool>,
+	) -> Result<WorkerState, GarageError> {
+		let queue = &self.garage.replication_queue;
+		let QueuedTask {
+			key: queue_key,
+			when,
+			task,
+		} = match queue.first()? {
+			None => {
+				self.next_attempt = None;
+				return Ok(WorkerState::Idle);
+			}
+			Some(x) => x,
+		};
+
+		if when > now_msec() {
+			self.next_attempt = Some(when);
+			return Ok(WorkerState::Idle);
+		}
+
+		match task {
+			None => {
+				error!("Invalid task in replication queue, dropping it");
+			}
+			Some(ReplicationTask::Version {
+				bucket_id,
+				key,
+				version_uuid,
+				attempts,
+			}) => match self.replicate_version(bucket_id, &key, version_uuid).await {
+				Ok(status) => {
+					self.set_status(bucket_id, &key, version_uuid, status)
+						.await?;
+					match status {
+						Some(ReplicationStatus::Completed) => self.replicated += 1,
+						Some(ReplicationStatus::Failed) => self.failed += 1,
+						_ => (),
+					}
+				}
+				Err(e) if attempts + 1 >= MAX_ATTEMPTS => {
+					error!(
+						"Could not replicate {:?} in bucket {:?} after {} attempts, giving up: {}",
+						key,
+						bucket_id,
+						attempts + 1,
+						e
+					);
+					self.set_status(
+						bucket_id,
+						&key,
+						version_uuid,
+						Some(ReplicationStatus::Failed),
+					)
+					.await?;
+					self.failed += 1;
+				}
+				Err(e) => {
+					let delay = retry_delay(attempts + 1);
+					warn!(
+						"Could not replicate {:?} in bucket {:?} (attempt {}), retrying in {:?}: {}",
+						key,
+						bucket_id,
+						attempts + 1,
+						delay,
+						e
+					);
+					queue.push(
+						now_msec() + delay.as_millis() as u64,
+						&ReplicationTask::Version {
+							bucket_id,
+							key,
+							version_uuid,
+							attempts: attempts + 1,
+						},
+					)?;
+				}
+			},
+			Some(ReplicationTask::Backfill {
+				bucket_id,
+				start_after,
+			}) => match self.backfill(bucket_id, start_after.clone()).await {
+				Ok(None) => {
+					info!("Replication backfill of bucket {:?} finished", bucket_id);
+				}
+				Ok(Some(next)) => {
+					queue.push(
+						now_msec(),
+						&ReplicationTask::Backfill {
+							bucket_id,
+							start_after: Some(next),
+						},
+					)?;
+				}
+				Err(e) => {
+					warn!(
+						"Error during replication backfill of bucket {:?}, retrying in {:?}: {}",
+						bucket_id, RETRY_DELAY, e
+					);
+					queue.push(
+						now_msec() + RETRY_DELAY.as_millis() as u64,
+						&ReplicationTask::Backfill {
+							bucket_id,
+							start_after,
+						},
+					)?;
+				}
+			},
+		}
+
+		queue.remove(&queue_key)?;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		let queue = &self.garage.replication_queue;
+		match self.next_attempt {
+			Some(when) => {
+				let delay = Duration::from_millis(when.saturating_sub(now_msec()));
+				select! {
+					_ = tokio::time::sleep(delay) => (),
+					_ = queue.wait() => (),
+				}
+			}
+			None => queue.wait().await,
+		}
+		WorkerState::Busy
+	}
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+	RETRY_DELAY * 2u32.pow(std::cmp::min(attempts, RETRY_MAX_BACKOFF_POWER))
+}
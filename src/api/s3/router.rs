@@ -137,6 +137,10 @@ pub enum Endpoint {
 		key: String,
 		version_id: Option<String>,
 	},
+	GetObjectAttributes {
+		key: String,
+		version_id: Option<String>,
+	},
 	GetObjectLegalHold {
 		key: String,
 		version_id: Option<String>,
@@ -386,6 +390,7 @@ impl Endpoint {
 									query_opt::response_content_type,
 									query_opt::response_expires),
 				ACL => GetObjectAcl (query_opt::version_id),
+				ATTRIBUTES => GetObjectAttributes (query_opt::version_id),
 				LEGAL_HOLD => GetObjectLegalHold (query_opt::version_id),
 				RETENTION => GetObjectRetention (query_opt::version_id),
 				TAGGING => GetObjectTagging (query_opt::version_id),
@@ -557,6 +562,7 @@ impl Endpoint {
 				DeleteObjectTagging,
 				GetObject,
 				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectRetention,
 				GetObjectTagging,
@@ -586,7 +592,6 @@ impl Endpoint {
 			self,
 			[
 				GetBucketAccelerateConfiguration,
-				GetBucketAcl,
 				GetBucketAnalyticsConfiguration,
 				GetBucketEncryption,
 				GetBucketIntelligentTieringConfiguration,
@@ -597,14 +602,13 @@ impl Endpoint {
 				GetBucketMetricsConfiguration,
 				GetBucketNotificationConfiguration,
 				GetBucketOwnershipControls,
-				GetBucketPolicy,
 				GetBucketPolicyStatus,
 				GetBucketReplication,
 				GetBucketRequestPayment,
 				GetBucketTagging,
 				GetBucketVersioning,
 				GetObject,
-				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectLockConfiguration,
 				GetObjectRetention,
@@ -636,6 +640,26 @@ impl Endpoint {
 				GetBucketCors,
 				PutBucketCors,
 				DeleteBucketCors,
+				PutObjectLockConfiguration,
+				GetBucketPolicy,
+				PutBucketPolicy,
+				DeleteBucketPolicy,
+				PutBucketLogging,
+				PutBucketTagging,
+				DeleteBucketTagging,
+				PutBucketAcl,
+				PutBucketOwnershipControls,
+				DeleteBucketOwnershipControls,
+				PutPublicAccessBlock,
+				DeletePublicAccessBlock,
+				PutBucketReplication,
+				DeleteBucketReplication,
+				PutBucketNotificationConfiguration,
+				PutBucketEncryption,
+				DeleteBucketEncryption,
+				PutBucketVersioning,
+				GetBucketAcl,
+				GetObjectAcl,
 			]
 		};
 		if readonly {
@@ -654,6 +678,7 @@ generateQueryParameters! {
 		"accelerate" => ACCELERATE,
 		"acl" => ACL,
 		"analytics" => ANALYTICS,
+		"attributes" => ATTRIBUTES,
 		"cors" => CORS,
 		"delete" => DELETE,
 		"encryption" => ENCRYPTION,
@@ -876,17 +901,17 @@ mod tests {
 			DELETE "/?analytics&id=list1" => DeleteBucketAnalyticsConfiguration
 			DELETE "/?analytics&id=Id" => DeleteBucketAnalyticsConfiguration
 			OWNER_DELETE "/?cors" => DeleteBucketCors
-			DELETE "/?encryption" => DeleteBucketEncryption
+			OWNER_DELETE "/?encryption" => DeleteBucketEncryption
 			DELETE "/?intelligent-tiering&id=Id" => DeleteBucketIntelligentTieringConfiguration
 			DELETE "/?inventory&id=list1" => DeleteBucketInventoryConfiguration
 			DELETE "/?inventory&id=Id" => DeleteBucketInventoryConfiguration
 			DELETE "/?lifecycle" => DeleteBucketLifecycle
 			DELETE "/?metrics&id=ExampleMetrics" => DeleteBucketMetricsConfiguration
 			DELETE "/?metrics&id=Id" => DeleteBucketMetricsConfiguration
-			DELETE "/?ownershipControls" => DeleteBucketOwnershipControls
-			DELETE "/?policy" => DeleteBucketPolicy
-			DELETE "/?replication" => DeleteBucketReplication
-			DELETE "/?tagging" => DeleteBucketTagging
+			OWNER_DELETE "/?ownershipControls" => DeleteBucketOwnershipControls
+			OWNER_DELETE "/?policy" => DeleteBucketPolicy
+			OWNER_DELETE "/?replication" => DeleteBucketReplication
+			OWNER_DELETE "/?tagging" => DeleteBucketTagging
 			OWNER_DELETE "/?website" => DeleteBucketWebsite
 			DELETE "/my-second-image.jpg" => DeleteObject
 			DELETE "/my-third-image.jpg?versionId=UIORUnfndfiufdisojhr398493jfdkjFJjkndnqUifhnw89493jJFJ" => DeleteObject
@@ -894,9 +919,9 @@ mod tests {
 			POST "/?delete" => DeleteObjects
 			DELETE "/exampleobject?tagging" => DeleteObjectTagging
 			DELETE "/{Key+}?tagging&versionId=VersionId" => DeleteObjectTagging
-			DELETE "/?publicAccessBlock" => DeletePublicAccessBlock
+			OWNER_DELETE "/?publicAccessBlock" => DeletePublicAccessBlock
 			GET "/?accelerate" => GetBucketAccelerateConfiguration
-			GET "/?acl" => GetBucketAcl
+			OWNER_GET "/?acl" => GetBucketAcl
 			GET "/?analytics&id=Id" => GetBucketAnalyticsConfiguration
 			OWNER_GET "/?cors" => GetBucketCors
 			GET "/?encryption" => GetBucketEncryption
@@ -910,7 +935,7 @@ mod tests {
 			GET "/?metrics&id=Id" => GetBucketMetricsConfiguration
 			GET "/?notification" => GetBucketNotificationConfiguration
 			GET "/?ownershipControls" => GetBucketOwnershipControls
-			GET "/?policy" => GetBucketPolicy
+			OWNER_GET "/?policy" => GetBucketPolicy
 			GET "/?policyStatus" => GetBucketPolicyStatus
 			GET "/?replication" => GetBucketReplication
 			GET "/?requestPayment" => GetBucketRequestPayment
@@ -921,9 +946,10 @@ mod tests {
 			GET "/myObject?versionId=3/L4kqtJlcpXroDTDmpUMLUo" => GetObject
 			GET "/Junk3.txt?response-cache-control=No-cache&response-content-disposition=attachment%3B%20filename%3Dtesting.txt&response-content-encoding=x-gzip&response-content-language=mi%2C%20en&response-expires=Thu%2C%2001%20Dec%201994%2016:00:00%20GMT" => GetObject
 			GET "/Key+?partNumber=1&response-cache-control=ResponseCacheControl&response-content-disposition=ResponseContentDisposition&response-content-encoding=ResponseContentEncoding&response-content-language=ResponseContentLanguage&response-content-type=ResponseContentType&response-expires=ResponseExpires&versionId=VersionId" => GetObject
-			GET "/my-image.jpg?acl" => GetObjectAcl
-			GET "/my-image.jpg?versionId=3/L4kqtJlcpXroDVBH40Nr8X8gdRQBpUMLUo&acl" => GetObjectAcl
-			GET "/{Key+}?acl&versionId=VersionId" => GetObjectAcl
+			OWNER_GET "/my-image.jpg?acl" => GetObjectAcl
+			OWNER_GET "/my-image.jpg?versionId=3/L4kqtJlcpXroDVBH40Nr8X8gdRQBpUMLUo&acl" => GetObjectAcl
+			OWNER_GET "/{Key+}?acl&versionId=VersionId" => GetObjectAcl
+			GET "/{Key+}?attributes&versionId=VersionId" => GetObjectAttributes
 			GET "/{Key+}?legal-hold&versionId=VersionId" => GetObjectLegalHold
 			GET "/?object-lock" => GetObjectLockConfiguration
 			GET "/{Key+}?retention&versionId=VersionId" => GetObjectRetention
@@ -971,25 +997,25 @@ mod tests {
 			GET "/example-object?uploadId=XXBsb2FkIElEIGZvciBlbHZpbmcncyVcdS1tb3ZpZS5tMnRzEEEwbG9hZA&max-parts=2&part-number-marker=1" => ListParts
 			GET "/Key+?max-parts=2&part-number-marker=2&uploadId=UploadId" => ListParts
 			PUT "/?accelerate" => PutBucketAccelerateConfiguration
-			PUT "/?acl" => PutBucketAcl
+			OWNER_PUT "/?acl" => PutBucketAcl
 			PUT "/?analytics&id=report1" => PutBucketAnalyticsConfiguration
 			PUT "/?analytics&id=Id" => PutBucketAnalyticsConfiguration
 			OWNER_PUT "/?cors" => PutBucketCors
-			PUT "/?encryption" => PutBucketEncryption
+			OWNER_PUT "/?encryption" => PutBucketEncryption
 			PUT "/?intelligent-tiering&id=Id" => PutBucketIntelligentTieringConfiguration
 			PUT "/?inventory&id=report1" => PutBucketInventoryConfiguration
 			PUT "/?inventory&id=Id" => PutBucketInventoryConfiguration
 			PUT "/?lifecycle" => PutBucketLifecycleConfiguration
-			PUT "/?logging" => PutBucketLogging
+			OWNER_PUT "/?logging" => PutBucketLogging
 			PUT "/?metrics&id=EntireBucket" => PutBucketMetricsConfiguration
 			PUT "/?metrics&id=Id" => PutBucketMetricsConfiguration
-			PUT "/?notification" => PutBucketNotificationConfiguration
-			PUT "/?ownershipControls" => PutBucketOwnershipControls
-			PUT "/?policy" => PutBucketPolicy
-			PUT "/?replication" => PutBucketReplication
+			OWNER_PUT "/?notification" => PutBucketNotificationConfiguration
+			OWNER_PUT "/?ownershipControls" => PutBucketOwnershipControls
+			OWNER_PUT "/?policy" => PutBucketPolicy
+			OWNER_PUT "/?replication" => PutBucketReplication
 			PUT "/?requestPayment" => PutBucketRequestPayment
-			PUT "/?tagging" => PutBucketTagging
-			PUT "/?versioning" => PutBucketVersioning
+			OWNER_PUT "/?tagging" => PutBucketTagging
+			OWNER_PUT "/?versioning" => PutBucketVersioning
 			OWNER_PUT "/?website" => PutBucketWebsite
 			PUT "/my-image.jpg" => PutObject
 			PUT "/Key+" => PutObject
@@ -997,11 +1023,11 @@ mod tests {
 			PUT "/my-image.jpg?acl&versionId=3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY+MTRCxf3vjVBH40Nrjfkd" => PutObjectAcl
 			PUT "/{Key+}?acl&versionId=VersionId" => PutObjectAcl
 			PUT "/{Key+}?legal-hold&versionId=VersionId" => PutObjectLegalHold
-			PUT "/?object-lock" => PutObjectLockConfiguration
+			OWNER_PUT "/?object-lock" => PutObjectLockConfiguration
 			PUT "/{Key+}?retention&versionId=VersionId" => PutObjectRetention
 			PUT "/object-key?tagging" => PutObjectTagging
 			PUT "/{Key+}?tagging&versionId=VersionId" => PutObjectTagging
-			PUT "/?publicAccessBlock" => PutPublicAccessBlock
+			OWNER_PUT "/?publicAccessBlock" => PutPublicAccessBlock
 			POST "/object-one.csv?restore" => RestoreObject
 			POST "/{Key+}?restore&versionId=VersionId" => RestoreObject
 			PUT "/my-movie.m2ts?partNumber=1&uploadId=VCVsb2FkIElEIGZvciBlbZZpbmcncyBteS1tb3ZpZS5tMnRzIHVwbG9hZR" => UploadPart
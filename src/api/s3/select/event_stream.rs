@@ -0,0 +1,115 @@
+//! Encoding of the messages of the AWS event stream format, in which
+//! the results of SelectObjectContent are returned.
+//!
+//! Each message is made of a prelude (total length, headers length and
+//! the CRC32 of these two values), headers, a payload and the CRC32 of
+//! the whole message.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+const PRELUDE_LEN: usize = 12;
+const MESSAGE_CRC_LEN: usize = 4;
+
+const HEADER_VALUE_TYPE_STRING: u8 = 7;
+
+pub fn records_message(payload: &[u8]) -> Bytes {
+	message(
+		&[
+			(":message-type", "event"),
+			(":event-type", "Records"),
+			(":content-type", "application/octet-stream"),
+		],
+		payload,
+	)
+}
+
+pub fn stats_message(bytes_scanned: u64, bytes_processed: u64, bytes_returned: u64) -> Bytes {
+	let payload = format!(
+		"<Stats><BytesScanned>{}</BytesScanned><BytesProcessed>{}</BytesProcessed><BytesReturned>{}</BytesReturned></Stats>",
+		bytes_scanned, bytes_processed, bytes_returned
+	);
+	message(
+		&[
+			(":message-type", "event"),
+			(":event-type", "Stats"),
+			(":content-type", "text/xml"),
+		],
+		payload.as_bytes(),
+	)
+}
+
+pub fn end_message() -> Bytes {
+	message(&[(":message-type", "event"), (":event-type", "End")], &[])
+}
+
+/// Errors that happen once the response has started being sent
+/// are returned to the client as an error message
+pub fn error_message(code: &str, message_text: &str) -> Bytes {
+	message(
+		&[
+			(":message-type", "error"),
+			(":error-code", code),
+			(":error-message", message_text),
+		],
+		&[],
+	)
+}
+
+fn message(headers: &[(&str, &str)], payload: &[u8]) -> Bytes {
+	let mut encoded_headers = BytesMut::new();
+	for (name, value) in headers {
+		encoded_headers.put_u8(name.len() as u8);
+		encoded_headers.put_slice(name.as_bytes());
+		encoded_headers.put_u8(HEADER_VALUE_TYPE_STRING);
+		encoded_headers.put_u16(value.len() as u16);
+		encoded_headers.put_slice(value.as_bytes());
+	}
+
+	let total_len = PRELUDE_LEN + encoded_headers.len() + payload.len() + MESSAGE_CRC_LEN;
+	let mut msg = BytesMut::with_capacity(total_len);
+	msg.put_u32(total_len as u32);
+	msg.put_u32(encoded_headers.len() as u32);
+	msg.put_u32(crc32fast::hash(&msg[..]));
+	msg.put_slice(&encoded_headers);
+	msg.put_slice(payload);
+	msg.put_u32(crc32fast::hash(&msg[..]));
+	msg.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::convert::TryInto;
+
+	#[test]
+	fn test_message_framing() {
+		let msg = records_message(b"a,b\n");
+		let total_len = u32::from_be_bytes(msg[0..4].try_into().unwrap()) as usize;
+		let headers_len = u32::from_be_bytes(msg[4..8].try_into().unwrap()) as usize;
+		assert_eq!(total_len, msg.len());
+		assert_eq!(
+			u32::from_be_bytes(msg[8..12].try_into().unwrap()),
+			crc32fast::hash(&msg[..8])
+		);
+		assert_eq!(
+			&msg[PRELUDE_LEN + headers_len..total_len - MESSAGE_CRC_LEN],
+			b"a,b\n"
+		);
+		assert_eq!(
+			u32::from_be_bytes(msg[total_len - 4..].try_into().unwrap()),
+			crc32fast::hash(&msg[..total_len - 4])
+		);
+
+		// First header is `:message-type` = `event`
+		assert_eq!(msg[PRELUDE_LEN] as usize, ":message-type".len());
+		assert_eq!(&msg[PRELUDE_LEN + 1..PRELUDE_LEN + 14], b":message-type");
+		assert_eq!(msg[PRELUDE_LEN + 14], HEADER_VALUE_TYPE_STRING);
+		assert_eq!(&msg[PRELUDE_LEN + 15..PRELUDE_LEN + 17], &[0, 5]);
+		assert_eq!(&msg[PRELUDE_LEN + 17..PRELUDE_LEN + 22], b"event");
+
+		// The End message has no payload
+		let end = end_message();
+		let headers_len = u32::from_be_bytes(end[4..8].try_into().unwrap()) as usize;
+		assert_eq!(end.len(), PRELUDE_LEN + headers_len + MESSAGE_CRC_LEN);
+	}
+}
@@ -0,0 +1,422 @@
+//! Reading of the records of CSV and JSON objects
+
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::sql::{PathElem, Value};
+use crate::error::*;
+
+/// Records larger than this are rejected, as they have to be held in memory
+const MAX_RECORD_SIZE: usize = 1024 * 1024;
+
+const READ_SIZE: usize = 64 * 1024;
+
+pub enum Record {
+	Csv {
+		fields: Vec<String>,
+		/// Column names, if the file header is used
+		header: Option<Arc<Vec<String>>>,
+	},
+	Json(serde_json::Value),
+}
+
+impl Record {
+	/// Value at a path such as `name`, `_2` or `user.roles[0]`,
+	/// or Null if there is no such value in this record
+	pub fn lookup(&self, path: &[PathElem]) -> Value {
+		match self {
+			Record::Csv { fields, header } => {
+				let index = match path {
+					[PathElem::Name { name, quoted }] => {
+						let position = name
+							.strip_prefix('_')
+							.filter(|_| !quoted)
+							.and_then(|n| n.parse::<usize>().ok());
+						match (position, header) {
+							(Some(n), _) => n.checked_sub(1),
+							(None, Some(header)) => {
+								header.iter().position(|h| name_matches(h, name, *quoted))
+							}
+							(None, None) => None,
+						}
+					}
+					_ => None,
+				};
+				index
+					.and_then(|i| fields.get(i))
+					.map(|f| Value::String(f.clone()))
+					.unwrap_or(Value::Null)
+			}
+			Record::Json(v) => {
+				let mut v = v;
+				for elem in path {
+					let next = match (elem, v) {
+						(PathElem::Name { name, quoted }, serde_json::Value::Object(map)) => {
+							map.get(name).or_else(|| {
+								map.iter()
+									.find(|(k, _)| name_matches(k, name, *quoted))
+									.map(|(_, v)| v)
+							})
+						}
+						(PathElem::Index(i), serde_json::Value::Array(a)) => a.get(*i),
+						_ => None,
+					};
+					match next {
+						Some(x) => v = x,
+						None => return Value::Null,
+					}
+				}
+				Value::from_json(v)
+			}
+		}
+	}
+}
+
+fn name_matches(candidate: &str, name: &str, quoted: bool) -> bool {
+	if quoted {
+		candidate == name
+	} else {
+		candidate.eq_ignore_ascii_case(name)
+	}
+}
+
+// ---- input formats ----
+
+pub enum InputFormat {
+	Csv(CsvInputFormat),
+	/// JSON documents or JSON Lines, which are both read as
+	/// a sequence of whitespace-separated JSON values
+	Json,
+}
+
+pub struct CsvInputFormat {
+	pub file_header_info: FileHeaderInfo,
+	pub comments: Option<u8>,
+	pub field_delimiter: u8,
+	pub record_delimiter: Vec<u8>,
+	pub quote: u8,
+	pub quote_escape: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHeaderInfo {
+	/// The first line contains column names, which can be used in queries
+	Use,
+	/// The first line is skipped
+	Ignore,
+	None,
+}
+
+impl Default for CsvInputFormat {
+	fn default() -> Self {
+		Self {
+			file_header_info: FileHeaderInfo::None,
+			comments: None,
+			field_delimiter: b',',
+			record_delimiter: b"\n".to_vec(),
+			quote: b'"',
+			quote_escape: b'"',
+		}
+	}
+}
+
+enum Parsed<T> {
+	Record(T, usize),
+	Skip(usize),
+	NeedMore,
+	End,
+}
+
+// ---- record reader ----
+
+pub struct RecordReader<R> {
+	reader: R,
+	format: InputFormat,
+	buf: Vec<u8>,
+	pos: usize,
+	eof: bool,
+	header: Option<Arc<Vec<String>>>,
+	first_record: bool,
+	bytes_processed: u64,
+}
+
+impl<R: AsyncRead + Unpin> RecordReader<R> {
+	pub fn new(reader: R, format: InputFormat) -> Self {
+		Self {
+			reader,
+			format,
+			buf: vec![],
+			pos: 0,
+			eof: false,
+			header: None,
+			first_record: true,
+			bytes_processed: 0,
+		}
+	}
+
+	/// Number of (uncompressed) bytes read from the object
+	pub fn bytes_processed(&self) -> u64 {
+		self.bytes_processed
+	}
+
+	pub async fn next_record(&mut self) -> Result<Option<Record>, Error> {
+		loop {
+			let data = &self.buf[self.pos..];
+			match &self.format {
+				InputFormat::Csv(format) => match parse_csv_record(format, data, self.eof)? {
+					Parsed::Record(fields, len) => {
+						self.pos += len;
+						let first_record = std::mem::replace(&mut self.first_record, false);
+						match format.file_header_info {
+							FileHeaderInfo::Use if first_record => {
+								self.header = Some(Arc::new(fields));
+							}
+							FileHeaderInfo::Ignore if first_record => (),
+							_ => {
+								return Ok(Some(Record::Csv {
+									fields,
+									header: self.header.clone(),
+								}))
+							}
+						}
+					}
+					Parsed::Skip(len) => self.pos += len,
+					Parsed::NeedMore => self.fill().await?,
+					Parsed::End => return Ok(None),
+				},
+				InputFormat::Json => match parse_json_record(data, self.eof)? {
+					Parsed::Record(value, len) => {
+						self.pos += len;
+						return Ok(Some(Record::Json(value)));
+					}
+					Parsed::Skip(len) => self.pos += len,
+					Parsed::NeedMore => self.fill().await?,
+					Parsed::End => return Ok(None),
+				},
+			}
+		}
+	}
+
+	async fn fill(&mut self) -> Result<(), Error> {
+		self.buf.drain(..self.pos);
+		self.pos = 0;
+		if self.buf.len() > MAX_RECORD_SIZE {
+			return Err(Error::bad_request(format!(
+				"A record of the object is larger than the maximum record size of {} bytes",
+				MAX_RECORD_SIZE
+			)));
+		}
+
+		self.buf.reserve(READ_SIZE);
+		let n = self
+			.reader
+			.read_buf(&mut self.buf)
+			.await
+			.map_err(|e| Error::from(garage_util::error::Error::from(e)))?;
+		self.bytes_processed += n as u64;
+		if n == 0 {
+			self.eof = true;
+		}
+		Ok(())
+	}
+}
+
+fn parse_csv_record(
+	format: &CsvInputFormat,
+	data: &[u8],
+	eof: bool,
+) -> Result<Parsed<Vec<String>>, Error> {
+	if data.is_empty() {
+		return Ok(if eof { Parsed::End } else { Parsed::NeedMore });
+	}
+
+	let delim = &format.record_delimiter[..];
+	// With the default record delimiter, also accept CRLF line endings
+	let at_record_end = |i: usize| -> Option<usize> {
+		if data[i..].starts_with(delim) {
+			Some(delim.len())
+		} else if delim == b"\n" && data[i..].starts_with(b"\r\n") {
+			Some(2)
+		} else {
+			None
+		}
+	};
+	let maybe_truncated = |i: usize| !eof && data.len() - i < delim.len().max(2);
+
+	if format.comments.is_some() && format.comments == data.first().copied() {
+		return Ok(
+			match (0..data.len()).find_map(|i| at_record_end(i).map(|l| i + l)) {
+				Some(len) => Parsed::Skip(len),
+				None if eof => Parsed::Skip(data.len()),
+				None => Parsed::NeedMore,
+			},
+		);
+	}
+
+	let mut fields = vec![];
+	let mut field = vec![];
+	let mut in_quotes = false;
+	let mut was_quoted = false;
+	let mut i = 0;
+	let len = loop {
+		if i == data.len() {
+			if !eof {
+				return Ok(Parsed::NeedMore);
+			}
+			if in_quotes {
+				return Err(Error::bad_request(
+					"Unterminated quoted field in CSV object",
+				));
+			}
+			break i;
+		}
+
+		let c = data[i];
+		if in_quotes {
+			if c == format.quote_escape && i + 1 == data.len() && !eof {
+				return Ok(Parsed::NeedMore);
+			}
+			if c == format.quote_escape && data.get(i + 1) == Some(&format.quote) {
+				field.push(format.quote);
+				i += 2;
+			} else if c == format.quote {
+				in_quotes = false;
+				i += 1;
+			} else {
+				field.push(c);
+				i += 1;
+			}
+		} else if let Some(l) = at_record_end(i) {
+			break i + l;
+		} else if maybe_truncated(i)
+			&& (delim.starts_with(&data[i..]) || (c == b'\r' && delim == b"\n"))
+		{
+			return Ok(Parsed::NeedMore);
+		} else if c == format.field_delimiter {
+			fields.push(String::from_utf8_lossy(&field).into_owned());
+			field.clear();
+			i += 1;
+		} else if c == format.quote && field.is_empty() {
+			in_quotes = true;
+			was_quoted = true;
+			i += 1;
+		} else {
+			field.push(c);
+			i += 1;
+		}
+	};
+
+	if fields.is_empty() && field.is_empty() && !was_quoted {
+		// Skip empty lines
+		return Ok(Parsed::Skip(len));
+	}
+	fields.push(String::from_utf8_lossy(&field).into_owned());
+	Ok(Parsed::Record(fields, len))
+}
+
+fn parse_json_record(data: &[u8], eof: bool) -> Result<Parsed<serde_json::Value>, Error> {
+	if data.is_empty() {
+		return Ok(if eof { Parsed::End } else { Parsed::NeedMore });
+	}
+	if data.iter().all(u8::is_ascii_whitespace) {
+		return Ok(Parsed::Skip(data.len()));
+	}
+
+	let mut values = serde_json::Deserializer::from_slice(data).into_iter::<serde_json::Value>();
+	match values.next() {
+		// A value that ends the buffer, such as a number, could be truncated
+		Some(Ok(_)) if !eof && values.byte_offset() == data.len() => Ok(Parsed::NeedMore),
+		Some(Ok(v)) => Ok(Parsed::Record(v, values.byte_offset())),
+		Some(Err(e)) if e.is_eof() && !eof => Ok(Parsed::NeedMore),
+		Some(Err(e)) => Err(Error::bad_request(format!("Invalid JSON in object: {}", e))),
+		None => Ok(Parsed::Skip(data.len())),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	async fn read_all(data: &'static [u8], format: InputFormat) -> Result<Vec<Record>, Error> {
+		let mut reader = RecordReader::new(data, format);
+		let mut records = vec![];
+		while let Some(rec) = reader.next_record().await? {
+			records.push(rec);
+		}
+		assert_eq!(reader.bytes_processed(), data.len() as u64);
+		Ok(records)
+	}
+
+	fn csv_fields(records: &[Record]) -> Vec<Vec<&str>> {
+		records
+			.iter()
+			.map(|r| match r {
+				Record::Csv { fields, .. } => fields.iter().map(String::as_str).collect(),
+				_ => panic!("expected CSV record"),
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_csv_records() {
+		let data = b"name,comment\r\n# a comment\nalice,\"hello, \"\"world\"\"\"\n\nbob,\"multi\nline\"\ncarol,";
+		let format = CsvInputFormat {
+			file_header_info: FileHeaderInfo::Use,
+			comments: Some(b'#'),
+			..Default::default()
+		};
+		let records = read_all(data, InputFormat::Csv(format)).await.unwrap();
+		assert_eq!(
+			csv_fields(&records),
+			vec![
+				vec!["alice", "hello, \"world\""],
+				vec!["bob", "multi\nline"],
+				vec!["carol", ""],
+			]
+		);
+		let name = [PathElem::Name {
+			name: "NAME".into(),
+			quoted: false,
+		}];
+		assert_eq!(records[1].lookup(&name), Value::String("bob".into()));
+
+		let format = CsvInputFormat {
+			file_header_info: FileHeaderInfo::Ignore,
+			field_delimiter: b'\t',
+			record_delimiter: b"\r\n".to_vec(),
+			..Default::default()
+		};
+		let records = read_all(b"a\tb\r\n1\t2\r\n3\t4", InputFormat::Csv(format))
+			.await
+			.unwrap();
+		assert_eq!(csv_fields(&records), vec![vec!["1", "2"], vec!["3", "4"]]);
+
+		let unterminated = read_all(b"a,\"b\n", InputFormat::Csv(Default::default())).await;
+		assert!(unterminated.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_json_records() {
+		let data = b"{\"a\": 1, \"b\": {\"c\": [true]}}\n\n{\"a\": 2}\n12\n";
+		let records = read_all(data, InputFormat::Json).await.unwrap();
+		assert_eq!(records.len(), 3);
+		let path = [
+			PathElem::Name {
+				name: "b".into(),
+				quoted: false,
+			},
+			PathElem::Name {
+				name: "c".into(),
+				quoted: false,
+			},
+			PathElem::Index(0),
+		];
+		assert_eq!(records[0].lookup(&path), Value::Bool(true));
+		assert_eq!(records[1].lookup(&path), Value::Null);
+
+		assert!(read_all(b"{\"a\": 1}\n{\"a\"", InputFormat::Json)
+			.await
+			.is_err());
+	}
+}
@@ -0,0 +1,657 @@
+//! Implementation of SelectObjectContent, which runs an SQL query
+//! on a CSV or JSON object and returns only the matching records
+//! or the requested aggregates.
+
+mod event_stream;
+mod input;
+mod sql;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder};
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use hyper::{Request, Response, StatusCode};
+use quick_xml::de::from_reader;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+
+use garage_model::s3::object_table::*;
+use garage_net::stream::ByteStream;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::encryption::EncryptionParams;
+use crate::error::*;
+use crate::get::{full_object_byte_stream, response_body_from_stream};
+use crate::versioning::find_data_version;
+use crate::xml::{IntValue, Value};
+
+use event_stream::*;
+use input::*;
+use sql::{parse_query, Aggregator, Projection, Query, Value as SqlValue};
+
+/// Matching records are sent in messages of about this size
+const RECORDS_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Maximum size of the SQL expression of a request, as in AWS
+const MAX_EXPRESSION_SIZE: usize = 256 * 1024;
+
+/// Maximum size of the XML body of a request
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+pub async fn handle_select_object_content(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = ctx;
+
+	let (head, body) = req.into_parts();
+	let body = body.collect_limited(MAX_REQUEST_SIZE).await?;
+	let request: SelectObjectContentRequest = from_reader(&body as &[u8])?;
+	let params = request.validate()?;
+
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+
+	let version = find_data_version(&object, None)?;
+	let version_data = match &version.state {
+		ObjectVersionState::Complete(x) => x,
+		_ => unreachable!(),
+	};
+	let version_meta = match version_data {
+		ObjectVersionData::DeleteMarker => unreachable!(),
+		ObjectVersionData::Inline(meta, _) => meta,
+		ObjectVersionData::FirstBlock(meta, _) => meta,
+	};
+
+	let (encryption, _) =
+		EncryptionParams::check_decrypt(&garage, &head.headers, &version_meta.encryption).await?;
+
+	let stream = full_object_byte_stream(garage.clone(), version, version_data, encryption);
+
+	let (tx, rx) = mpsc::channel(2);
+	tokio::spawn(run_select(params, stream, tx));
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(response_body_from_stream(
+			tokio_stream::wrappers::ReceiverStream::new(rx),
+		))?)
+}
+
+struct SelectParams {
+	query: Query,
+	compression: CompressionType,
+	input: InputFormat,
+	output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompressionType {
+	None,
+	Gzip,
+	Bzip2,
+}
+
+/// Run the query on the object and send the resulting event stream messages.
+/// The response has already been started, so errors are sent as messages.
+async fn run_select(
+	params: SelectParams,
+	stream: ByteStream,
+	tx: mpsc::Sender<Result<Bytes, std::io::Error>>,
+) {
+	let bytes_scanned = Arc::new(AtomicU64::new(0));
+	let bytes_scanned2 = bytes_scanned.clone();
+	let stream = stream.inspect(move |x| {
+		if let Ok(bytes) = x {
+			bytes_scanned2.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+		}
+	});
+
+	let reader = StreamReader::new(stream);
+	let reader: Box<dyn AsyncRead + Send + Unpin> = match params.compression {
+		CompressionType::None => Box::new(reader),
+		CompressionType::Gzip => {
+			let mut decoder = GzipDecoder::new(reader);
+			decoder.multiple_members(true);
+			Box::new(decoder)
+		}
+		CompressionType::Bzip2 => Box::new(BzDecoder::new(reader)),
+	};
+	let mut records = RecordReader::new(reader, params.input);
+
+	let res = select_records(&params.query, &params.output, &mut records, &tx).await;
+	let messages = match res {
+		Ok(bytes_returned) => vec![
+			stats_message(
+				bytes_scanned.load(Ordering::Relaxed),
+				records.bytes_processed(),
+				bytes_returned,
+			),
+			end_message(),
+		],
+		Err(e) => {
+			warn!("Error in SelectObjectContent: {}", e);
+			vec![error_message(e.aws_code(), &e.to_string())]
+		}
+	};
+	for msg in messages {
+		if tx.send(Ok(msg)).await.is_err() {
+			break;
+		}
+	}
+}
+
+/// Send the matching records, returning the number of bytes of records sent
+async fn select_records<R: AsyncRead + Unpin>(
+	query: &Query,
+	output: &OutputFormat,
+	records: &mut RecordReader<R>,
+	tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+) -> Result<u64, Error> {
+	let mut aggregator = match &query.projection {
+		Projection::Aggregates(items) => Some(Aggregator::new(items)),
+		_ => None,
+	};
+
+	let mut out = vec![];
+	let mut bytes_returned = 0;
+	let mut n_matched = 0;
+	while aggregator.is_some() || Some(n_matched) != query.limit {
+		let record = match records.next_record().await? {
+			Some(r) => r,
+			None => break,
+		};
+		if !query.matches(&record)? {
+			continue;
+		}
+		n_matched += 1;
+
+		match &mut aggregator {
+			Some(agg) => agg.update(&record)?,
+			None => {
+				match &query.projection {
+					Projection::All => output.write_record(&record, &mut out),
+					_ => output.write_values(&query.project(&record)?, &mut out),
+				}
+				if out.len() >= RECORDS_MESSAGE_SIZE {
+					bytes_returned += send_records(tx, &mut out).await?;
+				}
+			}
+		}
+	}
+
+	if let Some(agg) = aggregator {
+		if query.limit != Some(0) {
+			output.write_values(&agg.finish(), &mut out);
+		}
+	}
+	if !out.is_empty() {
+		bytes_returned += send_records(tx, &mut out).await?;
+	}
+	Ok(bytes_returned)
+}
+
+async fn send_records(
+	tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+	out: &mut Vec<u8>,
+) -> Result<u64, Error> {
+	let len = out.len() as u64;
+	tx.send(Ok(records_message(out)))
+		.await
+		.ok()
+		.ok_or_internal_error("SelectObjectContent response was dropped")?;
+	out.clear();
+	Ok(len)
+}
+
+// ---- output formats ----
+
+enum OutputFormat {
+	Csv {
+		field_delimiter: String,
+		record_delimiter: String,
+		quote: String,
+		quote_escape: String,
+		always_quote: bool,
+	},
+	Json {
+		record_delimiter: String,
+	},
+}
+
+impl OutputFormat {
+	/// Write a record as it is, for `SELECT *`
+	fn write_record(&self, record: &Record, out: &mut Vec<u8>) {
+		match (self, record) {
+			(OutputFormat::Csv { .. }, Record::Csv { fields, .. }) => {
+				self.write_csv_fields(fields.iter().map(String::as_str), out)
+			}
+			(OutputFormat::Csv { .. }, Record::Json(v)) => {
+				let fields = match v {
+					serde_json::Value::Object(map) => map
+						.values()
+						.map(|x| SqlValue::from_json(x).to_csv_field())
+						.collect::<Vec<_>>(),
+					v => vec![SqlValue::from_json(v).to_csv_field()],
+				};
+				self.write_csv_fields(fields.iter().map(String::as_str), out)
+			}
+			(OutputFormat::Json { .. }, Record::Csv { fields, header }) => {
+				let values = fields
+					.iter()
+					.enumerate()
+					.map(|(i, f)| {
+						let name = header
+							.as_ref()
+							.and_then(|h| h.get(i).cloned())
+							.unwrap_or_else(|| format!("_{}", i + 1));
+						(name, SqlValue::String(f.clone()))
+					})
+					.collect::<Vec<_>>();
+				self.write_values(&values, out)
+			}
+			(OutputFormat::Json { record_delimiter }, Record::Json(v)) => {
+				out.extend_from_slice(v.to_string().as_bytes());
+				out.extend_from_slice(record_delimiter.as_bytes());
+			}
+		}
+	}
+
+	fn write_values(&self, values: &[(String, SqlValue)], out: &mut Vec<u8>) {
+		match self {
+			OutputFormat::Csv { .. } => {
+				let fields = values
+					.iter()
+					.map(|(_, v)| v.to_csv_field())
+					.collect::<Vec<_>>();
+				self.write_csv_fields(fields.iter().map(String::as_str), out)
+			}
+			OutputFormat::Json { record_delimiter } => {
+				// Written by hand to keep the columns in the order of the query
+				out.push(b'{');
+				for (i, (name, value)) in values.iter().enumerate() {
+					if i > 0 {
+						out.push(b',');
+					}
+					out.extend_from_slice(
+						serde_json::Value::from(name.as_str())
+							.to_string()
+							.as_bytes(),
+					);
+					out.push(b':');
+					out.extend_from_slice(value.to_json().to_string().as_bytes());
+				}
+				out.push(b'}');
+				out.extend_from_slice(record_delimiter.as_bytes());
+			}
+		}
+	}
+
+	fn write_csv_fields<'a>(&self, fields: impl Iterator<Item = &'a str>, out: &mut Vec<u8>) {
+		let (field_delimiter, record_delimiter, quote, quote_escape, always_quote) = match self {
+			OutputFormat::Csv {
+				field_delimiter,
+				record_delimiter,
+				quote,
+				quote_escape,
+				always_quote,
+			} => (
+				field_delimiter,
+				record_delimiter,
+				quote,
+				quote_escape,
+				*always_quote,
+			),
+			OutputFormat::Json { .. } => unreachable!(),
+		};
+		for (i, field) in fields.enumerate() {
+			if i > 0 {
+				out.extend_from_slice(field_delimiter.as_bytes());
+			}
+			let needs_quotes = always_quote
+				|| [
+					field_delimiter.as_str(),
+					record_delimiter.as_str(),
+					quote.as_str(),
+					"\n",
+					"\r",
+				]
+				.iter()
+				.any(|s| !s.is_empty() && field.contains(*s));
+			if needs_quotes {
+				out.extend_from_slice(quote.as_bytes());
+				let escaped = format!("{}{}", quote_escape, quote);
+				out.extend_from_slice(field.replace(quote.as_str(), &escaped).as_bytes());
+				out.extend_from_slice(quote.as_bytes());
+			} else {
+				out.extend_from_slice(field.as_bytes());
+			}
+		}
+		out.extend_from_slice(record_delimiter.as_bytes());
+	}
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename = "SelectObjectContentRequest")]
+pub struct SelectObjectContentRequest {
+	#[serde(rename = "Expression")]
+	pub expression: Value,
+	#[serde(rename = "ExpressionType")]
+	pub expression_type: Value,
+	#[serde(rename = "RequestProgress")]
+	pub request_progress: Option<RequestProgress>,
+	#[serde(rename = "InputSerialization")]
+	pub input_serialization: InputSerialization,
+	#[serde(rename = "OutputSerialization")]
+	pub output_serialization: OutputSerialization,
+	#[serde(rename = "ScanRange")]
+	pub scan_range: Option<ScanRange>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct RequestProgress {
+	#[serde(rename = "Enabled")]
+	pub enabled: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct ScanRange {
+	#[serde(rename = "Start")]
+	pub start: Option<IntValue>,
+	#[serde(rename = "End")]
+	pub end: Option<IntValue>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct InputSerialization {
+	#[serde(rename = "CompressionType")]
+	pub compression_type: Option<Value>,
+	#[serde(rename = "CSV")]
+	pub csv: Option<CsvInput>,
+	#[serde(rename = "JSON")]
+	pub json: Option<JsonInput>,
+	#[serde(rename = "Parquet")]
+	pub parquet: Option<ParquetInput>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct CsvInput {
+	#[serde(rename = "AllowQuotedRecordDelimiter")]
+	pub allow_quoted_record_delimiter: Option<Value>,
+	#[serde(rename = "Comments")]
+	pub comments: Option<Value>,
+	#[serde(rename = "FieldDelimiter")]
+	pub field_delimiter: Option<Value>,
+	#[serde(rename = "FileHeaderInfo")]
+	pub file_header_info: Option<Value>,
+	#[serde(rename = "QuoteCharacter")]
+	pub quote_character: Option<Value>,
+	#[serde(rename = "QuoteEscapeCharacter")]
+	pub quote_escape_character: Option<Value>,
+	#[serde(rename = "RecordDelimiter")]
+	pub record_delimiter: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct JsonInput {
+	#[serde(rename = "Type")]
+	pub json_type: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct ParquetInput {}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct OutputSerialization {
+	#[serde(rename = "CSV")]
+	pub csv: Option<CsvOutput>,
+	#[serde(rename = "JSON")]
+	pub json: Option<JsonOutput>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct CsvOutput {
+	#[serde(rename = "FieldDelimiter")]
+	pub field_delimiter: Option<Value>,
+	#[serde(rename = "QuoteCharacter")]
+	pub quote_character: Option<Value>,
+	#[serde(rename = "QuoteEscapeCharacter")]
+	pub quote_escape_character: Option<Value>,
+	#[serde(rename = "QuoteFields")]
+	pub quote_fields: Option<Value>,
+	#[serde(rename = "RecordDelimiter")]
+	pub record_delimiter: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct JsonOutput {
+	#[serde(rename = "RecordDelimiter")]
+	pub record_delimiter: Option<Value>,
+}
+
+fn single_byte(v: &Option<Value>, default: u8, what: &str) -> Result<u8, Error> {
+	match v {
+		None => Ok(default),
+		Some(Value(s)) if s.len() == 1 => Ok(s.as_bytes()[0]),
+		Some(_) => Err(Error::bad_request(format!(
+			"{} must be a single character",
+			what
+		))),
+	}
+}
+
+fn string_or(v: Option<Value>, default: &str) -> String {
+	v.map(|x| x.0).unwrap_or_else(|| default.to_string())
+}
+
+impl SelectObjectContentRequest {
+	fn validate(self) -> Result<SelectParams, Error> {
+		if !self.expression_type.0.eq_ignore_ascii_case("SQL") {
+			return Err(Error::bad_request("ExpressionType must be SQL"));
+		}
+		if self.scan_range.is_some() {
+			return Err(Error::NotImplemented(
+				"ScanRange in SelectObjectContent".into(),
+			));
+		}
+		if self.expression.0.len() > MAX_EXPRESSION_SIZE {
+			return Err(Error::bad_request(format!(
+				"The SQL expression must be no more than {} bytes",
+				MAX_EXPRESSION_SIZE
+			)));
+		}
+		let query = parse_query(&self.expression.0)?;
+
+		let input_ser = self.input_serialization;
+		let compression = match input_ser.compression_type.as_ref().map(|x| x.0.as_str()) {
+			None | Some("NONE") => CompressionType::None,
+			Some("GZIP") => CompressionType::Gzip,
+			Some("BZIP2") => CompressionType::Bzip2,
+			Some(c) => {
+				return Err(Error::bad_request(format!(
+					"Invalid compression type: {}",
+					c
+				)))
+			}
+		};
+		let input = match (input_ser.csv, input_ser.json, input_ser.parquet) {
+			(Some(csv), None, None) => {
+				let file_header_info = match csv.file_header_info.as_ref().map(|x| x.0.as_str()) {
+					None | Some("NONE") => FileHeaderInfo::None,
+					Some("USE") => FileHeaderInfo::Use,
+					Some("IGNORE") => FileHeaderInfo::Ignore,
+					Some(x) => {
+						return Err(Error::bad_request(format!("Invalid FileHeaderInfo: {}", x)))
+					}
+				};
+				let comments = match &csv.comments {
+					None => None,
+					Some(_) => Some(single_byte(&csv.comments, b'#', "Comments")?),
+				};
+				let quote = single_byte(&csv.quote_character, b'"', "QuoteCharacter")?;
+				let record_delimiter = string_or(csv.record_delimiter, "\n").into_bytes();
+				if record_delimiter.is_empty() {
+					return Err(Error::bad_request("RecordDelimiter cannot be empty"));
+				}
+				InputFormat::Csv(CsvInputFormat {
+					file_header_info,
+					comments,
+					field_delimiter: single_byte(&csv.field_delimiter, b',', "FieldDelimiter")?,
+					record_delimiter,
+					quote,
+					quote_escape: single_byte(
+						&csv.quote_escape_character,
+						quote,
+						"QuoteEscapeCharacter",
+					)?,
+				})
+			}
+			(None, Some(json), None) => match json.json_type.as_ref().map(|x| x.0.as_str()) {
+				None | Some("DOCUMENT") | Some("LINES") => InputFormat::Json,
+				Some(x) => return Err(Error::bad_request(format!("Invalid JSON type: {}", x))),
+			},
+			(None, None, Some(_)) => {
+				return Err(Error::NotImplemented(
+					"Parquet input in SelectObjectContent".into(),
+				))
+			}
+			_ => {
+				return Err(Error::bad_request(
+					"Exactly one input format must be specified",
+				))
+			}
+		};
+
+		let output_ser = self.output_serialization;
+		let output = match (output_ser.csv, output_ser.json) {
+			(Some(csv), None) => {
+				let quote = string_or(csv.quote_character, "\"");
+				OutputFormat::Csv {
+					field_delimiter: string_or(csv.field_delimiter, ","),
+					record_delimiter: string_or(csv.record_delimiter, "\n"),
+					quote_escape: string_or(csv.quote_escape_character, &quote),
+					quote,
+					always_quote: match csv.quote_fields.as_ref().map(|x| x.0.as_str()) {
+						None | Some("ASNEEDED") => false,
+						Some("ALWAYS") => true,
+						Some(x) => {
+							return Err(Error::bad_request(format!("Invalid QuoteFields: {}", x)))
+						}
+					},
+				}
+			}
+			(None, Some(json)) => OutputFormat::Json {
+				record_delimiter: string_or(json.record_delimiter, "\n"),
+			},
+			_ => {
+				return Err(Error::bad_request(
+					"Exactly one output format must be specified",
+				))
+			}
+		};
+
+		Ok(SelectParams {
+			query,
+			compression,
+			input,
+			output,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_select_request() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SelectObjectContentRequest xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Expression>SELECT s.name FROM S3Object s WHERE s.size &gt; 10</Expression>
+  <ExpressionType>SQL</ExpressionType>
+  <InputSerialization>
+    <CompressionType>GZIP</CompressionType>
+    <CSV>
+      <FileHeaderInfo>USE</FileHeaderInfo>
+      <FieldDelimiter>&#9;</FieldDelimiter>
+    </CSV>
+  </InputSerialization>
+  <OutputSerialization>
+    <JSON>
+      <RecordDelimiter>,</RecordDelimiter>
+    </JSON>
+  </OutputSerialization>
+</SelectObjectContentRequest>"#;
+		let req: SelectObjectContentRequest = from_str(message).unwrap();
+		assert_eq!(
+			req.expression.0,
+			"SELECT s.name FROM S3Object s WHERE s.size > 10"
+		);
+		let params = req.validate()?;
+		assert_eq!(params.compression, CompressionType::Gzip);
+		match params.input {
+			InputFormat::Csv(csv) => {
+				assert_eq!(csv.file_header_info, FileHeaderInfo::Use);
+				assert_eq!(csv.field_delimiter, b'\t');
+			}
+			_ => panic!("expected CSV input"),
+		}
+
+		let parquet = message.replace(
+			"<CSV>\n      <FileHeaderInfo>USE</FileHeaderInfo>\n      <FieldDelimiter>&#9;</FieldDelimiter>\n    </CSV>",
+			"<Parquet></Parquet>",
+		);
+		let req: SelectObjectContentRequest = from_str(&parquet).unwrap();
+		assert!(matches!(req.validate(), Err(Error::NotImplemented(_))));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_output_formats() {
+		let csv = OutputFormat::Csv {
+			field_delimiter: ",".into(),
+			record_delimiter: "\n".into(),
+			quote: "\"".into(),
+			quote_escape: "\"".into(),
+			always_quote: false,
+		};
+		let record = Record::Csv {
+			fields: vec!["a".into(), "b,c".into(), "say \"hi\"".into()],
+			header: Some(Arc::new(vec!["x".into(), "y".into(), "z".into()])),
+		};
+		let mut out = vec![];
+		csv.write_record(&record, &mut out);
+		assert_eq!(out, b"a,\"b,c\",\"say \"\"hi\"\"\"\n");
+
+		let json = OutputFormat::Json {
+			record_delimiter: "\n".into(),
+		};
+		let mut out = vec![];
+		json.write_record(&record, &mut out);
+		json.write_values(
+			&[("z".into(), SqlValue::Int(3)), ("a".into(), SqlValue::Null)],
+			&mut out,
+		);
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"{\"x\":\"a\",\"y\":\"b,c\",\"z\":\"say \\\"hi\\\"\"}\n{\"z\":3,\"a\":null}\n"
+		);
+	}
+}
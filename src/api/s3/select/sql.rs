@@ -0,0 +1,1204 @@
+//! Parsing and evaluation of the subset of the S3 Select SQL dialect
+//! supported by Garage:
+//!
+//! ```sql
+//! SELECT * | expr [[AS] name], ... | aggregate(expr) [[AS] name], ...
+//! FROM S3Object [[AS] alias]
+//! [WHERE condition]
+//! [LIMIT n]
+//! ```
+//!
+//! where conditions are made of comparisons, `LIKE`, `IS [NOT] NULL`,
+//! `AND`, `OR` and `NOT`, and aggregates are `COUNT`, `SUM`, `AVG`,
+//! `MIN` and `MAX`.
+
+use std::cmp::Ordering;
+
+use super::input::Record;
+use crate::error::*;
+
+// ---- query structure ----
+
+#[derive(Debug)]
+pub struct Query {
+	pub projection: Projection,
+	pub filter: Option<Expr>,
+	pub limit: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum Projection {
+	/// `SELECT *`: records are returned as they are
+	All,
+	Columns(Vec<SelectItem>),
+	Aggregates(Vec<AggregateItem>),
+}
+
+#[derive(Debug)]
+pub struct SelectItem {
+	pub expr: Expr,
+	pub name: String,
+}
+
+#[derive(Debug)]
+pub struct AggregateItem {
+	pub function: AggregateFunction,
+	/// None for `COUNT(*)`
+	pub arg: Option<Expr>,
+	pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+	Count,
+	Sum,
+	Avg,
+	Min,
+	Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+	Literal(Value),
+	Column(Vec<PathElem>),
+	Negate(Box<Expr>),
+	Cast(Box<Expr>, CastType),
+	Compare(CompareOp, Box<Expr>, Box<Expr>),
+	Like {
+		expr: Box<Expr>,
+		pattern: Box<Expr>,
+		escape: Option<char>,
+		negated: bool,
+	},
+	IsNull {
+		expr: Box<Expr>,
+		negated: bool,
+	},
+	Not(Box<Expr>),
+	And(Box<Expr>, Box<Expr>),
+	Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathElem {
+	/// Unquoted names are matched case-insensitively
+	Name {
+		name: String,
+		quoted: bool,
+	},
+	Index(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+	Eq,
+	Ne,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastType {
+	Int,
+	Float,
+	String,
+	Bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Int(i64),
+	Float(f64),
+	String(String),
+	/// JSON objects and arrays
+	Json(serde_json::Value),
+}
+
+// ---- values ----
+
+impl Value {
+	pub fn from_json(v: &serde_json::Value) -> Self {
+		match v {
+			serde_json::Value::Null => Value::Null,
+			serde_json::Value::Bool(b) => Value::Bool(*b),
+			serde_json::Value::Number(n) => match n.as_i64() {
+				Some(i) => Value::Int(i),
+				None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+			},
+			serde_json::Value::String(s) => Value::String(s.clone()),
+			v => Value::Json(v.clone()),
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		match self {
+			Value::Null => serde_json::Value::Null,
+			Value::Bool(b) => serde_json::Value::Bool(*b),
+			Value::Int(i) => serde_json::Value::from(*i),
+			Value::Float(f) => serde_json::Number::from_f64(*f)
+				.map(serde_json::Value::Number)
+				.unwrap_or(serde_json::Value::Null),
+			Value::String(s) => serde_json::Value::String(s.clone()),
+			Value::Json(v) => v.clone(),
+		}
+	}
+
+	pub fn to_csv_field(&self) -> String {
+		match self {
+			Value::Null => String::new(),
+			Value::Bool(b) => b.to_string(),
+			Value::Int(i) => i.to_string(),
+			Value::Float(f) => f.to_string(),
+			Value::String(s) => s.clone(),
+			Value::Json(v) => v.to_string(),
+		}
+	}
+
+	/// Numeric value, strings being parsed as numbers as CSV fields are always strings
+	fn as_number(&self) -> Option<Value> {
+		match self {
+			Value::Int(_) | Value::Float(_) => Some(self.clone()),
+			Value::String(s) => {
+				let s = s.trim();
+				s.parse::<i64>()
+					.map(Value::Int)
+					.or_else(|_| s.parse::<f64>().map(Value::Float))
+					.ok()
+			}
+			_ => None,
+		}
+	}
+
+	fn as_f64(&self) -> Option<f64> {
+		match self.as_number()? {
+			Value::Int(i) => Some(i as f64),
+			Value::Float(f) => Some(f),
+			_ => None,
+		}
+	}
+
+	fn is_true(&self) -> bool {
+		matches!(self, Value::Bool(true))
+	}
+
+	fn compare(&self, other: &Value) -> Option<Ordering> {
+		match (self, other) {
+			(Value::Null, _) | (_, Value::Null) => None,
+			(Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+			(Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+			(Value::Json(a), Value::Json(b)) => (a == b).then_some(Ordering::Equal),
+			(a, b) => match (a.as_number()?, b.as_number()?) {
+				(Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+				(a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+			},
+		}
+	}
+
+	fn cast(self, ty: CastType) -> Result<Value, Error> {
+		let res = match (&self, ty) {
+			(Value::Null, _) => Some(Value::Null),
+			(_, CastType::String) => Some(Value::String(self.to_csv_field())),
+			(Value::Bool(b), CastType::Int) => Some(Value::Int(*b as i64)),
+			(Value::Float(f), CastType::Int) => Some(Value::Int(f.trunc() as i64)),
+			(_, CastType::Int) => match self.as_number() {
+				Some(Value::Float(f)) => Some(Value::Int(f.trunc() as i64)),
+				n => n,
+			},
+			(_, CastType::Float) => self.as_f64().map(Value::Float),
+			(Value::Bool(_), CastType::Bool) => Some(self.clone()),
+			(Value::String(s), CastType::Bool) => match s.trim().to_lowercase().as_str() {
+				"true" => Some(Value::Bool(true)),
+				"false" => Some(Value::Bool(false)),
+				_ => None,
+			},
+			(_, CastType::Bool) => self
+				.as_number()
+				.map(|n| Value::Bool(n.as_f64() != Some(0.0))),
+		};
+		res.ok_or_else(|| {
+			Error::bad_request(format!("Cannot cast {} to {:?}", self.to_csv_field(), ty))
+		})
+	}
+}
+
+// ---- evaluation ----
+
+impl Query {
+	pub fn matches(&self, record: &Record) -> Result<bool, Error> {
+		match &self.filter {
+			None => Ok(true),
+			Some(filter) => Ok(filter.eval(record)?.is_true()),
+		}
+	}
+
+	/// Values of the selected columns, for queries that select columns
+	pub fn project(&self, record: &Record) -> Result<Vec<(String, Value)>, Error> {
+		match &self.projection {
+			Projection::Columns(items) => items
+				.iter()
+				.map(|item| Ok((item.name.clone(), item.expr.eval(record)?)))
+				.collect(),
+			_ => Err(Error::internal_error("Query does not select columns")),
+		}
+	}
+}
+
+impl Expr {
+	pub fn eval(&self, record: &Record) -> Result<Value, Error> {
+		match self {
+			Expr::Literal(v) => Ok(v.clone()),
+			Expr::Column(path) => Ok(record.lookup(path)),
+			Expr::Negate(e) => match e.eval(record)? {
+				Value::Null => Ok(Value::Null),
+				v => match v.as_number() {
+					Some(Value::Int(i)) => Ok(Value::Int(-i)),
+					Some(Value::Float(f)) => Ok(Value::Float(-f)),
+					_ => Err(Error::bad_request(format!(
+						"Cannot negate non-numeric value {}",
+						v.to_csv_field()
+					))),
+				},
+			},
+			Expr::Cast(e, ty) => e.eval(record)?.cast(*ty),
+			Expr::Compare(op, a, b) => {
+				let (a, b) = (a.eval(record)?, b.eval(record)?);
+				Ok(match a.compare(&b) {
+					None if a == Value::Null || b == Value::Null => Value::Null,
+					None => Value::Bool(*op == CompareOp::Ne),
+					Some(ord) => Value::Bool(match op {
+						CompareOp::Eq => ord == Ordering::Equal,
+						CompareOp::Ne => ord != Ordering::Equal,
+						CompareOp::Lt => ord == Ordering::Less,
+						CompareOp::Le => ord != Ordering::Greater,
+						CompareOp::Gt => ord == Ordering::Greater,
+						CompareOp::Ge => ord != Ordering::Less,
+					}),
+				})
+			}
+			Expr::Like {
+				expr,
+				pattern,
+				escape,
+				negated,
+			} => match (expr.eval(record)?, pattern.eval(record)?) {
+				(Value::String(s), Value::String(p)) => {
+					let s = s.chars().collect::<Vec<_>>();
+					let p = p.chars().collect::<Vec<_>>();
+					Ok(Value::Bool(like_match(&s, &p, *escape) != *negated))
+				}
+				_ => Ok(Value::Null),
+			},
+			Expr::IsNull { expr, negated } => {
+				let is_null = expr.eval(record)? == Value::Null;
+				Ok(Value::Bool(is_null != *negated))
+			}
+			Expr::Not(e) => Ok(match e.eval(record)? {
+				Value::Bool(b) => Value::Bool(!b),
+				_ => Value::Null,
+			}),
+			Expr::And(a, b) => Ok(match (a.eval(record)?, b.eval(record)?) {
+				(Value::Bool(false), _) | (_, Value::Bool(false)) => Value::Bool(false),
+				(Value::Bool(true), Value::Bool(true)) => Value::Bool(true),
+				_ => Value::Null,
+			}),
+			Expr::Or(a, b) => Ok(match (a.eval(record)?, b.eval(record)?) {
+				(Value::Bool(true), _) | (_, Value::Bool(true)) => Value::Bool(true),
+				(Value::Bool(false), Value::Bool(false)) => Value::Bool(false),
+				_ => Value::Null,
+			}),
+		}
+	}
+}
+
+/// SQL LIKE matching, where `%` matches any sequence of characters
+/// and `_` matches any single character.
+///
+/// Runs in O(len(s) * len(p)): on a mismatch we only ever backtrack to the
+/// most recent `%`, since any earlier one can't lead to a different outcome.
+fn like_match(s: &[char], p: &[char], escape: Option<char>) -> bool {
+	enum Tok {
+		Any,
+		One,
+		Lit(char),
+	}
+
+	let mut toks = Vec::with_capacity(p.len());
+	let mut i = 0;
+	while i < p.len() {
+		match p[i] {
+			c if Some(c) == escape && i + 1 < p.len() => {
+				toks.push(Tok::Lit(p[i + 1]));
+				i += 1;
+			}
+			'%' => toks.push(Tok::Any),
+			'_' => toks.push(Tok::One),
+			c => toks.push(Tok::Lit(c)),
+		}
+		i += 1;
+	}
+
+	let (mut si, mut ti) = (0, 0);
+	let mut backtrack: Option<(usize, usize)> = None;
+	while si < s.len() {
+		match toks.get(ti) {
+			Some(Tok::Any) => {
+				backtrack = Some((ti + 1, si));
+				ti += 1;
+				continue;
+			}
+			Some(Tok::One) => {
+				si += 1;
+				ti += 1;
+				continue;
+			}
+			Some(Tok::Lit(c)) if *c == s[si] => {
+				si += 1;
+				ti += 1;
+				continue;
+			}
+			_ => (),
+		}
+		match backtrack {
+			Some((bt, bs)) => {
+				// let the last `%` swallow one more character and retry
+				backtrack = Some((bt, bs + 1));
+				ti = bt;
+				si = bs + 1;
+			}
+			None => return false,
+		}
+	}
+	toks[ti..].iter().all(|t| matches!(t, Tok::Any))
+}
+
+// ---- aggregation ----
+
+pub struct Aggregator<'a> {
+	items: &'a [AggregateItem],
+	states: Vec<AggregateState>,
+}
+
+enum AggregateState {
+	Count(u64),
+	Sum(Option<Value>),
+	Avg(f64, u64),
+	MinMax(Option<Value>),
+}
+
+impl<'a> Aggregator<'a> {
+	pub fn new(items: &'a [AggregateItem]) -> Self {
+		let states = items
+			.iter()
+			.map(|item| match item.function {
+				AggregateFunction::Count => AggregateState::Count(0),
+				AggregateFunction::Sum => AggregateState::Sum(None),
+				AggregateFunction::Avg => AggregateState::Avg(0., 0),
+				AggregateFunction::Min | AggregateFunction::Max => AggregateState::MinMax(None),
+			})
+			.collect();
+		Self { items, states }
+	}
+
+	pub fn update(&mut self, record: &Record) -> Result<(), Error> {
+		for (item, state) in self.items.iter().zip(self.states.iter_mut()) {
+			let value = match &item.arg {
+				None => Value::Bool(true),
+				Some(e) => e.eval(record)?,
+			};
+			if value == Value::Null {
+				continue;
+			}
+			let not_numeric = || {
+				Error::bad_request(format!(
+					"Cannot aggregate non-numeric value {}",
+					value.to_csv_field()
+				))
+			};
+			match state {
+				AggregateState::Count(n) => *n += 1,
+				AggregateState::Sum(sum) => {
+					let value = value.as_number().ok_or_else(not_numeric)?;
+					*sum = Some(match (sum.take(), value) {
+						(None, v) => v,
+						(Some(Value::Int(a)), Value::Int(b)) => match a.checked_add(b) {
+							Some(x) => Value::Int(x),
+							None => Value::Float(a as f64 + b as f64),
+						},
+						(Some(a), b) => Value::Float(a.as_f64().unwrap() + b.as_f64().unwrap()),
+					});
+				}
+				AggregateState::Avg(sum, n) => {
+					*sum += value.as_f64().ok_or_else(not_numeric)?;
+					*n += 1;
+				}
+				AggregateState::MinMax(current) => {
+					// Numeric strings, such as CSV fields, are compared as numbers
+					let value = value.as_number().unwrap_or(value);
+					let wanted = match item.function {
+						AggregateFunction::Min => Ordering::Less,
+						_ => Ordering::Greater,
+					};
+					let replace = match current {
+						None => true,
+						Some(c) => value.compare(c) == Some(wanted),
+					};
+					if replace {
+						*current = Some(value);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn finish(self) -> Vec<(String, Value)> {
+		self.items
+			.iter()
+			.zip(self.states)
+			.map(|(item, state)| {
+				let value = match state {
+					AggregateState::Count(n) => Value::Int(n as i64),
+					AggregateState::Avg(_, 0) => Value::Null,
+					AggregateState::Avg(sum, n) => Value::Float(sum / n as f64),
+					AggregateState::Sum(v) | AggregateState::MinMax(v) => v.unwrap_or(Value::Null),
+				};
+				(item.name.clone(), value)
+			})
+			.collect()
+	}
+}
+
+// ---- parsing ----
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Ident(String),
+	QuotedIdent(String),
+	Str(String),
+	Number(String),
+	Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+	"<>", "!=", "<=", ">=", "*", ",", "(", ")", ".", "[", "]", "=", "<", ">", "-", ";",
+];
+
+const RESERVED: &[&str] = &[
+	"select", "from", "where", "limit", "as", "and", "or", "not", "like", "escape", "is", "null",
+	"true", "false",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, Error> {
+	let chars = sql.chars().collect::<Vec<_>>();
+	let mut tokens = vec![];
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push(Token::Ident(chars[start..i].iter().collect()));
+		} else if c.is_ascii_digit() {
+			let start = i;
+			while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+				i += 1;
+			}
+			tokens.push(Token::Number(chars[start..i].iter().collect()));
+		} else if c == '\'' || c == '"' {
+			// Quotes are escaped by doubling them
+			let mut s = String::new();
+			i += 1;
+			loop {
+				match chars.get(i) {
+					None => return Err(Error::bad_request("Unterminated quoted string in SQL")),
+					Some(x) if *x == c && chars.get(i + 1) == Some(&c) => {
+						s.push(c);
+						i += 2;
+					}
+					Some(x) if *x == c => {
+						i += 1;
+						break;
+					}
+					Some(x) => {
+						s.push(*x);
+						i += 1;
+					}
+				}
+			}
+			tokens.push(if c == '\'' {
+				Token::Str(s)
+			} else {
+				Token::QuotedIdent(s)
+			});
+		} else {
+			let sym = SYMBOLS
+				.iter()
+				.find(|sym| chars[i..].iter().take(sym.len()).copied().eq(sym.chars()))
+				.ok_or_else(|| Error::bad_request(format!("Unexpected character in SQL: {}", c)))?;
+			tokens.push(Token::Symbol(sym));
+			i += sym.len();
+		}
+	}
+	Ok(tokens)
+}
+
+/// Maximum nesting depth of parentheses, NOT, unary minus and CAST in
+/// SQL expressions, so that parsing and evaluating them cannot overflow
+/// the stack
+const MAX_EXPR_DEPTH: usize = 100;
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+	depth: usize,
+}
+
+pub fn parse_query(sql: &str) -> Result<Query, Error> {
+	let mut parser = Parser {
+		tokens: tokenize(sql)?,
+		pos: 0,
+		depth: 0,
+	};
+	parser.query()
+}
+
+/// Build a balanced tree of AND or OR expressions, so that long chains of
+/// conditions don't make expressions as deep as they are long
+fn balanced_tree(mut terms: Vec<Expr>, op: fn(Box<Expr>, Box<Expr>) -> Expr) -> Expr {
+	if terms.len() == 1 {
+		return terms.pop().unwrap();
+	}
+	let right = terms.split_off(terms.len() / 2);
+	op(
+		Box::new(balanced_tree(terms, op)),
+		Box::new(balanced_tree(right, op)),
+	)
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos)
+	}
+
+	fn peek_at(&self, offset: usize) -> Option<&Token> {
+		self.tokens.get(self.pos + offset)
+	}
+
+	fn next(&mut self) -> Result<Token, Error> {
+		let tok = self
+			.tokens
+			.get(self.pos)
+			.cloned()
+			.ok_or_else(|| Error::bad_request("Unexpected end of SQL expression"))?;
+		self.pos += 1;
+		Ok(tok)
+	}
+
+	fn is_keyword(tok: Option<&Token>, kw: &str) -> bool {
+		matches!(tok, Some(Token::Ident(s)) if s.eq_ignore_ascii_case(kw))
+	}
+
+	fn eat_keyword(&mut self, kw: &str) -> bool {
+		let res = Self::is_keyword(self.peek(), kw);
+		if res {
+			self.pos += 1;
+		}
+		res
+	}
+
+	fn expect_keyword(&mut self, kw: &str) -> Result<(), Error> {
+		if self.eat_keyword(kw) {
+			Ok(())
+		} else {
+			Err(self.unexpected(kw))
+		}
+	}
+
+	fn eat_symbol(&mut self, sym: &str) -> bool {
+		let res = matches!(self.peek(), Some(Token::Symbol(s)) if *s == sym);
+		if res {
+			self.pos += 1;
+		}
+		res
+	}
+
+	fn expect_symbol(&mut self, sym: &str) -> Result<(), Error> {
+		if self.eat_symbol(sym) {
+			Ok(())
+		} else {
+			Err(self.unexpected(sym))
+		}
+	}
+
+	fn unexpected(&self, expected: &str) -> Error {
+		match self.peek() {
+			None => Error::bad_request(format!(
+				"Unexpected end of SQL expression, expected {}",
+				expected
+			)),
+			Some(tok) => Error::bad_request(format!(
+				"Unexpected token {:?} in SQL expression, expected {}",
+				tok, expected
+			)),
+		}
+	}
+
+	/// An optional alias, with or without AS
+	fn alias(&mut self) -> Result<Option<String>, Error> {
+		if self.eat_keyword("as") {
+			match self.next()? {
+				Token::Ident(s) | Token::QuotedIdent(s) => Ok(Some(s)),
+				_ => Err(Error::bad_request("Invalid alias in SQL expression")),
+			}
+		} else {
+			match self.peek() {
+				Some(Token::Ident(s)) if !RESERVED.contains(&s.to_lowercase().as_str()) => {
+					let s = s.clone();
+					self.pos += 1;
+					Ok(Some(s))
+				}
+				Some(Token::QuotedIdent(s)) => {
+					let s = s.clone();
+					self.pos += 1;
+					Ok(Some(s))
+				}
+				_ => Ok(None),
+			}
+		}
+	}
+
+	fn query(&mut self) -> Result<Query, Error> {
+		self.expect_keyword("select")?;
+		let mut projection = self.projection()?;
+
+		self.expect_keyword("from")?;
+		if !self.eat_keyword("s3object") {
+			return Err(self.unexpected("S3Object"));
+		}
+		let table_alias = self.alias()?;
+
+		let mut filter = if self.eat_keyword("where") {
+			Some(self.expr()?)
+		} else {
+			None
+		};
+
+		let limit = if self.eat_keyword("limit") {
+			match self.next()? {
+				Token::Number(n) => Some(
+					n.parse::<u64>()
+						.ok()
+						.ok_or_bad_request("Invalid LIMIT in SQL expression")?,
+				),
+				_ => return Err(Error::bad_request("Invalid LIMIT in SQL expression")),
+			}
+		} else {
+			None
+		};
+
+		self.eat_symbol(";");
+		if self.peek().is_some() {
+			return Err(self.unexpected("end of SQL expression"));
+		}
+
+		// Remove the table name or alias from column references
+		let alias = table_alias.as_deref();
+		match &mut projection {
+			Projection::All => (),
+			Projection::Columns(items) => {
+				for item in items.iter_mut() {
+					item.expr.strip_table_alias(alias);
+				}
+			}
+			Projection::Aggregates(items) => {
+				for arg in items.iter_mut().filter_map(|i| i.arg.as_mut()) {
+					arg.strip_table_alias(alias);
+				}
+			}
+		}
+		if let Some(f) = &mut filter {
+			f.strip_table_alias(alias);
+		}
+
+		Ok(Query {
+			projection,
+			filter,
+			limit,
+		})
+	}
+
+	fn projection(&mut self) -> Result<Projection, Error> {
+		if self.eat_symbol("*") {
+			return Ok(Projection::All);
+		}
+
+		let mut columns = vec![];
+		let mut aggregates = vec![];
+		loop {
+			let position = columns.len() + aggregates.len() + 1;
+			let function = match self.peek() {
+				Some(Token::Ident(s)) if self.peek_at(1) == Some(&Token::Symbol("(")) => {
+					match s.to_lowercase().as_str() {
+						"count" => Some(AggregateFunction::Count),
+						"sum" => Some(AggregateFunction::Sum),
+						"avg" => Some(AggregateFunction::Avg),
+						"min" => Some(AggregateFunction::Min),
+						"max" => Some(AggregateFunction::Max),
+						_ => None,
+					}
+				}
+				_ => None,
+			};
+			if let Some(function) = function {
+				self.pos += 2;
+				let arg = if function == AggregateFunction::Count && self.eat_symbol("*") {
+					None
+				} else {
+					Some(self.expr()?)
+				};
+				self.expect_symbol(")")?;
+				let name = self.alias()?.unwrap_or_else(|| format!("_{}", position));
+				aggregates.push(AggregateItem {
+					function,
+					arg,
+					name,
+				});
+			} else {
+				let expr = self.expr()?;
+				let name = match (self.alias()?, &expr) {
+					(Some(alias), _) => alias,
+					(None, Expr::Column(path)) => match path.last() {
+						Some(PathElem::Name { name, .. }) => name.clone(),
+						_ => format!("_{}", position),
+					},
+					(None, _) => format!("_{}", position),
+				};
+				columns.push(SelectItem { expr, name });
+			}
+
+			if !self.eat_symbol(",") {
+				break;
+			}
+		}
+
+		match (columns.is_empty(), aggregates.is_empty()) {
+			(false, true) => Ok(Projection::Columns(columns)),
+			(true, false) => Ok(Projection::Aggregates(aggregates)),
+			_ => Err(Error::bad_request(
+				"Aggregate and non-aggregate expressions cannot be mixed in the select list",
+			)),
+		}
+	}
+
+	/// Parse a nested expression with `f`, failing if expressions are
+	/// nested too deeply
+	fn nested<T>(&mut self, f: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+		if self.depth >= MAX_EXPR_DEPTH {
+			return Err(Error::bad_request(format!(
+				"SQL expressions cannot be nested more than {} levels deep",
+				MAX_EXPR_DEPTH
+			)));
+		}
+		self.depth += 1;
+		let res = f(self);
+		self.depth -= 1;
+		res
+	}
+
+	fn expr(&mut self) -> Result<Expr, Error> {
+		let mut terms = vec![self.and_expr()?];
+		while self.eat_keyword("or") {
+			terms.push(self.and_expr()?);
+		}
+		Ok(balanced_tree(terms, Expr::Or))
+	}
+
+	fn and_expr(&mut self) -> Result<Expr, Error> {
+		let mut terms = vec![self.not_expr()?];
+		while self.eat_keyword("and") {
+			terms.push(self.not_expr()?);
+		}
+		Ok(balanced_tree(terms, Expr::And))
+	}
+
+	fn not_expr(&mut self) -> Result<Expr, Error> {
+		if self.eat_keyword("not") {
+			Ok(Expr::Not(Box::new(self.nested(Self::not_expr)?)))
+		} else {
+			self.comparison()
+		}
+	}
+
+	fn comparison(&mut self) -> Result<Expr, Error> {
+		let left = self.primary()?;
+
+		let op = match self.peek() {
+			Some(Token::Symbol("=")) => Some(CompareOp::Eq),
+			Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Some(CompareOp::Ne),
+			Some(Token::Symbol("<")) => Some(CompareOp::Lt),
+			Some(Token::Symbol("<=")) => Some(CompareOp::Le),
+			Some(Token::Symbol(">")) => Some(CompareOp::Gt),
+			Some(Token::Symbol(">=")) => Some(CompareOp::Ge),
+			_ => None,
+		};
+		if let Some(op) = op {
+			self.pos += 1;
+			let right = self.primary()?;
+			return Ok(Expr::Compare(op, Box::new(left), Box::new(right)));
+		}
+
+		if self.eat_keyword("is") {
+			let negated = self.eat_keyword("not");
+			self.expect_keyword("null")?;
+			return Ok(Expr::IsNull {
+				expr: Box::new(left),
+				negated,
+			});
+		}
+
+		let negated = Self::is_keyword(self.peek(), "not")
+			&& Self::is_keyword(self.peek_at(1), "like")
+			&& self.eat_keyword("not");
+		if self.eat_keyword("like") {
+			let pattern = self.primary()?;
+			let escape = if self.eat_keyword("escape") {
+				match self.next()? {
+					Token::Str(s) if s.chars().count() == 1 => s.chars().next(),
+					_ => return Err(Error::bad_request("ESCAPE must be a single character")),
+				}
+			} else {
+				None
+			};
+			return Ok(Expr::Like {
+				expr: Box::new(left),
+				pattern: Box::new(pattern),
+				escape,
+				negated,
+			});
+		}
+
+		Ok(left)
+	}
+
+	fn primary(&mut self) -> Result<Expr, Error> {
+		match self.next()? {
+			Token::Str(s) => Ok(Expr::Literal(Value::String(s))),
+			Token::Number(n) => n
+				.parse::<i64>()
+				.map(Value::Int)
+				.or_else(|_| n.parse::<f64>().map(Value::Float))
+				.map(Expr::Literal)
+				.map_err(|_| {
+					Error::bad_request(format!("Invalid number in SQL expression: {}", n))
+				}),
+			Token::Symbol("(") => {
+				let e = self.nested(Self::expr)?;
+				self.expect_symbol(")")?;
+				Ok(e)
+			}
+			Token::Symbol("-") => Ok(Expr::Negate(Box::new(self.nested(Self::primary)?))),
+			Token::Ident(s) if self.peek() == Some(&Token::Symbol("(")) => {
+				if !s.eq_ignore_ascii_case("cast") {
+					return Err(Error::bad_request(format!(
+						"Unsupported function in SQL expression: {}",
+						s
+					)));
+				}
+				self.pos += 1;
+				let e = self.nested(Self::expr)?;
+				self.expect_keyword("as")?;
+				let ty = match self.next()? {
+					Token::Ident(t) => match t.to_lowercase().as_str() {
+						"int" | "integer" | "bigint" => CastType::Int,
+						"float" | "double" | "real" | "decimal" | "numeric" => CastType::Float,
+						"string" | "varchar" | "char" => CastType::String,
+						"bool" | "boolean" => CastType::Bool,
+						_ => {
+							return Err(Error::bad_request(format!("Unsupported CAST type: {}", t)))
+						}
+					},
+					_ => return Err(Error::bad_request("Invalid CAST in SQL expression")),
+				};
+				self.expect_symbol(")")?;
+				Ok(Expr::Cast(Box::new(e), ty))
+			}
+			Token::Ident(s) if s.eq_ignore_ascii_case("null") => Ok(Expr::Literal(Value::Null)),
+			Token::Ident(s) if s.eq_ignore_ascii_case("true") => {
+				Ok(Expr::Literal(Value::Bool(true)))
+			}
+			Token::Ident(s) if s.eq_ignore_ascii_case("false") => {
+				Ok(Expr::Literal(Value::Bool(false)))
+			}
+			Token::Ident(name) if !RESERVED.contains(&name.to_lowercase().as_str()) => {
+				self.path(PathElem::Name {
+					name,
+					quoted: false,
+				})
+			}
+			Token::QuotedIdent(name) => self.path(PathElem::Name { name, quoted: true }),
+			tok => Err(Error::bad_request(format!(
+				"Unexpected token {:?} in SQL expression",
+				tok
+			))),
+		}
+	}
+
+	fn path(&mut self, first: PathElem) -> Result<Expr, Error> {
+		let mut path = vec![first];
+		loop {
+			if self.eat_symbol(".") {
+				match self.next()? {
+					Token::Ident(name) => path.push(PathElem::Name {
+						name,
+						quoted: false,
+					}),
+					Token::QuotedIdent(name) => path.push(PathElem::Name { name, quoted: true }),
+					_ => return Err(Error::bad_request("Invalid column name in SQL expression")),
+				}
+			} else if self.eat_symbol("[") {
+				match self.next()? {
+					Token::Number(n) => path.push(PathElem::Index(
+						n.parse()
+							.ok()
+							.ok_or_bad_request("Invalid index in SQL expression")?,
+					)),
+					_ => return Err(Error::bad_request("Invalid index in SQL expression")),
+				}
+				self.expect_symbol("]")?;
+			} else {
+				return Ok(Expr::Column(path));
+			}
+		}
+	}
+}
+
+impl Expr {
+	fn strip_table_alias(&mut self, alias: Option<&str>) {
+		match self {
+			Expr::Column(path) if path.len() > 1 => {
+				if let PathElem::Name {
+					name,
+					quoted: false,
+				} = &path[0]
+				{
+					if name.eq_ignore_ascii_case("s3object")
+						|| alias.map(|a| a.eq_ignore_ascii_case(name)) == Some(true)
+					{
+						path.remove(0);
+					}
+				}
+			}
+			Expr::Literal(_) | Expr::Column(_) => (),
+			Expr::Negate(e) | Expr::Cast(e, _) | Expr::Not(e) | Expr::IsNull { expr: e, .. } => {
+				e.strip_table_alias(alias)
+			}
+			Expr::Compare(_, a, b)
+			| Expr::And(a, b)
+			| Expr::Or(a, b)
+			| Expr::Like {
+				expr: a,
+				pattern: b,
+				..
+			} => {
+				a.strip_table_alias(alias);
+				b.strip_table_alias(alias);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	fn csv_record() -> Record {
+		Record::Csv {
+			fields: vec![
+				"alice".into(),
+				"34".into(),
+				"GET /index.html".into(),
+				"".into(),
+			],
+			header: Some(Arc::new(vec![
+				"name".into(),
+				"age".into(),
+				"Request".into(),
+				"referer".into(),
+			])),
+		}
+	}
+
+	fn filter(sql: &str, record: &Record) -> bool {
+		parse_query(sql).unwrap().matches(record).unwrap()
+	}
+
+	#[test]
+	fn test_parse_and_filter() {
+		let rec = csv_record();
+		assert!(filter("SELECT * FROM S3Object", &rec));
+		assert!(filter(
+			"select * from s3object s where s.name = 'alice'",
+			&rec
+		));
+		assert!(filter(
+			"SELECT * FROM S3Object WHERE _2 > 30 AND _2 <= 34",
+			&rec
+		));
+		assert!(filter(
+			"SELECT * FROM S3Object s WHERE s.\"Request\" LIKE 'GET %.html'",
+			&rec
+		));
+		assert!(filter(
+			"SELECT * FROM S3Object WHERE request LIKE 'G_T%'",
+			&rec
+		));
+		assert!(!filter(
+			"SELECT * FROM S3Object WHERE name NOT LIKE 'a%'",
+			&rec
+		));
+		assert!(!filter(
+			"SELECT * FROM S3Object WHERE \"request\" = 'x'",
+			&rec
+		));
+		assert!(filter("SELECT * FROM S3Object WHERE missing IS NULL", &rec));
+		assert!(filter(
+			"SELECT * FROM S3Object WHERE NOT (name = 'bob' OR age < 18)",
+			&rec
+		));
+		assert!(filter(
+			"SELECT * FROM S3Object WHERE CAST(age AS FLOAT) = 34.0",
+			&rec
+		));
+		assert!(!filter("SELECT * FROM S3Object WHERE missing = 'x'", &rec));
+
+		assert!(parse_query("SELECT FROM S3Object").is_err());
+		assert!(parse_query("SELECT * FROM other").is_err());
+		assert!(parse_query("SELECT name, COUNT(*) FROM S3Object").is_err());
+		assert!(parse_query("SELECT * FROM S3Object WHERE lower(name) = 'a'").is_err());
+		assert!(parse_query("SELECT * FROM S3Object LIMIT 10 garbage").is_err());
+	}
+
+	#[test]
+	fn test_projection() {
+		let rec = csv_record();
+		let q = parse_query("SELECT s.name, s._2 AS years, 'x' FROM S3Object s LIMIT 5").unwrap();
+		assert_eq!(q.limit, Some(5));
+		assert_eq!(
+			q.project(&rec).unwrap(),
+			vec![
+				("name".into(), Value::String("alice".into())),
+				("years".into(), Value::String("34".into())),
+				("_3".into(), Value::String("x".into())),
+			]
+		);
+
+		let json = Record::Json(serde_json::json!({
+			"user": {"name": "alice", "roles": ["admin", "dev"]},
+			"status": 200,
+		}));
+		let q = parse_query(
+			"SELECT s.user.name, s.user.roles[1] FROM S3Object s WHERE s.status >= 200",
+		)
+		.unwrap();
+		assert!(q.matches(&json).unwrap());
+		assert_eq!(
+			q.project(&json).unwrap(),
+			vec![
+				("name".into(), Value::String("alice".into())),
+				("_2".into(), Value::String("dev".into())),
+			]
+		);
+	}
+
+	#[test]
+	fn test_aggregates() {
+		let q = parse_query(
+			"SELECT COUNT(*), SUM(s.size) AS total, AVG(s.size), MIN(s.size), MAX(s.size), COUNT(s.size) FROM S3Object s WHERE s.size > 1",
+		)
+		.unwrap();
+		let items = match &q.projection {
+			Projection::Aggregates(items) => items,
+			_ => panic!("expected aggregates"),
+		};
+		let mut agg = Aggregator::new(items);
+		for size in &["1", "2", "3", "10", ""] {
+			let rec = Record::Csv {
+				fields: vec![size.to_string()],
+				header: Some(Arc::new(vec!["size".into()])),
+			};
+			if q.matches(&rec).unwrap() {
+				agg.update(&rec).unwrap();
+			}
+		}
+		assert_eq!(
+			agg.finish(),
+			vec![
+				("_1".into(), Value::Int(3)),
+				("total".into(), Value::Int(15)),
+				("_3".into(), Value::Float(5.)),
+				("_4".into(), Value::Int(2)),
+				("_5".into(), Value::Int(10)),
+				("_6".into(), Value::Int(3)),
+			]
+		);
+	}
+
+	#[test]
+	fn test_nesting_depth() {
+		let nested = |n: usize, open: &str, close: &str| {
+			format!(
+				"SELECT * FROM S3Object WHERE {}age = 34{}",
+				open.repeat(n),
+				close.repeat(n)
+			)
+		};
+		assert!(filter(&nested(50, "(", ")"), &csv_record()));
+		for (open, close) in [("(", ")"), ("NOT ", ""), ("- ", "")] {
+			let err = parse_query(&nested(100_000, open, close)).unwrap_err();
+			assert!(err.to_string().contains("nested"));
+		}
+
+		// Long chains of conditions are fine, and are not evaluated recursively
+		// as deep as they are long
+		let sql = format!(
+			"SELECT * FROM S3Object WHERE {} age = 34",
+			"age = 0 OR ".repeat(100_000)
+		);
+		assert!(filter(&sql, &csv_record()));
+	}
+
+	#[test]
+	fn test_like_match() {
+		let m = |s: &str, p: &str, e: Option<char>| {
+			let s = s.chars().collect::<Vec<_>>();
+			let p = p.chars().collect::<Vec<_>>();
+			like_match(&s, &p, e)
+		};
+		assert!(m("", "", None));
+		assert!(m("", "%", None));
+		assert!(!m("", "_", None));
+		assert!(m("abc", "a%", None));
+		assert!(m("abc", "%c", None));
+		assert!(m("abc", "a_c", None));
+		assert!(!m("abc", "a_", None));
+		assert!(m("abcbc", "%bc", None));
+		assert!(m("a%c", "a!%c", Some('!')));
+		assert!(!m("abc", "a!%c", Some('!')));
+		assert!(m("a_", "%!_", Some('!')));
+
+		// Would take exponential time with a naive recursive matcher
+		let s = "a".repeat(1000);
+		let p = "%a".repeat(50) + "b";
+		assert!(!m(&s, &p, None));
+		assert!(m(&s, &"%a".repeat(50), None));
+	}
+}
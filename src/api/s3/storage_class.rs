@@ -0,0 +1,33 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+
+use garage_model::garage::Garage;
+use garage_util::config::DEFAULT_STORAGE_CLASS;
+
+use crate::error::*;
+
+pub const X_AMZ_STORAGE_CLASS: HeaderName = HeaderName::from_static("x-amz-storage-class");
+
+/// Parse the storage class requested in the x-amz-storage-class header
+/// of a request that writes a new object.
+/// The default storage class is returned as None.
+pub(crate) fn request_storage_class(
+	garage: &Garage,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<String>, Error> {
+	match headers.get(X_AMZ_STORAGE_CLASS) {
+		None => Ok(None),
+		Some(v) => parse_storage_class(garage, v.to_str()?),
+	}
+}
+
+/// Check that a storage class is configured on this node.
+/// The default storage class is returned as None.
+pub(crate) fn parse_storage_class(garage: &Garage, class: &str) -> Result<Option<String>, Error> {
+	if class == DEFAULT_STORAGE_CLASS {
+		return Ok(None);
+	}
+	if !garage.config.data_dir.storage_classes().contains(class) {
+		return Err(Error::InvalidStorageClass(class.to_string()));
+	}
+	Ok(Some(class.to_string()))
+}
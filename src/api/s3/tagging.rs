@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, HashSet};
+
+use quick_xml::de::from_reader;
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use garage_model::bucket_table::Bucket;
+use garage_model::s3::object_table::*;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::versioning::*;
+use crate::xml::{to_xml_with_header, xmlns_tag, Value};
+
+pub const X_AMZ_TAGGING: HeaderName = HeaderName::from_static("x-amz-tagging");
+pub const X_AMZ_TAGGING_COUNT: HeaderName = HeaderName::from_static("x-amz-tagging-count");
+pub const X_AMZ_TAGGING_DIRECTIVE: HeaderName = HeaderName::from_static("x-amz-tagging-directive");
+
+const MAX_TAGS: usize = 10;
+const MAX_BUCKET_TAGS: usize = 50;
+const MAX_TAG_KEY_LEN: usize = 128;
+const MAX_TAG_VALUE_LEN: usize = 256;
+
+pub async fn handle_get_object_tagging(
+	ctx: ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = &ctx;
+
+	let object = garage
+		.object_table
+		.get(bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = find_data_version(&object, version_id)?;
+
+	let tagging = Tagging::from_tag_set(version.tags.get());
+	let xml = to_xml_with_header(&tagging)?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.header(X_AMZ_VERSION_ID, self::version_id(version))
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_object_tagging(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let body = req.into_body().collect().await?;
+
+	let tagging: Tagging = from_reader(&body as &[u8])?;
+	let tag_set = tagging.into_tag_set()?;
+
+	let version_id = update_object_tags(&ctx, key, version_id, tag_set).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(X_AMZ_VERSION_ID, version_id)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_object_tagging(
+	ctx: ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<ResBody>, Error> {
+	let version_id = update_object_tags(&ctx, key, version_id, ObjectTagSet::default()).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.header(X_AMZ_VERSION_ID, version_id)
+		.body(empty_body())?)
+}
+
+pub async fn handle_get_bucket_tagging(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let tags = bucket_params
+		.tags
+		.get()
+		.as_ref()
+		.ok_or(Error::NoSuchTagSet)?;
+	let xml = to_xml_with_header(&Tagging::from_tags(tags.iter()))?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_tagging(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let body = req.into_body().collect().await?;
+
+	let tagging: Tagging = from_reader(&body as &[u8])?;
+	let tags = tagging.into_bucket_tags()?;
+
+	update_bucket_tags(ctx, Some(tags)).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+pub async fn handle_delete_bucket_tagging(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	update_bucket_tags(ctx, None).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+async fn update_bucket_tags(
+	ctx: ReqCtx,
+	tags: Option<BTreeMap<String, String>>,
+) -> Result<(), Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	bucket_params.tags.update(tags);
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+	Ok(())
+}
+
+/// Replace the tag set of an object version, returns the version ID
+/// of the updated version
+async fn update_object_tags(
+	ctx: &ReqCtx,
+	key: &str,
+	version_id: Option<&str>,
+	tag_set: ObjectTagSet,
+) -> Result<String, Error> {
+	let ReqCtx {
+		garage, bucket_id, ..
+	} = ctx;
+
+	let object = garage
+		.object_table
+		.get(bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = find_data_version(&object, version_id)?;
+
+	let mut new_version = version.clone();
+	new_version.tags.update(tag_set);
+
+	let object = Object::new(*bucket_id, key.to_string(), vec![new_version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(self::version_id(version))
+}
+
+// ---- helpers ----
+
+/// Parse the tag set given in the x-amz-tagging header, encoded as URL query parameters
+pub(crate) fn parse_tagging_header(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ObjectTagSet>, Error> {
+	let value = match headers.get(X_AMZ_TAGGING) {
+		Some(v) => v.to_str()?,
+		None => return Ok(None),
+	};
+	let tags = form_urlencoded::parse(value.as_bytes())
+		.map(|(k, v)| (k.into_owned(), v.into_owned()))
+		.collect::<Vec<_>>();
+	validate_tags(&tags, MAX_TAGS)?;
+	Ok(Some(ObjectTagSet(tags)))
+}
+
+/// Number of tags to be returned in the x-amz-tagging-count header,
+/// if there are any
+pub(crate) fn tagging_count(version: &ObjectVersion) -> Option<usize> {
+	Some(version.tags.get().0.len()).filter(|n| *n > 0)
+}
+
+fn validate_tags(tags: &[(String, String)], max_tags: usize) -> Result<(), Error> {
+	if tags.len() > max_tags {
+		return Err(Error::InvalidTag(format!(
+			"Tag count cannot be greater than {}",
+			max_tags
+		)));
+	}
+	let mut keys = HashSet::new();
+	for (k, v) in tags.iter() {
+		if k.is_empty() || k.chars().count() > MAX_TAG_KEY_LEN {
+			return Err(Error::InvalidTag(format!(
+				"The TagKey you have provided is invalid: {}",
+				k
+			)));
+		}
+		if v.chars().count() > MAX_TAG_VALUE_LEN {
+			return Err(Error::InvalidTag(format!(
+				"The TagValue you have provided is invalid: {}",
+				v
+			)));
+		}
+		if !keys.insert(k.as_str()) {
+			return Err(Error::InvalidTag(format!(
+				"Cannot provide multiple Tags with the same key: {}",
+				k
+			)));
+		}
+	}
+	Ok(())
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename = "Tagging")]
+pub struct Tagging {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "TagSet")]
+	pub tag_set: TagSet,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TagSet {
+	#[serde(rename = "Tag", default)]
+	pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "Value")]
+	pub value: Value,
+}
+
+impl Tagging {
+	pub fn from_tag_set(tag_set: &ObjectTagSet) -> Self {
+		Self::from_tags(tag_set.0.iter().map(|(k, v)| (k, v)))
+	}
+
+	pub fn from_tags<'a>(tags: impl Iterator<Item = (&'a String, &'a String)>) -> Self {
+		Self {
+			xmlns: (),
+			tag_set: TagSet {
+				tags: tags
+					.map(|(k, v)| Tag {
+						key: Value(k.clone()),
+						value: Value(v.clone()),
+					})
+					.collect(),
+			},
+		}
+	}
+
+	pub fn into_tag_set(self) -> Result<ObjectTagSet, Error> {
+		let tags = self.into_tags();
+		validate_tags(&tags, MAX_TAGS)?;
+		Ok(ObjectTagSet(tags))
+	}
+
+	pub fn into_bucket_tags(self) -> Result<BTreeMap<String, String>, Error> {
+		let tags = self.into_tags();
+		validate_tags(&tags, MAX_BUCKET_TAGS)?;
+		Ok(tags.into_iter().collect())
+	}
+
+	fn into_tags(self) -> Vec<(String, String)> {
+		self.tag_set
+			.tags
+			.into_iter()
+			.map(|t| (t.key.0, t.value.0))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_tagging() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TagSet>
+    <Tag>
+      <Key>project</Key>
+      <Value>garage</Value>
+    </Tag>
+    <Tag>
+      <Key>empty</Key>
+      <Value></Value>
+    </Tag>
+  </TagSet>
+</Tagging>"#;
+		let tagging: Tagging = from_str(message).unwrap();
+		let tag_set = tagging.into_tag_set()?;
+		assert_eq!(
+			tag_set,
+			ObjectTagSet(vec![
+				("project".into(), "garage".into()),
+				("empty".into(), "".into()),
+			])
+		);
+
+		let message2 = to_xml_with_header(&Tagging::from_tag_set(&tag_set))?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_bucket_tags() -> Result<(), Error> {
+		let tagging = |n: usize| Tagging {
+			xmlns: (),
+			tag_set: TagSet {
+				tags: (0..n)
+					.rev()
+					.map(|i| Tag {
+						key: Value(format!("key{:02}", i)),
+						value: Value(i.to_string()),
+					})
+					.collect(),
+			},
+		};
+
+		let tags = tagging(3).into_bucket_tags()?;
+		assert_eq!(
+			tags.into_iter().collect::<Vec<_>>(),
+			vec![
+				("key00".into(), "0".into()),
+				("key01".into(), "1".into()),
+				("key02".into(), "2".into()),
+			]
+		);
+
+		// Buckets can have more tags than objects
+		assert!(tagging(50).into_bucket_tags().is_ok());
+		assert!(matches!(
+			tagging(51).into_bucket_tags(),
+			Err(Error::InvalidTag(_))
+		));
+		assert!(matches!(
+			tagging(11).into_tag_set(),
+			Err(Error::InvalidTag(_))
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_parse_tagging_header() -> Result<(), Error> {
+		let mut headers = HeaderMap::new();
+		assert_eq!(parse_tagging_header(&headers)?, None);
+
+		headers.insert(X_AMZ_TAGGING, "a=1&b=hello%20world&c=".parse().unwrap());
+		assert_eq!(
+			parse_tagging_header(&headers)?,
+			Some(ObjectTagSet(vec![
+				("a".into(), "1".into()),
+				("b".into(), "hello world".into()),
+				("c".into(), "".into()),
+			]))
+		);
+
+		headers.insert(X_AMZ_TAGGING, "a=1&a=2".parse().unwrap());
+		assert!(matches!(
+			parse_tagging_header(&headers),
+			Err(Error::InvalidTag(_))
+		));
+
+		Ok(())
+	}
+}
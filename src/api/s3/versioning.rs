@@ -0,0 +1,231 @@
+use quick_xml::de::from_reader;
+
+use hyper::{header::HeaderName, Request, Response, StatusCode};
+
+use garage_util::data::*;
+
+use garage_model::bucket_table::*;
+use garage_model::s3::object_table::*;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{ReqBody, ResBody};
+use crate::error::*;
+use crate::xml::{to_xml_with_header, Value, VersioningConfiguration};
+
+pub const X_AMZ_VERSION_ID: HeaderName = HeaderName::from_static("x-amz-version-id");
+pub const X_AMZ_DELETE_MARKER: HeaderName = HeaderName::from_static("x-amz-delete-marker");
+
+/// Version ID used for object versions that were not written
+/// with versioning enabled on the bucket
+pub const NULL_VERSION_ID: &str = "null";
+
+pub async fn handle_get_bucket_versioning(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
+	let ReqCtx { bucket_params, .. } = ctx;
+
+	let status = match bucket_params.versioning.get() {
+		None => None,
+		Some(BucketVersioning::Enabled) => Some(Value("Enabled".into())),
+		Some(BucketVersioning::Suspended) => Some(Value("Suspended".into())),
+	};
+	let xml = to_xml_with_header(&VersioningConfiguration { xmlns: (), status })?;
+
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(string_body(xml))?)
+}
+
+pub async fn handle_put_bucket_versioning(
+	ctx: ReqCtx,
+	req: Request<ReqBody>,
+) -> Result<Response<ResBody>, Error> {
+	let ReqCtx {
+		garage,
+		bucket_id,
+		mut bucket_params,
+		..
+	} = ctx;
+
+	let body = req.into_body().collect().await?;
+
+	let conf: VersioningConfiguration = from_reader(&body as &[u8])?;
+	let versioning = match conf.status.as_ref().map(|s| s.0.as_str()) {
+		Some("Enabled") => BucketVersioning::Enabled,
+		Some("Suspended") => BucketVersioning::Suspended,
+		Some(s) => {
+			return Err(Error::bad_request(format!(
+				"Invalid versioning status: {}",
+				s
+			)))
+		}
+		None => return Err(Error::bad_request("Missing versioning status")),
+	};
+
+	if versioning == BucketVersioning::Suspended && bucket_params.object_lock_enabled() {
+		return Err(Error::InvalidBucketState(
+			"An Object Lock configuration is present on this bucket, so the versioning state cannot be changed".into(),
+		));
+	}
+
+	bucket_params.versioning.update(Some(versioning));
+	garage
+		.bucket_table
+		.insert(&Bucket::present(bucket_id, bucket_params))
+		.await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(empty_body())?)
+}
+
+// ---- helpers ----
+
+/// Version ID of an object version, as exposed in the S3 API
+pub(crate) fn version_id(version: &ObjectVersion) -> String {
+	encode_version_id(version.uuid, version.versioned)
+}
+
+pub(crate) fn encode_version_id(uuid: Uuid, versioned: bool) -> String {
+	if versioned {
+		hex::encode(uuid)
+	} else {
+		NULL_VERSION_ID.to_string()
+	}
+}
+
+/// Find the complete version of an object (data or delete marker)
+/// that is designated by a version ID.
+///
+/// The "null" version ID designates the version that was not written with versioning
+/// enabled. Other version IDs are the hex-encoded uuid of the version.
+pub(crate) fn find_version<'a>(
+	object: &'a Object,
+	version_id: &str,
+) -> Result<&'a ObjectVersion, Error> {
+	let found = if version_id == NULL_VERSION_ID {
+		object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| v.is_complete() && !v.versioned)
+	} else {
+		let uuid = decode_version_id(version_id)?;
+		object
+			.versions()
+			.iter()
+			.find(|v| v.is_complete() && v.uuid == uuid)
+	};
+	found.ok_or(Error::NoSuchVersion)
+}
+
+/// Select the version of an object to read: the version designated by version_id
+/// if specified, or the current version otherwise.
+/// Returns an error if the selected version is a delete marker.
+pub(crate) fn find_data_version<'a>(
+	object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<&'a ObjectVersion, Error> {
+	match version_id {
+		None => {
+			let version = object.current_version().ok_or(Error::NoSuchKey)?;
+			if !version.is_data() {
+				return Err(Error::CurrentVersionIsDeleteMarker(self::version_id(
+					version,
+				)));
+			}
+			Ok(version)
+		}
+		Some(vid) => {
+			let version = find_version(object, vid)?;
+			if !version.is_data() {
+				return Err(Error::VersionIsDeleteMarker(self::version_id(version)));
+			}
+			Ok(version)
+		}
+	}
+}
+
+fn decode_version_id(id: &str) -> Result<Uuid, Error> {
+	let id_bin = hex::decode(id).map_err(|_| Error::bad_request("Invalid version id"))?;
+	if id_bin.len() != 32 {
+		return Err(Error::bad_request("Invalid version id"));
+	}
+	let mut uuid = [0u8; 32];
+	uuid.copy_from_slice(&id_bin[..]);
+	Ok(Uuid::from(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(uuid: u8, timestamp: u64, versioned: bool, data: bool) -> ObjectVersion {
+		let state = if data {
+			ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					size: 0,
+					etag: "".into(),
+					encryption: ObjectVersionEncryption::Plaintext {
+						inner: ObjectVersionMetaInner {
+							headers: vec![],
+							checksum: None,
+						},
+					},
+				},
+				vec![],
+			)
+		} else {
+			ObjectVersionData::DeleteMarker
+		};
+		ObjectVersion {
+			uuid: Uuid::from([uuid; 32]),
+			timestamp,
+			state: ObjectVersionState::Complete(state),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
+		}
+	}
+
+	#[test]
+	fn test_find_version() -> Result<(), Error> {
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![
+				version(1, 1, false, true),
+				version(2, 2, true, true),
+				version(3, 3, true, false),
+			],
+		);
+
+		assert_eq!(find_version(&object, "null")?.uuid, Uuid::from([1; 32]));
+		assert_eq!(
+			find_version(&object, &hex::encode([2u8; 32]))?.uuid,
+			Uuid::from([2; 32])
+		);
+		assert!(matches!(
+			find_version(&object, &hex::encode([4u8; 32])),
+			Err(Error::NoSuchVersion)
+		));
+		assert!(find_version(&object, "xyz").is_err());
+
+		assert!(matches!(
+			find_data_version(&object, None),
+			Err(Error::CurrentVersionIsDeleteMarker(_))
+		));
+		assert!(matches!(
+			find_data_version(&object, Some(&hex::encode([3u8; 32]))),
+			Err(Error::VersionIsDeleteMarker(_))
+		));
+		assert_eq!(
+			find_data_version(&object, Some("null"))?.uuid,
+			Uuid::from([1; 32])
+		);
+
+		Ok(())
+	}
+}
@@ -3,7 +3,7 @@ use quick_xml::de::from_reader;
 use hyper::{header::HeaderName, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use garage_model::bucket_table::*;
+use garage_model::bucket_table::{self, Bucket, RedirectAll, ReplaceKey, WebsiteConfig};
 
 use garage_api_common::helpers::*;
 
@@ -14,20 +14,13 @@ use crate::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
 pub const X_AMZ_WEBSITE_REDIRECT_LOCATION: HeaderName =
 	HeaderName::from_static("x-amz-website-redirect-location");
 
+/// Maximum number of routing rules in a website configuration
+const MAX_ROUTING_RULES: usize = 50;
+
 pub async fn handle_get_website(ctx: ReqCtx) -> Result<Response<ResBody>, Error> {
 	let ReqCtx { bucket_params, .. } = ctx;
 	if let Some(website) = bucket_params.website_config.get() {
-		let wc = WebsiteConfiguration {
-			xmlns: (),
-			error_document: website.error_document.as_ref().map(|v| Key {
-				key: Value(v.to_string()),
-			}),
-			index_document: Some(Suffix {
-				suffix: Value(website.index_document.to_string()),
-			}),
-			redirect_all_requests_to: None,
-			routing_rules: None,
-		};
+		let wc = WebsiteConfiguration::from_garage_website_config(website);
 		let xml = to_xml_with_header(&wc)?;
 		Ok(Response::builder()
 			.status(StatusCode::OK)
@@ -98,13 +91,13 @@ pub struct WebsiteConfiguration {
 	#[serde(rename = "RedirectAllRequestsTo")]
 	pub redirect_all_requests_to: Option<Target>,
 	#[serde(rename = "RoutingRules")]
-	pub routing_rules: Option<Vec<RoutingRule>>,
+	pub routing_rules: Option<RoutingRules>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct RoutingRule {
+pub struct RoutingRules {
 	#[serde(rename = "RoutingRule")]
-	pub inner: RoutingRuleInner,
+	pub rules: Vec<RoutingRuleInner>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -178,8 +171,14 @@ impl WebsiteConfiguration {
 			rart.validate()?;
 		}
 		if let Some(ref rrs) = self.routing_rules {
-			for rr in rrs {
-				rr.inner.validate()?;
+			if rrs.rules.len() > MAX_ROUTING_RULES {
+				return Err(Error::bad_request(format!(
+					"Bad XML: website configuration can have at most {} routing rules",
+					MAX_ROUTING_RULES
+				)));
+			}
+			for rr in rrs.rules.iter() {
+				rr.validate()?;
 			}
 		}
 
@@ -187,22 +186,59 @@ impl WebsiteConfiguration {
 	}
 
 	pub fn into_garage_website_config(self) -> Result<WebsiteConfig, Error> {
-		if self.redirect_all_requests_to.is_some() {
-			Err(Error::NotImplemented(
-				"S3 website redirects are not currently implemented in Garage.".into(),
-			))
-		} else if self.routing_rules.map(|x| !x.is_empty()).unwrap_or(false) {
-			Err(Error::NotImplemented(
-				"S3 routing rules are not currently implemented in Garage.".into(),
-			))
-		} else {
-			Ok(WebsiteConfig {
-				index_document: self
-					.index_document
-					.map(|x| x.suffix.0)
-					.unwrap_or_else(|| "index.html".to_string()),
-				error_document: self.error_document.map(|x| x.key.0),
-			})
+		Ok(WebsiteConfig {
+			index_document: self
+				.index_document
+				.map(|x| x.suffix.0)
+				.unwrap_or_else(|| "index.html".to_string()),
+			error_document: self.error_document.map(|x| x.key.0),
+			redirect_all: self.redirect_all_requests_to.map(|x| RedirectAll {
+				hostname: x.hostname.0,
+				protocol: x.protocol.map(|p| p.0),
+			}),
+			routing_rules: self
+				.routing_rules
+				.map(|x| x.rules)
+				.unwrap_or_default()
+				.into_iter()
+				.map(RoutingRuleInner::into_garage_routing_rule)
+				.collect(),
+		})
+	}
+
+	pub fn from_garage_website_config(website: &WebsiteConfig) -> Self {
+		if let Some(redirect_all) = &website.redirect_all {
+			return WebsiteConfiguration {
+				xmlns: (),
+				error_document: None,
+				index_document: None,
+				redirect_all_requests_to: Some(Target {
+					hostname: Value(redirect_all.hostname.clone()),
+					protocol: redirect_all.protocol.clone().map(Value),
+				}),
+				routing_rules: None,
+			};
+		}
+		WebsiteConfiguration {
+			xmlns: (),
+			error_document: website.error_document.as_ref().map(|v| Key {
+				key: Value(v.to_string()),
+			}),
+			index_document: Some(Suffix {
+				suffix: Value(website.index_document.to_string()),
+			}),
+			redirect_all_requests_to: None,
+			routing_rules: if website.routing_rules.is_empty() {
+				None
+			} else {
+				Some(RoutingRules {
+					rules: website
+						.routing_rules
+						.iter()
+						.map(RoutingRuleInner::from_garage_routing_rule)
+						.collect(),
+				})
+			},
 		}
 	}
 }
@@ -244,6 +280,9 @@ impl Target {
 
 impl RoutingRuleInner {
 	pub fn validate(&self) -> Result<(), Error> {
+		if let Some(ref condition) = self.condition {
+			condition.validate()?;
+		}
 		let has_prefix = self
 			.condition
 			.as_ref()
@@ -251,6 +290,73 @@ impl RoutingRuleInner {
 			.is_some();
 		self.redirect.validate(has_prefix)
 	}
+
+	fn into_garage_routing_rule(self) -> bucket_table::RoutingRule {
+		let (prefix, http_error_code) = match self.condition {
+			Some(c) => (c.prefix.map(|x| x.0), c.http_error_code.map(|x| x.0 as u16)),
+			None => (None, None),
+		};
+		let redirect = self.redirect;
+		let replace_key = match (redirect.replace_prefix, redirect.replace_full) {
+			(Some(p), _) => Some(ReplaceKey::Prefix(p.0)),
+			(None, Some(k)) => Some(ReplaceKey::Full(k.0)),
+			(None, None) => None,
+		};
+		bucket_table::RoutingRule {
+			prefix,
+			http_error_code,
+			redirect: bucket_table::Redirect {
+				hostname: redirect.hostname.map(|x| x.0),
+				protocol: redirect.protocol.map(|x| x.0),
+				http_redirect_code: redirect
+					.http_redirect_code
+					.map(|x| x.0 as u16)
+					.unwrap_or(301),
+				replace_key,
+			},
+		}
+	}
+
+	fn from_garage_routing_rule(rule: &bucket_table::RoutingRule) -> Self {
+		let condition = if rule.prefix.is_some() || rule.http_error_code.is_some() {
+			Some(Condition {
+				http_error_code: rule.http_error_code.map(|x| IntValue(x as i64)),
+				prefix: rule.prefix.clone().map(Value),
+			})
+		} else {
+			None
+		};
+		let redirect = &rule.redirect;
+		let (replace_prefix, replace_full) = match &redirect.replace_key {
+			Some(ReplaceKey::Prefix(p)) => (Some(Value(p.clone())), None),
+			Some(ReplaceKey::Full(k)) => (None, Some(Value(k.clone()))),
+			None => (None, None),
+		};
+		RoutingRuleInner {
+			condition,
+			redirect: Redirect {
+				hostname: redirect.hostname.clone().map(Value),
+				protocol: redirect.protocol.clone().map(Value),
+				http_redirect_code: Some(IntValue(redirect.http_redirect_code as i64)),
+				replace_prefix,
+				replace_full,
+			},
+		}
+	}
+}
+
+impl Condition {
+	pub fn validate(&self) -> Result<(), Error> {
+		match self.http_error_code {
+			None if self.prefix.is_none() => Err(Error::bad_request(
+				"Bad XML: routing rule condition must have KeyPrefixEquals or HttpErrorCodeReturnedEquals",
+			)),
+			Some(IntValue(code)) if !(400..600).contains(&code) => Err(Error::bad_request(
+				"Bad XML: HttpErrorCodeReturnedEquals must be a 4xx or 5xx code",
+			)),
+			_ => Ok(()),
+		}
+	}
 }
 
 impl Redirect {
@@ -272,6 +378,13 @@ impl Redirect {
 				return Err(Error::bad_request("Bad XML: invalid protocol"));
 			}
 		}
+		if let Some(IntValue(code)) = self.http_redirect_code {
+			if !(300..400).contains(&code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpRedirectCode must be a 3xx code",
+				));
+			}
+		}
 		// TODO there are probably more invalid cases, but which ones?
 		Ok(())
 	}
@@ -326,8 +439,8 @@ mod tests {
 				hostname: Value("garage.tld".to_owned()),
 				protocol: Some(Value("https".to_owned())),
 			}),
-			routing_rules: Some(vec![RoutingRule {
-				inner: RoutingRuleInner {
+			routing_rules: Some(RoutingRules {
+				rules: vec![RoutingRuleInner {
 					condition: Some(Condition {
 						http_error_code: Some(IntValue(404)),
 						prefix: Some(Value("prefix1".to_owned())),
@@ -339,8 +452,8 @@ mod tests {
 						replace_prefix: Some(Value("prefix2".to_owned())),
 						replace_full: Some(Value("fullkey".to_owned())),
 					},
-				},
-			}]),
+				}],
+			}),
 		};
 		assert_eq! {
 			ref_value,
@@ -354,4 +467,95 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn test_routing_rules_conversion() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<WebsiteConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+   <IndexDocument>
+      <Suffix>index.html</Suffix>
+   </IndexDocument>
+   <RoutingRules>
+      <RoutingRule>
+         <Condition>
+            <KeyPrefixEquals>docs/</KeyPrefixEquals>
+         </Condition>
+         <Redirect>
+            <ReplaceKeyPrefixWith>documents/</ReplaceKeyPrefixWith>
+         </Redirect>
+      </RoutingRule>
+      <RoutingRule>
+         <Condition>
+            <HttpErrorCodeReturnedEquals>404</HttpErrorCodeReturnedEquals>
+         </Condition>
+         <Redirect>
+            <HostName>fallback.tld</HostName>
+            <Protocol>https</Protocol>
+            <HttpRedirectCode>302</HttpRedirectCode>
+            <ReplaceKeyWith>not-found.html</ReplaceKeyWith>
+         </Redirect>
+      </RoutingRule>
+   </RoutingRules>
+</WebsiteConfiguration>"#;
+		let conf: WebsiteConfiguration = from_str(message).unwrap();
+		conf.validate()?;
+		let website = conf.into_garage_website_config()?;
+
+		assert_eq!(
+			website.routing_rules,
+			vec![
+				bucket_table::RoutingRule {
+					prefix: Some("docs/".to_string()),
+					http_error_code: None,
+					redirect: bucket_table::Redirect {
+						hostname: None,
+						protocol: None,
+						http_redirect_code: 301,
+						replace_key: Some(ReplaceKey::Prefix("documents/".to_string())),
+					},
+				},
+				bucket_table::RoutingRule {
+					prefix: None,
+					http_error_code: Some(404),
+					redirect: bucket_table::Redirect {
+						hostname: Some("fallback.tld".to_string()),
+						protocol: Some("https".to_string()),
+						http_redirect_code: 302,
+						replace_key: Some(ReplaceKey::Full("not-found.html".to_string())),
+					},
+				},
+			]
+		);
+
+		let rule = website.routing_rule("docs/v1/", None).unwrap();
+		assert_eq!(rule.redirect_key("docs/v1/"), "documents/v1/");
+		assert!(website.routing_rule("blog/", None).is_none());
+		let rule = website.routing_rule("blog/", Some(404)).unwrap();
+		assert_eq!(rule.redirect_key("blog/"), "not-found.html");
+
+		let conf2 = WebsiteConfiguration::from_garage_website_config(&website);
+		assert_eq!(conf2.into_garage_website_config()?, website);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_routing_rules() {
+		let bad_redirect_code = r#"<WebsiteConfiguration>
+   <RoutingRules><RoutingRule>
+      <Condition><KeyPrefixEquals>a/</KeyPrefixEquals></Condition>
+      <Redirect><HttpRedirectCode>200</HttpRedirectCode></Redirect>
+   </RoutingRule></RoutingRules>
+</WebsiteConfiguration>"#;
+		let empty_condition = r#"<WebsiteConfiguration>
+   <RoutingRules><RoutingRule>
+      <Condition></Condition>
+      <Redirect><HostName>a.tld</HostName></Redirect>
+   </RoutingRule></RoutingRules>
+</WebsiteConfiguration>"#;
+		for message in [bad_redirect_code, empty_condition] {
+			let conf: WebsiteConfiguration = from_str(message).unwrap();
+			assert!(conf.validate().is_err());
+		}
+	}
 }
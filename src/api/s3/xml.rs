@@ -68,9 +68,11 @@ pub struct Deleted {
 	#[serde(rename = "Key")]
 	pub key: Value,
 	#[serde(rename = "VersionId")]
-	pub version_id: Value,
+	pub version_id: Option<Value>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_marker: Option<Value>,
 	#[serde(rename = "DeleteMarkerVersionId")]
-	pub delete_marker_version_id: Value,
+	pub delete_marker_version_id: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -139,6 +141,10 @@ pub struct CompleteMultipartUploadResult {
 	pub checksum_sha1: Option<Value>,
 	#[serde(rename = "ChecksumSHA256")]
 	pub checksum_sha256: Option<Value>,
+	#[serde(rename = "ChecksumCRC64NVME")]
+	pub checksum_crc64nvme: Option<Value>,
+	#[serde(rename = "ChecksumType")]
+	pub checksum_type: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -213,6 +219,8 @@ pub struct PartItem {
 	pub checksum_sha1: Option<Value>,
 	#[serde(rename = "ChecksumSHA256")]
 	pub checksum_sha256: Option<Value>,
+	#[serde(rename = "ChecksumCRC64NVME")]
+	pub checksum_crc64nvme: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -298,9 +306,71 @@ pub struct ListBucketResult {
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
-pub struct VersioningConfiguration {
+pub struct ListVersionsItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "ETag")]
+	pub etag: Value,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListDeleteMarkerItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListVersionsResult {
 	#[serde(serialize_with = "xmlns_tag")]
 	pub xmlns: (),
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Prefix")]
+	pub prefix: Value,
+	#[serde(rename = "KeyMarker")]
+	pub key_marker: Option<Value>,
+	#[serde(rename = "VersionIdMarker")]
+	pub version_id_marker: Option<Value>,
+	#[serde(rename = "NextKeyMarker")]
+	pub next_key_marker: Option<Value>,
+	#[serde(rename = "NextVersionIdMarker")]
+	pub next_version_id_marker: Option<Value>,
+	#[serde(rename = "MaxKeys")]
+	pub max_keys: IntValue,
+	#[serde(rename = "Delimiter")]
+	pub delimiter: Option<Value>,
+	#[serde(rename = "EncodingType")]
+	pub encoding_type: Option<Value>,
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "Version")]
+	pub versions: Vec<ListVersionsItem>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_markers: Vec<ListDeleteMarkerItem>,
+	#[serde(rename = "CommonPrefixes")]
+	pub common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersioningConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
 	#[serde(rename = "Status")]
 	pub status: Option<Value>,
 }
@@ -434,13 +504,15 @@ mod tests {
 			deleted: vec![
 				Deleted {
 					key: Value("a/plop".to_string()),
-					version_id: Value("qsdfjklm".to_string()),
-					delete_marker_version_id: Value("wxcvbn".to_string()),
+					version_id: Some(Value("qsdfjklm".to_string())),
+					delete_marker: Some(Value("true".to_string())),
+					delete_marker_version_id: Some(Value("wxcvbn".to_string())),
 				},
 				Deleted {
 					key: Value("b/plip".to_string()),
-					version_id: Value("1234".to_string()),
-					delete_marker_version_id: Value("4321".to_string()),
+					version_id: Some(Value("1234".to_string())),
+					delete_marker: None,
+					delete_marker_version_id: None,
 				},
 			],
 			errors: vec![
@@ -465,12 +537,12 @@ mod tests {
     <Deleted>\
         <Key>a/plop</Key>\
         <VersionId>qsdfjklm</VersionId>\
+        <DeleteMarker>true</DeleteMarker>\
         <DeleteMarkerVersionId>wxcvbn</DeleteMarkerVersionId>\
     </Deleted>\
     <Deleted>\
         <Key>b/plip</Key>\
         <VersionId>1234</VersionId>\
-        <DeleteMarkerVersionId>4321</DeleteMarkerVersionId>\
     </Deleted>\
     <Error>\
         <Code>NotFound</Code>\
@@ -520,6 +592,8 @@ mod tests {
 			checksum_crc32c: None,
 			checksum_sha1: Some(Value("ZJAnHyG8PeKz9tI8UTcHrJos39A=".into())),
 			checksum_sha256: None,
+			checksum_crc64nvme: None,
+			checksum_type: Some(Value("COMPOSITE".into())),
 		};
 		assert_eq!(
 			to_xml_with_header(&result)?,
@@ -530,6 +604,7 @@ mod tests {
 	<Key>a/plop</Key>\
 	<ETag>&quot;3858f62230ac3c915f300c664312c11f-9&quot;</ETag>\
     <ChecksumSHA1>ZJAnHyG8PeKz9tI8UTcHrJos39A=</ChecksumSHA1>\
+    <ChecksumType>COMPOSITE</ChecksumType>\
 </CompleteMultipartUploadResult>"
 		);
 		Ok(())
@@ -686,6 +761,67 @@ mod tests {
 		Ok(())
 	}
 
+	#[test]
+	fn list_object_versions() -> Result<(), ApiError> {
+		let result = ListVersionsResult {
+			xmlns: (),
+			name: Value("example-bucket".to_string()),
+			prefix: Value("".to_string()),
+			key_marker: None,
+			version_id_marker: None,
+			next_key_marker: Some(Value("photo.jpg".to_string())),
+			next_version_id_marker: Some(Value("null".to_string())),
+			max_keys: IntValue(2),
+			delimiter: None,
+			encoding_type: None,
+			is_truncated: Value("true".to_string()),
+			versions: vec![ListVersionsItem {
+				key: Value("photo.jpg".to_string()),
+				version_id: Value("null".to_string()),
+				is_latest: Value("false".to_string()),
+				last_modified: Value(msec_to_rfc3339(0)),
+				etag: Value("\"fba9dede5f27731c9771645a39863328\"".to_string()),
+				size: IntValue(434234),
+				storage_class: Value("STANDARD".to_string()),
+			}],
+			delete_markers: vec![ListDeleteMarkerItem {
+				key: Value("photo.jpg".to_string()),
+				version_id: Value("3HL4kqtJlcpXroDTDmJ".to_string()),
+				is_latest: Value("true".to_string()),
+				last_modified: Value(msec_to_rfc3339(1000)),
+			}],
+			common_prefixes: vec![],
+		};
+		assert_eq!(
+			to_xml_with_header(&result)?,
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+<ListVersionsResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+  <Name>example-bucket</Name>\
+  <Prefix></Prefix>\
+  <NextKeyMarker>photo.jpg</NextKeyMarker>\
+  <NextVersionIdMarker>null</NextVersionIdMarker>\
+  <MaxKeys>2</MaxKeys>\
+  <IsTruncated>true</IsTruncated>\
+  <Version>\
+    <Key>photo.jpg</Key>\
+    <VersionId>null</VersionId>\
+    <IsLatest>false</IsLatest>\
+    <LastModified>1970-01-01T00:00:00.000Z</LastModified>\
+    <ETag>&quot;fba9dede5f27731c9771645a39863328&quot;</ETag>\
+    <Size>434234</Size>\
+    <StorageClass>STANDARD</StorageClass>\
+  </Version>\
+  <DeleteMarker>\
+    <Key>photo.jpg</Key>\
+    <VersionId>3HL4kqtJlcpXroDTDmJ</VersionId>\
+    <IsLatest>true</IsLatest>\
+    <LastModified>1970-01-01T00:00:01.000Z</LastModified>\
+  </DeleteMarker>\
+</ListVersionsResult>"
+		);
+		Ok(())
+	}
+
 	#[test]
 	fn list_objects_v2_1() -> Result<(), ApiError> {
 		let result = ListBucketResult {
@@ -807,6 +943,7 @@ mod tests {
 						"5RQ3A5uk0w7ojNjvegohch4JRBBGN/cLhsNrPzfv/hA=".into(),
 					)),
 					checksum_sha1: None,
+					checksum_crc64nvme: None,
 				},
 				PartItem {
 					etag: Value("\"aaaa18db4cc2f85cedef654fccc4a4x8\"".to_string()),
@@ -817,6 +954,7 @@ mod tests {
 					checksum_crc32c: None,
 					checksum_crc32: Some(Value("ZJAnHyG8=".into())),
 					checksum_sha1: None,
+					checksum_crc64nvme: None,
 				},
 			],
 			initiator: Initiator {
@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
-use garage_util::config::DataDirEnum;
+use garage_util::config::{DataDirEnum, DEFAULT_STORAGE_CLASS};
 use garage_util::data::Hash;
 use garage_util::error::{Error, OkOrMessage};
 use garage_util::migrate::*;
@@ -28,6 +28,13 @@ pub(crate) struct DataLayout {
 	/// Secondary storage locations for each partition = locations
 	/// where data blocks might be, we check from these dirs when reading
 	pub(crate) part_sec: Vec<Vec<Idx>>,
+
+	/// Layouts of the data directories of storage classes other than
+	/// the default one. The fields above describe the data directories
+	/// of the default storage class; each other storage class has its
+	/// own set of data directories, with partitions assigned independently.
+	#[serde(default)]
+	pub(crate) storage_classes: BTreeMap<String, DataLayout>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -44,8 +51,33 @@ pub(crate) enum DataDirState {
 
 impl DataLayout {
 	pub(crate) fn initialize(dirs: &DataDirEnum) -> Result<Self, Error> {
-		let data_dirs = make_data_dirs(dirs)?;
+		let (data_dirs, class_dirs) = make_data_dirs(dirs)?;
+
+		let mut layout = Self::initialize_dirs(data_dirs)?;
+		for (class, data_dirs) in class_dirs {
+			layout
+				.storage_classes
+				.insert(class, Self::initialize_dirs(data_dirs)?);
+		}
+		Ok(layout)
+	}
+
+	pub(crate) fn update(mut self, dirs: &DataDirEnum) -> Result<Self, Error> {
+		let (data_dirs, class_dirs) = make_data_dirs(dirs)?;
+
+		let mut prev_classes = std::mem::take(&mut self.storage_classes);
+		let mut layout = self.update_dirs(data_dirs)?;
+		for (class, data_dirs) in class_dirs {
+			let class_layout = match prev_classes.remove(&class) {
+				Some(prev) => prev.update_dirs(data_dirs)?,
+				None => Self::initialize_dirs(data_dirs)?,
+			};
+			layout.storage_classes.insert(class, class_layout);
+		}
+		Ok(layout)
+	}
 
+	fn initialize_dirs(data_dirs: Vec<DataDir>) -> Result<Self, Error> {
 		// Split partitions proportionnally to capacity for all drives
 		// to affect primary storage location
 		let total_cap = data_dirs.iter().filter_map(|x| x.capacity()).sum::<u64>();
@@ -82,12 +114,12 @@ impl DataLayout {
 			markers: HashMap::new(),
 			part_prim,
 			part_sec,
+			storage_classes: BTreeMap::new(),
 		})
 	}
 
-	pub(crate) fn update(self, dirs: &DataDirEnum) -> Result<Self, Error> {
-		// Make list of new data directories, exit if nothing changed
-		let data_dirs = make_data_dirs(dirs)?;
+	fn update_dirs(self, data_dirs: Vec<DataDir>) -> Result<Self, Error> {
+		// Exit if the list of data directories has not changed
 		if data_dirs == self.data_dirs {
 			return Ok(self);
 		}
@@ -224,6 +256,7 @@ impl DataLayout {
 			markers: self.markers,
 			part_prim,
 			part_sec,
+			storage_classes: BTreeMap::new(),
 		})
 	}
 
@@ -256,15 +289,54 @@ impl DataLayout {
 			}
 		}
 
+		for class_layout in self.storage_classes.values_mut() {
+			class_layout.check_markers()?;
+		}
+
 		Ok(())
 	}
 
+	/// Get the layout of the data directories of a storage class.
+	/// Blocks of storage classes that have no data directory on this node
+	/// are stored in the data directories of the default storage class.
+	pub(crate) fn for_storage_class(&self, storage_class: Option<&str>) -> &Self {
+		storage_class
+			.and_then(|class| self.storage_classes.get(class))
+			.unwrap_or(self)
+	}
+
+	/// Get the layouts of the default storage class and of all other storage classes
+	pub(crate) fn all_layouts(&self) -> impl Iterator<Item = &Self> {
+		std::iter::once(self).chain(self.storage_classes.values())
+	}
+
 	pub(crate) fn primary_block_dir(&self, hash: &Hash) -> PathBuf {
 		let ipart = self.partition_from(hash);
 		let idir = self.part_prim[ipart] as usize;
 		self.block_dir_from(hash, &self.data_dirs[idir].path)
 	}
 
+	/// Get all directories where a block might be stored, starting with
+	/// its primary location for the given storage class
+	pub(crate) fn all_block_dirs<'a>(
+		&'a self,
+		hash: &'a Hash,
+		storage_class: Option<&str>,
+	) -> impl Iterator<Item = PathBuf> + 'a {
+		let class_layout = self.for_storage_class(storage_class);
+		let primary = class_layout.primary_block_dir(hash);
+		let other_primaries = self
+			.all_layouts()
+			.filter(move |l| !std::ptr::eq(*l, class_layout))
+			.map(move |l| l.primary_block_dir(hash));
+		let secondaries = self
+			.all_layouts()
+			.flat_map(move |l| l.secondary_block_dirs(hash));
+		std::iter::once(primary)
+			.chain(other_primaries)
+			.chain(secondaries)
+	}
+
 	pub(crate) fn secondary_block_dirs<'a>(
 		&'a self,
 		hash: &'a Hash,
@@ -296,6 +368,11 @@ impl DataLayout {
 			markers: self.markers.clone(),
 			part_prim: self.part_prim.clone(),
 			part_sec: self.part_sec.iter().map(|_| vec![]).collect::<Vec<_>>(),
+			storage_classes: self
+				.storage_classes
+				.iter()
+				.map(|(class, layout)| (class.clone(), layout.without_secondary_locations()))
+				.collect(),
 		}
 	}
 }
@@ -313,8 +390,14 @@ impl DataDir {
 	}
 }
 
-fn make_data_dirs(dirs: &DataDirEnum) -> Result<Vec<DataDir>, Error> {
+/// Data directories of the storage classes other than the default one
+type StorageClassDirs = BTreeMap<String, Vec<DataDir>>;
+
+/// Make the list of data directories of the default storage class,
+/// and the lists of data directories of the other storage classes
+fn make_data_dirs(dirs: &DataDirEnum) -> Result<(Vec<DataDir>, StorageClassDirs), Error> {
 	let mut data_dirs = vec![];
+	let mut class_dirs = StorageClassDirs::new();
 	match dirs {
 		DataDirEnum::Single(path) => data_dirs.push(DataDir {
 			path: path.clone(),
@@ -324,7 +407,22 @@ fn make_data_dirs(dirs: &DataDirEnum) -> Result<Vec<DataDir>, Error> {
 		}),
 		DataDirEnum::Multiple(dirs) => {
 			let mut ok = false;
+			let mut class_ok = BTreeMap::new();
 			for dir in dirs.iter() {
+				let class = dir
+					.storage_class
+					.as_deref()
+					.filter(|c| *c != DEFAULT_STORAGE_CLASS);
+				if let Some(class) = class {
+					if class.is_empty()
+						|| !class
+							.chars()
+							.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+					{
+						return Err(Error::Message(format!("invalid storage class `{}` for data directory {}, storage classes should be made of uppercase letters, digits and underscores", class, dir.path.to_string_lossy())));
+					}
+				}
+
 				let state = match &dir.capacity {
 					Some(cap) if dir.read_only == false => {
 						let capacity = cap.parse::<bytesize::ByteSize>()
@@ -332,7 +430,12 @@ fn make_data_dirs(dirs: &DataDirEnum) -> Result<Vec<DataDir>, Error> {
 						if capacity == 0 {
 							return Err(Error::Message(format!("data directory {} should have non-zero capacity", dir.path.to_string_lossy())));
 						}
-						ok = true;
+						match class {
+							Some(class) => {
+								class_ok.insert(class, true);
+							}
+							None => ok = true,
+						}
 						DataDirState::Active {
 							capacity,
 						}
@@ -342,20 +445,35 @@ fn make_data_dirs(dirs: &DataDirEnum) -> Result<Vec<DataDir>, Error> {
 					}
 					_ => return Err(Error::Message(format!("data directories in data_dir should have a capacity value or be marked read_only, not the case for {}", dir.path.to_string_lossy()))),
 				};
-				data_dirs.push(DataDir {
+				let data_dir = DataDir {
 					path: dir.path.clone(),
 					state,
-				});
+				};
+				match class {
+					Some(class) => {
+						class_ok.entry(class).or_insert(false);
+						class_dirs
+							.entry(class.to_string())
+							.or_default()
+							.push(data_dir);
+					}
+					None => data_dirs.push(data_dir),
+				}
 			}
 			if !ok {
 				return Err(Error::Message(
-					"incorrect data_dir configuration, no primary writable directory specified"
-						.into(),
+					format!("incorrect data_dir configuration, no primary writable directory specified for the default storage class ({})", DEFAULT_STORAGE_CLASS),
 				));
 			}
+			if let Some((class, _)) = class_ok.iter().find(|(_, ok)| !**ok) {
+				return Err(Error::Message(format!(
+					"incorrect data_dir configuration, no primary writable directory specified for storage class {}",
+					class
+				)));
+			}
 		}
 	}
-	Ok(data_dirs)
+	Ok((data_dirs, class_dirs))
 }
 
 fn dir_not_empty(path: &PathBuf) -> Result<bool, Error> {
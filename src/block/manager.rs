@@ -22,7 +22,7 @@ use garage_net::stream::{read_stream_to_end, stream_asyncread, ByteStream};
 use garage_db as db;
 
 use garage_util::background::{vars, BackgroundRunner};
-use garage_util::config::Config;
+use garage_util::config::{Config, DEFAULT_STORAGE_CLASS};
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
@@ -61,6 +61,9 @@ pub enum BlockRpc {
 	PutBlock {
 		hash: Hash,
 		header: DataBlockHeader,
+		/// Storage class of the block (None for the default storage class)
+		#[serde(default)]
+		storage_class: Option<String>,
 	},
 	/// Ask other node if they should have this block, but don't actually have it
 	NeedBlockQuery(Hash),
@@ -145,7 +148,10 @@ impl BlockManager {
 		let rc = db
 			.open_tree("block_local_rc")
 			.expect("Unable to open block_local_rc tree");
-		let rc = BlockRc::new(rc);
+		let storage_class_rc = db
+			.open_tree("block_local_storage_class_rc")
+			.expect("Unable to open block_local_storage_class_rc tree");
+		let rc = BlockRc::new(rc, storage_class_rc);
 
 		let resync = BlockResyncManager::new(db, &system);
 
@@ -250,26 +256,36 @@ impl BlockManager {
 			hash,
 			priority,
 			order_tag,
-			|stream| async move { Ok(stream) },
+			|stream, _| async move { Ok(stream) },
 		)
 		.await
 	}
 
 	/// Ask nodes that might have a (possibly compressed) block for it
-	/// Return its entire body
+	/// Return its entire body, and its storage class
 	pub(crate) async fn rpc_get_raw_block(
 		&self,
 		hash: &Hash,
 		priority: RequestPriority,
 		order_tag: Option<OrderTag>,
-	) -> Result<DataBlock, Error> {
-		self.rpc_get_raw_block_internal(hash, priority, order_tag, |block_stream| async move {
-			let (header, stream) = block_stream.into_parts();
-			read_stream_to_end(stream)
-				.await
-				.err_context("error in block data stream")
-				.map(|data| DataBlock::from_parts(header, data.into_bytes()))
-		})
+	) -> Result<(DataBlock, Option<String>), Error> {
+		self.rpc_get_raw_block_internal(
+			hash,
+			priority,
+			order_tag,
+			|block_stream, storage_class| async move {
+				let (header, stream) = block_stream.into_parts();
+				read_stream_to_end(stream)
+					.await
+					.err_context("error in block data stream")
+					.map(|data| {
+						(
+							DataBlock::from_parts(header, data.into_bytes()),
+							storage_class,
+						)
+					})
+			},
+		)
 		.await
 	}
 
@@ -281,7 +297,7 @@ impl BlockManager {
 		f: F,
 	) -> Result<T, Error>
 	where
-		F: Fn(DataBlockStream) -> Fut,
+		F: Fn(DataBlockStream, Option<String>) -> Fut,
 		Fut: futures::Future<Output = Result<T, Error>>,
 	{
 		let who = self
@@ -305,8 +321,8 @@ impl BlockManager {
 							continue;
 						}
 					};
-					let block_stream = match res.into_parts() {
-						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => DataBlockStream::from_parts(header, stream),
+					let (block_stream, storage_class) = match res.into_parts() {
+						(Ok(BlockRpc::PutBlock { hash: _, header, storage_class }), Some(stream)) => (DataBlockStream::from_parts(header, stream), storage_class),
 						(Ok(_), _) => {
 							debug!("Get block {:?}: node {:?} returned a malformed response", hash, node);
 							continue;
@@ -316,7 +332,7 @@ impl BlockManager {
 							continue;
 						}
 					};
-					match f(block_stream).await {
+					match f(block_stream, storage_class).await {
 						Ok(ret) => return Ok(ret),
 						Err(e) => {
 							debug!("Get block {:?}: error reading stream from node {:?}: {}", hash, node, e);
@@ -362,12 +378,14 @@ impl BlockManager {
 		}
 	}
 
-	/// Send block to nodes that should have it
+	/// Send block to nodes that should have it, to be stored in the data
+	/// directories of the given storage class (None for the default storage class)
 	pub async fn rpc_put_block(
 		&self,
 		hash: Hash,
 		data: Bytes,
 		prevent_compression: bool,
+		storage_class: Option<String>,
 		order_tag: Option<OrderTag>,
 	) -> Result<(), Error> {
 		let who = self.system.cluster_layout().current_storage_nodes_of(&hash);
@@ -384,8 +402,12 @@ impl BlockManager {
 			.await
 			.ok_or_message("could not reserve space for buffer of data to send to remote nodes")?;
 
-		let put_block_rpc =
-			Req::new(BlockRpc::PutBlock { hash, header })?.with_stream_from_buffer(bytes);
+		let put_block_rpc = Req::new(BlockRpc::PutBlock {
+			hash,
+			header,
+			storage_class,
+		})?
+		.with_stream_from_buffer(bytes);
 		let put_block_rpc = if let Some(tag) = order_tag {
 			put_block_rpc.with_order_tag(tag)
 		} else {
@@ -447,20 +469,28 @@ impl BlockManager {
 
 	//// ----- Managing the reference counter ----
 
-	/// Increment the number of time a block is used, putting it to resynchronization if it is
-	/// required, but not known
+	/// Increment the number of time a block is used by objects of a storage class
+	/// (None for the default storage class), putting it to resynchronization if it is
+	/// required, but not known, or if it has to be moved to another storage class
 	pub fn block_incref(
 		self: &Arc<Self>,
 		tx: &mut db::Transaction,
 		hash: Hash,
+		storage_class: Option<&str>,
 	) -> db::TxOpResult<()> {
-		if self.rc.block_incref(tx, &hash)? {
+		let storage_class = storage_class.unwrap_or(DEFAULT_STORAGE_CLASS);
+		let class_changed =
+			self.rc
+				.block_update_storage_class_rc(tx, &hash, None, Some(storage_class))?;
+		if self.rc.block_incref(tx, &hash)? || class_changed {
 			// When the reference counter is incremented, there is
 			// normally a node that is responsible for sending us the
 			// data of the block. However that operation may fail,
 			// so in all cases we add the block here to the todo list
 			// to check later that it arrived correctly, and if not
 			// we will fecth it from someone.
+			// The resync loop also moves the block if it is not stored
+			// in the data directories of its storage class.
 			let this = self.clone();
 			tokio::spawn(async move {
 				if let Err(e) = this
@@ -474,12 +504,18 @@ impl BlockManager {
 		Ok(())
 	}
 
-	/// Decrement the number of time a block is used
+	/// Decrement the number of time a block is used by objects of a storage class
+	/// (None for the default storage class)
 	pub fn block_decref(
 		self: &Arc<Self>,
 		tx: &mut db::Transaction,
 		hash: Hash,
+		storage_class: Option<&str>,
 	) -> db::TxOpResult<()> {
+		let storage_class = storage_class.unwrap_or(DEFAULT_STORAGE_CLASS);
+		let class_changed =
+			self.rc
+				.block_update_storage_class_rc(tx, &hash, Some(storage_class), None)?;
 		if self.rc.block_decref(tx, &hash)? {
 			// When the RC is decremented, it might drop to zero,
 			// indicating that we don't need the block.
@@ -495,31 +531,75 @@ impl BlockManager {
 					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
 				}
 			});
+		} else if class_changed {
+			self.put_to_resync_after_storage_class_change(hash);
+		}
+		Ok(())
+	}
+
+	/// Change the storage class of the objects of one of the references
+	/// to a block (None for the default storage class)
+	pub fn block_update_storage_class(
+		self: &Arc<Self>,
+		tx: &mut db::Transaction,
+		hash: Hash,
+		old_storage_class: Option<&str>,
+		new_storage_class: Option<&str>,
+	) -> db::TxOpResult<()> {
+		let class_changed = self.rc.block_update_storage_class_rc(
+			tx,
+			&hash,
+			Some(old_storage_class.unwrap_or(DEFAULT_STORAGE_CLASS)),
+			Some(new_storage_class.unwrap_or(DEFAULT_STORAGE_CLASS)),
+		)?;
+		if class_changed {
+			self.put_to_resync_after_storage_class_change(hash);
 		}
 		Ok(())
 	}
 
+	/// The block has to be moved to the data directories of another
+	/// storage class, which is done by the resync loop
+	fn put_to_resync_after_storage_class_change(self: &Arc<Self>, hash: Hash) {
+		let this = self.clone();
+		tokio::spawn(async move {
+			if let Err(e) = this
+				.resync
+				.put_to_resync(&hash, 2 * this.system.rpc_helper().rpc_timeout())
+			{
+				error!("Block {:?} could not be put in resync queue: {}.", hash, e);
+			}
+		});
+	}
+
 	// ---- Reading and writing blocks locally ----
 
 	async fn handle_put_block(
 		&self,
 		hash: Hash,
 		header: DataBlockHeader,
+		storage_class: Option<String>,
 		stream: Option<ByteStream>,
 	) -> Result<(), Error> {
 		let stream = stream.ok_or_message("missing stream")?;
 		let bytes = read_stream_to_end(stream).await?.into_bytes();
 		let data = DataBlock::from_parts(header, bytes);
-		self.write_block(&hash, &data).await
+		self.write_block(&hash, &data, storage_class.as_deref())
+			.await
 	}
 
-	/// Write a block to disk
-	pub(crate) async fn write_block(&self, hash: &Hash, data: &DataBlock) -> Result<(), Error> {
+	/// Write a block to disk, in the data directories of its storage class
+	pub(crate) async fn write_block(
+		&self,
+		hash: &Hash,
+		data: &DataBlock,
+		storage_class: Option<&str>,
+	) -> Result<(), Error> {
 		let tracer = opentelemetry::global::tracer("garage");
 
 		self.lock_mutate(hash)
 			.await
-			.write_block(hash, data, self)
+			.write_block(hash, data, storage_class, self)
 			.bound_record_duration(&self.metrics.block_write_duration)
 			.with_context(Context::current_with_span(
 				tracer.start("BlockManagerLocked::write_block"),
@@ -536,10 +616,15 @@ impl BlockManager {
 		};
 
 		let (header, data) = block.into_parts();
+		let storage_class = match self.get_block_storage_class(hash) {
+			Ok(class) => class,
+			Err(e) => return Resp::new(Err(e)),
+		};
 
 		let resp = Resp::new(Ok(BlockRpc::PutBlock {
 			hash: *hash,
 			header,
+			storage_class,
 		}))
 		.with_stream_from_buffer(data);
 
@@ -623,12 +708,45 @@ impl BlockManager {
 			.await
 	}
 
+	/// Get the storage class of the data directories where a locally stored
+	/// block should be (None for the default storage class), which depends on
+	/// the storage classes of the objects that reference it
+	pub(crate) fn get_block_storage_class(&self, hash: &Hash) -> Result<Option<String>, Error> {
+		Ok(self.rc.get_block_storage_class(hash)?.flatten())
+	}
+
+	/// Check whether a block is stored in the data directories of its storage class
+	pub(crate) fn is_in_storage_class_dirs(
+		&self,
+		hash: &Hash,
+		block_path: &DataBlockPath,
+	) -> Result<bool, Error> {
+		let storage_class = self.get_block_storage_class(hash)?;
+		let data_layout = self.data_layout.load();
+		let class_layout = data_layout.for_storage_class(storage_class.as_deref());
+		let dir = block_path.as_parts_ref().1.parent();
+		let in_class_dirs = std::iter::once(class_layout.primary_block_dir(hash))
+			.chain(class_layout.secondary_block_dirs(hash))
+			.any(|d| dir == Some(d.as_path()));
+		Ok(in_class_dirs)
+	}
+
+	/// Get the directory where a block should be stored,
+	/// according to its storage class
+	pub(crate) fn primary_block_dir(&self, hash: &Hash) -> Result<PathBuf, Error> {
+		let storage_class = self.get_block_storage_class(hash)?;
+		Ok(self
+			.data_layout
+			.load()
+			.for_storage_class(storage_class.as_deref())
+			.primary_block_dir(hash))
+	}
+
 	/// Find the path where a block is currently stored
 	pub(crate) async fn find_block(&self, hash: &Hash) -> Option<DataBlockPath> {
 		let data_layout = self.data_layout.load_full();
-		let dirs = Some(data_layout.primary_block_dir(hash))
-			.into_iter()
-			.chain(data_layout.secondary_block_dirs(hash));
+		let storage_class = self.get_block_storage_class(hash).ok().flatten();
+		let dirs = data_layout.all_block_dirs(hash, storage_class.as_deref());
 		let filename = hex::encode(hash.as_ref());
 
 		for dir in dirs {
@@ -669,9 +787,16 @@ impl BlockManager {
 		wrong_path: DataBlockPath,
 	) -> Result<usize, Error> {
 		let data = self.read_block_from(hash, &wrong_path).await?;
+		let storage_class = self.get_block_storage_class(hash)?;
 		self.lock_mutate(hash)
 			.await
-			.write_block_inner(hash, &data, self, Some(wrong_path))
+			.write_block_inner(
+				hash,
+				&data,
+				storage_class.as_deref(),
+				self,
+				Some(wrong_path),
+			)
 			.await?;
 		Ok(data.as_parts_ref().1.len())
 	}
@@ -692,8 +817,12 @@ impl BlockManager {
 impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	async fn handle(self: &Arc<Self>, mut message: Req<BlockRpc>, _from: NodeID) -> Resp<BlockRpc> {
 		match message.msg() {
-			BlockRpc::PutBlock { hash, header } => Resp::new(
-				self.handle_put_block(*hash, *header, message.take_stream())
+			BlockRpc::PutBlock {
+				hash,
+				header,
+				storage_class,
+			} => Resp::new(
+				self.handle_put_block(*hash, *header, storage_class.clone(), message.take_stream())
 					.await
 					.map(|()| BlockRpc::Ok),
 			),
@@ -711,23 +840,36 @@ impl BlockManagerLocked {
 		&self,
 		hash: &Hash,
 		data: &DataBlock,
+		storage_class: Option<&str>,
 		mgr: &BlockManager,
 	) -> Result<(), Error> {
 		let existing_path = mgr.find_block(hash).await;
-		self.write_block_inner(hash, data, mgr, existing_path).await
+		self.write_block_inner(hash, data, storage_class, mgr, existing_path)
+			.await
 	}
 
 	async fn write_block_inner(
 		&self,
 		hash: &Hash,
 		data: &DataBlock,
+		storage_class: Option<&str>,
 		mgr: &BlockManager,
 		existing_path: Option<DataBlockPath>,
 	) -> Result<(), Error> {
 		let (header, data) = data.as_parts_ref();
 		let compressed = header.is_compressed();
 
-		let directory = mgr.data_layout.load().primary_block_dir(hash);
+		// Blocks are stored in the storage class of the objects that reference
+		// them, or in the one they were sent with if no object references them yet
+		let storage_class = match mgr.rc.get_block_storage_class(hash)? {
+			Some(class) => class,
+			None => storage_class.map(str::to_string),
+		};
+		let directory = mgr
+			.data_layout
+			.load()
+			.for_storage_class(storage_class.as_deref())
+			.primary_block_dir(hash);
 
 		let mut tgt_path = directory.clone();
 		tgt_path.push(hex::encode(hash));
@@ -748,13 +890,25 @@ impl BlockManagerLocked {
 
 			// If the block is already stored compressed,
 			// keep the stored copy, we have nothing to do
-			(Some((DataBlockHeader::Compressed, _)), _) => return Ok(()),
+			// (unless it is in the wrong directory, see below)
+			(Some((DataBlockHeader::Compressed, p)), false) => {
+				if p.parent() != Some(directory.as_path()) {
+					// The stored copy is in the data directories of another storage
+					// class: let the resync loop move it, keeping it compressed
+					mgr.resync.put_to_resync(hash, Duration::from_secs(0))?;
+				}
+				return Ok(());
+			}
 
 			// If the block is already stored not compressed,
 			// and we don't have a compressed copy either,
 			// keep the stored copy, we have nothing to do
 			(Some((DataBlockHeader::Plain, _)), false) => return Ok(()),
 
+			// If the block is already stored compressed at the correct path,
+			// keep the stored copy, we have nothing to do
+			(Some((DataBlockHeader::Compressed, _)), true) => return Ok(()),
+
 			// If the block isn't stored already, just store what is given to us
 			(None, _) => None,
 		};
@@ -826,6 +980,7 @@ impl BlockManagerLocked {
 				fs::remove_file(path).await?;
 				mgr.metrics.delete_counter.add(1);
 			}
+			mgr.rc.clear_block_storage_class_rc(hash)?;
 		}
 		Ok(())
 	}
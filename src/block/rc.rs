@@ -1,10 +1,13 @@
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 
 use arc_swap::ArcSwapOption;
 
 use garage_db as db;
 
+use garage_util::config::DEFAULT_STORAGE_CLASS;
 use garage_util::data::*;
+use garage_util::encode::{nonversioned_decode, nonversioned_encode};
 use garage_util::error::*;
 use garage_util::time::*;
 
@@ -13,15 +16,22 @@ use crate::manager::BLOCK_GC_DELAY;
 pub type CalculateRefcount =
 	Box<dyn Fn(&db::Transaction, &Hash) -> db::TxResult<usize, Error> + Send + Sync>;
 
+/// Number of references to a block from objects of each storage class
+type StorageClassRc = BTreeMap<String, u64>;
+
 pub struct BlockRc {
 	pub rc_table: db::Tree,
+	/// Number of references to blocks for each storage class,
+	/// used to choose the data directories where they are stored
+	pub(crate) storage_class_rc_table: db::Tree,
 	pub(crate) recalc_rc: ArcSwapOption<Vec<CalculateRefcount>>,
 }
 
 impl BlockRc {
-	pub(crate) fn new(rc: db::Tree) -> Self {
+	pub(crate) fn new(rc: db::Tree, storage_class_rc: db::Tree) -> Self {
 		Self {
 			rc_table: rc,
+			storage_class_rc_table: storage_class_rc,
 			recalc_rc: ArcSwapOption::new(None),
 		}
 	}
@@ -61,6 +71,67 @@ impl BlockRc {
 		Ok(RcEntry::parse_opt(self.rc_table.get(hash.as_ref())?))
 	}
 
+	/// Move a reference to a block from objects of storage class `old` to
+	/// objects of storage class `new`, where None stands for a reference
+	/// that is added or removed. Returns true if the storage class where the
+	/// block should be stored changes.
+	/// This must be called before the reference counter is updated.
+	pub(crate) fn block_update_storage_class_rc(
+		&self,
+		tx: &mut db::Transaction,
+		hash: &Hash,
+		old: Option<&str>,
+		new: Option<&str>,
+	) -> db::TxOpResult<bool> {
+		let counts = match tx.get(&self.storage_class_rc_table, hash)? {
+			Some(bytes) => parse_storage_class_rc(&bytes),
+			None => StorageClassRc::new(),
+		};
+		let rc = RcEntry::parse_opt(tx.get(&self.rc_table, hash)?);
+		let mut counts = with_implicit_references(counts, &rc);
+		let old_placement = storage_class_placement(&counts);
+
+		if let Some(old) = old {
+			if let Some(n) = counts.get_mut(old) {
+				*n = n.saturating_sub(1);
+			}
+		}
+		if let Some(new) = new {
+			*counts.entry(new.to_string()).or_default() += 1;
+		}
+		counts.retain(|_, n| *n > 0);
+
+		if counts.is_empty() {
+			tx.remove(&self.storage_class_rc_table, hash)?;
+		} else {
+			let bytes = nonversioned_encode(&counts).expect("cannot encode storage class rc");
+			tx.insert(&self.storage_class_rc_table, hash, bytes)?;
+		}
+		Ok(storage_class_placement(&counts) != old_placement)
+	}
+
+	/// Get the storage class of the data directories where a block should
+	/// be stored (Some(None) for the default storage class), or None if no
+	/// object references the block
+	pub(crate) fn get_block_storage_class(
+		&self,
+		hash: &Hash,
+	) -> Result<Option<Option<String>>, Error> {
+		let counts = match self.storage_class_rc_table.get(hash)? {
+			Some(bytes) => parse_storage_class_rc(&bytes),
+			None => StorageClassRc::new(),
+		};
+		let counts = with_implicit_references(counts, &self.get_block_rc(hash)?);
+		Ok(storage_class_placement(&counts))
+	}
+
+	/// Forget the storage classes of the references to a block
+	/// once it is deleted
+	pub(crate) fn clear_block_storage_class_rc(&self, hash: &Hash) -> Result<(), Error> {
+		self.storage_class_rc_table.remove(hash)?;
+		Ok(())
+	}
+
 	/// Delete an entry in the RC table if it is deletable and the
 	/// deletion time has passed
 	pub(crate) fn clear_deleted_block_rc(&self, hash: &Hash) -> Result<(), Error> {
@@ -131,6 +202,38 @@ impl BlockRc {
 	}
 }
 
+fn parse_storage_class_rc(bytes: &[u8]) -> StorageClassRc {
+	nonversioned_decode(bytes).unwrap_or_else(|e| {
+		error!("Invalid storage class rc entry: {}", e);
+		StorageClassRc::new()
+	})
+}
+
+/// References to a block that are not counted in the storage class counters,
+/// such as those from before they were introduced, are references from
+/// objects of the default storage class
+fn with_implicit_references(mut counts: StorageClassRc, rc: &RcEntry) -> StorageClassRc {
+	let missing = rc.as_u64().saturating_sub(counts.values().sum());
+	if missing > 0 {
+		*counts.entry(DEFAULT_STORAGE_CLASS.to_string()).or_default() += missing;
+	}
+	counts
+}
+
+/// Data blocks are deduplicated, so a block can be referenced by objects of
+/// different storage classes. It is stored in the data directories of a
+/// storage class only if all the objects that reference it belong to that
+/// storage class, and in those of the default storage class otherwise, so
+/// that blocks shared with objects of the default storage class stay there.
+fn storage_class_placement(counts: &StorageClassRc) -> Option<Option<String>> {
+	let mut classes = counts.iter().filter(|(_, n)| **n > 0).map(|(c, _)| c);
+	match (classes.next(), classes.next()) {
+		(None, _) => None,
+		(Some(class), None) if class != DEFAULT_STORAGE_CLASS => Some(Some(class.clone())),
+		_ => Some(None),
+	}
+}
+
 /// Describes the state of the reference counter for a block
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum RcEntry {
@@ -238,3 +341,55 @@ impl RcEntry {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_shared_block_storage_class() {
+		let mut counts = StorageClassRc::new();
+		assert_eq!(storage_class_placement(&counts), None);
+
+		counts.insert("STANDARD_IA".into(), 2);
+		assert_eq!(
+			storage_class_placement(&counts),
+			Some(Some("STANDARD_IA".into()))
+		);
+
+		// a block shared with an object of another storage class
+		// goes back to the default storage class
+		counts.insert("GLACIER".into(), 1);
+		assert_eq!(storage_class_placement(&counts), Some(None));
+		counts.remove("GLACIER");
+		counts.insert(DEFAULT_STORAGE_CLASS.into(), 1);
+		assert_eq!(storage_class_placement(&counts), Some(None));
+
+		// it is moved again once only objects of one storage class reference it
+		counts.insert(DEFAULT_STORAGE_CLASS.into(), 0);
+		assert_eq!(
+			storage_class_placement(&counts),
+			Some(Some("STANDARD_IA".into()))
+		);
+	}
+
+	#[test]
+	fn test_implicit_storage_class_references() {
+		// a block referenced before storage class counters were introduced
+		// stays in the default storage class when a reference is added
+		let counts =
+			with_implicit_references(StorageClassRc::new(), &RcEntry::Present { count: 2 });
+		assert_eq!(storage_class_placement(&counts), Some(None));
+		let mut counts = counts;
+		*counts.entry("STANDARD_IA".into()).or_default() += 1;
+		assert_eq!(storage_class_placement(&counts), Some(None));
+
+		// once all references are counted, nothing is added
+		let counts = with_implicit_references(counts, &RcEntry::Present { count: 3 });
+		assert_eq!(counts.get(DEFAULT_STORAGE_CLASS), Some(&2));
+		assert_eq!(counts.get("STANDARD_IA"), Some(&1));
+
+		let counts = with_implicit_references(StorageClassRc::new(), &RcEntry::Absent);
+		assert_eq!(storage_class_placement(&counts), None);
+	}
+}
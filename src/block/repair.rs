@@ -581,7 +581,7 @@ impl Worker for RebalanceWorker {
 
 	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
 		if let Some((path, hash)) = self.block_iter.next().await? {
-			let prim_loc = self.manager.data_layout.load().primary_block_dir(&hash);
+			let prim_loc = self.manager.primary_block_dir(&hash)?;
 			if path.ancestors().all(|x| x != prim_loc) {
 				let block_path = match path.extension() {
 					None => DataBlockPath::plain(path.clone()),
@@ -635,20 +635,24 @@ impl BlockStoreIterator {
 	fn new(manager: &BlockManager) -> Self {
 		let data_layout = manager.data_layout.load_full();
 
-		let mut dir_cap = vec![0; data_layout.data_dirs.len()];
-		for prim in data_layout.part_prim.iter() {
-			dir_cap[*prim as usize] += 1;
-		}
-		for sec_vec in data_layout.part_sec.iter() {
-			for sec in sec_vec.iter() {
-				dir_cap[*sec as usize] += 1;
+		let mut dirs = vec![];
+		for layout in data_layout.all_layouts() {
+			let mut dir_cap = vec![0; layout.data_dirs.len()];
+			for prim in layout.part_prim.iter() {
+				dir_cap[*prim as usize] += 1;
+			}
+			for sec_vec in layout.part_sec.iter() {
+				for sec in sec_vec.iter() {
+					dir_cap[*sec as usize] += 1;
+				}
 			}
+			dirs.extend(layout.data_dirs.iter().zip(dir_cap));
 		}
-		let sum_cap = dir_cap.iter().sum::<usize>() as u64;
+		let sum_cap = dirs.iter().map(|(_, cap)| *cap).sum::<usize>() as u64;
 
 		let mut cum_cap = 0;
 		let mut todo = vec![];
-		for (dir, cap) in data_layout.data_dirs.iter().zip(dir_cap.into_iter()) {
+		for (dir, cap) in dirs.into_iter() {
 			let progress_min = (cum_cap * PROGRESS_FP) / sum_cap;
 			let progress_max = ((cum_cap + cap as u64) * PROGRESS_FP) / sum_cap;
 			cum_cap += cap as u64;
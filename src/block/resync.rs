@@ -375,7 +375,7 @@ impl BlockResyncManager {
 			}
 
 			info!("Resync block {:?}: offloading and deleting", hash);
-			let existing_path = existing_path.unwrap();
+			let existing_path = existing_path.as_ref().unwrap();
 
 			let mut who = manager
 				.system
@@ -425,11 +425,12 @@ impl BlockResyncManager {
 						.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
 				}
 
-				let block = manager.read_block_from(hash, &existing_path).await?;
+				let block = manager.read_block_from(hash, existing_path).await?;
 				let (header, bytes) = block.into_parts();
 				let put_block_message = Req::new(BlockRpc::PutBlock {
 					hash: *hash,
 					header,
+					storage_class: manager.get_block_storage_class(hash)?,
 				})?
 				.with_stream_from_buffer(bytes);
 				manager
@@ -492,11 +493,26 @@ impl BlockResyncManager {
 				);
 				manager.rc.recalculate_rc(hash)?;
 			}
-			let block_data = block_data?;
+			let (block_data, storage_class) = block_data?;
 
 			manager.metrics.resync_recv_counter.add(1);
 
-			manager.write_block(hash, &block_data).await?;
+			manager
+				.write_block(hash, &block_data, storage_class.as_deref())
+				.await?;
+		}
+
+		if let (true, Some(existing_path)) = (rc.is_nonzero(), existing_path) {
+			// The storage class of the objects that reference the block may have
+			// changed, in which case it has to be moved to the data directories
+			// of its new storage class.
+			if !manager.is_in_storage_class_dirs(hash, &existing_path)? {
+				info!(
+					"Resync block {:?}: moving block to the data directories of its storage class",
+					hash
+				);
+				manager.fix_block_location(hash, existing_path).await?;
+			}
 		}
 
 		Ok(())
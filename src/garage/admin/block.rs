@@ -178,6 +178,12 @@ impl AdminRpcHandler {
 							uuid: del_uuid,
 							timestamp: ov.timestamp + 1,
 							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+							versioned: false,
+							tags: Default::default(),
+							lock: Default::default(),
+							replication: Default::default(),
+							public_read: Default::default(),
+							storage_class: Default::default(),
 						}],
 					);
 					self.garage.object_table.insert(&deleted_object).await?;
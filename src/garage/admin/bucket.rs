@@ -27,6 +27,10 @@ impl AdminRpcHandler {
 			BucketOperation::Allow(query) => self.handle_bucket_allow(query).await,
 			BucketOperation::Deny(query) => self.handle_bucket_deny(query).await,
 			BucketOperation::Website(query) => self.handle_bucket_website(query).await,
+			BucketOperation::PublicRead(query) => self.handle_bucket_public_read(query).await,
+			BucketOperation::PublicAccessBlock(query) => {
+				self.handle_bucket_public_access_block(query).await
+			}
 			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query).await,
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
@@ -393,6 +397,8 @@ impl AdminRpcHandler {
 			Some(WebsiteConfig {
 				index_document: query.index_document.clone(),
 				error_document: query.error_document.clone(),
+				redirect_all: None,
+				routing_rules: vec![],
 			})
 		} else {
 			None
@@ -410,6 +416,93 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::Ok(msg))
 	}
 
+	async fn handle_bucket_public_read(&self, query: &PublicReadOpt) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.bucket)
+			.await?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		if !(query.allow ^ query.deny) {
+			return Err(Error::BadRequest(
+				"You must specify exactly one flag, either --allow or --deny".to_string(),
+			));
+		}
+
+		if query.allow
+			&& bucket_state
+				.effective_public_access_block(&self.garage.config.public_access_block)
+				.block_public_acls
+		{
+			return Err(Error::BadRequest(format!(
+				"Public access to {} is blocked by its public access block settings",
+				&query.bucket
+			)));
+		}
+
+		bucket_state.public_read.update(query.allow);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		let msg = if query.allow {
+			format!("Public read access allowed for {}", &query.bucket)
+		} else {
+			format!("Public read access denied for {}", &query.bucket)
+		};
+
+		Ok(AdminRpc::Ok(msg))
+	}
+
+	async fn handle_bucket_public_access_block(
+		&self,
+		query: &PublicAccessBlockOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.bucket)
+			.await?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		let block = PublicAccessBlock {
+			block_public_acls: query.all || query.block_public_acls,
+			ignore_public_acls: query.all || query.ignore_public_acls,
+			block_public_policy: query.all || query.block_public_policy,
+			restrict_public_buckets: query.all || query.restrict_public_buckets,
+		};
+		let any_setting = block != PublicAccessBlock::default();
+		if !(any_setting ^ query.clear) {
+			return Err(Error::BadRequest(
+				"You must either specify settings to enable or --clear".to_string(),
+			));
+		}
+
+		bucket_state
+			.public_access_block
+			.update(Some(block).filter(|_| any_setting));
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		let msg = if query.clear {
+			format!("Public access block settings removed for {}", &query.bucket)
+		} else {
+			format!("Public access block settings updated for {}", &query.bucket)
+		};
+
+		Ok(AdminRpc::Ok(msg))
+	}
+
 	async fn handle_bucket_set_quotas(&self, query: &SetQuotasOpt) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use garage_util::time::{now_msec, rfc3339_to_msec};
+
 use garage_table::*;
 
 use garage_model::helper::error::*;
@@ -20,6 +22,11 @@ impl AdminRpcHandler {
 			KeyOperation::Allow(query) => self.handle_allow_key(query).await,
 			KeyOperation::Deny(query) => self.handle_deny_key(query).await,
 			KeyOperation::Import(query) => self.handle_import_key(query).await,
+			KeyOperation::Rotate(query) => self.handle_rotate_key(query).await,
+			KeyOperation::RetireSecret(query) => self.handle_retire_key_secret(query).await,
+			KeyOperation::SetExpiration(query) => self.handle_key_expiration(query).await,
+			KeyOperation::Activate(query) => self.handle_key_set_active(query, true).await,
+			KeyOperation::Deactivate(query) => self.handle_key_set_active(query, false).await,
 		}
 	}
 
@@ -34,11 +41,27 @@ impl AdminRpcHandler {
 				10000,
 				EnumerationOrder::Forward,
 			)
-			.await?
-			.iter()
-			.map(|k| (k.key_id.to_string(), k.params().unwrap().name.get().clone()))
-			.collect::<Vec<_>>();
-		Ok(AdminRpc::KeyList(key_ids))
+			.await?;
+
+		let mut keys = vec![];
+		let mut expired_keys = vec![];
+		for k in key_ids.iter() {
+			let params = k.params().unwrap();
+			if k.is_expired() {
+				expired_keys.push((
+					k.key_id.to_string(),
+					params.name.get().clone(),
+					params.expiration.get().unwrap(),
+				));
+			} else {
+				keys.push((
+					k.key_id.to_string(),
+					params.name.get().clone(),
+					k.is_active(),
+				));
+			}
+		}
+		Ok(AdminRpc::KeyList { keys, expired_keys })
 	}
 
 	async fn handle_key_info(&self, query: &KeyInfoOpt) -> Result<AdminRpc, Error> {
@@ -49,7 +72,7 @@ impl AdminRpcHandler {
 			.await?;
 
 		if !query.show_secret {
-			key.state.as_option_mut().unwrap().secret_key = "(redacted)".into();
+			redact_secret_keys(&mut key);
 		}
 
 		self.key_info_result(key).await
@@ -123,6 +146,95 @@ impl AdminRpcHandler {
 		self.key_info_result(key).await
 	}
 
+	async fn handle_rotate_key(&self, query: &KeyRotateOpt) -> Result<AdminRpc, Error> {
+		let mut key = self
+			.garage
+			.key_helper()
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+		if key.params_mut().unwrap().rotate_secret_key().is_none() {
+			return Err(Error::BadRequest(format!(
+				"Key {} already has {} secret keys, use `garage key retire-secret` to retire the old ones first",
+				key.key_id,
+				KeyParams::MAX_SECRET_KEYS
+			)));
+		}
+		self.garage.key_table.insert(&key).await?;
+		self.key_info_result(key).await
+	}
+
+	async fn handle_retire_key_secret(
+		&self,
+		query: &KeyRetireSecretOpt,
+	) -> Result<AdminRpc, Error> {
+		let mut key = self
+			.garage
+			.key_helper()
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+
+		if !query.yes {
+			return Err(Error::BadRequest(
+				"Add --yes flag to really perform this operation".to_string(),
+			));
+		}
+
+		let retired = key.params_mut().unwrap().retire_old_secret_keys();
+		if retired.is_empty() {
+			return Err(Error::BadRequest(format!(
+				"Key {} has no old secret key to retire",
+				key.key_id
+			)));
+		}
+		self.garage.key_table.insert(&key).await?;
+
+		redact_secret_keys(&mut key);
+		self.key_info_result(key).await
+	}
+
+	async fn handle_key_expiration(&self, query: &KeyExpirationOpt) -> Result<AdminRpc, Error> {
+		let expiration = match (&query.at, &query.duration, query.never) {
+			(Some(at), None, false) => Some(rfc3339_to_msec(at).ok_or_bad_request(
+				"Invalid date passed for --at parameter, expected a RFC3339 timestamp",
+			)?),
+			(None, Some(duration), false) => {
+				let duration = parse_duration::parse::parse(duration)
+					.ok_or_bad_request("Invalid duration passed for --in parameter")?;
+				Some(now_msec() + duration.as_millis() as u64)
+			}
+			(None, None, true) => None,
+			_ => {
+				return Err(Error::BadRequest(
+					"Exactly one of --at, --in or --never must be given".to_string(),
+				))
+			}
+		};
+
+		let mut key = self
+			.garage
+			.key_helper()
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+		key.params_mut().unwrap().expiration.update(expiration);
+		self.garage.key_table.insert(&key).await?;
+		self.key_info_result(key).await
+	}
+
+	async fn handle_key_set_active(
+		&self,
+		query: &KeyActivateOpt,
+		active: bool,
+	) -> Result<AdminRpc, Error> {
+		let mut key = self
+			.garage
+			.key_helper()
+			.get_existing_matching_key(&query.key_pattern)
+			.await?;
+		key.params_mut().unwrap().active.update(active);
+		self.garage.key_table.insert(&key).await?;
+		self.key_info_result(key).await
+	}
+
 	async fn handle_import_key(&self, query: &KeyImportOpt) -> Result<AdminRpc, Error> {
 		if !query.yes {
 			return Err(Error::BadRequest("This command is intended to re-import keys that were previously generated by Garage. If you want to create a new key, use `garage key new` instead. Add the --yes flag if you really want to re-import a key.".to_string()));
@@ -159,3 +271,13 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::KeyInfo(key, relevant_buckets))
 	}
 }
+
+fn redact_secret_keys(key: &mut Key) {
+	let params = key.params_mut().unwrap();
+	params.secret_key = "(redacted)".into();
+	if let Some(secret_keys) = params.secret_keys.get_mut() {
+		for secret_key in secret_keys.iter_mut() {
+			*secret_key = "(redacted)".into();
+		}
+	}
+}
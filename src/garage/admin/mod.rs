@@ -57,7 +57,10 @@ pub enum AdminRpc {
 		counters: HashMap<String, i64>,
 		mpu_counters: HashMap<String, i64>,
 	},
-	KeyList(Vec<(String, String)>),
+	KeyList {
+		keys: Vec<(String, String, bool)>,
+		expired_keys: Vec<(String, String, u64)>,
+	},
 	KeyInfo(Key, HashMap<Uuid, Bucket>),
 	WorkerList(
 		HashMap<usize, garage_util::background::WorkerInfo>,
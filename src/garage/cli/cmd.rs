@@ -231,8 +231,8 @@ pub async fn cmd_admin(
 		} => {
 			print_bucket_info(&bucket, &relevant_keys, &counters, &mpu_counters);
 		}
-		AdminRpc::KeyList(kl) => {
-			print_key_list(kl);
+		AdminRpc::KeyList { keys, expired_keys } => {
+			print_key_list(keys, expired_keys);
 		}
 		AdminRpc::KeyInfo(key, rb) => {
 			print_key_info(&key, &rb);
@@ -228,6 +228,14 @@ pub enum BucketOperation {
 	#[structopt(name = "website", version = garage_version())]
 	Website(WebsiteOpt),
 
+	/// Allow or deny anonymous read access through the S3 API
+	#[structopt(name = "public-read", version = garage_version())]
+	PublicRead(PublicReadOpt),
+
+	/// Block public access to a bucket, regardless of its other settings
+	#[structopt(name = "public-access-block", version = garage_version())]
+	PublicAccessBlock(PublicAccessBlockOpt),
+
 	/// Set the quotas for this bucket
 	#[structopt(name = "set-quotas", version = garage_version())]
 	SetQuotas(SetQuotasOpt),
@@ -259,6 +267,50 @@ pub struct WebsiteOpt {
 	pub error_document: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct PublicReadOpt {
+	/// Allow unsigned GetObject, HeadObject and ListObjectsV2 requests
+	#[structopt(long = "allow")]
+	pub allow: bool,
+
+	/// Require signed requests again
+	#[structopt(long = "deny")]
+	pub deny: bool,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct PublicAccessBlockOpt {
+	/// Enable all the settings below
+	#[structopt(long = "all")]
+	pub all: bool,
+
+	/// Reject requests that make the bucket or its objects public
+	#[structopt(long = "block-public-acls")]
+	pub block_public_acls: bool,
+
+	/// Ignore the public-read flags of the bucket and of its objects
+	#[structopt(long = "ignore-public-acls")]
+	pub ignore_public_acls: bool,
+
+	/// Reject bucket policies that allow anonymous access
+	#[structopt(long = "block-public-policy")]
+	pub block_public_policy: bool,
+
+	/// Reject all anonymous requests, including website requests
+	#[structopt(long = "restrict-public-buckets")]
+	pub restrict_public_buckets: bool,
+
+	/// Remove the public access block settings of the bucket
+	#[structopt(long = "clear")]
+	pub clear: bool,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct BucketOpt {
 	/// Bucket name
@@ -380,6 +432,26 @@ pub enum KeyOperation {
 	/// Import key
 	#[structopt(name = "import", version = garage_version())]
 	Import(KeyImportOpt),
+
+	/// Add a new secret key to a key, the current one staying valid until it is retired
+	#[structopt(name = "rotate", version = garage_version())]
+	Rotate(KeyRotateOpt),
+
+	/// Retire the old secret keys of a key after a rotation
+	#[structopt(name = "retire-secret", version = garage_version())]
+	RetireSecret(KeyRetireSecretOpt),
+
+	/// Set or remove the expiration date of a key
+	#[structopt(name = "set-expiration", version = garage_version())]
+	SetExpiration(KeyExpirationOpt),
+
+	/// Allow requests to be made with a key
+	#[structopt(name = "activate", version = garage_version())]
+	Activate(KeyActivateOpt),
+
+	/// Refuse all requests made with a key, without deleting it
+	#[structopt(name = "deactivate", version = garage_version())]
+	Deactivate(KeyActivateOpt),
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -444,6 +516,47 @@ pub struct KeyImportOpt {
 	pub yes: bool,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyRotateOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyRetireSecretOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+
+	/// Confirm retirement of the old secret keys
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyExpirationOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+
+	/// Expiration date of the key, as a RFC3339 timestamp
+	/// (e.g. 2025-01-01T00:00:00Z)
+	#[structopt(long = "at")]
+	pub at: Option<String>,
+
+	/// Make the key expire after this duration from now (e.g. 30d)
+	#[structopt(long = "in")]
+	pub duration: Option<String>,
+
+	/// Remove the expiration date of the key
+	#[structopt(long = "never")]
+	pub never: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyActivateOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
 pub struct RepairOpt {
 	/// Launch repair operation on all nodes
@@ -50,13 +50,28 @@ pub fn print_bucket_list(bl: Vec<Bucket>) {
 	format_table(table);
 }
 
-pub fn print_key_list(kl: Vec<(String, String)>) {
+pub fn print_key_list(kl: Vec<(String, String, bool)>, expired: Vec<(String, String, u64)>) {
 	println!("List of keys:");
 	let mut table = vec![];
-	for key in kl {
-		table.push(format!("\t{}\t{}", key.0, key.1));
+	for (key_id, name, active) in kl {
+		let status = if active { "" } else { "(inactive)" };
+		table.push(format!("\t{}\t{}\t{}", key_id, name, status));
 	}
 	format_table(table);
+
+	if !expired.is_empty() {
+		println!("\nExpired keys:");
+		let mut table = vec![];
+		for (key_id, name, expiration) in expired {
+			table.push(format!(
+				"\t{}\t{}\texpired {}",
+				key_id,
+				name,
+				msec_to_rfc3339(expiration)
+			));
+		}
+		format_table(table);
+	}
 }
 
 pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
@@ -81,8 +96,19 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 		Deletable::Present(p) => {
 			println!("Key name: {}", p.name.get());
 			println!("Key ID: {}", key.key_id);
-			println!("Secret key: {}", p.secret_key);
+			println!("Secret key: {}", p.current_secret_key());
+			for old_secret_key in p.old_secret_keys() {
+				println!("Old secret key (not yet retired): {}", old_secret_key);
+			}
 			println!("Can create buckets: {}", p.allow_create_bucket.get());
+			println!("Active: {}", p.active.get());
+			match p.expiration.get() {
+				Some(exp) if key.is_expired() => {
+					println!("Expiration: {} (expired)", msec_to_rfc3339(*exp))
+				}
+				Some(exp) => println!("Expiration: {}", msec_to_rfc3339(*exp)),
+				None => println!("Expiration: never"),
+			}
 			println!("\nKey-specific bucket aliases:");
 			let mut table = vec![];
 			for (alias_name, _, alias) in p.local_aliases.items().iter() {
@@ -177,6 +203,25 @@ pub fn print_bucket_info(
 			);
 
 			println!("\nWebsite access: {}", p.website_config.get().is_some());
+			println!("Public read access: {}", p.public_read.get());
+
+			if let Some(block) = p.public_access_block.get() {
+				println!("\nPublic access block:");
+				println!(" block public ACLs: {}", block.block_public_acls);
+				println!(" ignore public ACLs: {}", block.ignore_public_acls);
+				println!(" block public policy: {}", block.block_public_policy);
+				println!(
+					" restrict public buckets: {}",
+					block.restrict_public_buckets
+				);
+			}
+
+			if let Some(tags) = p.tags.get() {
+				println!("\nTags:");
+				for (k, v) in tags.iter() {
+					println!(" {} = {}", k, v);
+				}
+			}
 
 			let quotas = p.quotas.get();
 			if quotas.max_size.is_some() || quotas.max_objects.is_some() {
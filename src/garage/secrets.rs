@@ -27,6 +27,16 @@ pub struct Secrets {
 	#[structopt(long = "rpc-secret-file", env = "GARAGE_RPC_SECRET_FILE")]
 	pub rpc_secret_file: Option<PathBuf>,
 
+	/// SSE-S3 master key, replaces sse_master_key in config.toml when running the
+	/// Garage daemon
+	#[structopt(long = "sse-master-key", env = "GARAGE_SSE_MASTER_KEY")]
+	pub sse_master_key: Option<String>,
+
+	/// SSE-S3 master key file path, replaces sse_master_key in config.toml and
+	/// sse-master-key when running the Garage daemon
+	#[structopt(long = "sse-master-key-file", env = "GARAGE_SSE_MASTER_KEY_FILE")]
+	pub sse_master_key_file: Option<PathBuf>,
+
 	/// Admin API authentication token, replaces admin.admin_token in config.toml when
 	/// running the Garage daemon
 	#[structopt(long = "admin-token", env = "GARAGE_ADMIN_TOKEN")]
@@ -65,6 +75,15 @@ pub fn fill_secrets(mut config: Config, secrets: Secrets) -> Result<Config, Erro
 		allow_world_readable,
 	)?;
 
+	fill_secret(
+		&mut config.sse_master_key,
+		&config.sse_master_key_file,
+		&secrets.sse_master_key,
+		&secrets.sse_master_key_file,
+		"sse_master_key",
+		allow_world_readable,
+	)?;
+
 	fill_secret(
 		&mut config.admin.admin_token,
 		&config.admin.admin_token_file,
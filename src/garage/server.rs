@@ -7,7 +7,9 @@ use garage_util::config::*;
 use garage_util::error::Error;
 
 use garage_api_admin::api_server::AdminApiServer;
+use garage_api_s3::access_log_worker::AccessLogWorker;
 use garage_api_s3::api_server::S3ApiServer;
+use garage_api_s3::replication::ReplicationWorker;
 use garage_model::garage::Garage;
 use garage_web::WebServer;
 
@@ -52,6 +54,8 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 
 	info!("Spawning Garage workers...");
 	garage.spawn_workers(&background)?;
+	background.spawn_worker(ReplicationWorker::new(garage.clone())?);
+	background.spawn_worker(AccessLogWorker::new(garage.clone()));
 
 	if config.admin.trace_sink.is_some() {
 		info!("Initialize tracing...");
@@ -3,6 +3,10 @@ use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Once;
 
+use garage_model::permission::BucketKeyPerm;
+use garage_model::session::{session_token_key, SessionClaims};
+use garage_util::data::Uuid;
+
 use super::ext::*;
 
 // https://xkcd.com/221/
@@ -217,6 +221,40 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 
 		key
 	}
+
+	/// Build temporary credentials derived from `key` that give read and write
+	/// access to `bucket`, returning them with their session token
+	pub fn session_key(&self, key: &Key, bucket: &str) -> (Key, String) {
+		let output = self
+			.command()
+			.args(["bucket", "info", bucket])
+			.expect_success_output("Could not get bucket info");
+		let stdout = String::from_utf8(output.stdout).unwrap();
+		let bucket_id = stdout
+			.lines()
+			.find_map(|line| line.strip_prefix("Bucket: "))
+			.and_then(|id| hex::decode(id).ok())
+			.and_then(|id| Uuid::try_from(&id[..]))
+			.expect("Invalid bucket ID");
+
+		let perm = BucketKeyPerm {
+			timestamp: 0,
+			allow_read: true,
+			allow_write: true,
+			allow_owner: false,
+		};
+		let signing_key = session_token_key(&hex::decode(GARAGE_TEST_SECRET).unwrap());
+		let creds = SessionClaims::new(&key.id, 3600, vec![(bucket_id, perm)], None)
+			.into_credentials(&signing_key);
+
+		(
+			Key {
+				id: creds.access_key_id,
+				secret: creds.secret_access_key,
+			},
+			creds.session_token,
+		)
+	}
 }
 
 static mut INSTANCE: MaybeUninit<Instance> = MaybeUninit::uninit();
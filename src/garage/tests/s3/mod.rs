@@ -2,6 +2,8 @@ mod list;
 mod multipart;
 mod objects;
 mod presigned;
+mod public_read;
+mod select;
 mod simple;
 mod ssec;
 mod streaming_signature;
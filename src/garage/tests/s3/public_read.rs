@@ -0,0 +1,83 @@
+use crate::common;
+use crate::common::ext::*;
+
+use aws_sdk_s3::primitives::ByteStream;
+use http::{Method, Request, StatusCode};
+use http_body_util::BodyExt;
+use http_body_util::Full as FullBody;
+use hyper::body::Bytes;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+const BODY: &[u8; 12] = b"public data\n";
+
+pub type Body = FullBody<Bytes>;
+
+#[tokio::test]
+async fn test_public_read() {
+	const BCKT_NAME: &str = "public-read";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("data.txt")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+
+	let req = |method: Method, path: &str| {
+		Request::builder()
+			.method(method)
+			.uri(format!("{}{}/{}", ctx.garage.s3_uri(), BCKT_NAME, path))
+			.body(Body::new(Bytes::new()))
+			.unwrap()
+	};
+
+	// Anonymous requests are refused by default
+	let resp = client.request(req(Method::GET, "data.txt")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "public-read", "--allow", BCKT_NAME])
+		.quiet()
+		.expect_success_status("Could not allow public read on bucket");
+
+	let resp = client.request(req(Method::GET, "data.txt")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.into_body().collect().await.unwrap().to_bytes(),
+		BODY.as_ref()
+	);
+
+	let resp = client.request(req(Method::HEAD, "data.txt")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let resp = client
+		.request(req(Method::GET, "?list-type=2"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = resp.into_body().collect().await.unwrap().to_bytes();
+	assert!(String::from_utf8_lossy(&body).contains("<Key>data.txt</Key>"));
+
+	// Only reads are allowed, and only the listed operations
+	let resp = client.request(req(Method::PUT, "other.txt")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+	let resp = client.request(req(Method::GET, "?versions")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+	ctx.garage
+		.command()
+		.args(["bucket", "public-read", "--deny", BCKT_NAME])
+		.quiet()
+		.expect_success_status("Could not deny public read on bucket");
+
+	let resp = client.request(req(Method::GET, "data.txt")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
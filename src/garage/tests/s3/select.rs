@@ -0,0 +1,146 @@
+use crate::common;
+use aws_sdk_s3::operation::select_object_content::SelectObjectContentOutput;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{
+	CsvInput, CsvOutput, ExpressionType, FileHeaderInfo, InputSerialization, JsonInput, JsonOutput,
+	JsonType, OutputSerialization, SelectObjectContentEventStream,
+};
+
+const CSV_DATA: &[u8] =
+	b"name,age,city\nalice,34,Paris\nbob,27,Lyon\ncarol,41,\"Saint-Denis, Reunion\"\n";
+const JSON_DATA: &[u8] =
+	b"{\"level\": \"info\", \"size\": 10}\n{\"level\": \"error\", \"size\": 25}\n{\"level\": \"error\", \"size\": 5}\n";
+
+async fn collect_records(mut output: SelectObjectContentOutput) -> String {
+	let mut records = vec![];
+	let mut got_stats = false;
+	let mut got_end = false;
+	while let Some(event) = output.payload.recv().await.unwrap() {
+		match event {
+			SelectObjectContentEventStream::Records(r) => {
+				records.extend_from_slice(r.payload().unwrap().as_ref())
+			}
+			SelectObjectContentEventStream::Stats(_) => got_stats = true,
+			SelectObjectContentEventStream::End(_) => got_end = true,
+			_ => (),
+		}
+	}
+	assert!(got_stats);
+	assert!(got_end);
+	String::from_utf8(records).unwrap()
+}
+
+#[tokio::test]
+async fn test_select_object_content() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("select");
+
+	for (key, data) in [("people.csv", CSV_DATA), ("logs.json", JSON_DATA)] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from_static(data))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let csv_input = InputSerialization::builder()
+		.csv(
+			CsvInput::builder()
+				.file_header_info(FileHeaderInfo::Use)
+				.build(),
+		)
+		.build();
+
+	{
+		// Projection and filter on a CSV object, with CSV output
+		let output = ctx
+			.client
+			.select_object_content()
+			.bucket(&bucket)
+			.key("people.csv")
+			.expression("SELECT s.name, s.city FROM S3Object s WHERE CAST(s.age AS INT) > 30")
+			.expression_type(ExpressionType::Sql)
+			.input_serialization(csv_input.clone())
+			.output_serialization(
+				OutputSerialization::builder()
+					.csv(CsvOutput::builder().build())
+					.build(),
+			)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(
+			collect_records(output).await,
+			"alice,Paris\ncarol,\"Saint-Denis, Reunion\"\n"
+		);
+	}
+
+	{
+		// LIKE and LIMIT, with JSON output
+		let output = ctx
+			.client
+			.select_object_content()
+			.bucket(&bucket)
+			.key("people.csv")
+			.expression("SELECT name FROM S3Object WHERE name LIKE '%o%' LIMIT 1")
+			.expression_type(ExpressionType::Sql)
+			.input_serialization(csv_input.clone())
+			.output_serialization(
+				OutputSerialization::builder()
+					.json(JsonOutput::builder().build())
+					.build(),
+			)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(collect_records(output).await, "{\"name\":\"bob\"}\n");
+	}
+
+	{
+		// Aggregates on a JSON Lines object
+		let output = ctx
+			.client
+			.select_object_content()
+			.bucket(&bucket)
+			.key("logs.json")
+			.expression("SELECT COUNT(*), SUM(s.size) FROM S3Object s WHERE s.level = 'error'")
+			.expression_type(ExpressionType::Sql)
+			.input_serialization(
+				InputSerialization::builder()
+					.json(JsonInput::builder().r#type(JsonType::Lines).build())
+					.build(),
+			)
+			.output_serialization(
+				OutputSerialization::builder()
+					.csv(CsvOutput::builder().build())
+					.build(),
+			)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(collect_records(output).await, "2,30\n");
+	}
+
+	{
+		// Invalid SQL is rejected before the response is started
+		let res = ctx
+			.client
+			.select_object_content()
+			.bucket(&bucket)
+			.key("people.csv")
+			.expression("SELECT name FROM elsewhere")
+			.expression_type(ExpressionType::Sql)
+			.input_serialization(csv_input.clone())
+			.output_serialization(
+				OutputSerialization::builder()
+					.csv(CsvOutput::builder().build())
+					.build(),
+			)
+			.send()
+			.await;
+		assert!(res.is_err());
+	}
+}
@@ -5,7 +5,7 @@ use crc32fast::Hasher as Crc32;
 
 use crate::common;
 use crate::common::ext::CommandExt;
-use common::custom_requester::BodySignature;
+use common::custom_requester::{BodySignature, CustomRequester};
 use hyper::Method;
 
 const STD_KEY: &str = "hello-world";
@@ -116,6 +116,41 @@ async fn test_putobject_streaming() {
 	}
 }
 
+#[tokio::test]
+async fn test_putobject_streaming_session_token() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("putobject-streaming-session-token");
+	let (session_key, session_token) = ctx.garage.session_key(&ctx.key, &bucket);
+	let session_request = CustomRequester::new_s3(ctx.garage, &session_key);
+
+	// Chunk signatures must be checked with the secret key of the
+	// temporary credentials, not with that of the parent key
+	let res = session_request
+		.builder(bucket.clone())
+		.method(Method::PUT)
+		.path(STD_KEY.to_owned())
+		.signed_header("x-amz-security-token", session_token)
+		.vhost_style(true)
+		.body(BODY.to_vec())
+		.body_signature(BodySignature::Streaming { chunk_size: 16 })
+		.send()
+		.await
+		.unwrap();
+	assert!(res.status().is_success(), "got response: {:?}", res);
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+
+	assert_bytes_eq!(o.body, BODY);
+	assert_eq!(o.content_length.unwrap(), 62);
+}
+
 #[tokio::test]
 async fn test_putobject_streaming_unsigned_trailer() {
 	let ctx = common::context();
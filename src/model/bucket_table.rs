@@ -1,11 +1,14 @@
 use garage_table::crdt::*;
 use garage_table::*;
+use garage_util::config::PublicAccessBlockConfig;
 use garage_util::data::*;
 use garage_util::time::*;
 
 use crate::permission::BucketKeyPerm;
 
 mod v08 {
+	use std::collections::BTreeMap;
+
 	use crate::permission::BucketKeyPerm;
 	use garage_util::crdt;
 	use garage_util::data::Uuid;
@@ -54,12 +57,93 @@ mod v08 {
 		/// Bucket quotas
 		#[serde(default)]
 		pub quotas: crdt::Lww<BucketQuotas>,
+		/// Versioning state (None if versioning was never enabled)
+		#[serde(default)]
+		pub versioning: crdt::Lww<Option<BucketVersioning>>,
+		/// Object lock configuration (None if object lock is not enabled,
+		/// once enabled it cannot be disabled)
+		#[serde(default)]
+		pub object_lock: crdt::Lww<Option<ObjectLockConfig>>,
+		/// Bucket policy, as the JSON document given in PutBucketPolicy
+		#[serde(default)]
+		pub policy: crdt::Lww<Option<String>>,
+		/// Whether unsigned GetObject, HeadObject and ListObjectsV2 requests
+		/// are allowed on this bucket through the S3 API endpoint
+		#[serde(default)]
+		pub public_read: crdt::Lww<bool>,
+		/// Server-side encryption applied to new objects that don't
+		/// request one explicitly
+		#[serde(default)]
+		pub default_encryption: crdt::Lww<Option<BucketDefaultEncryption>>,
+		/// Event notification rules
+		#[serde(default)]
+		pub notification_config: crdt::Lww<Option<Vec<NotificationRule>>>,
+		/// Replication configuration
+		#[serde(default)]
+		pub replication_config: crdt::Lww<Option<ReplicationConfig>>,
+		/// Access logging configuration
+		#[serde(default)]
+		pub logging_config: crdt::Lww<Option<BucketLoggingConfig>>,
+		/// Tags of the bucket, set with PutBucketTagging
+		#[serde(default)]
+		pub tags: crdt::Lww<Option<BTreeMap<String, String>>>,
+		/// Object ownership setting, set with PutBucketOwnershipControls
+		#[serde(default)]
+		pub object_ownership: crdt::Lww<Option<ObjectOwnership>>,
+		/// Public access block settings, set with PutPublicAccessBlock
+		#[serde(default)]
+		pub public_access_block: crdt::Lww<Option<PublicAccessBlock>>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 	pub struct WebsiteConfig {
 		pub index_document: String,
 		pub error_document: Option<String>,
+		/// If set, all requests are redirected to this host
+		/// and the other fields are ignored
+		#[serde(default)]
+		pub redirect_all: Option<RedirectAll>,
+		/// Redirection rules, evaluated in order
+		#[serde(default)]
+		pub routing_rules: Vec<RoutingRule>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RedirectAll {
+		pub hostname: String,
+		/// If None, the protocol of the original request is kept
+		pub protocol: Option<String>,
+	}
+
+	/// Website redirection rule
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RoutingRule {
+		/// If Some(x), requested key has to start with prefix x
+		pub prefix: Option<String>,
+		/// If Some(x), the rule applies only when serving the requested key
+		/// fails with HTTP status code x
+		pub http_error_code: Option<u16>,
+		pub redirect: Redirect,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Redirect {
+		/// If None, the host of the original request is kept
+		pub hostname: Option<String>,
+		/// If None, the protocol of the original request is kept
+		pub protocol: Option<String>,
+		/// HTTP status code of the redirection response
+		pub http_redirect_code: u16,
+		/// How the requested key is rewritten, if at all
+		pub replace_key: Option<ReplaceKey>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum ReplaceKey {
+		/// The prefix matched by the rule is replaced by x
+		Prefix(String),
+		/// The whole key is replaced by x
+		Full(String),
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -85,6 +169,15 @@ mod v08 {
 		pub abort_incomplete_mpu_days: Option<usize>,
 		/// Expiration policy for stored objects
 		pub expiration: Option<LifecycleExpiration>,
+		/// Transitions of stored objects to other storage classes
+		#[serde(default)]
+		pub transitions: Vec<LifecycleTransition>,
+		/// Expiration policy for noncurrent object versions
+		#[serde(default)]
+		pub noncurrent_version_expiration: Option<LifecycleNoncurrentExpiration>,
+		/// Whether delete markers with no noncurrent versions are removed
+		#[serde(default)]
+		pub expired_object_delete_marker: bool,
 	}
 
 	/// A lifecycle filter is a set of conditions that must all be true.
@@ -98,6 +191,9 @@ mod v08 {
 		pub size_gt: Option<u64>,
 		/// If Some(x), object size has to be less than x
 		pub size_lt: Option<u64>,
+		/// Object has to have all of these tags, as tuples (tag key, tag value)
+		#[serde(default)]
+		pub tags: Vec<(String, String)>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -108,6 +204,177 @@ mod v08 {
 		AtDate(String),
 	}
 
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct LifecycleTransition {
+		/// When objects are moved, given in the same way as an expiration
+		/// (a number of days after their creation, or a date)
+		pub when: LifecycleExpiration,
+		/// The storage class objects are moved to
+		pub storage_class: String,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct LifecycleNoncurrentExpiration {
+		/// Noncurrent versions expire x days after they became noncurrent
+		pub noncurrent_days: usize,
+		/// If Some(x), the x most recent noncurrent versions are always kept
+		pub newer_noncurrent_versions: Option<usize>,
+	}
+
+	/// Versioning state of a bucket, as set by PutBucketVersioning
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum BucketVersioning {
+		/// New object versions are kept alongside previous ones
+		Enabled,
+		/// New object versions replace the "null" version,
+		/// but versions created while versioning was enabled are kept
+		Suspended,
+	}
+
+	/// Object ownership setting of a bucket. Objects in Garage have no owner
+	/// of their own, so the only effect of this setting is whether ACLs are
+	/// disabled (BucketOwnerEnforced) or not.
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectOwnership {
+		BucketOwnerEnforced,
+		BucketOwnerPreferred,
+		ObjectWriter,
+	}
+
+	/// Public access block settings of a bucket
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+	pub struct PublicAccessBlock {
+		/// Reject requests that make the bucket or its objects public with an ACL
+		pub block_public_acls: bool,
+		/// Ignore the ACLs that make the bucket or its objects public
+		pub ignore_public_acls: bool,
+		/// Reject bucket policies that allow anonymous access
+		pub block_public_policy: bool,
+		/// Reject all anonymous requests to the bucket, including website requests
+		pub restrict_public_buckets: bool,
+	}
+
+	/// Object lock configuration of a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+	pub struct ObjectLockConfig {
+		/// Retention applied to new object versions that don't specify one
+		pub default_retention: Option<ObjectLockDefaultRetention>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectLockDefaultRetention {
+		pub mode: ObjectLockMode,
+		pub period: ObjectLockPeriod,
+	}
+
+	/// Retention mode of a locked object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectLockMode {
+		/// Version can be deleted or its retention shortened only by
+		/// users that explicitly bypass governance retention
+		Governance,
+		/// Version cannot be deleted by anyone until its retention expires
+		Compliance,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectLockPeriod {
+		Days(u32),
+		Years(u32),
+	}
+
+	/// Default server-side encryption of a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum BucketDefaultEncryption {
+		/// Encryption with keys managed by Garage (SSE-S3)
+		SseS3,
+		/// Encryption with data keys wrapped by the key provider (SSE-KMS),
+		/// using its default key if no key ID is given
+		SseKms { key_id: Option<String> },
+	}
+
+	/// Event notification rule
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct NotificationRule {
+		/// The ID of the rule
+		pub id: Option<String>,
+		/// Whether the rule was given as a queue or a topic configuration
+		pub kind: NotificationRuleKind,
+		/// ARN of the destination, as it was given in the configuration
+		pub destination_arn: String,
+		/// Destination to which matching events are delivered
+		pub target: NotificationTarget,
+		/// Event types that are delivered, e.g. `s3:ObjectCreated:*`
+		pub events: Vec<String>,
+		/// If Some(x), object key has to start with prefix x
+		pub prefix: Option<String>,
+		/// If Some(x), object key has to end with suffix x
+		pub suffix: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum NotificationRuleKind {
+		Queue,
+		Topic,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum NotificationTarget {
+		/// HTTP endpoint declared in the `notifications.webhooks`
+		/// section of the configuration file
+		Webhook { name: String },
+		/// Events are stored as items of a K2V partition
+		K2V {
+			bucket_id: Uuid,
+			partition_key: String,
+		},
+	}
+
+	/// Replication configuration of a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicationConfig {
+		/// IAM role given in the configuration (not used by Garage,
+		/// kept so that it can be returned by GetBucketReplication)
+		pub role: String,
+		pub rules: Vec<ReplicationRule>,
+	}
+
+	/// Replication rule
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ReplicationRule {
+		/// The ID of the rule
+		pub id: Option<String>,
+		/// When several rules match an object, the one with the highest
+		/// priority is applied
+		pub priority: i64,
+		/// Whether the rule is active
+		pub enabled: bool,
+		/// If Some(x), object key has to start with prefix x
+		pub prefix: Option<String>,
+		/// Object has to have all of these tags, as tuples (tag key, tag value)
+		pub tags: Vec<(String, String)>,
+		/// Remote endpoint declared in the `bucket_replication.remotes`
+		/// section of the configuration file
+		pub remote: String,
+		/// Name of the destination bucket on the remote endpoint
+		pub destination_bucket: String,
+		/// Whether delete markers are replicated
+		pub delete_marker_replication: bool,
+		/// Whether objects that existed before the rule was added are replicated
+		pub existing_object_replication: bool,
+	}
+
+	/// Access logging configuration of a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketLoggingConfig {
+		/// Bucket in which access logs are written
+		pub target_bucket: Uuid,
+		/// Name of the target bucket, as given in PutBucketLogging
+		pub target_bucket_name: String,
+		/// Prefix of the keys of the log objects
+		pub target_prefix: String,
+	}
+
 	#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 	pub struct BucketQuotas {
 		/// Maximum size in bytes (bucket size = sum of sizes of objects in the bucket)
@@ -137,8 +404,131 @@ impl BucketParams {
 			cors_config: crdt::Lww::new(None),
 			lifecycle_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			versioning: crdt::Lww::new(None),
+			object_lock: crdt::Lww::new(None),
+			policy: crdt::Lww::new(None),
+			public_read: crdt::Lww::new(false),
+			default_encryption: crdt::Lww::new(None),
+			notification_config: crdt::Lww::new(None),
+			replication_config: crdt::Lww::new(None),
+			logging_config: crdt::Lww::new(None),
+			tags: crdt::Lww::new(None),
+			object_ownership: crdt::Lww::new(None),
+			public_access_block: crdt::Lww::new(None),
 		}
 	}
+
+	/// Returns true if new object versions written in this bucket
+	/// must be kept as distinct versions
+	pub fn versioning_enabled(&self) -> bool {
+		matches!(self.versioning.get(), Some(BucketVersioning::Enabled))
+	}
+
+	/// Returns true if object lock is enabled on this bucket
+	pub fn object_lock_enabled(&self) -> bool {
+		self.object_lock.get().is_some()
+	}
+
+	/// Returns the public access block settings that apply to this bucket,
+	/// which are those of the bucket combined with those of the cluster
+	pub fn effective_public_access_block(
+		&self,
+		cluster: &PublicAccessBlockConfig,
+	) -> PublicAccessBlock {
+		let bucket = self.public_access_block.get().unwrap_or_default();
+		PublicAccessBlock {
+			block_public_acls: bucket.block_public_acls || cluster.block_public_acls,
+			ignore_public_acls: bucket.ignore_public_acls || cluster.ignore_public_acls,
+			block_public_policy: bucket.block_public_policy || cluster.block_public_policy,
+			restrict_public_buckets: bucket.restrict_public_buckets
+				|| cluster.restrict_public_buckets,
+		}
+	}
+
+	/// Returns true if ACLs are disabled on this bucket by its ownership controls
+	pub fn acls_disabled(&self) -> bool {
+		matches!(
+			self.object_ownership.get(),
+			Some(ObjectOwnership::BucketOwnerEnforced)
+		)
+	}
+}
+
+impl NotificationRule {
+	/// Returns true if events of type `event_name` (e.g. `s3:ObjectCreated:Put`)
+	/// on object `key` are delivered by this rule
+	pub fn matches(&self, event_name: &str, key: &str) -> bool {
+		let event_match = self.events.iter().any(|e| match e.strip_suffix('*') {
+			Some(pfx) => event_name.starts_with(pfx),
+			None => e == event_name,
+		});
+		event_match
+			&& self.prefix.as_ref().is_none_or(|p| key.starts_with(p))
+			&& self.suffix.as_ref().is_none_or(|s| key.ends_with(s))
+	}
+}
+
+impl WebsiteConfig {
+	/// Returns the first routing rule that applies to a request for key `key`,
+	/// `error_code` being the HTTP status code of the error returned when
+	/// serving it, or None if the rule is evaluated before serving it
+	pub fn routing_rule(&self, key: &str, error_code: Option<u16>) -> Option<&RoutingRule> {
+		self.routing_rules.iter().find(|r| {
+			r.http_error_code == error_code && r.prefix.iter().all(|p| key.starts_with(p))
+		})
+	}
+}
+
+impl RoutingRule {
+	/// Returns the key to which a request for key `key` is redirected
+	pub fn redirect_key(&self, key: &str) -> String {
+		match &self.redirect.replace_key {
+			None => key.to_string(),
+			Some(ReplaceKey::Full(k)) => k.clone(),
+			Some(ReplaceKey::Prefix(p)) => {
+				let prefix = self.prefix.as_deref().unwrap_or_default();
+				format!("{}{}", p, key.strip_prefix(prefix).unwrap_or(key))
+			}
+		}
+	}
+}
+
+impl ReplicationConfig {
+	/// Returns the enabled rule with the highest priority that applies
+	/// to object `key` having tags `tags`, if any
+	pub fn matching_rule(&self, key: &str, tags: &[(String, String)]) -> Option<&ReplicationRule> {
+		self.rules
+			.iter()
+			.filter(|r| r.enabled && r.matches(key, tags))
+			.max_by_key(|r| r.priority)
+	}
+}
+
+impl ReplicationRule {
+	/// Returns true if object `key` having tags `tags` is replicated by this rule
+	pub fn matches(&self, key: &str, tags: &[(String, String)]) -> bool {
+		self.prefix.iter().all(|p| key.starts_with(p)) && self.tags.iter().all(|t| tags.contains(t))
+	}
+}
+
+impl ObjectLockPeriod {
+	/// Compute the date (in msec since epoch) until which an object version
+	/// created at timestamp `from` is retained
+	pub fn retain_until(&self, from: u64) -> u64 {
+		use chrono::{Days, Months, TimeZone, Utc};
+
+		let from_date = Utc
+			.timestamp_millis_opt(from as i64)
+			.single()
+			.unwrap_or_default();
+		let until = match self {
+			Self::Days(d) => from_date.checked_add_days(Days::new(*d as u64)),
+			Self::Years(y) => from_date.checked_add_months(Months::new(y.saturating_mul(12))),
+		};
+		until
+			.map(|d| d.timestamp_millis() as u64)
+			.unwrap_or(u64::MAX)
+	}
 }
 
 impl Crdt for BucketParams {
@@ -153,6 +543,17 @@ impl Crdt for BucketParams {
 		self.cors_config.merge(&o.cors_config);
 		self.lifecycle_config.merge(&o.lifecycle_config);
 		self.quotas.merge(&o.quotas);
+		self.versioning.merge(&o.versioning);
+		self.object_lock.merge(&o.object_lock);
+		self.policy.merge(&o.policy);
+		self.public_read.merge(&o.public_read);
+		self.default_encryption.merge(&o.default_encryption);
+		self.notification_config.merge(&o.notification_config);
+		self.replication_config.merge(&o.replication_config);
+		self.logging_config.merge(&o.logging_config);
+		self.tags.merge(&o.tags);
+		self.object_ownership.merge(&o.object_ownership);
+		self.public_access_block.merge(&o.public_access_block);
 	}
 }
 
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -7,6 +8,7 @@ use garage_db as db;
 
 use garage_util::background::*;
 use garage_util::config::*;
+use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::persister::PersisterShared;
 
@@ -18,10 +20,13 @@ use garage_table::replication::TableFullReplication;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
+use crate::s3::access_log::AccessLogBuffer;
 use crate::s3::block_ref_table::*;
 use crate::s3::lifecycle_worker;
 use crate::s3::mpu_table::*;
+use crate::s3::notification::{NotificationManager, NotificationWorker};
 use crate::s3::object_table::*;
+use crate::s3::replication::ReplicationQueue;
 use crate::s3::version_table::*;
 
 use crate::bucket_alias_table::*;
@@ -29,6 +34,7 @@ use crate::bucket_table::*;
 use crate::helper;
 use crate::index_counter::*;
 use crate::key_table::*;
+use crate::kms::{KeyProvider, LocalKeyring};
 
 #[cfg(feature = "k2v")]
 use crate::k2v::{item_table::*, rpc::*, sub::*};
@@ -43,6 +49,14 @@ pub struct Garage {
 	/// The replication factor of this cluster
 	pub replication_factor: ReplicationFactor,
 
+	/// Master key wrapping the data keys of SSE-S3 encrypted objects, if configured
+	pub sse_master_key: Option<[u8; 32]>,
+	/// Key provider wrapping the data keys of SSE-KMS encrypted objects, if configured
+	pub key_provider: Option<Arc<dyn KeyProvider>>,
+	/// Key authenticating the session tokens of temporary credentials,
+	/// derived from the RPC secret so that it is the same on all nodes
+	pub session_token_key: Hash,
+
 	/// The local database
 	pub db: db::Db,
 	/// The membership manager
@@ -75,6 +89,13 @@ pub struct Garage {
 
 	/// Persister for lifecycle worker info
 	pub lifecycle_persister: PersisterShared<lifecycle_worker::LifecycleWorkerPersisted>,
+	/// Outbox of bucket event notifications to be delivered by this node
+	pub notifications: NotificationManager,
+	/// Queue of object versions to be replicated to remote endpoints by this node
+	pub replication_queue: ReplicationQueue,
+	/// Access log records of requests handled by this node, waiting to be
+	/// written to the target buckets
+	pub access_log: AccessLogBuffer,
 
 	#[cfg(feature = "k2v")]
 	pub k2v: GarageK2V,
@@ -143,6 +164,25 @@ impl Garage {
 		.and_then(|x| NetworkKey::from_slice(&x))
 		.ok_or_message("Invalid RPC secret key: expected 32 bytes of random hex, please check the documentation for requirements")?;
 
+		let session_token_key = crate::session::session_token_key(&network_key[..]);
+
+		let sse_master_key = match &config.sse_master_key {
+			None => None,
+			Some(key) => Some(
+				hex::decode(key)
+					.ok()
+					.and_then(|x| <[u8; 32]>::try_from(x).ok())
+					.ok_or_message("Invalid SSE master key: expected 32 bytes of random hex, please check the documentation for requirements")?,
+			),
+		};
+
+		let key_provider = match &config.sse_kms {
+			None => None,
+			Some(kms) => {
+				Some(Arc::new(LocalKeyring::load(&kms.keyring_file)?) as Arc<dyn KeyProvider>)
+			}
+		};
+
 		let (replication_factor, consistency_mode) = parse_replication_mode(&config)?;
 
 		info!("Initialize background variable system...");
@@ -243,6 +283,13 @@ impl Garage {
 			PersisterShared::new(&system.metadata_dir, "lifecycle_worker_state");
 		lifecycle_worker::register_bg_vars(&lifecycle_persister, &mut bg_vars);
 
+		info!("Initialize event notification outbox...");
+		let notifications = NotificationManager::new(&db, config.s3_api.s3_region.clone());
+
+		info!("Initialize bucket replication queue...");
+		let replication_queue = ReplicationQueue::new(&db);
+		let access_log = AccessLogBuffer::new();
+
 		// ---- K2V ----
 		#[cfg(feature = "k2v")]
 		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
@@ -260,6 +307,9 @@ impl Garage {
 			config,
 			bg_vars,
 			replication_factor,
+			sse_master_key,
+			key_provider,
+			session_token_key,
 			db,
 			system,
 			block_manager,
@@ -274,6 +324,9 @@ impl Garage {
 			version_table,
 			block_ref_table,
 			lifecycle_persister,
+			notifications,
+			replication_queue,
+			access_log,
 			#[cfg(feature = "k2v")]
 			k2v,
 		}))
@@ -298,6 +351,8 @@ impl Garage {
 			self.lifecycle_persister.clone(),
 		));
 
+		bg.spawn_worker(NotificationWorker::new(self.clone())?);
+
 		#[cfg(feature = "k2v")]
 		self.k2v.spawn_workers(bg);
 
@@ -144,7 +144,7 @@ impl<'a> BucketHelper<'a> {
 			.get_range(
 				&bucket_id,
 				None,
-				Some(ObjectFilter::IsData),
+				Some(ObjectFilter::HasVersions),
 				10,
 				EnumerationOrder::Forward,
 			)
@@ -224,6 +224,12 @@ impl<'a> BucketHelper<'a> {
 							state: ObjectVersionState::Aborted,
 							uuid: v.uuid,
 							timestamp: v.timestamp,
+							versioned: v.versioned,
+							tags: Default::default(),
+							lock: Default::default(),
+							replication: Default::default(),
+							public_read: Default::default(),
+							storage_class: Default::default(),
 						})
 						.collect::<Vec<_>>();
 					if !aborted_versions.is_empty() {
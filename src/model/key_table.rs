@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use garage_util::crdt::{self, Crdt};
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::{DeletedFilter, EmptyKey, Entry, TableSchema};
 
@@ -26,9 +27,15 @@ mod v08 {
 	/// Configuration for a key
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 	pub struct KeyParams {
-		/// The secret_key associated (immutable)
+		/// The secret_key the key was created with (immutable)
 		pub secret_key: String,
 
+		/// The secret keys that are currently valid, set when the key is
+		/// rotated (None if it never was, in which case only `secret_key`
+		/// is valid). The most recent secret key comes last.
+		#[serde(default)]
+		pub secret_keys: crdt::Lww<Option<Vec<String>>>,
+
 		/// Name for the key
 		pub name: crdt::Lww<String>,
 
@@ -43,6 +50,20 @@ mod v08 {
 		/// A key can have a local view of buckets names it is
 		/// the only one to see, this is the namespace for these aliases
 		pub local_aliases: crdt::LwwMap<String, Option<Uuid>>,
+
+		/// Time after which the key can no longer be used,
+		/// in msec since the Unix epoch (None if the key never expires)
+		#[serde(default)]
+		pub expiration: crdt::Lww<Option<u64>>,
+
+		/// Whether the key can be used; inactive keys are kept
+		/// with their permissions but all requests made with them are refused
+		#[serde(default = "default_active")]
+		pub active: crdt::Lww<bool>,
+	}
+
+	fn default_active() -> crdt::Lww<bool> {
+		crdt::Lww::raw(0, true)
 	}
 
 	impl garage_util::migrate::InitialFormat for Key {}
@@ -51,23 +72,91 @@ mod v08 {
 pub use v08::*;
 
 impl KeyParams {
+	/// Maximum number of secret keys that can be valid at the same time
+	pub const MAX_SECRET_KEYS: usize = 2;
+
 	fn new(secret_key: &str, name: &str) -> Self {
 		KeyParams {
 			secret_key: secret_key.to_string(),
+			secret_keys: crdt::Lww::new(None),
 			name: crdt::Lww::new(name.to_string()),
 			allow_create_bucket: crdt::Lww::new(false),
 			authorized_buckets: crdt::Map::new(),
 			local_aliases: crdt::LwwMap::new(),
+			expiration: crdt::Lww::new(None),
+			active: crdt::Lww::new(true),
 		}
 	}
+
+	/// Get the secret keys that can be used to sign requests,
+	/// the most recent one coming last
+	pub fn valid_secret_keys(&self) -> Vec<&str> {
+		match self.secret_keys.get() {
+			None => vec![self.secret_key.as_str()],
+			Some(keys) => keys.iter().map(String::as_str).collect(),
+		}
+	}
+
+	/// Get the most recent secret key
+	pub fn current_secret_key(&self) -> &str {
+		self.valid_secret_keys()
+			.last()
+			.copied()
+			.unwrap_or(self.secret_key.as_str())
+	}
+
+	/// Get the secret keys that are still valid but will be retired,
+	/// i.e. all valid secret keys but the most recent one
+	pub fn old_secret_keys(&self) -> Vec<&str> {
+		let mut keys = self.valid_secret_keys();
+		keys.pop();
+		keys
+	}
+
+	/// Add a newly generated secret key, the previous ones staying valid
+	/// until they are retired. Returns the new secret key, or None if the
+	/// maximum number of secret keys has been reached.
+	pub fn rotate_secret_key(&mut self) -> Option<String> {
+		let mut keys = self
+			.valid_secret_keys()
+			.into_iter()
+			.map(String::from)
+			.collect::<Vec<_>>();
+		if keys.len() >= Self::MAX_SECRET_KEYS {
+			return None;
+		}
+		let new_secret_key = hex::encode(&rand::random::<[u8; 32]>()[..]);
+		keys.push(new_secret_key.clone());
+		self.secret_keys.update(Some(keys));
+		Some(new_secret_key)
+	}
+
+	/// Retire all secret keys but the most recent one.
+	/// Returns the retired secret keys.
+	pub fn retire_old_secret_keys(&mut self) -> Vec<String> {
+		let mut keys = self
+			.valid_secret_keys()
+			.into_iter()
+			.map(String::from)
+			.collect::<Vec<_>>();
+		let current = match keys.pop() {
+			Some(k) if !keys.is_empty() => k,
+			_ => return vec![],
+		};
+		self.secret_keys.update(Some(vec![current]));
+		keys
+	}
 }
 
 impl Crdt for KeyParams {
 	fn merge(&mut self, o: &Self) {
+		self.secret_keys.merge(&o.secret_keys);
 		self.name.merge(&o.name);
 		self.allow_create_bucket.merge(&o.allow_create_bucket);
 		self.authorized_buckets.merge(&o.authorized_buckets);
 		self.local_aliases.merge(&o.local_aliases);
+		self.expiration.merge(&o.expiration);
+		self.active.merge(&o.active);
 	}
 }
 
@@ -143,6 +232,27 @@ impl Key {
 	pub fn allow_owner(&self, bucket: &Uuid) -> bool {
 		self.bucket_permissions(bucket).allow_owner
 	}
+
+	/// Returns true if the expiration time of the key has passed
+	pub fn is_expired(&self) -> bool {
+		self.params()
+			.and_then(|params| *params.expiration.get())
+			.map(|exp| now_msec() >= exp)
+			.unwrap_or(false)
+	}
+
+	/// Returns true if the key has not been deactivated
+	pub fn is_active(&self) -> bool {
+		self.params()
+			.map(|params| *params.active.get())
+			.unwrap_or(false)
+	}
+
+	/// Returns true if requests can be made with this key,
+	/// i.e. if it is not deleted, not deactivated and not expired
+	pub fn is_usable(&self) -> bool {
+		!self.is_deleted() && self.is_active() && !self.is_expired()
+	}
 }
 
 impl Entry<EmptyKey, String> for Key {
@@ -192,3 +302,33 @@ impl TableSchema for KeyTable {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rotate_secret_key() {
+		let mut key = Key::new("test");
+		let original = key.params().unwrap().secret_key.clone();
+		let mut other = key.clone();
+		let params = key.params_mut().unwrap();
+		assert_eq!(params.valid_secret_keys(), vec![original.as_str()]);
+		assert!(params.retire_old_secret_keys().is_empty());
+
+		let new = params.rotate_secret_key().unwrap();
+		assert_eq!(params.current_secret_key(), new);
+		assert_eq!(params.old_secret_keys(), vec![original.as_str()]);
+		assert!(params.rotate_secret_key().is_none());
+
+		assert_eq!(params.retire_old_secret_keys(), vec![original.clone()]);
+		assert_eq!(params.valid_secret_keys(), vec![new.as_str()]);
+
+		// a node that did not see the rotation gets the new secret keys when merging
+		other.merge(&key);
+		assert_eq!(
+			other.params().unwrap().valid_secret_keys(),
+			vec![new.as_str()]
+		);
+	}
+}
@@ -0,0 +1,252 @@
+//! Key providers for server-side encryption with KMS-managed keys (SSE-KMS).
+//!
+//! A key provider holds named master keys, which never leave it, and uses
+//! them to wrap and unwrap the data keys that objects are encrypted with.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+use aes_gcm::{
+	aead::{Aead, AeadCore, KeyInit, OsRng},
+	aes::cipher::typenum::Unsigned,
+	Aes256Gcm, Key, Nonce,
+};
+use async_trait::async_trait;
+use err_derive::Error;
+use serde::Deserialize;
+
+use garage_util::error::{Error as GarageError, OkOrMessage};
+
+/// Size of the data keys that objects are encrypted with
+pub const DATA_KEY_SIZE: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum KmsError {
+	#[error(display = "KMS key not found: {}", _0)]
+	NoSuchKey(String),
+
+	#[error(display = "No KMS key ID was specified and no default key is configured")]
+	NoDefaultKey,
+
+	#[error(display = "Could not unwrap data key")]
+	InvalidWrappedKey,
+
+	#[error(display = "KMS error: {}", _0)]
+	Backend(String),
+}
+
+/// A service that wraps and unwraps data keys using named master keys
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+	/// Key ID used when a request or a bucket configuration does not specify one
+	fn default_key_id(&self) -> Option<&str>;
+
+	/// Encrypt a data key with the current version of a master key
+	async fn wrap_data_key(
+		&self,
+		key_id: &str,
+		data_key: &[u8; DATA_KEY_SIZE],
+	) -> Result<Vec<u8>, KmsError>;
+
+	/// Decrypt a data key wrapped by `wrap_data_key` with the same key ID,
+	/// possibly with a previous version of the master key
+	async fn unwrap_data_key(
+		&self,
+		key_id: &str,
+		wrapped_key: &[u8],
+	) -> Result<[u8; DATA_KEY_SIZE], KmsError>;
+}
+
+// ---- local keyring file ----
+
+/// Key provider that reads its master keys from a local TOML file:
+///
+/// ```toml
+/// default_key = "main"
+///
+/// [keys]
+/// main = ["<hex key, version 1>", "<hex key, version 2>"]
+/// archive = ["<hex key, version 1>"]
+/// ```
+///
+/// Keys are rotated by appending a new version: the last version of a key
+/// wraps new data keys, previous versions are kept to unwrap existing ones.
+pub struct LocalKeyring {
+	default_key: Option<String>,
+	keys: HashMap<String, Vec<Key<Aes256Gcm>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocalKeyringFile {
+	default_key: Option<String>,
+	keys: HashMap<String, Vec<String>>,
+}
+
+impl LocalKeyring {
+	pub fn load(path: &Path) -> Result<Self, GarageError> {
+		let data = std::fs::read_to_string(path).ok_or_message(format!(
+			"Unable to read SSE-KMS keyring file {}",
+			path.display()
+		))?;
+		Self::parse(&data)
+	}
+
+	fn parse(data: &str) -> Result<Self, GarageError> {
+		let file: LocalKeyringFile =
+			toml::from_str(data).ok_or_message("Invalid SSE-KMS keyring file")?;
+
+		let mut keys = HashMap::new();
+		for (key_id, versions) in file.keys {
+			if versions.is_empty() {
+				return Err(GarageError::Message(format!(
+					"SSE-KMS key {} has no versions",
+					key_id
+				)));
+			}
+			let versions = versions
+				.iter()
+				.map(|v| {
+					hex::decode(v)
+						.ok()
+						.and_then(|x| <[u8; 32]>::try_from(x).ok())
+						.map(Key::<Aes256Gcm>::from)
+						.ok_or_message(format!(
+							"Invalid version of SSE-KMS key {}: expected 32 bytes of random hex",
+							key_id
+						))
+				})
+				.collect::<Result<Vec<_>, _>>()?;
+			keys.insert(key_id, versions);
+		}
+
+		if let Some(default_key) = &file.default_key {
+			if !keys.contains_key(default_key) {
+				return Err(GarageError::Message(format!(
+					"Default SSE-KMS key {} is not in the keyring",
+					default_key
+				)));
+			}
+		}
+
+		Ok(Self {
+			default_key: file.default_key,
+			keys,
+		})
+	}
+
+	fn versions(&self, key_id: &str) -> Result<&[Key<Aes256Gcm>], KmsError> {
+		self.keys
+			.get(key_id)
+			.map(Vec::as_slice)
+			.ok_or_else(|| KmsError::NoSuchKey(key_id.to_string()))
+	}
+}
+
+// Wrapped keys are the big-endian version number of the master key,
+// followed by the nonce and the encrypted data key.
+const VERSION_SIZE: usize = 4;
+
+#[async_trait]
+impl KeyProvider for LocalKeyring {
+	fn default_key_id(&self) -> Option<&str> {
+		self.default_key.as_deref()
+	}
+
+	async fn wrap_data_key(
+		&self,
+		key_id: &str,
+		data_key: &[u8; DATA_KEY_SIZE],
+	) -> Result<Vec<u8>, KmsError> {
+		let versions = self.versions(key_id)?;
+		let version = versions.len() as u32;
+		let cipher = Aes256Gcm::new(&versions[versions.len() - 1]);
+		let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+		let ciphertext = cipher
+			.encrypt(&nonce, &data_key[..])
+			.map_err(|_| KmsError::Backend("Encryption failed".into()))?;
+		Ok([&version.to_be_bytes()[..], nonce.as_slice(), &ciphertext].concat())
+	}
+
+	async fn unwrap_data_key(
+		&self,
+		key_id: &str,
+		wrapped_key: &[u8],
+	) -> Result<[u8; DATA_KEY_SIZE], KmsError> {
+		let versions = self.versions(key_id)?;
+		let nonce_size = <Aes256Gcm as AeadCore>::NonceSize::to_usize();
+		if wrapped_key.len() < VERSION_SIZE + nonce_size {
+			return Err(KmsError::InvalidWrappedKey);
+		}
+		let (version, rest) = wrapped_key.split_at(VERSION_SIZE);
+		let (nonce, ciphertext) = rest.split_at(nonce_size);
+
+		let version = u32::from_be_bytes(version.try_into().unwrap()) as usize;
+		let master_key = version
+			.checked_sub(1)
+			.and_then(|i| versions.get(i))
+			.ok_or(KmsError::InvalidWrappedKey)?;
+
+		Aes256Gcm::new(master_key)
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.ok()
+			.and_then(|k| k.try_into().ok())
+			.ok_or(KmsError::InvalidWrappedKey)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KEY_V1: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+	const KEY_V2: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+
+	#[tokio::test]
+	async fn test_local_keyring_rotation() {
+		let data_key = [42u8; DATA_KEY_SIZE];
+
+		let before = LocalKeyring::parse(&format!(
+			"default_key = \"main\"\n[keys]\nmain = [\"{}\"]\n",
+			KEY_V1
+		))
+		.unwrap();
+		assert_eq!(before.default_key_id(), Some("main"));
+		let wrapped_v1 = before.wrap_data_key("main", &data_key).await.unwrap();
+		assert!(matches!(
+			before.wrap_data_key("other", &data_key).await,
+			Err(KmsError::NoSuchKey(_))
+		));
+
+		let after = LocalKeyring::parse(&format!(
+			"default_key = \"main\"\n[keys]\nmain = [\"{}\", \"{}\"]\n",
+			KEY_V1, KEY_V2
+		))
+		.unwrap();
+		let wrapped_v2 = after.wrap_data_key("main", &data_key).await.unwrap();
+		assert_eq!(&wrapped_v2[..VERSION_SIZE], &2u32.to_be_bytes());
+
+		// Data keys wrapped with the previous version can still be unwrapped
+		assert_eq!(
+			after.unwrap_data_key("main", &wrapped_v1).await.unwrap(),
+			data_key
+		);
+		assert_eq!(
+			after.unwrap_data_key("main", &wrapped_v2).await.unwrap(),
+			data_key
+		);
+		assert!(before.unwrap_data_key("main", &wrapped_v2).await.is_err());
+	}
+
+	#[test]
+	fn test_invalid_keyrings() {
+		assert!(LocalKeyring::parse("[keys]\nmain = []\n").is_err());
+		assert!(LocalKeyring::parse("[keys]\nmain = [\"0102\"]\n").is_err());
+		assert!(LocalKeyring::parse(&format!(
+			"default_key = \"other\"\n[keys]\nmain = [\"{}\"]\n",
+			KEY_V1
+		))
+		.is_err());
+	}
+}
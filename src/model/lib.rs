@@ -8,6 +8,9 @@ pub mod index_counter;
 pub mod bucket_alias_table;
 pub mod bucket_table;
 pub mod key_table;
+pub mod session;
+
+pub mod kms;
 
 #[cfg(feature = "k2v")]
 pub mod k2v;
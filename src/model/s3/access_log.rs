@@ -0,0 +1,329 @@
+//! Buffer of bucket access log records.
+//!
+//! Requests to buckets that have access logging enabled are recorded, in the
+//! server access log format of AWS, in a buffer that is local to the node that
+//! handled them. The buffer is periodically flushed by the access log worker
+//! of the S3 API, which writes its content as objects in the target buckets.
+//! Records that are still in the buffer when the node stops are lost.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{TimeZone, Utc};
+use tokio::sync::Notify;
+
+use garage_util::data::*;
+
+use crate::bucket_table::BucketLoggingConfig;
+
+/// Size of the buffered records above which the buffer is flushed
+/// without waiting for the next periodic flush
+const FLUSH_THRESHOLD: usize = 4 << 20;
+
+/// Maximum size of the buffered records. Above it, for instance when a target
+/// bucket keeps rejecting writes, the oldest records are dropped.
+const MAX_BUFFER_SIZE: usize = 64 << 20;
+
+/// A request, as recorded in the access log of a bucket
+#[derive(Clone, Debug, Default)]
+pub struct AccessLogRecord {
+	/// Name of the bucket targeted by the request
+	pub bucket: String,
+	/// Time at which the request was received (in msec since epoch)
+	pub time: u64,
+	pub remote_ip: Option<String>,
+	/// Access key ID of the requester, None for anonymous requests
+	pub requester: Option<String>,
+	pub request_id: String,
+	/// Name of the API endpoint, e.g. `GetObject`
+	pub operation: String,
+	pub key: Option<String>,
+	/// Request line, e.g. `GET /bucket/key HTTP/1.1`
+	pub request_uri: String,
+	pub http_status: u16,
+	pub error_code: Option<String>,
+	/// Number of bytes of the response body
+	pub bytes_sent: u64,
+	/// Time between the reception of the request and the end of the
+	/// response (in msec)
+	pub total_time: u64,
+	/// Time between the reception of the request and the sending of
+	/// the response headers (in msec)
+	pub turnaround_time: u64,
+	pub referer: Option<String>,
+	pub user_agent: Option<String>,
+	pub version_id: Option<String>,
+	pub host: Option<String>,
+}
+
+impl AccessLogRecord {
+	/// Format the record as a line of the AWS server access log format
+	pub fn to_line(&self) -> String {
+		let time = Utc
+			.timestamp_millis_opt(self.time as i64)
+			.single()
+			.map(|t| t.format("[%d/%b/%Y:%H:%M:%S %z]").to_string())
+			.unwrap_or_else(|| "-".to_string());
+		let fields = [
+			// Bucket owner: Garage buckets have no single owner
+			"-".to_string(),
+			field(Some(&self.bucket)),
+			time,
+			field(self.remote_ip.as_deref()),
+			field(self.requester.as_deref()),
+			field(Some(&self.request_id)),
+			field(Some(&self.operation)),
+			field(self.key.as_deref()),
+			quoted_field(Some(&self.request_uri)),
+			self.http_status.to_string(),
+			field(self.error_code.as_deref()),
+			self.bytes_sent.to_string(),
+			// Object size
+			"-".to_string(),
+			self.total_time.to_string(),
+			self.turnaround_time.to_string(),
+			quoted_field(self.referer.as_deref()),
+			quoted_field(self.user_agent.as_deref()),
+			field(self.version_id.as_deref()),
+			// Host ID, signature version, cipher suite, authentication type
+			"-".to_string(),
+			"-".to_string(),
+			"-".to_string(),
+			"-".to_string(),
+			field(self.host.as_deref()),
+		];
+		fields.join(" ")
+	}
+}
+
+fn field(value: Option<&str>) -> String {
+	match value {
+		Some(v) if !v.is_empty() => v.replace(char::is_whitespace, "+"),
+		_ => "-".to_string(),
+	}
+}
+
+fn quoted_field(value: Option<&str>) -> String {
+	match value {
+		Some(v) if !v.is_empty() => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+		_ => "-".to_string(),
+	}
+}
+
+/// Records waiting to be written to a target bucket, under a given prefix
+pub struct AccessLogBatch {
+	pub target_bucket: Uuid,
+	pub target_prefix: String,
+	/// Records as lines of text
+	pub lines: Vec<String>,
+}
+
+pub struct AccessLogBuffer {
+	buffer: Mutex<BufferState>,
+	notify: Notify,
+	max_size: usize,
+}
+
+#[derive(Default)]
+struct BufferState {
+	/// Buffered lines, by target bucket and prefix
+	lines: HashMap<(Uuid, String), Vec<String>>,
+	/// Total size of the buffered lines
+	size: usize,
+	/// Number of lines dropped because the buffer was full
+	dropped: usize,
+}
+
+impl BufferState {
+	/// Drop the oldest lines of the largest buffers until the total size
+	/// is at most `max_size`
+	fn trim(&mut self, max_size: usize) {
+		while self.size > max_size {
+			let lines = match self.lines.values_mut().max_by_key(|l| l.len()) {
+				Some(lines) => lines,
+				None => break,
+			};
+			let mut count = 0;
+			let mut size = 0;
+			for line in lines.iter() {
+				if self.size - size <= max_size {
+					break;
+				}
+				size += line.len() + 1;
+				count += 1;
+			}
+			lines.drain(..count);
+			self.size -= size;
+			self.dropped += count;
+		}
+		self.lines.retain(|_, lines| !lines.is_empty());
+	}
+}
+
+impl AccessLogBuffer {
+	pub(crate) fn new() -> Self {
+		Self::with_max_size(MAX_BUFFER_SIZE)
+	}
+
+	fn with_max_size(max_size: usize) -> Self {
+		Self {
+			buffer: Mutex::new(BufferState::default()),
+			notify: Notify::new(),
+			max_size,
+		}
+	}
+
+	/// Get number of buffered records
+	pub fn buffered_len(&self) -> usize {
+		let buffer = self.buffer.lock().unwrap();
+		buffer.lines.values().map(Vec::len).sum()
+	}
+
+	/// Add a record to the access log configured by `config`
+	pub fn push(&self, config: &BucketLoggingConfig, record: &AccessLogRecord) {
+		let line = record.to_line();
+		let mut buffer = self.buffer.lock().unwrap();
+		buffer.size += line.len() + 1;
+		buffer
+			.lines
+			.entry((config.target_bucket, config.target_prefix.clone()))
+			.or_default()
+			.push(line);
+		buffer.trim(self.max_size);
+		if buffer.size >= FLUSH_THRESHOLD {
+			self.notify.notify_one();
+		}
+	}
+
+	/// Take all buffered records
+	pub fn take(&self) -> Vec<AccessLogBatch> {
+		let mut buffer = self.buffer.lock().unwrap();
+		buffer.size = 0;
+		std::mem::take(&mut buffer.lines)
+			.into_iter()
+			.map(|((target_bucket, target_prefix), lines)| AccessLogBatch {
+				target_bucket,
+				target_prefix,
+				lines,
+			})
+			.collect()
+	}
+
+	/// Put back records taken with `take()` that could not be written
+	pub fn put_back(&self, batch: AccessLogBatch) {
+		let mut buffer = self.buffer.lock().unwrap();
+		buffer.size += batch.lines.iter().map(|l| l.len() + 1).sum::<usize>();
+		buffer
+			.lines
+			.entry((batch.target_bucket, batch.target_prefix))
+			.or_default()
+			.splice(0..0, batch.lines);
+		buffer.trim(self.max_size);
+	}
+
+	/// Get the number of records dropped because the buffer was full since
+	/// the last call
+	pub fn take_dropped(&self) -> usize {
+		let mut buffer = self.buffer.lock().unwrap();
+		std::mem::take(&mut buffer.dropped)
+	}
+
+	/// Wait until the size of the buffer is above the flush threshold
+	pub async fn wait(&self) {
+		self.notify.notified().await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_record_to_line() {
+		let record = AccessLogRecord {
+			bucket: "website".to_string(),
+			time: 1549411238000,
+			remote_ip: Some("192.0.2.3".to_string()),
+			requester: Some("GK31c2f218a2e44f485b94239e".to_string()),
+			request_id: "3E57427F3EXAMPLE".to_string(),
+			operation: "GetObject".to_string(),
+			key: Some("docs/my page.html".to_string()),
+			request_uri: "GET /website/docs/my%20page.html HTTP/1.1".to_string(),
+			http_status: 200,
+			error_code: None,
+			bytes_sent: 2662,
+			total_time: 70,
+			turnaround_time: 10,
+			referer: None,
+			user_agent: Some("S3Console/0.4 \"quoted\"".to_string()),
+			version_id: None,
+			host: Some("website.s3.garage.tld".to_string()),
+		};
+		assert_eq!(
+			record.to_line(),
+			"- website [06/Feb/2019:00:00:38 +0000] 192.0.2.3 GK31c2f218a2e44f485b94239e \
+			3E57427F3EXAMPLE GetObject docs/my+page.html \"GET /website/docs/my%20page.html HTTP/1.1\" \
+			200 - 2662 - 70 10 - \"S3Console/0.4 \\\"quoted\\\"\" - - - - - website.s3.garage.tld"
+		);
+	}
+
+	#[test]
+	fn test_buffer() {
+		let buffer = AccessLogBuffer::new();
+		let config = |prefix: &str| BucketLoggingConfig {
+			target_bucket: Uuid::from([1u8; 32]),
+			target_bucket_name: "logs".to_string(),
+			target_prefix: prefix.to_string(),
+		};
+		let record = AccessLogRecord::default();
+
+		buffer.push(&config("a/"), &record);
+		buffer.push(&config("a/"), &record);
+		buffer.push(&config("b/"), &record);
+		assert_eq!(buffer.buffered_len(), 3);
+
+		let mut batches = buffer.take();
+		batches.sort_by(|a, b| a.target_prefix.cmp(&b.target_prefix));
+		assert_eq!(batches.len(), 2);
+		assert_eq!(batches[0].target_prefix, "a/");
+		assert_eq!(batches[0].lines.len(), 2);
+		assert_eq!(batches[1].lines.len(), 1);
+		assert_eq!(buffer.buffered_len(), 0);
+	}
+
+	#[test]
+	fn test_buffer_max_size() {
+		let record = AccessLogRecord::default();
+		let line_size = record.to_line().len() + 1;
+		let buffer = AccessLogBuffer::with_max_size(3 * line_size);
+		let config = BucketLoggingConfig {
+			target_bucket: Uuid::from([1u8; 32]),
+			target_bucket_name: "logs".to_string(),
+			target_prefix: "a/".to_string(),
+		};
+		let with_id = |id: &str| AccessLogRecord {
+			request_id: id.to_string(),
+			..Default::default()
+		};
+
+		for id in ["1", "2", "3", "4"] {
+			buffer.push(&config, &with_id(id));
+		}
+		assert_eq!(buffer.buffered_len(), 3);
+		assert_eq!(buffer.take_dropped(), 1);
+		assert_eq!(buffer.take_dropped(), 0);
+
+		// Records put back after a failed write are older than the
+		// records pushed in the meantime, they are dropped first
+		let batch = buffer.take().pop().unwrap();
+		buffer.push(&config, &with_id("5"));
+		buffer.put_back(batch);
+		assert_eq!(buffer.take_dropped(), 1);
+		let lines = buffer.take().pop().unwrap().lines;
+		let ids = lines
+			.iter()
+			.map(|l| l.split(' ').nth(6).unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(ids, ["3", "4", "5"]);
+	}
+}
@@ -29,6 +29,12 @@ mod v08 {
 		// Keep track of deleted status
 		/// Is the Version that contains this block deleted
 		pub deleted: crdt::Bool,
+
+		/// Storage class of the object version that contains this block
+		/// (None for the default storage class), which determines the
+		/// data directories where the block is stored
+		#[serde(default)]
+		pub storage_class: crdt::Lww<Option<String>>,
 	}
 
 	impl garage_util::migrate::InitialFormat for BlockRef {}
@@ -51,6 +57,7 @@ impl Entry<Hash, Uuid> for BlockRef {
 impl Crdt for BlockRef {
 	fn merge(&mut self, other: &Self) {
 		self.deleted.merge(&other.deleted);
+		self.storage_class.merge(&other.storage_class);
 	}
 }
 
@@ -73,13 +80,26 @@ impl TableSchema for BlockRefTable {
 		new: Option<&Self::E>,
 	) -> db::TxOpResult<()> {
 		let block = old.or(new).unwrap().block;
-		let was_before = old.map(|x| !x.deleted.get()).unwrap_or(false);
-		let is_after = new.map(|x| !x.deleted.get()).unwrap_or(false);
-		if is_after && !was_before {
-			self.block_manager.block_incref(tx, block)?;
-		}
-		if was_before && !is_after {
-			self.block_manager.block_decref(tx, block)?;
+		let was_before = old.filter(|x| !x.deleted.get());
+		let is_after = new.filter(|x| !x.deleted.get());
+		match (was_before, is_after) {
+			(None, Some(new)) => {
+				let storage_class = new.storage_class.get().as_deref();
+				self.block_manager.block_incref(tx, block, storage_class)?;
+			}
+			(Some(old), None) => {
+				let storage_class = old.storage_class.get().as_deref();
+				self.block_manager.block_decref(tx, block, storage_class)?;
+			}
+			(Some(old), Some(new)) if old.storage_class.get() != new.storage_class.get() => {
+				self.block_manager.block_update_storage_class(
+					tx,
+					block,
+					old.storage_class.get().as_deref(),
+					new.storage_class.get().as_deref(),
+				)?;
+			}
+			_ => (),
 		}
 		Ok(())
 	}
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -7,13 +8,15 @@ use tokio::sync::watch;
 
 use garage_util::background::*;
 use garage_util::data::*;
-use garage_util::error::Error;
+use garage_util::error::{Error, OkOrMessage};
 use garage_util::persister::PersisterShared;
 use garage_util::time::*;
 
-use garage_table::EmptyKey;
+use garage_table::{EmptyKey, Entry};
 
 use crate::bucket_table::*;
+use crate::s3::block_ref_table::*;
+use crate::s3::notification::*;
 use crate::s3::object_table::*;
 
 use crate::garage::Garage;
@@ -48,6 +51,7 @@ enum State {
 		pos: Vec<u8>,
 		counter: usize,
 		objects_expired: usize,
+		objects_transitioned: usize,
 		mpu_aborted: usize,
 		last_bucket: Option<Bucket>,
 	},
@@ -96,6 +100,7 @@ impl State {
 			pos: vec![],
 			counter: 0,
 			objects_expired: 0,
+			objects_transitioned: 0,
 			mpu_aborted: 0,
 			last_bucket: None,
 		}
@@ -118,6 +123,7 @@ impl Worker for LifecycleWorker {
 				date,
 				counter,
 				objects_expired,
+				objects_transitioned,
 				mpu_aborted,
 				..
 			} => {
@@ -134,6 +140,7 @@ impl Worker for LifecycleWorker {
 					freeform: vec![
 						format!("Started: {}", date),
 						format!("Objects expired: {}", objects_expired),
+						format!("Objects transitioned: {}", objects_transitioned),
 						format!("Multipart uploads aborted: { }", mpu_aborted),
 					],
 					..Default::default()
@@ -149,6 +156,7 @@ impl Worker for LifecycleWorker {
 				date,
 				counter,
 				objects_expired,
+				objects_transitioned,
 				mpu_aborted,
 				pos,
 				last_bucket,
@@ -163,7 +171,7 @@ impl Worker for LifecycleWorker {
 						.get_gt(&pos)?
 					{
 						None => {
-							info!("Lifecycle worker finished for {}, objects expired: {}, mpu aborted: {}", date, *objects_expired, *mpu_aborted);
+							info!("Lifecycle worker finished for {}, objects expired: {}, objects transitioned: {}, mpu aborted: {}", date, *objects_expired, *objects_transitioned, *mpu_aborted);
 							self.persister
 								.set_with(|x| x.last_completed = Some(date.to_string()))?;
 							self.state = State::Completed(*date);
@@ -178,6 +186,7 @@ impl Worker for LifecycleWorker {
 						*date,
 						&object,
 						objects_expired,
+						objects_transitioned,
 						mpu_aborted,
 						last_bucket,
 					)
@@ -232,13 +241,14 @@ async fn process_object(
 	now_date: NaiveDate,
 	object: &Object,
 	objects_expired: &mut usize,
+	objects_transitioned: &mut usize,
 	mpu_aborted: &mut usize,
 	last_bucket: &mut Option<Bucket>,
 ) -> Result<Skip, Error> {
 	if !object
 		.versions()
 		.iter()
-		.any(|x| x.is_data() || x.is_uploading(None))
+		.any(|x| x.is_complete() || x.is_uploading(None))
 	{
 		return Ok(Skip::NextObject);
 	}
@@ -275,6 +285,10 @@ async fn process_object(
 
 	let db = garage.object_table.data.store.db();
 
+	// The current version is moved to another storage class at most once per day,
+	// and not at all if it was expired
+	let mut skip_transitions = false;
+
 	for rule in lifecycle_policy.iter() {
 		if !rule.enabled {
 			continue;
@@ -287,7 +301,7 @@ async fn process_object(
 		}
 
 		if let Some(expire) = &rule.expiration {
-			if let Some(current_version) = object.versions().iter().rev().find(|v| v.is_data()) {
+			if let Some(current_version) = object.current_version().filter(|v| v.is_data()) {
 				let version_date = next_date(current_version.timestamp);
 
 				let current_version_data = match &current_version.state {
@@ -296,6 +310,7 @@ async fn process_object(
 				};
 
 				let size_match = check_size_filter(current_version_data, &rule.filter);
+				let tags_match = check_tags_filter(current_version, &rule.filter);
 				let date_match = match expire {
 					LifecycleExpiration::AfterDays(n_days) => {
 						(now_date - version_date) >= chrono::Duration::days(*n_days as i64)
@@ -310,16 +325,26 @@ async fn process_object(
 					}
 				};
 
-				if size_match && date_match {
+				if size_match && tags_match && date_match {
 					// Delete expired version
+					let delete_marker = ObjectVersion {
+						uuid: gen_uuid(),
+						timestamp: std::cmp::max(now_msec(), current_version.timestamp + 1),
+						state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+						versioned: bucket
+							.state
+							.as_option()
+							.is_some_and(|p| p.versioning_enabled()),
+						tags: Default::default(),
+						lock: Default::default(),
+						replication: Default::default(),
+						public_read: Default::default(),
+						storage_class: Default::default(),
+					};
 					let deleted_object = Object::new(
 						object.bucket_id,
 						object.key.clone(),
-						vec![ObjectVersion {
-							uuid: gen_uuid(),
-							timestamp: std::cmp::max(now_msec(), current_version.timestamp + 1),
-							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
-						}],
+						vec![delete_marker.clone()],
 					);
 					info!(
 						"Lifecycle: expiring 1 object in bucket {:?}",
@@ -327,10 +352,112 @@ async fn process_object(
 					);
 					db.transaction(|tx| garage.object_table.queue_insert(tx, &deleted_object))?;
 					*objects_expired += 1;
+					skip_transitions = true;
+
+					let event_name = if delete_marker.versioned {
+						ObjectEventName::DeleteMarkerCreated
+					} else {
+						ObjectEventName::Delete
+					};
+					notify_expiration(garage, &bucket, &object.key, &delete_marker, event_name);
+				}
+			}
+		}
+
+		if !skip_transitions {
+			if let Some(current_version) = object.current_version().filter(|v| v.is_data()) {
+				if let Some(storage_class) =
+					transition_storage_class(current_version, now_date, rule)
+				{
+					skip_transitions = true;
+					match transition_version(garage, object, current_version, storage_class).await {
+						Ok(()) => {
+							info!(
+								"Lifecycle: moved 1 object to storage class {} in bucket {:?}",
+								storage_class, object.bucket_id
+							);
+							*objects_transitioned += 1;
+						}
+						Err(e) => {
+							// The transition will be tried again on the next run
+							warn!(
+								"Lifecycle: could not move object {:?} in bucket {:?} to storage class {}: {}",
+								object.key, object.bucket_id, storage_class, e
+							);
+						}
+					}
 				}
 			}
 		}
 
+		if let Some(noncurrent_exp) = &rule.noncurrent_version_expiration {
+			let expired_versions =
+				expired_noncurrent_versions(object, now_date, noncurrent_exp, &rule.filter);
+			if !expired_versions.is_empty() {
+				let n_expired = expired_versions.len();
+				info!(
+					"Lifecycle: expiring {} noncurrent version(s) in bucket {:?}",
+					n_expired, object.bucket_id
+				);
+				let expired_object =
+					Object::new(object.bucket_id, object.key.clone(), expired_versions);
+				db.transaction(|tx| garage.object_table.queue_insert(tx, &expired_object))?;
+				*objects_expired += n_expired;
+
+				for version in expired_object.versions() {
+					notify_expiration(
+						garage,
+						&bucket,
+						&object.key,
+						version,
+						ObjectEventName::Delete,
+					);
+				}
+			}
+		}
+
+		if rule.expired_object_delete_marker {
+			if let Some(marker) = expired_delete_marker(object) {
+				// Remove the delete marker, and write a non-versioned delete marker
+				// in its place so that the object entry can be garbage collected
+				let deleted_object = Object::new(
+					object.bucket_id,
+					object.key.clone(),
+					vec![
+						ObjectVersion {
+							state: ObjectVersionState::Aborted,
+							..marker.clone()
+						},
+						ObjectVersion {
+							uuid: gen_uuid(),
+							timestamp: std::cmp::max(now_msec(), marker.timestamp + 1),
+							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+							versioned: false,
+							tags: Default::default(),
+							lock: Default::default(),
+							replication: Default::default(),
+							public_read: Default::default(),
+							storage_class: Default::default(),
+						},
+					],
+				);
+				info!(
+					"Lifecycle: removing 1 expired delete marker in bucket {:?}",
+					object.bucket_id
+				);
+				db.transaction(|tx| garage.object_table.queue_insert(tx, &deleted_object))?;
+				*objects_expired += 1;
+
+				notify_expiration(
+					garage,
+					&bucket,
+					&object.key,
+					marker,
+					ObjectEventName::Delete,
+				);
+			}
+		}
+
 		if let Some(abort_mpu_days) = &rule.abort_incomplete_mpu_days {
 			let aborted_versions = object
 				.versions()
@@ -342,7 +469,7 @@ async fn process_object(
 					{
 						Some(ObjectVersion {
 							state: ObjectVersionState::Aborted,
-							..*v
+							..v.clone()
 						})
 					} else {
 						None
@@ -368,6 +495,125 @@ async fn process_object(
 	Ok(Skip::NextObject)
 }
 
+/// Find the storage class that an object version must be moved to according
+/// to the transitions of a lifecycle rule, i.e. the storage class of the
+/// most recent transition that applies, if the version is not already in it
+fn transition_storage_class<'a>(
+	version: &ObjectVersion,
+	now_date: NaiveDate,
+	rule: &'a LifecycleRule,
+) -> Option<&'a str> {
+	let version_data = match &version.state {
+		ObjectVersionState::Complete(c) => c,
+		_ => unreachable!(),
+	};
+	if !check_size_filter(version_data, &rule.filter) || !check_tags_filter(version, &rule.filter) {
+		return None;
+	}
+
+	let version_date = next_date(version.timestamp);
+	let (_, storage_class) = rule
+		.transitions
+		.iter()
+		.filter_map(|t| {
+			let transition_date = match &t.when {
+				LifecycleExpiration::AfterDays(n_days) => {
+					version_date + chrono::Duration::days(*n_days as i64)
+				}
+				LifecycleExpiration::AtDate(date) => parse_lifecycle_date(date).ok()?,
+			};
+			(now_date >= transition_date).then_some((transition_date, t.storage_class.as_str()))
+		})
+		.max()?;
+
+	(version.storage_class.get().as_deref() != Some(storage_class)).then_some(storage_class)
+}
+
+/// Record the new storage class of an object version, both in the object
+/// and in the references to its data blocks. Blocks are moved by the storage
+/// nodes once all the objects that reference them are in the new storage class,
+/// so that blocks shared with other objects are not moved from under them.
+async fn transition_version(
+	garage: &Garage,
+	object: &Object,
+	version: &ObjectVersion,
+	storage_class: &str,
+) -> Result<(), Error> {
+	let mut new_storage_class = version.storage_class.clone();
+	new_storage_class.update(Some(storage_class.to_string()));
+
+	if let ObjectVersionState::Complete(ObjectVersionData::FirstBlock(..)) = &version.state {
+		let block_list = garage
+			.version_table
+			.get(&version.uuid, &EmptyKey)
+			.await?
+			.ok_or_message("version of object not found")?;
+		let block_refs = block_list
+			.blocks
+			.items()
+			.iter()
+			.map(|(_, b)| b.hash)
+			.collect::<BTreeSet<_>>()
+			.into_iter()
+			.map(|hash| BlockRef {
+				block: hash,
+				version: version.uuid,
+				deleted: false.into(),
+				storage_class: new_storage_class.clone(),
+			})
+			.collect::<Vec<_>>();
+		garage.block_ref_table.insert_many(&block_refs[..]).await?;
+	}
+
+	let updated_object = Object::new(
+		object.bucket_id,
+		object.key.clone(),
+		vec![ObjectVersion {
+			storage_class: new_storage_class,
+			..version.clone()
+		}],
+	);
+	let db = garage.object_table.data.store.db();
+	db.transaction(|tx| garage.object_table.queue_insert(tx, &updated_object))?;
+	Ok(())
+}
+
+/// Queue notifications for the removal of an object version by the lifecycle worker
+fn notify_expiration(
+	garage: &Garage,
+	bucket: &Bucket,
+	key: &str,
+	version: &ObjectVersion,
+	name: ObjectEventName,
+) {
+	let rules: &[NotificationRule] = bucket
+		.state
+		.as_option()
+		.and_then(|s| s.notification_config.get().as_deref())
+		.unwrap_or_default();
+	if rules.is_empty() {
+		return;
+	}
+
+	let event = ObjectEvent {
+		name,
+		bucket_name: bucket_notification_name(bucket),
+		key: key.to_string(),
+		version_id: if version.versioned {
+			hex::encode(version.uuid)
+		} else {
+			"null".to_string()
+		},
+		size: None,
+		etag: None,
+		timestamp: version.timestamp,
+		principal: None,
+	};
+	if let Err(e) = garage.notifications.emit(rules, &event) {
+		error!("Lifecycle: could not queue event notification: {}", e);
+	}
+}
+
 fn check_size_filter(version_data: &ObjectVersionData, filter: &LifecycleFilter) -> bool {
 	let size = match version_data {
 		ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) => meta.size,
@@ -386,6 +632,81 @@ fn check_size_filter(version_data: &ObjectVersionData, filter: &LifecycleFilter)
 	true
 }
 
+fn check_tags_filter(version: &ObjectVersion, filter: &LifecycleFilter) -> bool {
+	let tags = &version.tags.get().0;
+	filter.tags.iter().all(|t| tags.contains(t))
+}
+
+/// Select the noncurrent versions of an object that have expired,
+/// and return them marked as aborted
+fn expired_noncurrent_versions(
+	object: &Object,
+	now_date: NaiveDate,
+	expiration: &LifecycleNoncurrentExpiration,
+	filter: &LifecycleFilter,
+) -> Vec<ObjectVersion> {
+	// Complete versions, from the current version to the oldest one
+	let complete_versions = object
+		.versions()
+		.iter()
+		.rev()
+		.filter(|v| v.is_complete())
+		.collect::<Vec<_>>();
+
+	// A version becomes noncurrent when the next complete version is written
+	complete_versions
+		.windows(2)
+		.enumerate()
+		.filter_map(|(i, w)| {
+			let (newer, version) = (w[0], w[1]);
+			if expiration.newer_noncurrent_versions.is_some_and(|n| i < n) {
+				return None;
+			}
+			let noncurrent_date = next_date(newer.timestamp);
+			if (now_date - noncurrent_date)
+				< chrono::Duration::days(expiration.noncurrent_days as i64)
+			{
+				return None;
+			}
+			// Versions protected by object lock are never expired
+			if version.lock.is_locked(now_msec(), false) {
+				return None;
+			}
+			let filter_match = match &version.state {
+				// Delete markers have no size and no tags, so they only
+				// match filters that have no such conditions
+				ObjectVersionState::Complete(ObjectVersionData::DeleteMarker) => {
+					filter.size_gt.is_none() && filter.size_lt.is_none() && filter.tags.is_empty()
+				}
+				ObjectVersionState::Complete(data) => {
+					check_size_filter(data, filter) && check_tags_filter(version, filter)
+				}
+				_ => unreachable!(),
+			};
+			filter_match.then(|| ObjectVersion {
+				state: ObjectVersionState::Aborted,
+				..version.clone()
+			})
+		})
+		.collect()
+}
+
+/// Return the current version of the object if it is a delete marker
+/// and no other version of the object remains
+fn expired_delete_marker(object: &Object) -> Option<&ObjectVersion> {
+	let current_version = object.current_version()?;
+	// A null delete marker left alone is a tombstone already, and the
+	// object entry will be garbage collected without our help
+	if current_version.is_data() || object.is_tombstone() {
+		return None;
+	}
+	let others = object
+		.versions()
+		.iter()
+		.any(|v| v.uuid != current_version.uuid && (v.is_complete() || v.is_uploading(None)));
+	(!others).then_some(current_version)
+}
+
 fn midnight_ts(date: NaiveDate, use_local_tz: bool) -> u64 {
 	let midnight = date.and_hms_opt(0, 0, 0).expect("midnight does not exist");
 	if use_local_tz {
@@ -412,3 +733,233 @@ fn today(use_local_tz: bool) -> NaiveDate {
 	}
 	Utc::now().naive_utc().date()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DAY: u64 = 24 * 3600 * 1000;
+
+	fn version(uuid: u8, day: u64, data: bool, tags: &[(&str, &str)]) -> ObjectVersion {
+		let data = if data {
+			ObjectVersionData::Inline(
+				ObjectVersionMeta {
+					size: 10,
+					etag: "".into(),
+					encryption: ObjectVersionEncryption::Plaintext {
+						inner: ObjectVersionMetaInner {
+							headers: vec![],
+							checksum: None,
+						},
+					},
+				},
+				vec![],
+			)
+		} else {
+			ObjectVersionData::DeleteMarker
+		};
+		let tags = tags
+			.iter()
+			.map(|(k, v)| (k.to_string(), v.to_string()))
+			.collect();
+		ObjectVersion {
+			uuid: Uuid::from([uuid; 32]),
+			timestamp: day * DAY,
+			state: ObjectVersionState::Complete(data),
+			versioned: true,
+			tags: garage_util::crdt::Lww::raw(0, ObjectTagSet(tags)),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
+		}
+	}
+
+	fn date(day: u64) -> NaiveDate {
+		DateTime::<Utc>::from_timestamp_millis((day * DAY) as i64)
+			.unwrap()
+			.date_naive()
+	}
+
+	fn uuids(versions: &[ObjectVersion]) -> Vec<u8> {
+		versions.iter().map(|v| v.uuid.as_slice()[0]).collect()
+	}
+
+	#[test]
+	fn test_expired_noncurrent_versions() {
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![
+				version(1, 100, true, &[("k", "v")]),
+				version(2, 110, true, &[]),
+				version(3, 120, false, &[]),
+				version(4, 130, true, &[("k", "v")]),
+			],
+		);
+		let filter = LifecycleFilter::default();
+		let expiration = |days, newer| LifecycleNoncurrentExpiration {
+			noncurrent_days: days,
+			newer_noncurrent_versions: newer,
+		};
+
+		// Versions 1, 2, 3 became noncurrent on days 110, 120 and 130
+		let expired =
+			expired_noncurrent_versions(&object, date(132), &expiration(10, None), &filter);
+		assert_eq!(uuids(&expired), vec![2, 1]);
+		assert!(expired
+			.iter()
+			.all(|v| v.state == ObjectVersionState::Aborted));
+
+		let expired =
+			expired_noncurrent_versions(&object, date(200), &expiration(10, None), &filter);
+		assert_eq!(uuids(&expired), vec![3, 2, 1]);
+
+		let expired =
+			expired_noncurrent_versions(&object, date(200), &expiration(10, Some(2)), &filter);
+		assert_eq!(uuids(&expired), vec![1]);
+
+		let filter = LifecycleFilter {
+			tags: vec![("k".into(), "v".into())],
+			..Default::default()
+		};
+		let expired =
+			expired_noncurrent_versions(&object, date(200), &expiration(10, None), &filter);
+		assert_eq!(uuids(&expired), vec![1]);
+
+		// Delete markers never match size conditions
+		let filter = LifecycleFilter {
+			size_lt: Some(100),
+			..Default::default()
+		};
+		let expired =
+			expired_noncurrent_versions(&object, date(200), &expiration(10, None), &filter);
+		assert_eq!(uuids(&expired), vec![2, 1]);
+
+		// Locked versions are never expired
+		let mut retained = version(1, 100, true, &[]);
+		retained.lock.retention = garage_util::crdt::Lww::raw(
+			0,
+			Some(ObjectRetention {
+				mode: ObjectLockMode::Compliance,
+				retain_until: u64::MAX,
+			}),
+		);
+		let mut held = version(2, 110, true, &[]);
+		held.lock.legal_hold = garage_util::crdt::Lww::raw(0, true);
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![
+				retained,
+				held,
+				version(3, 120, false, &[]),
+				version(4, 130, true, &[]),
+			],
+		);
+		let expired = expired_noncurrent_versions(
+			&object,
+			date(200),
+			&expiration(10, None),
+			&LifecycleFilter::default(),
+		);
+		assert_eq!(uuids(&expired), vec![3]);
+	}
+
+	#[test]
+	fn test_transition_storage_class() {
+		let rule = LifecycleRule {
+			id: None,
+			enabled: true,
+			filter: LifecycleFilter::default(),
+			abort_incomplete_mpu_days: None,
+			expiration: None,
+			transitions: vec![
+				LifecycleTransition {
+					when: LifecycleExpiration::AfterDays(30),
+					storage_class: "STANDARD_IA".into(),
+				},
+				LifecycleTransition {
+					when: LifecycleExpiration::AfterDays(90),
+					storage_class: "GLACIER".into(),
+				},
+			],
+			noncurrent_version_expiration: None,
+			expired_object_delete_marker: false,
+		};
+
+		// The version is created on day 100, days are counted from day 101
+		let v = version(1, 100, true, &[]);
+		assert_eq!(transition_storage_class(&v, date(130), &rule), None);
+		assert_eq!(
+			transition_storage_class(&v, date(131), &rule),
+			Some("STANDARD_IA")
+		);
+		assert_eq!(
+			transition_storage_class(&v, date(191), &rule),
+			Some("GLACIER")
+		);
+
+		// Versions already in the target storage class are not moved again
+		let mut v_ia = v.clone();
+		v_ia.storage_class = garage_util::crdt::Lww::raw(0, Some("STANDARD_IA".into()));
+		assert_eq!(transition_storage_class(&v_ia, date(150), &rule), None);
+		assert_eq!(
+			transition_storage_class(&v_ia, date(200), &rule),
+			Some("GLACIER")
+		);
+
+		// Versions that do not match the filter are not moved
+		let rule = LifecycleRule {
+			filter: LifecycleFilter {
+				size_gt: Some(100),
+				..Default::default()
+			},
+			..rule
+		};
+		assert_eq!(transition_storage_class(&v, date(200), &rule), None);
+	}
+
+	#[test]
+	fn test_expired_delete_marker() {
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![version(1, 100, true, &[]), version(2, 110, false, &[])],
+		);
+		assert!(expired_delete_marker(&object).is_none());
+
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![version(2, 110, false, &[])],
+		);
+		assert_eq!(
+			expired_delete_marker(&object).map(|v| v.uuid),
+			Some(Uuid::from([2; 32]))
+		);
+
+		// A null delete marker alone is already a tombstone
+		let mut null_marker = version(2, 110, false, &[]);
+		null_marker.versioned = false;
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![null_marker.clone()],
+		);
+		assert!(expired_delete_marker(&object).is_none());
+
+		// ... but not if an aborted upload was left behind after it
+		let mut aborted = version(3, 120, true, &[]);
+		aborted.state = ObjectVersionState::Aborted;
+		let object = Object::new(
+			Uuid::from([0x42; 32]),
+			"a".into(),
+			vec![null_marker, aborted],
+		);
+		assert_eq!(
+			expired_delete_marker(&object).map(|v| v.uuid),
+			Some(Uuid::from([2; 32]))
+		);
+	}
+}
@@ -3,4 +3,7 @@ pub mod mpu_table;
 pub mod object_table;
 pub mod version_table;
 
+pub mod access_log;
 pub mod lifecycle_worker;
+pub mod notification;
+pub mod replication;
@@ -0,0 +1,448 @@
+//! Bucket event notifications.
+//!
+//! Events are matched against the notification rules of the bucket by
+//! the node that handles the request, and the resulting messages are
+//! written to an outbox that is local to that node. The notification
+//! worker delivers them to their destination, retrying failed deliveries
+//! with exponential backoff: messages are delivered at least once,
+//! unless they could not be delivered after `MAX_DELIVERY_ATTEMPTS` tries.
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::{connect::HttpConnector, Client as HttpClient};
+use hyper_util::rt::TokioExecutor;
+use tokio::select;
+use tokio::sync::{watch, Notify};
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::migrate::Migrate;
+use garage_util::time::*;
+
+use crate::bucket_table::*;
+use crate::garage::Garage;
+
+#[cfg(feature = "k2v")]
+use crate::k2v::item_table::DvvsValue;
+
+// The delay before a failed delivery is retried, multiplied by 2, 4, 8, etc.
+// for every consecutive failure, up to 10 seconds * 2^8 (~43 minutes).
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(10);
+const DELIVERY_RETRY_MAX_BACKOFF_POWER: u32 = 8;
+// Messages that could not be delivered after this number of attempts
+// (about 6 hours) are dropped.
+const MAX_DELIVERY_ATTEMPTS: u32 = 16;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Types of events that can be notified
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectEventName {
+	Put,
+	Post,
+	Copy,
+	CompleteMultipartUpload,
+	Delete,
+	DeleteMarkerCreated,
+}
+
+/// Event names that can be used in notification rules
+pub const SUPPORTED_EVENT_NAMES: &[&str] = &[
+	"s3:ObjectCreated:*",
+	"s3:ObjectCreated:Put",
+	"s3:ObjectCreated:Post",
+	"s3:ObjectCreated:Copy",
+	"s3:ObjectCreated:CompleteMultipartUpload",
+	"s3:ObjectRemoved:*",
+	"s3:ObjectRemoved:Delete",
+	"s3:ObjectRemoved:DeleteMarkerCreated",
+];
+
+impl ObjectEventName {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Put => "s3:ObjectCreated:Put",
+			Self::Post => "s3:ObjectCreated:Post",
+			Self::Copy => "s3:ObjectCreated:Copy",
+			Self::CompleteMultipartUpload => "s3:ObjectCreated:CompleteMultipartUpload",
+			Self::Delete => "s3:ObjectRemoved:Delete",
+			Self::DeleteMarkerCreated => "s3:ObjectRemoved:DeleteMarkerCreated",
+		}
+	}
+}
+
+/// An event on an object, that is notified to the destinations
+/// of the matching notification rules of its bucket
+#[derive(Clone, Debug)]
+pub struct ObjectEvent {
+	pub name: ObjectEventName,
+	pub bucket_name: String,
+	pub key: String,
+	/// Version ID of the created or deleted version, as returned to S3 clients
+	pub version_id: String,
+	/// Size and etag of created objects
+	pub size: Option<u64>,
+	pub etag: Option<String>,
+	/// Timestamp of the created or deleted version
+	pub timestamp: u64,
+	/// Access key ID of the requester, None for anonymous requests
+	/// and for events generated by Garage itself (e.g. lifecycle expiration)
+	pub principal: Option<String>,
+}
+
+mod v011 {
+	use crate::bucket_table::NotificationTarget;
+	use serde::{Deserialize, Serialize};
+
+	/// A message waiting in the outbox
+	#[derive(Serialize, Deserialize)]
+	pub struct OutboxMessage {
+		pub target: NotificationTarget,
+		/// Identifier of the message, used as sort key for K2V targets
+		/// so that retries overwrite the same item
+		pub id: String,
+		/// JSON payload
+		pub payload: String,
+		/// Number of failed delivery attempts
+		pub attempts: u32,
+	}
+
+	impl garage_util::migrate::InitialFormat for OutboxMessage {
+		const VERSION_MARKER: &'static [u8] = b"G011nom";
+	}
+}
+
+use v011::*;
+
+pub struct NotificationManager {
+	/// Messages waiting to be delivered. The key in this tree is:
+	///     concat(time of next delivery attempt (8 bytes), random id (32 bytes))
+	pub(crate) outbox: db::Tree,
+	notify: Notify,
+	region: String,
+}
+
+impl NotificationManager {
+	pub(crate) fn new(db: &db::Db, region: String) -> Self {
+		let outbox = db
+			.open_tree("notification_outbox")
+			.expect("Unable to open notification_outbox tree");
+		Self {
+			outbox,
+			notify: Notify::new(),
+			region,
+		}
+	}
+
+	/// Get number of messages waiting to be delivered
+	pub fn outbox_len(&self) -> Result<usize, Error> {
+		Ok(self.outbox.len()?)
+	}
+
+	/// Queue messages for an event to the destinations
+	/// of the rules of the bucket that match it
+	pub fn emit(&self, rules: &[NotificationRule], event: &ObjectEvent) -> Result<(), Error> {
+		let event_name = event.name.as_str();
+		let mut queued = false;
+		for rule in rules.iter().filter(|r| r.matches(event_name, &event.key)) {
+			let msg = OutboxMessage {
+				target: rule.target.clone(),
+				id: format!(
+					"{}-{}",
+					msec_to_rfc3339(event.timestamp),
+					hex::encode(&gen_uuid().as_slice()[..8])
+				),
+				payload: self.event_payload(rule, event).to_string(),
+				attempts: 0,
+			};
+			self.queue_message(now_msec(), &msg)?;
+			queued = true;
+		}
+		if queued {
+			self.notify.notify_one();
+		}
+		Ok(())
+	}
+
+	fn queue_message(&self, when: u64, msg: &OutboxMessage) -> Result<(), Error> {
+		let key = [&u64::to_be_bytes(when)[..], gen_uuid().as_slice()].concat();
+		self.outbox.insert(key, msg.encode()?)?;
+		Ok(())
+	}
+
+	/// Build the message for an event, in the format used by AWS S3
+	fn event_payload(&self, rule: &NotificationRule, event: &ObjectEvent) -> serde_json::Value {
+		let key: String = form_urlencoded::byte_serialize(event.key.as_bytes()).collect();
+		let mut object = serde_json::json!({
+			"key": key,
+			"versionId": event.version_id,
+			"sequencer": format!("{:016X}", event.timestamp),
+		});
+		if let Some(size) = event.size {
+			object["size"] = size.into();
+		}
+		if let Some(etag) = &event.etag {
+			object["eTag"] = etag.clone().into();
+		}
+
+		serde_json::json!({
+			"Records": [{
+				"eventVersion": "2.1",
+				"eventSource": "aws:s3",
+				"awsRegion": self.region,
+				"eventTime": msec_to_rfc3339(now_msec()),
+				"eventName": event.name.as_str().trim_start_matches("s3:"),
+				"userIdentity": {
+					"principalId": event.principal.as_deref().unwrap_or("anonymous"),
+				},
+				"s3": {
+					"s3SchemaVersion": "1.0",
+					"configurationId": rule.id.as_deref().unwrap_or_default(),
+					"bucket": {
+						"name": event.bucket_name,
+						"arn": format!("arn:aws:s3:::{}", event.bucket_name),
+					},
+					"object": object,
+				},
+			}],
+		})
+	}
+}
+
+/// Returns the name under which a bucket is notified for events
+/// that are not the result of a request: its first global alias
+pub fn bucket_notification_name(bucket: &Bucket) -> String {
+	bucket
+		.state
+		.as_option()
+		.and_then(|p| p.aliases.items().iter().find(|(_, _, active)| *active))
+		.map(|(name, _, _)| name.clone())
+		.unwrap_or_else(|| hex::encode(bucket.id))
+}
+
+pub struct NotificationWorker {
+	garage: Arc<Garage>,
+	client: HttpClient<HttpsConnector<HttpConnector>, Full<Bytes>>,
+	delivered: usize,
+	dropped: usize,
+	next_attempt: Option<u64>,
+}
+
+impl NotificationWorker {
+	pub fn new(garage: Arc<Garage>) -> Result<Self, Error> {
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()?
+			.https_or_http()
+			.enable_http1()
+			.build();
+		let client = HttpClient::builder(TokioExecutor::new()).build(connector);
+		Ok(Self {
+			garage,
+			client,
+			delivered: 0,
+			dropped: 0,
+			next_attempt: None,
+		})
+	}
+
+	async fn deliver(&self, msg: &OutboxMessage) -> Result<(), Error> {
+		match &msg.target {
+			NotificationTarget::Webhook { name } => {
+				let webhook = self
+					.garage
+					.config
+					.notifications
+					.webhooks
+					.get(name)
+					.ok_or_else(|| Error::Message(format!("webhook {} is not configured", name)))?;
+				let mut req = http::Request::post(&webhook.url)
+					.header(http::header::CONTENT_TYPE, "application/json");
+				if let Some(auth) = &webhook.authorization {
+					req = req.header(http::header::AUTHORIZATION, auth);
+				}
+				let req = req
+					.body(Full::new(Bytes::from(msg.payload.clone())))
+					.map_err(|e| Error::Message(format!("invalid webhook request: {}", e)))?;
+				let resp = tokio::time::timeout(WEBHOOK_TIMEOUT, self.client.request(req))
+					.await
+					.map_err(|_| Error::Message("webhook request timed out".into()))?
+					.map_err(|e| Error::Message(format!("webhook request failed: {}", e)))?;
+				if !resp.status().is_success() {
+					return Err(Error::Message(format!(
+						"webhook returned status {}",
+						resp.status()
+					)));
+				}
+				Ok(())
+			}
+			#[cfg(feature = "k2v")]
+			NotificationTarget::K2V {
+				bucket_id,
+				partition_key,
+			} => {
+				self.garage
+					.k2v
+					.rpc
+					.insert(
+						*bucket_id,
+						partition_key.clone(),
+						msg.id.clone(),
+						None,
+						DvvsValue::Value(msg.payload.as_bytes().to_vec()),
+					)
+					.await
+			}
+			#[cfg(not(feature = "k2v"))]
+			NotificationTarget::K2V { .. } => Err(Error::Message(
+				"K2V support is not enabled in this build".into(),
+			)),
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for NotificationWorker {
+	fn name(&self) -> String {
+		"Event notification delivery".to_string()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: self
+				.garage
+				.notifications
+				.outbox_len()
+				.ok()
+				.map(|x| x as u64),
+			freeform: vec![
+				format!("Messages delivered: {}", self.delivered),
+				format!("Messages dropped: {}", self.dropped),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let manager = &self.garage.notifications;
+		let (key, value) = match manager.outbox.first()? {
+			None => {
+				self.next_attempt = None;
+				return Ok(WorkerState::Idle);
+			}
+			Some(kv) => kv,
+		};
+
+		let when = u64::from_be_bytes(key[..8].try_into().unwrap());
+		if when > now_msec() {
+			self.next_attempt = Some(when);
+			return Ok(WorkerState::Idle);
+		}
+
+		let mut msg = match OutboxMessage::decode(&value) {
+			Some(msg) => msg,
+			None => {
+				error!("Invalid message in notification outbox, dropping it");
+				manager.outbox.remove(&key)?;
+				self.dropped += 1;
+				return Ok(WorkerState::Busy);
+			}
+		};
+
+		match self.deliver(&msg).await {
+			Ok(()) => {
+				manager.outbox.remove(&key)?;
+				self.delivered += 1;
+			}
+			Err(e) => {
+				msg.attempts += 1;
+				manager.outbox.remove(&key)?;
+				if msg.attempts >= MAX_DELIVERY_ATTEMPTS {
+					error!(
+						"Dropping event notification {} after {} failed delivery attempts: {}",
+						msg.id, msg.attempts, e
+					);
+					self.dropped += 1;
+				} else {
+					let delay = DELIVERY_RETRY_DELAY
+						* 2u32.pow(std::cmp::min(
+							msg.attempts,
+							DELIVERY_RETRY_MAX_BACKOFF_POWER,
+						));
+					warn!(
+						"Could not deliver event notification {} (attempt {}), retrying in {:?}: {}",
+						msg.id, msg.attempts, delay, e
+					);
+					manager.queue_message(now_msec() + delay.as_millis() as u64, &msg)?;
+				}
+			}
+		}
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		let notified = self.garage.notifications.notify.notified();
+		match self.next_attempt {
+			Some(when) => {
+				let delay = Duration::from_millis(when.saturating_sub(now_msec()));
+				select! {
+					_ = tokio::time::sleep(delay) => (),
+					_ = notified => (),
+				}
+			}
+			None => notified.await,
+		}
+		WorkerState::Busy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rule(events: &[&str], prefix: Option<&str>, suffix: Option<&str>) -> NotificationRule {
+		NotificationRule {
+			id: None,
+			kind: NotificationRuleKind::Queue,
+			destination_arn: "arn:garage:webhook::test".into(),
+			target: NotificationTarget::Webhook {
+				name: "test".into(),
+			},
+			events: events.iter().map(|e| e.to_string()).collect(),
+			prefix: prefix.map(String::from),
+			suffix: suffix.map(String::from),
+		}
+	}
+
+	#[test]
+	fn test_rule_matches() {
+		let put = ObjectEventName::Put.as_str();
+		let delete = ObjectEventName::Delete.as_str();
+
+		let r = rule(&["s3:ObjectCreated:*"], None, None);
+		assert!(r.matches(put, "a"));
+		assert!(!r.matches(delete, "a"));
+
+		let r = rule(
+			&[
+				"s3:ObjectRemoved:DeleteMarkerCreated",
+				"s3:ObjectCreated:Put",
+			],
+			Some("logs/"),
+			Some(".gz"),
+		);
+		assert!(r.matches(put, "logs/a.gz"));
+		assert!(!r.matches(put, "logs/a.txt"));
+		assert!(!r.matches(put, "data/a.gz"));
+		assert!(!r.matches(delete, "logs/a.gz"));
+		assert!(r.matches(ObjectEventName::DeleteMarkerCreated.as_str(), "logs/a.gz"));
+	}
+}
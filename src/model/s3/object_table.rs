@@ -3,12 +3,14 @@ use std::sync::Arc;
 
 use garage_db as db;
 
+use garage_util::config::DEFAULT_STORAGE_CLASS;
 use garage_util::data::*;
 
 use garage_table::crdt::*;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
+use crate::bucket_table::ObjectLockMode;
 use crate::index_counter::*;
 use crate::s3::mpu_table::*;
 use crate::s3::version_table::*;
@@ -172,6 +174,8 @@ mod v09 {
 }
 
 mod v010 {
+	use crate::bucket_table::ObjectLockMode;
+	use garage_util::crdt;
 	use garage_util::data::{Hash, Uuid};
 	use serde::{Deserialize, Serialize};
 
@@ -199,6 +203,29 @@ mod v010 {
 		pub timestamp: u64,
 		/// State of the version
 		pub state: ObjectVersionState,
+		/// Whether this version was created while versioning was enabled
+		/// on the bucket. Such versions are not removed when a newer version
+		/// is written. Other versions are the "null" version of the object.
+		#[serde(default)]
+		pub versioned: bool,
+		/// Tag set of the version. It is stored separately from the rest
+		/// of the version metadata, as it can be updated after the version is written.
+		#[serde(default)]
+		pub tags: crdt::Lww<ObjectTagSet>,
+		/// Object lock retention and legal hold of the version
+		#[serde(default)]
+		pub lock: ObjectVersionLock,
+		/// Replication status of the version, None if no replication
+		/// rule applies to it
+		#[serde(default)]
+		pub replication: crdt::Lww<Option<ReplicationStatus>>,
+		/// Whether the version can be read by anonymous users,
+		/// as set by the public-read canned ACL
+		#[serde(default)]
+		pub public_read: crdt::Lww<bool>,
+		/// Storage class of the version (None for the default storage class)
+		#[serde(default)]
+		pub storage_class: crdt::Lww<Option<String>>,
 	}
 
 	/// State of an object version
@@ -210,6 +237,13 @@ mod v010 {
 			multipart: bool,
 			/// Checksum algorithm to use
 			checksum_algorithm: Option<ChecksumAlgorithm>,
+			/// Type of checksum, for multipart uploads with a checksum algorithm
+			#[serde(default)]
+			checksum_type: Option<ChecksumType>,
+			/// Indicates whether this is a temporary marker written while
+			/// committing a conditional write, not an actual upload
+			#[serde(default)]
+			commit_marker: bool,
 			/// Encryption params + headers to be included in the final object
 			encryption: ObjectVersionEncryption,
 		},
@@ -262,6 +296,30 @@ mod v010 {
 			/// Plain-text headers
 			inner: ObjectVersionMetaInner,
 		},
+		SseS3 {
+			/// Encrypted serialized ObjectVersionInner struct, using the
+			/// object's data key
+			#[serde(with = "serde_bytes")]
+			inner: Vec<u8>,
+			/// Whether data blocks are compressed in addition to being encrypted
+			compressed: bool,
+			/// Data key of the object, encrypted using the node's SSE master key
+			#[serde(with = "serde_bytes")]
+			wrapped_key: Vec<u8>,
+		},
+		SseKms {
+			/// Encrypted serialized ObjectVersionInner struct, using the
+			/// object's data key
+			#[serde(with = "serde_bytes")]
+			inner: Vec<u8>,
+			/// Whether data blocks are compressed in addition to being encrypted
+			compressed: bool,
+			/// ID of the key provider's master key that wraps the data key
+			key_id: String,
+			/// Data key of the object, as wrapped by the key provider
+			#[serde(with = "serde_bytes")]
+			wrapped_key: Vec<u8>,
+		},
 	}
 
 	/// Vector of headers, as tuples of the format (header name, header value)
@@ -273,12 +331,58 @@ mod v010 {
 
 	pub type HeaderList = Vec<(String, String)>;
 
+	/// Vector of object tags, as tuples of the format (tag key, tag value)
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Serialize, Deserialize)]
+	pub struct ObjectTagSet(pub Vec<(String, String)>);
+
+	/// Replication status of an object version, as returned
+	/// in the x-amz-replication-status header
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ReplicationStatus {
+		/// The version is waiting to be sent to the remote endpoint
+		Pending,
+		/// The version was written on the remote endpoint
+		Completed,
+		/// The version could not be written on the remote endpoint
+		Failed,
+		/// The version was written by replication from another cluster
+		Replica,
+	}
+
+	/// Object lock information of an object version
+	#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+	pub struct ObjectVersionLock {
+		/// Retention mode and date until which the version cannot be deleted
+		pub retention: crdt::Lww<Option<ObjectRetention>>,
+		/// Whether a legal hold prevents the version from being deleted
+		pub legal_hold: crdt::Lww<bool>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub struct ObjectRetention {
+		pub mode: ObjectLockMode,
+		/// Timestamp (in msec since epoch) until which the version is retained
+		pub retain_until: u64,
+	}
+
 	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 	pub enum ChecksumAlgorithm {
 		Crc32,
 		Crc32c,
 		Sha1,
 		Sha256,
+		Crc64Nvme,
+	}
+
+	/// Type of the checksum of an object uploaded with a multipart upload
+	/// (x-amz-checksum-type)
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ChecksumType {
+		/// Checksum of the concatenation of the checksums of the parts
+		Composite,
+		/// Checksum of the full object, combined from the checksums of the parts
+		/// (CRC algorithms only)
+		FullObject,
 	}
 
 	/// Checksum value for x-amz-checksum-algorithm
@@ -288,6 +392,7 @@ mod v010 {
 		Crc32c(#[serde(with = "serde_bytes")] [u8; 4]),
 		Sha1(#[serde(with = "serde_bytes")] [u8; 20]),
 		Sha256(#[serde(with = "serde_bytes")] [u8; 32]),
+		Crc64Nvme(#[serde(with = "serde_bytes")] [u8; 8]),
 	}
 
 	impl garage_util::migrate::Migrate for Object {
@@ -313,6 +418,8 @@ mod v010 {
 					ObjectVersionState::Uploading {
 						multipart,
 						checksum_algorithm: None,
+						checksum_type: None,
+						commit_marker: false,
 						encryption: migrate_headers(headers),
 					}
 				}
@@ -321,6 +428,12 @@ mod v010 {
 				}
 				v09::ObjectVersionState::Aborted => ObjectVersionState::Aborted,
 			},
+			versioned: false,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
 		}
 	}
 
@@ -407,6 +520,12 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Get the current version of the object, i.e. the last complete version
+	/// (which can be a delete marker)
+	pub fn current_version(&self) -> Option<&ObjectVersion> {
+		self.versions.iter().rev().find(|v| v.is_complete())
+	}
 }
 
 impl Crdt for ObjectVersionState {
@@ -434,6 +553,40 @@ impl AutoCrdt for ObjectVersionData {
 	const WARN_IF_DIFFERENT: bool = true;
 }
 
+impl AutoCrdt for ChecksumValue {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl AutoCrdt for ObjectTagSet {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl Crdt for ObjectVersionLock {
+	fn merge(&mut self, other: &Self) {
+		self.retention.merge(&other.retention);
+		self.legal_hold.merge(&other.legal_hold);
+	}
+}
+
+impl ObjectVersionLock {
+	/// Returns the retention of the version if it is still in effect at time `now`
+	pub fn active_retention(&self, now: u64) -> Option<&ObjectRetention> {
+		self.retention
+			.get()
+			.as_ref()
+			.filter(|r| r.retain_until > now)
+	}
+
+	/// Is the version protected from deletion at time `now`.
+	/// Governance retention does not protect the version if `bypass_governance` is set.
+	pub fn is_locked(&self, now: u64, bypass_governance: bool) -> bool {
+		*self.legal_hold.get()
+			|| self
+				.active_retention(now)
+				.is_some_and(|r| r.mode == ObjectLockMode::Compliance || !bypass_governance)
+	}
+}
+
 impl ObjectVersion {
 	fn cmp_key(&self) -> (u64, Uuid) {
 		(self.timestamp, self.uuid)
@@ -453,6 +606,17 @@ impl ObjectVersion {
 		}
 	}
 
+	/// Is the object version a marker for a conditional write being committed
+	pub fn is_commit_marker(&self) -> bool {
+		matches!(
+			self.state,
+			ObjectVersionState::Uploading {
+				commit_marker: true,
+				..
+			}
+		)
+	}
+
 	/// Is the object version completely received
 	pub fn is_complete(&self) -> bool {
 		matches!(self.state, ObjectVersionState::Complete(_))
@@ -466,6 +630,14 @@ impl ObjectVersion {
 			_ => false,
 		}
 	}
+
+	/// Name of the storage class of the object version
+	pub fn storage_class_name(&self) -> &str {
+		self.storage_class
+			.get()
+			.as_deref()
+			.unwrap_or(DEFAULT_STORAGE_CLASS)
+	}
 }
 
 impl Entry<Uuid, String> for Object {
@@ -477,6 +649,7 @@ impl Entry<Uuid, String> for Object {
 	}
 	fn is_tombstone(&self) -> bool {
 		self.versions.len() == 1
+			&& !self.versions[0].versioned
 			&& self.versions[0].state
 				== ObjectVersionState::Complete(ObjectVersionData::DeleteMarker)
 	}
@@ -489,6 +662,7 @@ impl ChecksumValue {
 			ChecksumValue::Crc32c(_) => ChecksumAlgorithm::Crc32c,
 			ChecksumValue::Sha1(_) => ChecksumAlgorithm::Sha1,
 			ChecksumValue::Sha256(_) => ChecksumAlgorithm::Sha256,
+			ChecksumValue::Crc64Nvme(_) => ChecksumAlgorithm::Crc64Nvme,
 		}
 	}
 }
@@ -503,6 +677,12 @@ impl Crdt for Object {
 			{
 				Ok(i) => {
 					self.versions[i].state.merge(&other_v.state);
+					self.versions[i].versioned |= other_v.versioned;
+					self.versions[i].tags.merge(&other_v.tags);
+					self.versions[i].lock.merge(&other_v.lock);
+					self.versions[i].replication.merge(&other_v.replication);
+					self.versions[i].public_read.merge(&other_v.public_read);
+					self.versions[i].storage_class.merge(&other_v.storage_class);
 				}
 				Err(i) => {
 					self.versions.insert(i, other_v.clone());
@@ -511,7 +691,9 @@ impl Crdt for Object {
 		}
 
 		// Remove versions which are obsolete, i.e. those that come
-		// before the last version which .is_complete().
+		// before the last version which .is_complete(), except complete
+		// versions that were written with versioning enabled and the
+		// last complete "null" version.
 		let last_complete = self
 			.versions
 			.iter()
@@ -521,7 +703,23 @@ impl Crdt for Object {
 			.map(|(vi, _)| vi);
 
 		if let Some(last_vi) = last_complete {
-			self.versions = self.versions.drain(last_vi..).collect::<Vec<_>>();
+			let last_null = self
+				.versions
+				.iter()
+				.enumerate()
+				.rev()
+				.find(|(_, v)| v.is_complete() && !v.versioned)
+				.map(|(vi, _)| vi);
+
+			self.versions = self
+				.versions
+				.drain(..)
+				.enumerate()
+				.filter(|(vi, v)| {
+					*vi >= last_vi || (v.is_complete() && (v.versioned || Some(*vi) == last_null))
+				})
+				.map(|(_, v)| v)
+				.collect::<Vec<_>>();
 		}
 	}
 }
@@ -534,7 +732,7 @@ pub struct ObjectTable {
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum ObjectFilter {
-	/// Is the object version available (received and not a tombstone)
+	/// Is the current object version available (received and not a tombstone)
 	IsData,
 	/// Is the object version currently being uploaded
 	///
@@ -542,6 +740,9 @@ pub enum ObjectFilter {
 	/// matches only non-multipart uploads if check_multipart is Some(false)
 	/// matches both if check_multipart is None
 	IsUploading { check_multipart: Option<bool> },
+	/// Does the object have any stored version, current or noncurrent
+	/// (including delete markers written while versioning was enabled)
+	HasVersions,
 }
 
 impl TableSchema for ObjectTable {
@@ -640,11 +841,15 @@ impl TableSchema for ObjectTable {
 
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		match filter {
-			ObjectFilter::IsData => entry.versions.iter().any(|v| v.is_data()),
+			ObjectFilter::IsData => entry.current_version().is_some_and(|v| v.is_data()),
 			ObjectFilter::IsUploading { check_multipart } => entry
 				.versions
 				.iter()
 				.any(|v| v.is_uploading(*check_multipart)),
+			ObjectFilter::HasVersions => entry
+				.versions
+				.iter()
+				.any(|v| v.is_data() || (v.is_complete() && v.versioned)),
 		}
 	}
 }
@@ -666,7 +871,7 @@ impl CountedItem for Object {
 
 	fn counts(&self) -> Vec<(&'static str, i64)> {
 		let versions = self.versions();
-		let n_objects = if versions.iter().any(|v| v.is_data()) {
+		let n_objects = if self.current_version().is_some_and(|v| v.is_data()) {
 			1
 		} else {
 			0
@@ -688,3 +893,62 @@ impl CountedItem for Object {
 		]
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(uuid: u8, timestamp: u64, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: Uuid::from([uuid; 32]),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned,
+			tags: Default::default(),
+			lock: Default::default(),
+			replication: Default::default(),
+			public_read: Default::default(),
+			storage_class: Default::default(),
+		}
+	}
+
+	fn uuids(object: &Object) -> Vec<u8> {
+		object
+			.versions()
+			.iter()
+			.map(|v| v.uuid.as_slice()[0])
+			.collect()
+	}
+
+	#[test]
+	fn test_object_merge_versioned() {
+		let bucket_id = Uuid::from([0x42; 32]);
+		let mut obj = Object::new(bucket_id, "a".into(), vec![version(1, 1, false)]);
+
+		// A versioned version keeps the previous null version
+		obj.merge(&Object::new(
+			bucket_id,
+			"a".into(),
+			vec![version(2, 2, true)],
+		));
+		assert_eq!(uuids(&obj), vec![1, 2]);
+
+		// A new null version replaces the previous null version only
+		obj.merge(&Object::new(
+			bucket_id,
+			"a".into(),
+			vec![version(3, 3, false)],
+		));
+		assert_eq!(uuids(&obj), vec![2, 3]);
+		assert_eq!(obj.current_version().unwrap().uuid, Uuid::from([3; 32]));
+
+		// Without versioning, only the last complete version is kept
+		let mut obj = Object::new(bucket_id, "a".into(), vec![version(1, 1, false)]);
+		obj.merge(&Object::new(
+			bucket_id,
+			"a".into(),
+			vec![version(2, 2, false)],
+		));
+		assert_eq!(uuids(&obj), vec![2]);
+	}
+}
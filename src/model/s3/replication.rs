@@ -0,0 +1,136 @@
+//! Queue of bucket replication tasks.
+//!
+//! Object versions that must be copied to a remote S3 endpoint are
+//! recorded in a queue that is local to the node that handled the request
+//! that created them. The queue is processed by the replication worker of
+//! the S3 API, which is also responsible for updating the replication status
+//! of the versions.
+
+use std::convert::TryInto;
+
+use tokio::sync::Notify;
+
+use garage_db as db;
+
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::migrate::Migrate;
+use garage_util::time::*;
+
+use crate::bucket_table::*;
+use crate::s3::object_table::*;
+
+mod v011 {
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// A task waiting in the replication queue
+	#[derive(Clone, Debug, Serialize, Deserialize)]
+	pub enum ReplicationTask {
+		/// Copy a version of an object (or a delete marker) to the remote endpoint
+		Version {
+			bucket_id: Uuid,
+			key: String,
+			version_uuid: Uuid,
+			/// Number of failed attempts
+			attempts: u32,
+		},
+		/// Queue the replication of the existing objects of a bucket,
+		/// starting after key `start_after`
+		Backfill {
+			bucket_id: Uuid,
+			start_after: Option<String>,
+		},
+	}
+
+	impl garage_util::migrate::InitialFormat for ReplicationTask {
+		const VERSION_MARKER: &'static [u8] = b"G011rpt";
+	}
+}
+
+pub use v011::*;
+
+/// A task read from the replication queue
+pub struct QueuedTask {
+	/// Key of the task in the queue
+	pub key: Vec<u8>,
+	/// Time at which the task must be processed
+	pub when: u64,
+	/// The task, None if it could not be decoded
+	pub task: Option<ReplicationTask>,
+}
+
+pub struct ReplicationQueue {
+	/// Tasks waiting to be processed. The key in this tree is:
+	///     concat(time of next attempt (8 bytes), random id (32 bytes))
+	queue: db::Tree,
+	notify: Notify,
+}
+
+impl ReplicationQueue {
+	pub(crate) fn new(db: &db::Db) -> Self {
+		let queue = db
+			.open_tree("replication_queue")
+			.expect("Unable to open replication_queue tree");
+		Self {
+			queue,
+			notify: Notify::new(),
+		}
+	}
+
+	/// Get number of tasks waiting to be processed
+	pub fn queue_len(&self) -> Result<usize, Error> {
+		Ok(self.queue.len()?)
+	}
+
+	/// Queue a task to be processed at time `when` (in msec since epoch)
+	pub fn push(&self, when: u64, task: &ReplicationTask) -> Result<(), Error> {
+		let key = [&u64::to_be_bytes(when)[..], gen_uuid().as_slice()].concat();
+		self.queue.insert(key, task.encode()?)?;
+		if when <= now_msec() {
+			self.notify.notify_one();
+		}
+		Ok(())
+	}
+
+	/// Get the task with the earliest processing time
+	pub fn first(&self) -> Result<Option<QueuedTask>, Error> {
+		Ok(self.queue.first()?.map(|(key, value)| QueuedTask {
+			when: u64::from_be_bytes(key[..8].try_into().unwrap()),
+			task: ReplicationTask::decode(&value),
+			key,
+		}))
+	}
+
+	/// Remove a task from the queue, using the key of a `QueuedTask`
+	pub fn remove(&self, key: &[u8]) -> Result<(), Error> {
+		self.queue.remove(key)?;
+		Ok(())
+	}
+
+	/// Wait until a new task is queued for immediate processing
+	pub async fn wait(&self) {
+		self.notify.notified().await
+	}
+}
+
+/// Returns the replication status of a new version of object `key`
+/// in a bucket with parameters `bucket_params`: Pending if it must be
+/// copied to a remote endpoint, None otherwise
+pub fn new_version_replication_status(
+	bucket_params: &BucketParams,
+	key: &str,
+	tags: &ObjectTagSet,
+	delete_marker: bool,
+) -> Option<ReplicationStatus> {
+	let rule = bucket_params
+		.replication_config
+		.get()
+		.as_ref()?
+		.matching_rule(key, &tags.0)?;
+	if delete_marker && !rule.delete_marker_replication {
+		None
+	} else {
+		Some(ReplicationStatus::Pending)
+	}
+}
@@ -67,6 +67,7 @@ pub(crate) mod v09 {
 	use serde::{Deserialize, Serialize};
 
 	use super::v08;
+	use crate::s3::object_table::ChecksumValue;
 
 	pub use v08::{VersionBlock, VersionBlockKey};
 
@@ -83,6 +84,10 @@ pub(crate) mod v09 {
 		pub deleted: crdt::Bool,
 		/// list of blocks of data composing the version
 		pub blocks: crdt::Map<VersionBlockKey, VersionBlock>,
+		/// Checksum of each part in case of a completed multipart upload
+		/// with a checksum algorithm, empty otherwise
+		#[serde(default)]
+		pub parts_checksums: crdt::Map<u64, ChecksumValue>,
 
 		// Back link to owner of this version (either an object or a multipart
 		// upload), used to find whether it has been deleted and this version
@@ -113,6 +118,7 @@ pub(crate) mod v09 {
 				uuid: old.uuid,
 				deleted: old.deleted,
 				blocks: old.blocks,
+				parts_checksums: crdt::Map::new(),
 				backlink: VersionBacklink::Object {
 					bucket_id: old.bucket_id,
 					key: old.key,
@@ -130,6 +136,7 @@ impl Version {
 			uuid,
 			deleted: deleted.into(),
 			blocks: crdt::Map::new(),
+			parts_checksums: crdt::Map::new(),
 			backlink,
 		}
 	}
@@ -188,8 +195,10 @@ impl Crdt for Version {
 
 		if self.deleted.get() {
 			self.blocks.clear();
+			self.parts_checksums.clear();
 		} else {
 			self.blocks.merge(&other.blocks);
+			self.parts_checksums.merge(&other.parts_checksums);
 		}
 	}
 }
@@ -219,6 +228,7 @@ impl TableSchema for VersionTable {
 					block: vb.hash,
 					version: old_v.uuid,
 					deleted: true.into(),
+					storage_class: Default::default(),
 				});
 				for block_ref in deleted_block_refs {
 					let res = self.block_ref_table.queue_insert(tx, &block_ref);
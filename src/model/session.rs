@@ -0,0 +1,234 @@
+//! Temporary credentials derived from an access key.
+//!
+//! Temporary credentials are not stored anywhere: everything needed to check
+//! them is encoded in their session token, which is authenticated with a key
+//! derived from the RPC secret of the cluster. Their secret key is derived
+//! from the session token with the same key, so that it can be recomputed by
+//! any node when checking the signature of a request.
+
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use garage_util::crdt;
+use garage_util::data::*;
+use garage_util::encode::{nonversioned_decode, nonversioned_encode};
+use garage_util::time::now_msec;
+
+use crate::key_table::Key;
+use crate::permission::BucketKeyPerm;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Maximum validity of temporary credentials, in seconds
+pub const MAX_SESSION_DURATION: u64 = 12 * 3600;
+
+/// What temporary credentials give access to
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct SessionClaims {
+	/// Access key ID of the temporary credentials
+	pub access_key_id: String,
+	/// ID of the access key the credentials were derived from
+	pub parent_key_id: String,
+	/// Expiration time of the credentials, in msec since the Unix epoch
+	pub expiration: u64,
+	/// Permissions given on buckets. They are further restricted
+	/// to the permissions that the parent key has when a request is made.
+	pub buckets: Vec<(Uuid, BucketKeyPerm)>,
+	/// If set, only objects whose key starts with this prefix can be accessed
+	pub prefix: Option<String>,
+}
+
+/// Temporary credentials, as given to clients
+pub struct SessionCredentials {
+	pub access_key_id: String,
+	pub secret_access_key: String,
+	pub session_token: String,
+	pub claims: SessionClaims,
+}
+
+impl SessionClaims {
+	/// Create new claims for credentials valid for `duration` seconds,
+	/// generating a random access key ID
+	pub fn new(
+		parent_key_id: &str,
+		duration: u64,
+		buckets: Vec<(Uuid, BucketKeyPerm)>,
+		prefix: Option<String>,
+	) -> Self {
+		Self {
+			access_key_id: format!("GS{}", hex::encode(&rand::random::<[u8; 12]>()[..])),
+			parent_key_id: parent_key_id.to_string(),
+			expiration: now_msec() + duration * 1000,
+			buckets,
+			prefix,
+		}
+	}
+
+	/// Build the credentials that give access to what is described by these claims
+	pub fn into_credentials(self, signing_key: &Hash) -> SessionCredentials {
+		let encoded = nonversioned_encode(&self).expect("session claims can always be serialized");
+		let mac = compute_mac(signing_key, b"token", &encoded);
+
+		let mut token = encoded;
+		token.extend_from_slice(&mac);
+
+		SessionCredentials {
+			access_key_id: self.access_key_id.clone(),
+			secret_access_key: hex::encode(compute_mac(signing_key, b"secret", &token)),
+			session_token: BASE64_URL_SAFE_NO_PAD.encode(&token),
+			claims: self,
+		}
+	}
+
+	/// Decode a session token, returning None if it is invalid or was not
+	/// issued by this cluster. The expiration time is not checked.
+	/// The secret key of the credentials is returned with the claims.
+	pub fn decode(session_token: &str, signing_key: &Hash) -> Option<(Self, String)> {
+		let token = BASE64_URL_SAFE_NO_PAD.decode(session_token).ok()?;
+		if token.len() <= 32 {
+			return None;
+		}
+		let (encoded, mac) = token.split_at(token.len() - 32);
+
+		let mut expected = HmacSha256::new_from_slice(signing_key.as_slice()).ok()?;
+		expected.update(b"token");
+		expected.update(encoded);
+		expected.verify_slice(mac).ok()?;
+
+		let claims = nonversioned_decode::<Self>(encoded).ok()?;
+		let secret_access_key = hex::encode(compute_mac(signing_key, b"secret", &token));
+		Some((claims, secret_access_key))
+	}
+
+	/// Returns true if the credentials are no longer valid
+	pub fn is_expired(&self) -> bool {
+		now_msec() >= self.expiration
+	}
+
+	/// Build the key used to authorize requests made with the credentials:
+	/// a copy of the parent key whose bucket permissions are restricted to
+	/// those given by the claims, and which cannot create buckets
+	pub fn restrict_key(&self, parent: &Key) -> Key {
+		let mut key = parent.clone();
+		if let Some(params) = key.params_mut() {
+			let mut authorized_buckets = crdt::Map::new();
+			for (bucket_id, perm) in self.buckets.iter() {
+				let parent_perm = parent.bucket_permissions(bucket_id);
+				authorized_buckets.put(
+					*bucket_id,
+					BucketKeyPerm {
+						timestamp: parent_perm.timestamp,
+						allow_read: perm.allow_read && parent_perm.allow_read,
+						allow_write: perm.allow_write && parent_perm.allow_write,
+						allow_owner: perm.allow_owner && parent_perm.allow_owner,
+					},
+				);
+			}
+			params.authorized_buckets = authorized_buckets;
+			params.allow_create_bucket = crdt::Lww::new(false);
+		}
+		key
+	}
+
+	/// Returns true if the credentials can access the object with the given key
+	pub fn allow_object_key(&self, object_key: &str) -> bool {
+		match &self.prefix {
+			None => true,
+			Some(prefix) => object_key.starts_with(prefix.as_str()),
+		}
+	}
+}
+
+/// Derive the key authenticating session tokens from the RPC secret of the cluster
+pub fn session_token_key(rpc_secret: &[u8]) -> Hash {
+	blake2sum(&[b"garage session token key".as_slice(), rpc_secret].concat())
+}
+
+fn compute_mac(signing_key: &Hash, domain: &[u8], data: &[u8]) -> [u8; 32] {
+	let mut mac =
+		HmacSha256::new_from_slice(signing_key.as_slice()).expect("HMAC can take keys of any size");
+	mac.update(domain);
+	mac.update(data);
+	mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_session_token() {
+		let signing_key = blake2sum(b"cluster secret");
+		let bucket_id = gen_uuid();
+		let claims = SessionClaims::new(
+			"GK31c2f218a2e44f485b94239e",
+			3600,
+			vec![(
+				bucket_id,
+				BucketKeyPerm {
+					timestamp: 0,
+					allow_read: true,
+					allow_write: false,
+					allow_owner: false,
+				},
+			)],
+			Some("uploads/".into()),
+		);
+		let creds = claims.clone().into_credentials(&signing_key);
+		assert!(creds.access_key_id.starts_with("GS"));
+
+		let (decoded, secret) = SessionClaims::decode(&creds.session_token, &signing_key).unwrap();
+		assert_eq!(decoded, claims);
+		assert_eq!(secret, creds.secret_access_key);
+		assert!(!decoded.is_expired());
+		assert!(decoded.allow_object_key("uploads/file"));
+		assert!(!decoded.allow_object_key("private/file"));
+
+		// tokens signed with another key are rejected
+		let other_key = blake2sum(b"other secret");
+		assert!(SessionClaims::decode(&creds.session_token, &other_key).is_none());
+
+		// tampered tokens are rejected
+		let mut token = BASE64_URL_SAFE_NO_PAD.decode(&creds.session_token).unwrap();
+		token[4] ^= 1;
+		let tampered = BASE64_URL_SAFE_NO_PAD.encode(&token);
+		assert!(SessionClaims::decode(&tampered, &signing_key).is_none());
+		assert!(SessionClaims::decode("not a token", &signing_key).is_none());
+	}
+
+	#[test]
+	fn test_restrict_key() {
+		let bucket_a = gen_uuid();
+		let bucket_b = gen_uuid();
+		let mut parent = Key::new("parent");
+		let params = parent.params_mut().unwrap();
+		params.allow_create_bucket.update(true);
+		params.authorized_buckets.put(
+			bucket_a,
+			BucketKeyPerm {
+				timestamp: 1,
+				allow_read: true,
+				allow_write: true,
+				allow_owner: false,
+			},
+		);
+		params
+			.authorized_buckets
+			.put(bucket_b, BucketKeyPerm::ALL_PERMISSIONS);
+
+		let claims = SessionClaims::new(
+			&parent.key_id,
+			60,
+			vec![(bucket_a, BucketKeyPerm::ALL_PERMISSIONS)],
+			None,
+		);
+		let key = claims.restrict_key(&parent);
+		assert_eq!(key.key_id, parent.key_id);
+		assert!(key.allow_read(&bucket_a) && key.allow_write(&bucket_a));
+		assert!(!key.allow_owner(&bucket_a));
+		assert!(!key.allow_read(&bucket_b));
+		assert!(!key.params().unwrap().allow_create_bucket.get());
+	}
+}
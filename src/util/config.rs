@@ -1,8 +1,10 @@
 //! Contains type and functions related to Garage configuration file
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
+use ipnet::IpNet;
 use serde::{de, Deserialize};
 
 use crate::error::Error;
@@ -84,6 +86,11 @@ pub struct Config {
 	pub rpc_secret: Option<String>,
 	/// Optional file where RPC secret key is read from
 	pub rpc_secret_file: Option<PathBuf>,
+	/// Master key used to wrap the data keys of objects encrypted
+	/// with SSE-S3: 32 bytes hex encoded
+	pub sse_master_key: Option<String>,
+	/// Optional file where the SSE master key is read from
+	pub sse_master_key_file: Option<PathBuf>,
 	/// Address to bind for RPC
 	pub rpc_bind_addr: SocketAddr,
 	/// Bind outgoing sockets to rpc_bind_addr's IP address as well
@@ -126,6 +133,22 @@ pub struct Config {
 	/// Configuration for S3 api
 	pub s3_api: S3ApiConfig,
 
+	/// Configuration of the key provider for SSE-KMS encryption
+	pub sse_kms: Option<SseKmsConfig>,
+
+	/// Targets to which bucket event notifications can be delivered
+	#[serde(default)]
+	pub notifications: NotificationsConfig,
+
+	/// Remote S3 endpoints to which buckets can be replicated
+	#[serde(default)]
+	pub bucket_replication: BucketReplicationConfig,
+
+	/// Public access block settings applied to all buckets of the cluster,
+	/// in addition to the settings of each bucket
+	#[serde(default)]
+	pub public_access_block: PublicAccessBlockConfig,
+
 	/// Configuration for K2V api
 	pub k2v_api: Option<K2VApiConfig>,
 
@@ -155,6 +178,26 @@ pub struct DataDir {
 	/// Whether this is a legacy read-only path (capacity should be None)
 	#[serde(default)]
 	pub read_only: bool,
+	/// Storage class of the objects whose data blocks are stored in this
+	/// directory (the default storage class if not specified)
+	#[serde(default)]
+	pub storage_class: Option<String>,
+}
+
+/// Storage class of objects for which none was requested
+pub const DEFAULT_STORAGE_CLASS: &str = "STANDARD";
+
+impl DataDirEnum {
+	/// Storage classes that have data directories on this node,
+	/// always including the default storage class
+	pub fn storage_classes(&self) -> BTreeSet<String> {
+		let mut classes = BTreeSet::new();
+		classes.insert(DEFAULT_STORAGE_CLASS.to_string());
+		if let DataDirEnum::Multiple(dirs) = self {
+			classes.extend(dirs.iter().filter_map(|d| d.storage_class.clone()));
+		}
+		classes
+	}
 }
 
 /// Configuration for S3 api
@@ -167,6 +210,76 @@ pub struct S3ApiConfig {
 	/// Suffix to remove from domain name to find bucket. If None,
 	/// vhost-style S3 request are disabled
 	pub root_domain: Option<String>,
+	/// Addresses of the reverse proxies whose X-Forwarded-Proto header
+	/// is trusted to tell whether requests were made over TLS
+	#[serde(deserialize_with = "deserialize_ip_nets", default)]
+	pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Configuration of the key provider for SSE-KMS encryption
+#[derive(Deserialize, Debug, Clone)]
+pub struct SseKmsConfig {
+	/// File containing the named master keys of the local keyring
+	pub keyring_file: PathBuf,
+}
+
+/// Targets to which bucket event notifications can be delivered
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct NotificationsConfig {
+	/// HTTP endpoints, referenced by name in bucket notification
+	/// configurations as `arn:garage:webhook::<name>`
+	#[serde(default)]
+	pub webhooks: std::collections::HashMap<String, WebhookConfig>,
+}
+
+/// HTTP endpoint to which bucket event notifications are POSTed
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+	/// URL of the endpoint (http or https)
+	pub url: String,
+	/// Value of the Authorization header sent with each notification
+	pub authorization: Option<String>,
+}
+
+/// Remote S3 endpoints to which buckets can be replicated
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct BucketReplicationConfig {
+	/// S3 endpoints, referenced by name in bucket replication
+	/// configurations as `arn:garage:s3::<name>:<bucket>`
+	#[serde(default)]
+	pub remotes: std::collections::HashMap<String, ReplicationRemoteConfig>,
+}
+
+/// Public access block settings, with the same meaning as
+/// the PublicAccessBlockConfiguration of S3
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct PublicAccessBlockConfig {
+	/// Reject requests that make a bucket or an object public with an ACL
+	#[serde(default)]
+	pub block_public_acls: bool,
+	/// Ignore the ACLs that make buckets and objects public
+	#[serde(default)]
+	pub ignore_public_acls: bool,
+	/// Reject bucket policies that allow anonymous access
+	#[serde(default)]
+	pub block_public_policy: bool,
+	/// Reject all anonymous requests, including website requests
+	#[serde(default)]
+	pub restrict_public_buckets: bool,
+}
+
+/// Remote S3 endpoint to which objects are replicated
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReplicationRemoteConfig {
+	/// URL of the endpoint (http or https), requests are made path-style
+	pub endpoint: String,
+	/// Region used to sign requests
+	#[serde(default = "default_replication_region")]
+	pub region: String,
+	/// Access key ID used to write to the destination buckets
+	pub access_key_id: String,
+	/// Secret access key used to write to the destination buckets
+	pub secret_access_key: String,
 }
 
 /// Configuration for K2V api
@@ -281,6 +394,10 @@ fn default_compression() -> Option<i32> {
 	Some(1)
 }
 
+fn default_replication_region() -> String {
+	"us-east-1".into()
+}
+
 fn deserialize_compression<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
 where
 	D: de::Deserializer<'de>,
@@ -373,6 +490,20 @@ where
 	deserializer.deserialize_any(CapacityVisitor)
 }
 
+fn deserialize_ip_nets<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	Vec::<String>::deserialize(deserializer)?
+		.iter()
+		.map(|v| {
+			v.parse::<IpNet>()
+				.or_else(|_| v.parse::<IpAddr>().map(IpNet::from))
+				.map_err(|_| de::Error::custom(format!("Invalid IP address or range: '{}'", v)))
+		})
+		.collect()
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::error::Error;
@@ -395,11 +526,19 @@ mod tests {
 			[s3_api]
 			s3_region = "garage"
 			api_bind_addr = "[::]:3900"
+			trusted_proxies = ["10.0.0.1", "fd00::/8"]
 			"#
 		)?;
 
 		let config = super::read_config(path2.to_path_buf())?;
 		assert_eq!("foo", config.rpc_secret.unwrap());
+		assert_eq!(
+			config.s3_api.trusted_proxies,
+			vec![
+				"10.0.0.1/32".parse::<ipnet::IpNet>().unwrap(),
+				"fd00::/8".parse().unwrap()
+			]
+		);
 		drop(path2);
 		drop(file2);
 
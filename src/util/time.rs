@@ -1,5 +1,6 @@
 //! Module containing helper functions to manipulate time
 use chrono::{SecondsFormat, TimeZone, Utc};
+use std::convert::TryFrom;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Returns milliseconds since UNIX Epoch
@@ -28,3 +29,11 @@ pub fn msec_to_rfc3339(msecs: u64) -> String {
 	let timestamp = Utc.timestamp_opt(secs, nanos).unwrap();
 	timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
 }
+
+/// Parse a RFC3339 timestamp, such as "2021-01-01T12:30:00Z", into
+/// milliseconds since UNIX Epoch. Returns None for invalid timestamps
+/// and timestamps before the UNIX Epoch.
+pub fn rfc3339_to_msec(timestamp: &str) -> Option<u64> {
+	let timestamp = chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+	u64::try_from(timestamp.timestamp_millis()).ok()
+}
@@ -20,9 +20,11 @@ use opentelemetry::{
 
 use crate::error::*;
 
+use garage_api_common::access_log::AccessLog;
 use garage_api_common::cors::{
 	add_cors_headers, find_matching_cors_rule, handle_options_for_bucket,
 };
+use garage_api_common::encoding::uri_encode;
 use garage_api_common::generic_server::{server_loop, UnixListenerOn};
 use garage_api_common::helpers::*;
 use garage_api_s3::error::{
@@ -31,6 +33,7 @@ use garage_api_s3::error::{
 use garage_api_s3::get::{handle_get_without_ctx, handle_head_without_ctx};
 use garage_api_s3::website::X_AMZ_WEBSITE_REDIRECT_LOCATION;
 
+use garage_model::bucket_table::RoutingRule;
 use garage_model::garage::Garage;
 
 use garage_table::*;
@@ -164,11 +167,14 @@ impl WebServer {
 			metrics_tags.push(KeyValue::new("host", host_header.clone()));
 		}
 
+		let access_log = AccessLog::new(&req, &addr);
+		access_log.set_operation(&format!("WEBSITE.{}.OBJECT", req.method()));
+
 		let req = req.map(|_| ());
 
 		// The actual handler
 		let res = self
-			.serve_file(&req)
+			.serve_file(&req, &access_log)
 			.with_context(Context::current_with_span(span))
 			.record_duration(&self.metrics.request_duration, &metrics_tags[..])
 			.await;
@@ -186,8 +192,11 @@ impl WebServer {
 					host_header,
 					req.uri()
 				);
-				Ok(res
-					.map(|body| BoxBody::new(http_body_util::BodyExt::map_err(body, Error::from))))
+				Ok(access_log.finish(
+					res.map(|body| {
+						BoxBody::new(http_body_util::BodyExt::map_err(body, Error::from))
+					}),
+				))
 			}
 			Err(error) => {
 				info!(
@@ -203,7 +212,10 @@ impl WebServer {
 					error.http_status_code().to_string(),
 				));
 				self.metrics.error_counter.add(1, &metrics_tags);
-				Ok(error_to_res(error))
+				if let Error::ApiError(e) = &error {
+					access_log.set_error_code(e.aws_code());
+				}
+				Ok(access_log.finish(error_to_res(error)))
 			}
 		}
 	}
@@ -214,7 +226,7 @@ impl WebServer {
 			.object_table
 			.get(&bucket_id, &key.to_string())
 			.await?
-			.map(|object| object.versions().iter().any(|v| v.is_data()))
+			.and_then(|object| object.current_version().map(|v| v.is_data()))
 			.unwrap_or(false);
 		Ok(exists)
 	}
@@ -222,6 +234,7 @@ impl WebServer {
 	async fn serve_file(
 		self: &Arc<Self>,
 		req: &Request<()>,
+		access_log: &AccessLog,
 	) -> Result<Response<BoxBody<ApiError>>, Error> {
 		// Get http authority string (eg. [::1]:3902 or garage.tld:80)
 		let authority = req
@@ -251,17 +264,62 @@ impl WebServer {
 			.map_err(|_| Error::NotFound)?;
 		let bucket_params = bucket.state.into_option().unwrap();
 
+		let requested_key = path_to_requested_key(req.uri().path())?;
+		access_log.set_bucket(
+			&self.garage,
+			bucket_name,
+			&bucket_params,
+			None,
+			Some(&requested_key),
+		);
+
+		// Website access is anonymous access to the bucket, which is
+		// blocked by the RestrictPublicBuckets setting
+		if bucket_params
+			.effective_public_access_block(&self.garage.config.public_access_block)
+			.restrict_public_buckets
+		{
+			return Err(Error::NotFound);
+		}
+
 		let website_config = bucket_params
 			.website_config
 			.get()
 			.as_ref()
 			.ok_or(Error::NotFound)?;
 
+		// Redirect all requests to another host if asked to
+		if let Some(redirect_all) = &website_config.redirect_all {
+			let protocol = redirect_all
+				.protocol
+				.as_deref()
+				.unwrap_or_else(|| request_protocol(req));
+			let path_and_query = req
+				.uri()
+				.path_and_query()
+				.map(|x| x.as_str())
+				.unwrap_or("/");
+			return Ok(Response::builder()
+				.status(StatusCode::MOVED_PERMANENTLY)
+				.header(
+					LOCATION,
+					format!("{}://{}{}", protocol, redirect_all.hostname, path_and_query),
+				)
+				.body(empty_body())?);
+		}
+
 		// Get path
 		let path = req.uri().path().to_string();
 		let index = &website_config.index_document;
 		let (key, may_redirect) = path_to_keys(&path, index)?;
 
+		// Apply routing rules that don't depend on the result of the request
+		if *req.method() != Method::OPTIONS {
+			if let Some(rule) = website_config.routing_rule(&requested_key, None) {
+				return routing_rule_redirect(req, authority, rule, &requested_key);
+			}
+		}
+
 		debug!(
 			"Selected bucket: \"{}\" {:?}, target key: \"{}\", may redirect to: {:?}",
 			bucket_name, bucket_id, key, may_redirect
@@ -272,7 +330,7 @@ impl WebServer {
 				.map_err(ApiError::from)
 				.map(|res| res.map(|_empty_body: EmptyBody| empty_body())),
 			Method::HEAD => {
-				handle_head_without_ctx(self.garage.clone(), req, bucket_id, &key, None).await
+				handle_head_without_ctx(self.garage.clone(), req, bucket_id, &key, None, None).await
 			}
 			Method::GET => {
 				handle_get_without_ctx(
@@ -281,6 +339,7 @@ impl WebServer {
 					bucket_id,
 					&key,
 					None,
+					None,
 					Default::default(),
 				)
 				.await
@@ -290,15 +349,14 @@ impl WebServer {
 
 		// Try implicit redirect on error
 		let ret_doc_with_redir = match (&ret_doc, may_redirect) {
-			(Err(ApiError::NoSuchKey), ImplicitRedirect::To { key, url })
-				if self.check_key_exists(bucket_id, key.as_str()).await? =>
-			{
-				Ok(Response::builder()
-					.status(StatusCode::FOUND)
-					.header(LOCATION, url)
-					.body(empty_body())
-					.unwrap())
-			}
+			(
+				Err(ApiError::NoSuchKey | ApiError::CurrentVersionIsDeleteMarker(_)),
+				ImplicitRedirect::To { key, url },
+			) if self.check_key_exists(bucket_id, key.as_str()).await? => Ok(Response::builder()
+				.status(StatusCode::FOUND)
+				.header(LOCATION, url)
+				.body(empty_body())
+				.unwrap()),
 			(Ok(ret), _) if ret.headers().contains_key(X_AMZ_WEBSITE_REDIRECT_LOCATION) => {
 				let redirect_location = ret.headers().get(X_AMZ_WEBSITE_REDIRECT_LOCATION).unwrap();
 				Ok(Response::builder()
@@ -312,6 +370,16 @@ impl WebServer {
 
 		match ret_doc_with_redir.map_err(Error::from) {
 			Err(error) => {
+				// Apply routing rules that match the returned error code
+				if *req.method() != Method::OPTIONS {
+					let error_code = error.http_status_code().as_u16();
+					if let Some(rule) =
+						website_config.routing_rule(&requested_key, Some(error_code))
+					{
+						return routing_rule_redirect(req, authority, rule, &requested_key);
+					}
+				}
+
 				// For a HEAD or OPTIONS method, and for non-4xx errors,
 				// we don't return the error document as content,
 				// we return above and just return the error message
@@ -342,6 +410,7 @@ impl WebServer {
 					bucket_id,
 					&error_document,
 					None,
+					None,
 					Default::default(),
 				)
 				.await
@@ -404,6 +473,55 @@ fn error_to_res(e: Error) -> Response<BoxBody<Error>> {
 	http_error
 }
 
+/// Protocol of the original request, as announced by a reverse proxy
+/// in the X-Forwarded-Proto header
+fn request_protocol(req: &Request<()>) -> &'static str {
+	match req
+		.headers()
+		.get("x-forwarded-proto")
+		.and_then(|x| x.to_str().ok())
+	{
+		Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+		_ => "http",
+	}
+}
+
+fn routing_rule_redirect(
+	req: &Request<()>,
+	authority: &str,
+	rule: &RoutingRule,
+	key: &str,
+) -> Result<Response<BoxBody<ApiError>>, Error> {
+	let status = StatusCode::from_u16(rule.redirect.http_redirect_code)
+		.unwrap_or(StatusCode::MOVED_PERMANENTLY);
+	let location = routing_rule_location(rule, key, request_protocol(req), authority);
+	Ok(Response::builder()
+		.status(status)
+		.header(LOCATION, location)
+		.body(empty_body())?)
+}
+
+/// URL to which a request for key `key` is redirected by routing rule `rule`,
+/// `protocol` and `authority` being those of the original request
+fn routing_rule_location(rule: &RoutingRule, key: &str, protocol: &str, authority: &str) -> String {
+	format!(
+		"{}://{}/{}",
+		rule.redirect.protocol.as_deref().unwrap_or(protocol),
+		rule.redirect.hostname.as_deref().unwrap_or(authority),
+		uri_encode(&rule.redirect_key(key), false)
+	)
+}
+
+/// Key requested by the client, before the index document is appended
+/// (used to match the conditions of routing rules)
+fn path_to_requested_key(path: &str) -> Result<String, Error> {
+	let path_utf8 = percent_encoding::percent_decode_str(path).decode_utf8()?;
+	match path_utf8.strip_prefix('/') {
+		Some(key) => Ok(key.to_string()),
+		None => Err(Error::BadRequest("Path must start with a / (slash)".into())),
+	}
+}
+
 #[derive(Debug, PartialEq)]
 enum ImplicitRedirect {
 	No,
@@ -450,6 +568,8 @@ fn path_to_keys<'a>(path: &'a str, index: &str) -> Result<(String, ImplicitRedir
 mod tests {
 	use super::*;
 
+	use garage_model::bucket_table::{Redirect, ReplaceKey};
+
 	#[test]
 	fn path_to_keys_test() -> Result<(), Error> {
 		assert_eq!(
@@ -484,4 +604,49 @@ mod tests {
 		assert!(path_to_keys("i/am/relative", "index.html").is_err());
 		Ok(())
 	}
+
+	#[test]
+	fn routing_rule_location_test() {
+		let rule = |replace_key, hostname: Option<&str>, protocol: Option<&str>| RoutingRule {
+			prefix: Some("docs/".to_string()),
+			http_error_code: None,
+			redirect: Redirect {
+				hostname: hostname.map(str::to_string),
+				protocol: protocol.map(str::to_string),
+				http_redirect_code: 301,
+				replace_key,
+			},
+		};
+
+		assert_eq!(
+			routing_rule_location(&rule(None, None, None), "docs/a b.html", "http", "site.tld"),
+			"http://site.tld/docs/a%20b.html"
+		);
+		assert_eq!(
+			routing_rule_location(
+				&rule(
+					Some(ReplaceKey::Prefix("documents/".to_string())),
+					None,
+					None
+				),
+				"docs/v1/index.html",
+				"https",
+				"site.tld:3902"
+			),
+			"https://site.tld:3902/documents/v1/index.html"
+		);
+		assert_eq!(
+			routing_rule_location(
+				&rule(
+					Some(ReplaceKey::Full("moved.html".to_string())),
+					Some("other.tld"),
+					Some("https")
+				),
+				"docs/page.html",
+				"http",
+				"site.tld"
+			),
+			"https://other.tld/moved.html"
+		);
+	}
 }